@@ -0,0 +1,26 @@
+extern crate abnf_parser;
+extern crate serde_json;
+
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+
+//Usage: abnf_parser GRAMMAR.abnf OUTPUT.json
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() != 2 {
+        eprintln!("Usage: abnf_parser GRAMMAR.abnf OUTPUT.json");
+        process::exit(1);
+    }
+    let grammar = match abnf_parser::parse_file(Path::new(&args[0])) {
+        Ok(grammar) => grammar,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let of = File::create(&args[1]).expect("cannot create output file");
+    serde_json::to_writer(&of, &grammar.rules).expect("Can not write to output file");
+}
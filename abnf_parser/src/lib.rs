@@ -0,0 +1,469 @@
+extern crate rule_format;
+
+pub mod parser;
+
+use std::char;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use parser::{Concatenation, Element, ParseError, Pos, Repetition, RuleAst};
+
+//Alternatives are multiplied out (`a (b / c)` becomes the two rules `ab` and `ac`) as long as a
+//rule doesn't get more than this many alternatives. Bigger groups get their own nonterminal.
+const MAX_INLINE_ALTERNATIVES: usize = 64;
+
+//Value ranges with more code points, like `%x80-10FFFF`, are represented by this many evenly
+//spread code points
+const MAX_RANGE_CHARS: usize = 256;
+
+//RFC 5234 Appendix B.1. A grammar gets the core rules it uses without defining them.
+const CORE_RULES: &str = "\
+ALPHA = %x41-5A / %x61-7A
+BIT = \"0\" / \"1\"
+CHAR = %x01-7F
+CR = %x0D
+CRLF = CR LF
+CTL = %x00-1F / %x7F
+DIGIT = %x30-39
+DQUOTE = %x22
+HEXDIG = DIGIT / \"A\" / \"B\" / \"C\" / \"D\" / \"E\" / \"F\"
+HTAB = %x09
+LF = %x0A
+LWSP = *(WSP / CRLF WSP)
+OCTET = %x00-FF
+SP = %x20
+VCHAR = %x21-7E
+WSP = SP / HTAB
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbnfError {
+    pub file: Option<PathBuf>,
+    //None if the error isn't about a place in the grammar, e.g. if the file can't be read
+    pub pos: Option<Pos>,
+    pub message: String,
+}
+
+impl AbnfError {
+    fn new(pos: Option<Pos>, message: String) -> Self {
+        return AbnfError {
+            file: None,
+            pos,
+            message,
+        };
+    }
+}
+
+impl From<ParseError> for AbnfError {
+    fn from(err: ParseError) -> Self {
+        return AbnfError::new(Some(err.pos), err.message);
+    }
+}
+
+impl fmt::Display for AbnfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(pos) = self.pos {
+            write!(f, "{}:{}:", pos.line, pos.column)?;
+        }
+        if self.file.is_some() || self.pos.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for AbnfError {}
+
+//An ABNF grammar converted to grammartec rules. Rule names are upper case, the first rule of the
+//grammar is the start rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grammar {
+    pub start: String,
+    pub rules: Vec<(String, String)>,
+}
+
+pub fn parse_file(path: &Path) -> Result<Grammar, AbnfError> {
+    let mut src = String::new();
+    let in_file = |mut err: AbnfError| {
+        err.file = Some(path.to_path_buf());
+        return err;
+    };
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut src))
+        .map_err(|e| in_file(AbnfError::new(None, e.to_string())))?;
+    return parse_str(&src).map_err(in_file);
+}
+
+pub fn parse_str(src: &str) -> Result<Grammar, AbnfError> {
+    let mut rules = merge_rules(parser::parse(src)?)?;
+    let start = match rules.first() {
+        Some(rule) => rule.name.clone(),
+        None => return Err(AbnfError::new(None, "grammar does not contain any rules".to_string())),
+    };
+    add_core_rules(&mut rules);
+    let mut lowering = Lowering {
+        nonterminals: rules.iter().map(|rule| rule.name.clone()).collect(),
+        rules: vec![],
+        backslash: None,
+    };
+    for rule in rules.iter() {
+        let first = lowering.rules.len();
+        let definitions = lowering.lower_alternation(&rule.alternatives, &rule.name)?;
+        let num_definitions = definitions.len();
+        for def in definitions {
+            lowering.rules.push((rule.name.clone(), def));
+        }
+        //Helper rules were added while lowering, keep them behind the rule that uses them
+        lowering.rules[first..].rotate_right(num_definitions);
+    }
+    return Ok(Grammar {
+        start,
+        rules: lowering.rules,
+    });
+}
+
+//Adds the alternatives of `=/` to the rules they extend
+fn merge_rules(parsed: Vec<RuleAst>) -> Result<Vec<RuleAst>, AbnfError> {
+    let mut rules: Vec<RuleAst> = vec![];
+    let mut index = HashMap::new();
+    for rule in parsed {
+        match (index.get(&rule.name).cloned(), rule.incremental) {
+            (None, false) => {
+                index.insert(rule.name.clone(), rules.len());
+                rules.push(rule);
+            }
+            (Some(i), true) => rules[i].alternatives.extend(rule.alternatives),
+            (None, true) => {
+                return Err(AbnfError::new(
+                    Some(rule.pos),
+                    format!("=/ used for {} before it is defined", rule.name),
+                ))
+            }
+            (Some(_), false) => {
+                return Err(AbnfError::new(
+                    Some(rule.pos),
+                    format!("{} is already defined, use =/ to add alternatives", rule.name),
+                ))
+            }
+        }
+    }
+    return Ok(rules);
+}
+
+fn add_core_rules(rules: &mut Vec<RuleAst>) {
+    let core_rules = parser::parse(CORE_RULES).expect("RAND_555492965");
+    let mut something_changed = true;
+    while something_changed {
+        something_changed = false;
+        let mut used = HashSet::new();
+        for rule in rules.iter() {
+            used_rules(&rule.alternatives, &mut used);
+        }
+        for core_rule in core_rules.iter() {
+            if used.contains(&core_rule.name) && !rules.iter().any(|r| r.name == core_rule.name) {
+                rules.push(core_rule.clone());
+                something_changed = true;
+            }
+        }
+    }
+}
+
+fn used_rules(alternatives: &[Concatenation], used: &mut HashSet<String>) {
+    for repetition in alternatives.iter().flat_map(|c| c.iter()) {
+        match repetition.element {
+            Element::Rule(ref name) => {
+                used.insert(name.clone());
+            }
+            Element::Group(ref alternatives) | Element::Optional(ref alternatives) => {
+                used_rules(alternatives, used)
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Lowering {
+    nonterminals: HashSet<String>,
+    rules: Vec<(String, String)>,
+    backslash: Option<String>,
+}
+
+impl Lowering {
+    fn lower_alternation(
+        &mut self,
+        alternatives: &[Concatenation],
+        name: &str,
+    ) -> Result<Vec<String>, AbnfError> {
+        let mut definitions = vec![];
+        let mut seen = HashSet::new();
+        for concatenation in alternatives.iter() {
+            for def in self.lower_concatenation(concatenation, name)? {
+                if seen.insert(def.clone()) {
+                    definitions.push(def);
+                }
+            }
+        }
+        return Ok(definitions);
+    }
+
+    fn lower_concatenation(
+        &mut self,
+        concatenation: &Concatenation,
+        name: &str,
+    ) -> Result<Vec<String>, AbnfError> {
+        let mut definitions = vec![String::new()];
+        for repetition in concatenation.iter() {
+            let mut options = self.lower_repetition(repetition, name)?;
+            if concatenation.len() > 1 && options.len() > 1
+                && definitions.len() * options.len() > MAX_INLINE_ALTERNATIVES
+            {
+                options = vec![self.add_helper_rules(options, name, "BLOCK")];
+            }
+            definitions = definitions
+                .iter()
+                .flat_map(|def| options.iter().map(move |option| format!("{}{}", def, option)))
+                .collect();
+        }
+        return Ok(definitions);
+    }
+
+    fn lower_repetition(
+        &mut self,
+        repetition: &Repetition,
+        name: &str,
+    ) -> Result<Vec<String>, AbnfError> {
+        //RFC 3986 writes the empty path as `0<pchar>`, so the element isn't even looked at
+        if repetition.max == Some(0) {
+            return Ok(vec![String::new()]);
+        }
+        let mut options = self.lower_element(&repetition.element, repetition.pos, name)?;
+        return Ok(match (repetition.min, repetition.max) {
+            (1, Some(1)) => options,
+            (0, Some(1)) => {
+                if !options.contains(&String::new()) {
+                    options.push(String::new());
+                }
+                options
+            }
+            (min, max) => vec![self.repeat(options, min, max, name)],
+        });
+    }
+
+    //`min*max element`: the elements that are always there, followed by a recursive rule for an
+    //open upper bound or a chain of optional elements for a fixed one
+    fn repeat(
+        &mut self,
+        options: Vec<String>,
+        min: usize,
+        max: Option<usize>,
+        name: &str,
+    ) -> String {
+        if max.is_none() && min == 0 {
+            let star = self.add_helper_nonterm(name, "STAR");
+            for option in options {
+                self.rules.push((star.clone(), format!("{}{{{}}}", option, star)));
+            }
+            self.rules.push((star.clone(), String::new()));
+            return format!("{{{}}}", star);
+        }
+        let item = match options.len() {
+            1 => options[0].clone(),
+            _ => self.add_helper_rules(options.clone(), name, "ITEM"),
+        };
+        let max = match max {
+            Some(max) => max,
+            None => {
+                let plus = self.add_helper_nonterm(name, "PLUS");
+                for option in options.iter() {
+                    self.rules.push((plus.clone(), option.clone()));
+                }
+                for option in options.iter() {
+                    self.rules.push((plus.clone(), format!("{}{{{}}}", option, plus)));
+                }
+                return format!("{}{{{}}}", item.repeat(min - 1), plus);
+            }
+        };
+        let mut optional = String::new();
+        for _ in min..max {
+            let upto = self.add_helper_nonterm(name, "UPTO");
+            self.rules.push((upto.clone(), String::new()));
+            self.rules.push((upto.clone(), format!("{}{}", item, optional)));
+            optional = format!("{{{}}}", upto);
+        }
+        return format!("{}{}", item.repeat(min), optional);
+    }
+
+    fn lower_element(
+        &mut self,
+        element: &Element,
+        pos: Pos,
+        name: &str,
+    ) -> Result<Vec<String>, AbnfError> {
+        return match element {
+            &Element::Rule(ref rule) => {
+                if !self.nonterminals.contains(rule) {
+                    return Err(AbnfError::new(Some(pos), format!("undefined rule {}", rule)));
+                }
+                Ok(vec![format!("{{{}}}", rule)])
+            }
+            &Element::Group(ref alternatives) => self.lower_alternation(alternatives, name),
+            &Element::Optional(ref alternatives) => {
+                let mut options = self.lower_alternation(alternatives, name)?;
+                if !options.contains(&String::new()) {
+                    options.push(String::new());
+                }
+                Ok(options)
+            }
+            &Element::Chars(ref text) => Ok(vec![self.terminal(text)]),
+            &Element::Range(first, last) => Ok(range_chars(first, last)
+                .into_iter()
+                .map(|c| self.terminal(&c.to_string()))
+                .collect()),
+            &Element::Prose(ref prose) => Err(AbnfError::new(
+                Some(pos),
+                format!("cannot generate the prose value <{}>, replace it by a rule", prose),
+            )),
+        };
+    }
+
+    fn terminal(&mut self, literal: &str) -> String {
+        return rule_format::literal_to_format(literal, || {
+            if self.backslash.is_none() {
+                let backslash = self.add_helper_nonterm("BACKSLASH", "");
+                self.rules.push((backslash.clone(), "\\".to_string()));
+                self.backslash = Some(backslash);
+            }
+            self.backslash.clone().expect("RAND_1784083394")
+        });
+    }
+
+    //Adds a nonterminal for a part of the rule `name`, e.g. URI_STAR for a `*` in uri. More
+    //helpers of the same kind are numbered: URI_STAR2, URI_STAR3, ...
+    fn add_helper_nonterm(&mut self, name: &str, kind: &str) -> String {
+        let base = match kind {
+            "" => name.to_string(),
+            _ => format!("{}_{}", name, kind),
+        };
+        let mut new_nonterm = base.clone();
+        let mut i = 1;
+        while self.nonterminals.contains(&new_nonterm) {
+            i += 1;
+            new_nonterm = format!("{}{}", base, i);
+        }
+        self.nonterminals.insert(new_nonterm.clone());
+        return new_nonterm;
+    }
+
+    fn add_helper_rules(&mut self, definitions: Vec<String>, name: &str, kind: &str) -> String {
+        let new_nonterm = self.add_helper_nonterm(name, kind);
+        for def in definitions {
+            self.rules.push((new_nonterm.clone(), def));
+        }
+        return format!("{{{}}}", new_nonterm);
+    }
+}
+
+fn range_chars(first: u32, last: u32) -> Vec<char> {
+    let count = (last - first) as usize + 1;
+    if count <= MAX_RANGE_CHARS {
+        return (first..last + 1).filter_map(char::from_u32).collect();
+    }
+    return (0..MAX_RANGE_CHARS)
+        .map(|i| first + (i * (count - 1) / (MAX_RANGE_CHARS - 1)) as u32)
+        .filter_map(char::from_u32)
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_for<'a>(grammar: &'a Grammar, nt: &str) -> Vec<&'a str> {
+        return grammar
+            .rules
+            .iter()
+            .filter(|&&(ref name, _)| name == nt)
+            .map(|&(_, ref format)| format.as_str())
+            .collect();
+    }
+
+    #[test]
+    fn check_lowering() {
+        let grammar = parse_str(
+            "uri = scheme \":\" [\"//\" host] *(\"/\" segment)\n\
+             scheme = 1*ALPHA\n\
+             host = 1*3DIGIT \".\" 2*%x61-62 / \"{\" 3\"\\\" \"}\"\n\
+             segment = %s\"a\" %x2F.41\n\
+             segment =/ \"b\"",
+        ).expect("RAND_2464356971");
+        assert_eq!(grammar.start, "URI");
+        assert_eq!(grammar.rules[0].1, "{SCHEME}://{HOST}{URI_STAR}");
+        assert_eq!(
+            rules_for(&grammar, "URI"),
+            vec!["{SCHEME}://{HOST}{URI_STAR}", "{SCHEME}:{URI_STAR}"]
+        );
+        assert_eq!(rules_for(&grammar, "URI_STAR"), vec!["/{SEGMENT}{URI_STAR}", ""]);
+        assert_eq!(rules_for(&grammar, "SCHEME"), vec!["{SCHEME_PLUS}"]);
+        assert_eq!(rules_for(&grammar, "SCHEME_PLUS"), vec!["{ALPHA}", "{ALPHA}{SCHEME_PLUS}"]);
+        assert_eq!(
+            rules_for(&grammar, "HOST"),
+            vec![
+                "{DIGIT}{HOST_UPTO2}.{HOST_ITEM}{HOST_PLUS}",
+                "\\{{BACKSLASH}{BACKSLASH}{BACKSLASH}\\}",
+            ]
+        );
+        assert_eq!(rules_for(&grammar, "HOST_UPTO"), vec!["", "{DIGIT}"]);
+        assert_eq!(rules_for(&grammar, "HOST_UPTO2"), vec!["", "{DIGIT}{HOST_UPTO}"]);
+        assert_eq!(rules_for(&grammar, "HOST_ITEM"), vec!["a", "b"]);
+        assert_eq!(rules_for(&grammar, "SEGMENT"), vec!["a/A", "b"]);
+        //Only the core rules that are used are added
+        assert_eq!(rules_for(&grammar, "DIGIT").len(), 10);
+        assert_eq!(rules_for(&grammar, "ALPHA").len(), 52);
+        assert_eq!(rules_for(&grammar, "HEXDIG").len(), 0);
+    }
+
+    #[test]
+    fn check_bundled_grammar() {
+        let grammar = parse_file(Path::new("src/uri.abnf")).expect("RAND_389983959");
+        assert_eq!(grammar.start, "URI");
+        let defined = grammar
+            .rules
+            .iter()
+            .map(|&(ref nt, _)| nt.clone())
+            .collect::<HashSet<_>>();
+        for &(_, ref format) in grammar.rules.iter() {
+            let mut rest = format.as_str();
+            while let Some(open) = rest.find('{') {
+                if open > 0 && rest[..open].ends_with('\\') {
+                    rest = &rest[open + 1..];
+                    continue;
+                }
+                let close = rest[open..].find('}').expect("RAND_3021907349") + open;
+                assert!(defined.contains(&rest[open + 1..close]));
+                rest = &rest[close + 1..];
+            }
+        }
+        assert_eq!(rules_for(&grammar, "PATH-EMPTY"), vec![""]);
+        assert_eq!(rules_for(&grammar, "H16"), vec!["{HEXDIG}{H16_UPTO3}"]);
+    }
+
+    #[test]
+    fn check_errors() {
+        let err = parse_str("a = b\n").expect_err("RAND_3542957572");
+        assert_eq!(err.to_string(), "1:5: undefined rule B");
+        let err = parse_str("a = \"x\"\nA = \"y\"").expect_err("RAND_2760062450");
+        assert_eq!(err.pos, Some(Pos { line: 2, column: 1 }));
+        let err = parse_str("a = <some text>").expect_err("RAND_2808997749");
+        assert!(err.message.contains("prose"));
+        assert!(parse_str("; nothing\n").is_err());
+        //One of the picked code points is a surrogate
+        assert_eq!(range_chars(0, 0x10ffff).len(), MAX_RANGE_CHARS - 1);
+    }
+}
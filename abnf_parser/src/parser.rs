@@ -0,0 +1,482 @@
+use std::char;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleAst {
+    pub name: String,
+    //`name =/ ...` adds alternatives to a rule defined earlier
+    pub incremental: bool,
+    pub alternatives: Vec<Concatenation>,
+    pub pos: Pos,
+}
+
+pub type Concatenation = Vec<Repetition>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repetition {
+    pub min: usize,
+    //None for an open upper bound like in `1*DIGIT`
+    pub max: Option<usize>,
+    pub element: Element,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Rule(String),
+    Group(Vec<Concatenation>),
+    Optional(Vec<Concatenation>),
+    //A quoted string or a value like `%x0D.0A`. Quoted strings are case-insensitive unless they
+    //are written as `%s"..."`, the text is kept as written either way.
+    Chars(String),
+    //`%x30-39` as an inclusive range of code points
+    Range(u32, u32),
+    //`<...>`, a description in prose
+    Prose(String),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    idx: usize,
+}
+
+//Parses the rules of an ABNF grammar (RFC 5234, with the `%s` and `%i` strings of RFC 7405). Rule
+//names are returned in upper case, as ABNF doesn't care about their case.
+pub fn parse(src: &str) -> Result<Vec<RuleAst>, ParseError> {
+    let mut parser = Parser {
+        chars: src.chars().collect(),
+        idx: 0,
+    };
+    let mut rules = vec![];
+    loop {
+        parser.skip_empty_lines();
+        if parser.peek(0).is_none() {
+            return Ok(rules);
+        }
+        rules.push(parser.parse_rule()?);
+    }
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> Option<char> {
+        return self.chars.get(self.idx + offset).cloned();
+    }
+
+    fn pos_of(&self, idx: usize) -> Pos {
+        let mut pos = Pos { line: 1, column: 1 };
+        for &c in self.chars[..idx].iter() {
+            if c == '\n' {
+                pos.line += 1;
+                pos.column = 1;
+            } else {
+                pos.column += 1;
+            }
+        }
+        return pos;
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        return Err(ParseError {
+            pos: self.pos_of(self.idx),
+            message: message.to_string(),
+        });
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ParseError> {
+        return match self.peek(0) {
+            Some('\n') | Some('\r') => {
+                self.error(&format!("expected {}, found end of line", expected))
+            }
+            Some(c) => self.error(&format!("expected {}, found {:?}", expected, c)),
+            None => self.error(&format!("expected {}, found end of file", expected)),
+        };
+    }
+
+    //Skips lines that only contain whitespace and comments
+    fn skip_empty_lines(&mut self) {
+        loop {
+            let start = self.idx;
+            while self.peek(0) == Some(' ') || self.peek(0) == Some('\t') {
+                self.idx += 1;
+            }
+            self.skip_comment();
+            match self.peek(0) {
+                Some('\r') | Some('\n') => self.skip_newline(),
+                None => return,
+                _ => {
+                    self.idx = start;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek(0) == Some(';') {
+            while self.peek(0).is_some_and(|c| c != '\n' && c != '\r') {
+                self.idx += 1;
+            }
+        }
+    }
+
+    fn skip_newline(&mut self) {
+        if self.peek(0) == Some('\r') {
+            self.idx += 1;
+        }
+        if self.peek(0) == Some('\n') {
+            self.idx += 1;
+        }
+    }
+
+    fn at_newline(&self) -> bool {
+        return self.peek(0) == Some('\n') || self.peek(0) == Some('\r');
+    }
+
+    //Skips whitespace, comments and line breaks that are followed by an indented line, i.e. the
+    //c-wsp of the RFC. Returns whether anything was skipped.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.idx;
+        loop {
+            match self.peek(0) {
+                Some(' ') | Some('\t') => self.idx += 1,
+                Some(';') => self.skip_comment(),
+                Some('\r') | Some('\n') => {
+                    let line_end = self.idx;
+                    self.skip_newline();
+                    //Empty and comment lines inside a rule are allowed as well
+                    self.skip_empty_lines();
+                    if self.peek(0) != Some(' ') && self.peek(0) != Some('\t') {
+                        self.idx = line_end;
+                        return self.idx > start;
+                    }
+                }
+                _ => return self.idx > start,
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<String, ParseError> {
+        if !self.peek(0).is_some_and(|c| c.is_ascii_alphabetic()) {
+            return self.unexpected("rule name");
+        }
+        let mut name = String::new();
+        while let Some(c) = self.peek(0) {
+            if !c.is_ascii_alphanumeric() && c != '-' {
+                break;
+            }
+            name.push(c.to_ascii_uppercase());
+            self.idx += 1;
+        }
+        return Ok(name);
+    }
+
+    fn parse_rule(&mut self) -> Result<RuleAst, ParseError> {
+        let pos = self.pos_of(self.idx);
+        let name = self.parse_name()?;
+        self.skip_whitespace();
+        if self.peek(0) != Some('=') {
+            return self.unexpected("= or =/");
+        }
+        self.idx += 1;
+        let incremental = self.peek(0) == Some('/');
+        if incremental {
+            self.idx += 1;
+        }
+        self.skip_whitespace();
+        let alternatives = self.parse_alternation()?;
+        self.skip_whitespace();
+        if self.peek(0).is_some() && !self.at_newline() {
+            return self.unexpected("end of rule");
+        }
+        self.skip_newline();
+        return Ok(RuleAst {
+            name,
+            incremental,
+            alternatives,
+            pos,
+        });
+    }
+
+    fn parse_alternation(&mut self) -> Result<Vec<Concatenation>, ParseError> {
+        let mut alternatives = vec![self.parse_concatenation()?];
+        loop {
+            let start = self.idx;
+            self.skip_whitespace();
+            if self.peek(0) != Some('/') {
+                self.idx = start;
+                return Ok(alternatives);
+            }
+            self.idx += 1;
+            self.skip_whitespace();
+            alternatives.push(self.parse_concatenation()?);
+        }
+    }
+
+    fn starts_repetition(&self) -> bool {
+        return match self.peek(0) {
+            Some(c) => c.is_ascii_alphanumeric() || "*([\"%<".contains(c),
+            None => false,
+        };
+    }
+
+    fn parse_concatenation(&mut self) -> Result<Concatenation, ParseError> {
+        let mut repetitions = vec![self.parse_repetition()?];
+        loop {
+            let start = self.idx;
+            if !self.skip_whitespace() || !self.starts_repetition() {
+                self.idx = start;
+                return Ok(repetitions);
+            }
+            repetitions.push(self.parse_repetition()?);
+        }
+    }
+
+    fn parse_number(&mut self, radix: u32) -> Option<u32> {
+        let start = self.idx;
+        while self.peek(0).is_some_and(|c| c.is_digit(radix)) {
+            self.idx += 1;
+        }
+        let digits = self.chars[start..self.idx].iter().collect::<String>();
+        return u32::from_str_radix(&digits, radix).ok();
+    }
+
+    fn parse_repetition(&mut self) -> Result<Repetition, ParseError> {
+        let pos = self.pos_of(self.idx);
+        let min = self.parse_number(10);
+        let (min, max) = if self.peek(0) == Some('*') {
+            self.idx += 1;
+            (min.unwrap_or(0), self.parse_number(10))
+        } else {
+            (min.unwrap_or(1), Some(min.unwrap_or(1)))
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(ParseError {
+                pos,
+                message: format!("repetition {}*{} has no allowed count", min, max.unwrap_or(0)),
+            });
+        }
+        return Ok(Repetition {
+            min: min as usize,
+            max: max.map(|max| max as usize),
+            element: self.parse_element()?,
+            pos,
+        });
+    }
+
+    fn parse_element(&mut self) -> Result<Element, ParseError> {
+        return match self.peek(0) {
+            Some(c) if c.is_ascii_alphabetic() => Ok(Element::Rule(self.parse_name()?)),
+            Some('(') => Ok(Element::Group(self.parse_group(')')?)),
+            Some('[') => Ok(Element::Optional(self.parse_group(']')?)),
+            Some('"') => Ok(Element::Chars(self.parse_quoted()?)),
+            Some('<') => {
+                self.idx += 1;
+                let mut prose = String::new();
+                while let Some(c) = self.peek(0) {
+                    self.idx += 1;
+                    if c == '>' {
+                        return Ok(Element::Prose(prose));
+                    }
+                    prose.push(c);
+                }
+                self.error("unterminated prose value")
+            }
+            Some('%') => self.parse_value(),
+            _ => self.unexpected("element"),
+        };
+    }
+
+    fn parse_group(&mut self, end: char) -> Result<Vec<Concatenation>, ParseError> {
+        self.idx += 1;
+        self.skip_whitespace();
+        let alternatives = self.parse_alternation()?;
+        self.skip_whitespace();
+        if self.peek(0) != Some(end) {
+            return self.unexpected(&end.to_string());
+        }
+        self.idx += 1;
+        return Ok(alternatives);
+    }
+
+    fn parse_quoted(&mut self) -> Result<String, ParseError> {
+        self.idx += 1;
+        let mut text = String::new();
+        loop {
+            match self.peek(0) {
+                Some('"') => {
+                    self.idx += 1;
+                    return Ok(text);
+                }
+                Some(c) if c != '\n' && c != '\r' => {
+                    text.push(c);
+                    self.idx += 1;
+                }
+                _ => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    //`%x41`, `%d13.10`, `%b0-1`, `%s"Ab"` or `%i"ab"`
+    fn parse_value(&mut self) -> Result<Element, ParseError> {
+        self.idx += 1;
+        let radix = match self.peek(0).map(|c| c.to_ascii_lowercase()) {
+            Some('b') => 2,
+            Some('d') => 10,
+            Some('x') => 16,
+            Some('s') | Some('i') if self.peek(1) == Some('"') => {
+                self.idx += 1;
+                return Ok(Element::Chars(self.parse_quoted()?));
+            }
+            _ => return self.unexpected("b, d, x, s or i"),
+        };
+        self.idx += 1;
+        let first = self.parse_value_number(radix)?;
+        if self.peek(0) == Some('-') {
+            self.idx += 1;
+            let last = self.parse_value_number(radix)?;
+            if last < first {
+                return self.error("empty value range");
+            }
+            return Ok(Element::Range(first, last));
+        }
+        let mut text = String::new();
+        text.push(self.value_char(first)?);
+        while self.peek(0) == Some('.') {
+            self.idx += 1;
+            let value = self.parse_value_number(radix)?;
+            text.push(self.value_char(value)?);
+        }
+        return Ok(Element::Chars(text));
+    }
+
+    fn parse_value_number(&mut self, radix: u32) -> Result<u32, ParseError> {
+        return match self.parse_number(radix) {
+            Some(value) => Ok(value),
+            None => self.unexpected("number"),
+        };
+    }
+
+    fn value_char(&self, value: u32) -> Result<char, ParseError> {
+        return match char::from_u32(value) {
+            Some(c) => Ok(c),
+            None => self.error(&format!("{:#x} is not a character", value)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rep(min: usize, max: Option<usize>, element: Element) -> Element {
+        return Element::Group(vec![vec![Repetition {
+            min,
+            max,
+            element,
+            pos: Pos { line: 0, column: 0 },
+        }]]);
+    }
+
+    //Replaces the positions, so the elements can be compared
+    fn elements(alternatives: &[Concatenation]) -> Vec<Vec<Element>> {
+        return alternatives
+            .iter()
+            .map(|concatenation| {
+                concatenation
+                    .iter()
+                    .map(|r| rep(r.min, r.max, r.element.clone()))
+                    .collect()
+            })
+            .collect();
+    }
+
+    #[test]
+    fn check_rule_syntax() {
+        let rules = parse(
+            "; a comment\r\n\
+             \r\n\
+             uri = scheme \":\" [ \"//\" host ] ; comment\r\n\
+             \x20     *( \"/\" 1*pchar ) 2*3DIGIT\r\n\
+             scheme = %x61-7A / %d13.10 / %s\"Ab\"\n\
+             scheme =/ <anything> 4ALPHA\n",
+        ).expect("RAND_3259111368");
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].name, "URI");
+        assert_eq!(rules[0].pos, Pos { line: 3, column: 1 });
+        assert_eq!(
+            elements(&rules[0].alternatives),
+            vec![vec![
+                rep(1, Some(1), Element::Rule("SCHEME".to_string())),
+                rep(1, Some(1), Element::Chars(":".to_string())),
+                rep(1, Some(1), Element::Optional(vec![vec![
+                    Repetition {
+                        min: 1,
+                        max: Some(1),
+                        element: Element::Chars("//".to_string()),
+                        pos: Pos { line: 3, column: 20 },
+                    },
+                    Repetition {
+                        min: 1,
+                        max: Some(1),
+                        element: Element::Rule("HOST".to_string()),
+                        pos: Pos { line: 3, column: 25 },
+                    },
+                ]])),
+                rep(0, None, Element::Group(vec![vec![
+                    Repetition {
+                        min: 1,
+                        max: Some(1),
+                        element: Element::Chars("/".to_string()),
+                        pos: Pos { line: 4, column: 10 },
+                    },
+                    Repetition {
+                        min: 1,
+                        max: None,
+                        element: Element::Rule("PCHAR".to_string()),
+                        pos: Pos { line: 4, column: 14 },
+                    },
+                ]])),
+                rep(2, Some(3), Element::Rule("DIGIT".to_string())),
+            ]]
+        );
+        assert_eq!(
+            elements(&rules[1].alternatives),
+            vec![
+                vec![rep(1, Some(1), Element::Range(0x61, 0x7a))],
+                vec![rep(1, Some(1), Element::Chars("\r\n".to_string()))],
+                vec![rep(1, Some(1), Element::Chars("Ab".to_string()))],
+            ]
+        );
+        assert!(rules[2].incremental);
+        assert_eq!(rules[2].alternatives[0][0].element, Element::Prose("anything".to_string()));
+    }
+
+    #[test]
+    fn check_parse_errors() {
+        let err = parse("a = b\nc = (d\n").expect_err("RAND_1052254484");
+        assert_eq!(err.pos, Pos { line: 2, column: 7 });
+        assert_eq!(err.to_string(), "2:7: expected ), found end of line");
+        let err = parse("a = 3*2b").expect_err("RAND_3748045633");
+        assert_eq!(err.pos, Pos { line: 1, column: 5 });
+        assert!(parse("a = %x39-30").is_err());
+        assert!(parse(" a = b").is_err());
+    }
+}
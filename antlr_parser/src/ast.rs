@@ -0,0 +1,133 @@
+//Syntax tree of an ANTLR4 grammar. Actions, semantic predicates, element labels and options
+//don't change which strings a grammar derives, so the parser checks their syntax but doesn't keep
+//them in the tree.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrammarKind {
+    Combined,
+    Lexer,
+    Parser,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarAst {
+    pub kind: GrammarKind,
+    pub name: String,
+    pub options: Vec<(String, String)>,
+    pub imports: Vec<String>,
+    pub tokens: Vec<String>,
+    pub rules: Vec<RuleAst>,
+}
+
+impl GrammarAst {
+    pub fn get_rule(&self, name: &str) -> Option<&RuleAst> {
+        return self.rules.iter().find(|rule| rule.name == name);
+    }
+
+    pub fn get_option(&self, key: &str) -> Option<&str> {
+        return self
+            .options
+            .iter()
+            .find(|&&(ref k, _)| k == key)
+            .map(|&(_, ref v)| v.as_str());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleAst {
+    pub name: String,
+    pub fragment: bool,
+    //The lexer mode the rule was defined in, None for the default mode and for parser rules
+    pub mode: Option<String>,
+    pub alternatives: Vec<Alternative>,
+    pub pos: Pos,
+    //Position of the `;` that ends the rule
+    pub end: Pos,
+}
+
+impl RuleAst {
+    //Like ANTLR, tells lexer and parser rules apart by the case of the first letter
+    pub fn is_lexer_rule(&self) -> bool {
+        return self.name.chars().next().map_or(false, |c| c.is_uppercase());
+    }
+
+    //Rules like `WS : [ \t]+ -> skip ;` never reach the parser
+    pub fn is_skipped(&self) -> bool {
+        return !self.alternatives.is_empty() && self.alternatives.iter().all(|alt| alt.is_skipped());
+    }
+
+    //Skipped rules and rules like `COMMENT : '#' ~[\n]* -> channel(HIDDEN) ;`
+    pub fn is_hidden(&self) -> bool {
+        return !self.alternatives.is_empty() && self.alternatives.iter().all(|alt| alt.is_hidden());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+    //The `# Label` of a parser rule alternative
+    pub label: Option<String>,
+    pub elements: Vec<Element>,
+    //The `-> skip, channel(HIDDEN)` part of a lexer rule alternative
+    pub commands: Vec<LexerCommand>,
+}
+
+impl Alternative {
+    pub fn is_skipped(&self) -> bool {
+        return self.commands.iter().any(|cmd| cmd.name == "skip");
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        return self.is_skipped() || self.commands.iter().any(|cmd| {
+            cmd.name == "channel" && match cmd.arg {
+                Some(ref channel) => channel != "DEFAULT_TOKEN_CHANNEL" && channel != "0",
+                None => false,
+            }
+        });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexerCommand {
+    pub name: String,
+    pub arg: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub atom: Atom,
+    pub suffix: Option<Suffix>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Optional,
+    Star,
+    Plus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suffix {
+    pub repeat: Repeat,
+    pub greedy: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    //A quoted literal with all escape sequences already decoded
+    Literal(String),
+    //`'a'..'z'` or `[a-z0-9_]`, as inclusive character ranges
+    Set(Vec<(char, char)>),
+    //`~[...]`, `~'x'` or `~('a' | B)`; each atom is a Literal, Set or token Ref
+    Not(Vec<Atom>),
+    Wildcard,
+    //Reference to a parser rule, lexer rule or EOF
+    Ref(String),
+    Block(Vec<Alternative>),
+}
@@ -1,19 +1,71 @@
-#![feature(vec_remove_item)]
-mod lib;
-
+extern crate antlr_parser;
 extern crate serde;
 extern crate serde_json;
 
+use antlr_parser::{Alphabet, AntlrParser, LoweringOptions, RepetitionStyle, Separator};
 use std::env;
 use std::fs::File;
+use std::process;
 
+//Usage: antlr_parser [--separator none|skipped|TEXT] [--alphabet ascii|latin1|unicode:N]
+//                    [--repetition right|left|balanced] [--min-repeat N] [--max-repeat N]
+//                    GRAMMAR.g4 [MORE.g4 ...] OUTPUT.json
 fn main() {
-    let input_path = env::args().nth(1).expect("input filename missing");
-    let output_path = env::args().nth(2).expect("output filename missing");
+    let mut options = LoweringOptions::new();
+    options.separator = Separator::Text(" ".to_string());
+    let mut args = vec![];
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        if arg == "--separator" {
+            options.separator = match argv.next().expect("separator missing").as_str() {
+                "none" => Separator::None,
+                "skipped" => Separator::Skipped,
+                text => Separator::Text(text.to_string()),
+            };
+        } else if arg == "--alphabet" {
+            let alphabet = argv.next().expect("alphabet missing");
+            options.alphabet = match alphabet.as_str() {
+                "ascii" => Alphabet::Ascii,
+                "latin1" => Alphabet::Latin1,
+                _ if alphabet.starts_with("unicode:") => Alphabet::Unicode(
+                    alphabet["unicode:".len()..]
+                        .parse()
+                        .expect("number of unicode characters expected"),
+                ),
+                _ => panic!("unknown alphabet {}", alphabet),
+            };
+        } else if arg == "--repetition" {
+            options.repetition.style = match argv.next().expect("repetition missing").as_str() {
+                "right" => RepetitionStyle::Right,
+                "left" => RepetitionStyle::Left,
+                "balanced" => RepetitionStyle::Balanced,
+                style => panic!("unknown repetition style {}", style),
+            };
+        } else if arg == "--min-repeat" {
+            let min = argv.next().expect("minimum missing");
+            options.repetition.min = min.parse().expect("number expected");
+        } else if arg == "--max-repeat" {
+            let max = argv.next().expect("maximum missing");
+            options.repetition.max = Some(max.parse().expect("number expected"));
+        } else {
+            args.push(arg);
+        }
+    }
+    let output_path = args.pop().expect("output filename missing");
+    let input_paths = args.iter().map(|path| path.as_str()).collect::<Vec<_>>();
+    if input_paths.is_empty() {
+        panic!("input filename missing");
+    }
 
-    let mut my_parser = lib::AntlrParser::new();
-    my_parser.parse_antlr_grammar(&input_path);
+    let my_parser = AntlrParser::with_options(options);
+    let grammar = match my_parser.parse_files(&input_paths) {
+        Ok(grammar) => grammar,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
 
     let of = File::create(output_path).expect("cannot create output file");
-    serde_json::to_writer(&of, &my_parser.rules).expect("Can not write to output file");
+    serde_json::to_writer(&of, &grammar.rules).expect("Can not write to output file");
 }
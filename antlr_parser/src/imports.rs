@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use ast::{GrammarAst, GrammarKind};
+use parser;
+use AntlrError;
+
+pub fn read_grammar(path: &Path) -> Result<GrammarAst, AntlrError> {
+    let mut src = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut src))
+        .map_err(|e| AntlrError::new(e.to_string()).in_file(path))?;
+    return parser::parse(&src).map_err(|e| AntlrError::from(e).in_file(path));
+}
+
+struct GrammarSet {
+    //Grammars parsed from strings have no path
+    grammars: Vec<(Option<PathBuf>, GrammarAst)>,
+    merged: HashSet<usize>,
+}
+
+//Combines split lexer and parser grammars into one grammar. The main grammar is the first
+//parser (or combined) grammar that isn't imported by one of the others. Grammars named by
+//`import` and `options { tokenVocab = ...; }` are taken from the given files, or read from
+//`<name>.g4` next to the grammar that uses them. As in ANTLR, a rule of the importing grammar
+//overrides an imported rule with the same name, and earlier imports win over later ones.
+pub fn combine_grammars(files: &[PathBuf]) -> Result<GrammarAst, AntlrError> {
+    let mut grammars = vec![];
+    for file in files {
+        grammars.push((Some(file.clone()), read_grammar(file)?));
+    }
+    return combine_asts(grammars);
+}
+
+//Same as combine_grammars for grammars that are already parsed. Grammars without a path can't
+//read their imports from disk, everything they use has to be given.
+pub fn combine_asts(
+    grammars: Vec<(Option<PathBuf>, GrammarAst)>,
+) -> Result<GrammarAst, AntlrError> {
+    let given = grammars.len();
+    let mut set = GrammarSet {
+        grammars,
+        merged: HashSet::new(),
+    };
+    let imported = set.grammars
+        .iter()
+        .flat_map(|&(_, ref ast)| ast.imports.iter().cloned())
+        .collect::<HashSet<_>>();
+    let main = match set.grammars
+        .iter()
+        .position(|&(_, ref ast)| ast.kind != GrammarKind::Lexer && !imported.contains(&ast.name))
+    {
+        Some(main) => main,
+        None if given > 0 => 0,
+        None => return Err(AntlrError::new("no grammar files given".to_string())),
+    };
+    let mut combined = GrammarAst {
+        kind: GrammarKind::Combined,
+        name: set.grammars[main].1.name.clone(),
+        options: set.grammars[main].1.options.clone(),
+        imports: vec![],
+        tokens: vec![],
+        rules: vec![],
+    };
+    set.merge_into(main, &mut combined)?;
+    //Lexer grammars that were given without being referenced still provide tokens
+    for i in 0..given {
+        set.merge_into(i, &mut combined)?;
+    }
+    return Ok(combined);
+}
+
+impl GrammarSet {
+    fn find_or_load(&mut self, name: &str, user: &Option<PathBuf>) -> Result<usize, AntlrError> {
+        if let Some(i) = self.grammars.iter().position(|&(_, ref ast)| ast.name == name) {
+            return Ok(i);
+        }
+        let user = match user {
+            &Some(ref user) => user,
+            &None => return Err(AntlrError::new(format!("cannot find grammar {}", name))),
+        };
+        let path = user
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(format!("{}.g4", name));
+        if !path.is_file() {
+            return Err(AntlrError::new(format!(
+                "cannot find grammar {} (looked for {})",
+                name,
+                path.display()
+            )).in_file(user));
+        }
+        let ast = read_grammar(&path)?;
+        self.grammars.push((Some(path), ast));
+        return Ok(self.grammars.len() - 1);
+    }
+
+    fn merge_into(&mut self, i: usize, combined: &mut GrammarAst) -> Result<(), AntlrError> {
+        if !self.merged.insert(i) {
+            return Ok(());
+        }
+        let (path, ast) = self.grammars[i].clone();
+        for rule in ast.rules.iter() {
+            if combined.get_rule(&rule.name).is_none() {
+                combined.rules.push(rule.clone());
+            }
+        }
+        for token in ast.tokens.iter() {
+            if !combined.tokens.contains(token) {
+                combined.tokens.push(token.clone());
+            }
+        }
+        let mut used = ast.imports.clone();
+        if let Some(vocab) = ast.get_option("tokenVocab") {
+            used.push(vocab.to_string());
+        }
+        for name in used {
+            let j = self.find_or_load(&name, &path)?;
+            self.merge_into(j, combined)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::{self, TempDir};
+
+    fn write_grammars(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().expect("RAND_2753165380");
+        for &(name, content) in files.iter() {
+            let mut file = File::create(dir.path().join(name)).expect("RAND_1041337092");
+            file.write_all(content.as_bytes()).expect("RAND_3962066540");
+        }
+        return dir;
+    }
+
+    fn rule_text(ast: &GrammarAst, name: &str) -> Option<String> {
+        return ast.get_rule(name).map(|rule| format!("{:?}", rule.alternatives));
+    }
+
+    #[test]
+    fn check_split_grammars() {
+        let tmp = write_grammars(
+            &[
+                (
+                    "L.g4",
+                    "lexer grammar L;\ntokens { EXTRA }\nID : [a-b] ;\nKW : 'kw' ;\nWS : ' ' -> skip ;",
+                ),
+                ("Common.g4", "parser grammar Common;\nvalue : ID ;\nstmt : 'common' ;"),
+                (
+                    "P.g4",
+                    "parser grammar P;\noptions { tokenVocab = L; }\nimport Common;\n\
+                     prog : stmt+ ;\nstmt : KW value ;",
+                ),
+                ("Broken.g4", "parser grammar Broken;\nimport Missing;\nprog : 'x' ;"),
+            ],
+        );
+        let dir = tmp.path();
+        let ast = combine_grammars(&[dir.join("P.g4")]).expect("RAND_1398244914");
+        assert_eq!(ast.name, "P");
+        let names = ast.rules.iter().map(|rule| rule.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["prog", "stmt", "value", "ID", "KW", "WS"]);
+        assert!(rule_text(&ast, "stmt").expect("RAND_3373520001").contains("KW"));
+        assert_eq!(ast.tokens, vec!["EXTRA"]);
+
+        //The lexer grammar doesn't become the main grammar, even if it is given first
+        let ast = combine_grammars(&[dir.join("L.g4"), dir.join("P.g4")]).expect("RAND_859301477");
+        assert_eq!(ast.name, "P");
+        assert_eq!(ast.rules[0].name, "prog");
+
+        let err = combine_grammars(&[dir.join("Broken.g4")]).expect_err("RAND_2099627436");
+        assert!(err.message.contains("cannot find grammar Missing"));
+        assert_eq!(err.file, Some(dir.join("Broken.g4")));
+    }
+}
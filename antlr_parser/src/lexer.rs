@@ -0,0 +1,267 @@
+use ast::Pos;
+use parser::ParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    //A quoted literal, escape sequences are decoded later together with character sets
+    StringLit(String),
+    //The raw text between `[` and `]`. Depending on the context this is a character set or the
+    //arguments of a rule.
+    CharSet(String),
+    //The raw text between `{` and `}`
+    Action(String),
+    Int(String),
+    Colon,
+    ColonColon,
+    Semi,
+    Or,
+    LParen,
+    RParen,
+    Star,
+    Plus,
+    Question,
+    Tilde,
+    Dot,
+    Range,
+    Arrow,
+    Assign,
+    PlusAssign,
+    Pound,
+    Comma,
+    Lt,
+    Gt,
+    At,
+    Eof,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    idx: usize,
+    pos: Pos,
+}
+
+//Splits an ANTLR4 grammar into tokens, dropping whitespace, `//` and `/* */` comments
+pub fn tokenize(src: &str) -> Result<Vec<(Token, Pos)>, ParseError> {
+    let mut lexer = Lexer {
+        chars: src.chars().collect(),
+        idx: 0,
+        pos: Pos { line: 1, column: 1 },
+    };
+    let mut tokens = vec![];
+    loop {
+        lexer.skip_whitespace_and_comments()?;
+        let pos = lexer.pos;
+        match lexer.peek(0) {
+            None => {
+                tokens.push((Token::Eof, pos));
+                return Ok(tokens);
+            }
+            Some(c) => tokens.push((lexer.read_token(c)?, pos)),
+        }
+    }
+}
+
+impl Lexer {
+    fn peek(&self, offset: usize) -> Option<char> {
+        return self.chars.get(self.idx + offset).cloned();
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.idx += 1;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        return Some(c);
+    }
+
+    fn error<T>(&self, pos: Pos, message: &str) -> Result<T, ParseError> {
+        return Err(ParseError {
+            pos,
+            message: message.to_string(),
+        });
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => while self.peek(0).map_or(false, |c| c != '\n') {
+                    self.bump();
+                },
+                (Some('/'), Some('*')) => {
+                    let start = self.pos;
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match (self.peek(0), self.peek(1)) {
+                            (Some('*'), Some('/')) => break,
+                            (None, _) => return self.error(start, "unterminated comment"),
+                            _ => {
+                                self.bump();
+                            }
+                        }
+                    }
+                    self.bump();
+                    self.bump();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_token(&mut self, c: char) -> Result<Token, ParseError> {
+        let start = self.pos;
+        if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(c) = self.peek(0) {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                ident.push(c);
+                self.bump();
+            }
+            return Ok(Token::Ident(ident));
+        }
+        if c.is_ascii_digit() {
+            let mut int = String::new();
+            while let Some(c) = self.peek(0) {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                int.push(c);
+                self.bump();
+            }
+            return Ok(Token::Int(int));
+        }
+        self.bump();
+        let token = match c {
+            '\'' => Token::StringLit(self.read_delimited('\'', start, "unterminated string literal")?),
+            '[' => Token::CharSet(self.read_delimited(']', start, "unterminated character set")?),
+            '{' => Token::Action(self.read_action(start)?),
+            ':' if self.peek(0) == Some(':') => {
+                self.bump();
+                Token::ColonColon
+            }
+            ':' => Token::Colon,
+            ';' => Token::Semi,
+            '|' => Token::Or,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '*' => Token::Star,
+            '+' if self.peek(0) == Some('=') => {
+                self.bump();
+                Token::PlusAssign
+            }
+            '+' => Token::Plus,
+            '?' => Token::Question,
+            '~' => Token::Tilde,
+            '.' if self.peek(0) == Some('.') => {
+                self.bump();
+                Token::Range
+            }
+            '.' => Token::Dot,
+            '-' if self.peek(0) == Some('>') => {
+                self.bump();
+                Token::Arrow
+            }
+            '=' => Token::Assign,
+            '#' => Token::Pound,
+            ',' => Token::Comma,
+            '<' => Token::Lt,
+            '>' => Token::Gt,
+            '@' => Token::At,
+            _ => return self.error(start, &format!("unexpected character {:?}", c)),
+        };
+        return Ok(token);
+    }
+
+    //Reads up to the closing delimiter. Escape sequences are kept as they are, they only have to
+    //be skipped so an escaped delimiter doesn't end the token.
+    fn read_delimited(&mut self, end: char, start: Pos, message: &str) -> Result<String, ParseError> {
+        let mut content = String::new();
+        loop {
+            match self.bump() {
+                None => return self.error(start, message),
+                Some(c) if c == end => return Ok(content),
+                Some('\\') => {
+                    content.push('\\');
+                    match self.bump() {
+                        Some(c) => content.push(c),
+                        None => return self.error(start, message),
+                    }
+                }
+                Some(c) => content.push(c),
+            }
+        }
+    }
+
+    //Actions are target language code, so only nested braces and quoted strings are understood
+    fn read_action(&mut self, start: Pos) -> Result<String, ParseError> {
+        let mut content = String::new();
+        let mut depth = 1;
+        loop {
+            match self.bump() {
+                None => return self.error(start, "unterminated action"),
+                Some('{') => {
+                    depth += 1;
+                    content.push('{');
+                }
+                Some('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(content);
+                    }
+                    content.push('}');
+                }
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let string = self.read_delimited(quote, start, "unterminated action")?;
+                    content.push(quote);
+                    content.push_str(&string);
+                    content.push(quote);
+                }
+                Some(c) => content.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        return tokenize(src)
+            .expect("RAND_3380409316")
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+    }
+
+    #[test]
+    fn check_comments_and_delimited_tokens() {
+        assert_eq!(
+            tokens("a /* ; 'x' */ : [;\\]] // b ;\n '\\';' {x = \"}\";} ;"),
+            vec![
+                Token::Ident("a".to_string()),
+                Token::Colon,
+                Token::CharSet(";\\]".to_string()),
+                Token::StringLit("\\';".to_string()),
+                Token::Action("x = \"}\";".to_string()),
+                Token::Semi,
+                Token::Eof,
+            ]
+        );
+        let positions = tokenize("/* a\nb */ x\n  y").expect("RAND_1181846036");
+        assert_eq!(positions[0].1, Pos { line: 2, column: 6 });
+        assert_eq!(positions[1].1, Pos { line: 3, column: 3 });
+        let err = tokenize("a : 'b ;").expect_err("RAND_2913651470");
+        assert_eq!(err.pos, Pos { line: 1, column: 5 });
+    }
+}
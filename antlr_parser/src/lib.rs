@@ -1,33 +1,264 @@
+extern crate rule_format;
+#[cfg(test)]
+extern crate tempfile;
+
+pub mod ast;
+pub mod imports;
+mod lexer;
+pub mod modes;
+pub mod parser;
+
 use std::char;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
-
-enum State {
-    ReadNextChar,
-    InBrackets { depth: u8 },
-    CheckForSubrulesNormal,
-    CheckForSubrulesRegex,
-    InAction,
-    InRegex,
-    FoundDot,
-    FoundTwoDots,
-    FoundNot { depth: u8 },
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use ast::{Alternative, Atom, Element, GrammarAst, Pos, Repeat, RuleAst};
+use modes::LexerModes;
+use parser::ParseError;
+
+//Alternatives are multiplied out (`a (b | c)` becomes the two rules `ab` and `ac`) as long as a
+//rule doesn't get more than this many alternatives. Bigger blocks get their own nonterminal.
+const MAX_INLINE_ALTERNATIVES: usize = 64;
+
+//What is put between two tokens of a parser rule. ANTLR grammars leave the whitespace between
+//tokens to skipped lexer rules, so without a separator `return x` comes out as `returnx`.
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub enum Separator {
+    None,
+    Text(String),
+    //One of the lexer rules that are skipped or sent to a hidden channel, i.e. whitespace or
+    //comments. A single space is used if the grammar has no such rules.
+    Skipped,
+}
+
+//The characters that `.` and negated sets like `~["\\]` stand for
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub enum Alphabet {
+    //Printable ASCII, tab, newline and carriage return
+    Ascii,
+    //Ascii and the printable part of Latin-1
+    Latin1,
+    //Ascii and the given number of characters picked from all of Unicode. The same characters
+    //are picked every time, so the generated rules don't change between runs.
+    Unicode(usize),
+}
+
+impl Alphabet {
+    pub fn chars(&self) -> Vec<char> {
+        let mut chars = vec!['\t', '\n', '\r'];
+        chars.extend((0x20u8..0x7f).map(|c| c as char));
+        match self {
+            &Alphabet::Ascii => {}
+            &Alphabet::Latin1 => chars.extend((0xa0u8..=0xff).map(|c| c as char)),
+            &Alphabet::Unicode(samples) => {
+                let mut picked = HashSet::new();
+                let mut state = 0x2545_f491_4f6c_dd1du64;
+                //Stop early if there are not enough characters to pick
+                for _ in 0..samples * 4 {
+                    if picked.len() >= samples {
+                        break;
+                    }
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    let code = 0xa0 + ((state >> 33) as u32) % (0x110000 - 0xa0);
+                    if let Some(c) = char::from_u32(code) {
+                        if picked.insert(c) {
+                            chars.push(c);
+                        }
+                    }
+                }
+            }
+        }
+        return chars;
+    }
+}
+
+//How `*` and `+` become recursive rules, shown for a repeated E, a separator S and the helper R
+#[derive(Debug, Clone, Copy, PartialEq, Hash)]
+pub enum RepetitionStyle {
+    //R -> E S R | "" for `*` and R -> E | E S R for `+`
+    Right,
+    //R -> R S E | "" for `*` and R -> E | R S E for `+`
+    Left,
+    //R -> L | "" with L -> E | L S L. The list is a tree of repetitions, so recursive mutations
+    //can duplicate or drop whole parts of it instead of single elements at one end.
+    Balanced,
+}
+
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Repetition {
+    pub style: RepetitionStyle,
+    //`*` derives at least min and `+` at least max(min, 1) elements
+    pub min: usize,
+    //With an upper bound there is no recursion, every allowed count gets its own alternative
+    pub max: Option<usize>,
+}
+
+//By default tokens are concatenated without a separator and `.` stands for ASCII
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct LoweringOptions {
+    pub separator: Separator,
+    pub alphabet: Alphabet,
+    pub repetition: Repetition,
+}
+
+impl LoweringOptions {
+    pub fn new() -> Self {
+        return LoweringOptions {
+            separator: Separator::None,
+            alphabet: Alphabet::Ascii,
+            repetition: Repetition {
+                style: RepetitionStyle::Right,
+                min: 0,
+                max: None,
+            },
+        };
+    }
+}
+
+impl Default for LoweringOptions {
+    fn default() -> Self {
+        return LoweringOptions::new();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AntlrError {
+    pub file: Option<PathBuf>,
+    //None if the error isn't about a place in a grammar, e.g. if a file can't be read
+    pub pos: Option<Pos>,
+    pub message: String,
+}
+
+impl AntlrError {
+    pub fn new(message: String) -> Self {
+        return AntlrError {
+            file: None,
+            pos: None,
+            message,
+        };
+    }
+
+    //Sets the file the error happened in, unless it is already known
+    pub fn in_file(mut self, file: &Path) -> Self {
+        if self.file.is_none() {
+            self.file = Some(file.to_path_buf());
+        }
+        return self;
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        return self.pos.map(|pos| pos.line);
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        return self.pos.map(|pos| pos.column);
+    }
+}
+
+impl From<ParseError> for AntlrError {
+    fn from(err: ParseError) -> Self {
+        return AntlrError {
+            file: None,
+            pos: Some(err.pos),
+            message: err.message,
+        };
+    }
+}
+
+impl fmt::Display for AntlrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(pos) = self.pos {
+            write!(f, "{}:{}:", pos.line, pos.column)?;
+        }
+        if self.file.is_some() || self.pos.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for AntlrError {}
+
+//The part of the grammar source a rule was defined in, from its name to its closing `;`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+//An ANTLR grammar converted to grammartec rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grammar {
+    pub name: String,
+    //The grammartec name of the start rule, which is also the nonterminal of the first rule
+    pub start: String,
+    pub rules: Vec<(String, String)>,
+    //Maps the names of the ANTLR rules to their grammartec nonterminals
+    pub nonterminals: HashMap<String, String>,
+    //The span of the ANTLR rule each nonterminal comes from. Helper nonterminals like PROG_STAR
+    //get the span of the rule they were created for, special ones like SEPARATOR have none.
+    pub spans: HashMap<String, Span>,
+}
+
+impl Grammar {
+    //The rules with START -> {start} in front, as grammartec expects them
+    pub fn rules_with_start(&self) -> Vec<(String, String)> {
+        let mut rules = vec![("START".to_string(), format!("{{{}}}", self.start))];
+        rules.extend(self.rules.iter().cloned());
+        return rules;
+    }
 }
 
 pub struct AntlrParser {
     nonterminals: Vec<(String, String)>, //First is the original name, second the uppercase name
     pub rules: Vec<(String, String)>,
+    options: LoweringOptions,
+    hidden_tokens: Vec<String>,
+    separator: Option<String>, //Reference to the separator while a parser rule is lowered
+    modes: LexerModes,
+    alphabet: Vec<char>,
+    spans: HashMap<String, Span>,
+    rule_span: Option<Span>, //Span of the rule that is lowered
+}
+
+impl Default for AntlrParser {
+    fn default() -> Self {
+        return AntlrParser::new();
+    }
 }
 
 impl AntlrParser {
     pub fn new() -> AntlrParser {
+        return AntlrParser::with_options(LoweringOptions::new());
+    }
+
+    pub fn with_options(options: LoweringOptions) -> AntlrParser {
         AntlrParser {
             nonterminals: vec![],
             rules: vec![],
+            options,
+            hidden_tokens: vec![],
+            separator: None,
+            modes: LexerModes::default(),
+            alphabet: vec![],
+            spans: HashMap::new(),
+            rule_span: None,
         }
     }
 
+    //The lexer rules of the last parsed grammar that never reach the parser
+    pub fn get_hidden_tokens(&self) -> &Vec<String> {
+        return &self.hidden_tokens;
+    }
+
     //Adds a new Tuple to nonterminals. The first string contains the original name the second string contains its new name (Uppercase for grammartec syntax).
     pub fn add_nonterm(&mut self, string: String) {
         let mut new_nonterm = string.to_uppercase();
@@ -48,206 +279,397 @@ impl AntlrParser {
         return false;
     }
 
-    //This function parses an antlr grammar
-    pub fn parse_antlr_grammar(&mut self, file: &str) {
-        let f = File::open(file).expect("file not found");
-        let file = BufReader::new(&f);
-        let mut lines = file.lines();
-        let mut pre_body = String::new();
-        let mut body = String::new();
+    //This function parses an antlr grammar. Imported grammars and the lexer grammar named by
+    //tokenVocab are read from the same directory.
+    pub fn parse_antlr_grammar(&mut self, file: &str) -> Result<(), AntlrError> {
+        return self.parse_antlr_grammars(&[file]);
+    }
 
-        loop {
-            //find start line;
-            if lines
-                .next()
-                .expect("RAND_842445070")
-                .expect("RAND_842445070")
-                .starts_with("grammar")
-            {
-                break;
-            }
-        }
+    //Parses a set of grammar files, e.g. a lexer and a parser grammar, into one rule set
+    pub fn parse_antlr_grammars(&mut self, files: &[&str]) -> Result<(), AntlrError> {
+        let files = files.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let ast = imports::combine_grammars(&files)?;
+        self.lower_grammar(&ast)?;
+        return Ok(());
+    }
 
-        //Combine the rest to one string and remove comments
-        for line in lines {
-            pre_body.push_str(&(self.remove_single_line_comment(line.expect("RAND_3089939874"))));
-            pre_body.push('\n');
+    //Converts the source of a grammar with the options of this parser. Unlike
+    //parse_antlr_grammar this doesn't change the parser, so it can be used for many grammars.
+    pub fn parse_str(&self, src: &str) -> Result<Grammar, AntlrError> {
+        return self.parse_strs(&[src]);
+    }
+
+    //Same as parse_str for split grammars. The grammars named by `import` and tokenVocab have
+    //to be part of the given sources.
+    pub fn parse_strs(&self, srcs: &[&str]) -> Result<Grammar, AntlrError> {
+        let mut grammars = vec![];
+        for src in srcs.iter() {
+            grammars.push((None, parser::parse(src)?));
         }
+        return self.convert(&imports::combine_asts(grammars)?);
+    }
+
+    //Same as parse_str for grammar files, see parse_antlr_grammars
+    pub fn parse_files<P: AsRef<Path>>(&self, files: &[P]) -> Result<Grammar, AntlrError> {
+        let files = files
+            .iter()
+            .map(|file| file.as_ref().to_path_buf())
+            .collect::<Vec<_>>();
+        return self.convert(&imports::combine_grammars(&files)?);
+    }
 
-        //Replace escaped unicode
-        pre_body = self.replace_unicode(pre_body);
-        pre_body = self.basic_editing(&pre_body);
+    fn convert(&self, ast: &GrammarAst) -> Result<Grammar, AntlrError> {
+        let mut lowering = AntlrParser::with_options(self.options.clone());
+        lowering.lower_grammar(ast)?;
+        let start = match lowering.rules.first() {
+            Some(&(ref nt, _)) => nt.clone(),
+            None => {
+                return Err(AntlrError::new(format!(
+                    "grammar {} does not contain any rules",
+                    ast.name
+                )))
+            }
+        };
+        let nonterminals = lowering
+            .nonterminals
+            .into_iter()
+            .filter(|&(ref name, _)| !name.starts_with('<'))
+            .collect();
+        return Ok(Grammar {
+            name: ast.name.clone(),
+            start,
+            rules: lowering.rules,
+            nonterminals,
+            spans: lowering.spans,
+        });
+    }
 
-        //Remove unneeded stuff and mark subrules
-        for rule in pre_body.split(';') {
-            let rule_without_spaces = rule.replace(" ", "");
-            if rule_without_spaces.ends_with("->skip") {
-                continue;
-            } //We don't need the ignore conditions
-            let edited_rule = self.remove_fragment(rule.trim().to_string());
-            body.push_str(&(self.tokenize_subrules(&edited_rule) + ";"));
+    //Turns the syntax tree into grammartec rules. The first rule is used as start symbol, so the
+    //start rule goes first, followed by the other parser rules and the lexer rules.
+    fn lower_grammar(&mut self, ast: &GrammarAst) -> Result<(), ParseError> {
+        self.modes = LexerModes::new(ast);
+        self.alphabet = self.options.alphabet.chars();
+        self.hidden_tokens = ast.rules
+            .iter()
+            .filter(|rule| rule.is_hidden() && !rule.fragment)
+            .map(|rule| rule.name.clone())
+            .collect();
+        let separate_with_hidden =
+            self.options.separator == Separator::Skipped && !self.hidden_tokens.is_empty();
+        let mut rules = ast.rules
+            .iter()
+            .filter(|rule| !rule.is_skipped() || separate_with_hidden)
+            .collect::<Vec<_>>();
+        rules.sort_by_key(|rule| rule.is_lexer_rule());
+        if let Some(start) = find_start_rule(&rules) {
+            let start = rules.remove(start);
+            rules.insert(0, start);
         }
+        for rule in rules.iter() {
+            self.add_nonterm(rule.name.clone());
+        }
+        //Tokens from a `tokens { ... }` block without a rule are produced by `-> type(X)` or by
+        //actions, the latter are left empty
+        let mut typed: HashMap<String, Vec<String>> = HashMap::new();
+        let mut declared = ast.tokens.clone();
+        for rule in rules.iter().filter(|rule| rule.is_lexer_rule()) {
+            if let Some(ref token_type) = self.modes.tokens[&rule.name].token_type {
+                typed
+                    .entry(token_type.clone())
+                    .or_insert_with(|| vec![])
+                    .push(rule.name.clone());
+                if !declared.contains(token_type) {
+                    declared.push(token_type.clone());
+                }
+            }
+        }
+        declared.retain(|token| ast.get_rule(token).is_none());
+        for token in declared.iter() {
+            self.add_nonterm(token.to_string());
+        }
+        let prefixes = self.add_more_prefixes(ast);
+        let separator = match self.options.separator {
+            Separator::None => None,
+            _ => Some(self.add_special_nonterm("<separator>", "SEPARATOR")),
+        };
+        for rule in rules {
+            self.separator = match rule.is_lexer_rule() {
+                true => None,
+                false => separator.as_ref().map(|sep| format!("{{{}}}", sep)),
+            };
+            let span = Span {
+                start: rule.pos,
+                end: rule.end,
+            };
+            self.rule_span = Some(span);
+            let first = self.rules.len();
+            let definitions = if rule.is_skipped() {
+                let mut definitions = vec![];
+                for alternative in rule.alternatives.iter() {
+                    definitions.extend(self.lower_sequence(ast, &alternative.elements, &rule.name)?);
+                }
+                definitions
+            } else {
+                self.lower_alternatives(ast, &rule.alternatives, &rule.name)?
+            };
+            let mut definitions = definitions;
+            if let Some(info) = self.modes.tokens.get(&rule.name) {
+                if let Some(prefix) = prefixes.get(&info.mode) {
+                    if !info.more && !rule.fragment {
+                        definitions = definitions
+                            .into_iter()
+                            .map(|def| format!("{{{}}}{}", prefix, def))
+                            .collect();
+                    }
+                }
+            }
+            for token in typed.get(&rule.name).into_iter().flat_map(|tokens| tokens.iter()) {
+                definitions.push(format!("{{{}}}", self.replace_with_new_name(token)));
+            }
+            let new_name = self.replace_with_new_name(&rule.name).to_string();
+            self.spans.insert(new_name.clone(), span);
+            let num_definitions = definitions.len();
+            for def in definitions {
+                self.rules.push((new_name.clone(), def));
+            }
+            //Helper rules were added while lowering, keep them behind the rule that uses them
+            self.rules[first..].rotate_right(num_definitions);
+        }
+        self.separator = None;
+        self.rule_span = None;
+        for token in declared {
+            let new_name = self.replace_with_new_name(&token).to_string();
+            match typed.get(&token) {
+                Some(tokens) => for typed_token in tokens.iter() {
+                    let def = format!("{{{}}}", self.replace_with_new_name(typed_token));
+                    self.rules.push((new_name.clone(), def));
+                },
+                None => self.rules.push((new_name, String::new())),
+            }
+        }
+        if let Some(separator) = separator {
+            let definitions = match self.options.separator.clone() {
+                Separator::Text(ref text) => vec![self.terminal(text)],
+                _ if separate_with_hidden => self.hidden_tokens
+                    .iter()
+                    .map(|token| format!("{{{}}}", self.replace_with_new_name(token)))
+                    .collect(),
+                _ => vec![" ".to_string()],
+            };
+            for def in definitions {
+                self.rules.push((separator.clone(), def));
+            }
+        }
+        return Ok(());
+    }
 
-        //Find all nonterms
-        for rule in body.split(';') {
-            if rule.contains(":") {
-                let rule_name = rule.split(':').next().expect("RAND_913712400").trim();
-                if rule_name != "" {
-                    self.add_nonterm(rule_name.to_string());
+    //Tokens with a `more` command become the prefix of the next token. For the modes that have
+    //such tokens, X_PREFIX derives everything that can come before a token of mode X: more tokens
+    //that stay in X (repeated by X_MORE), possibly after more tokens that switched to X from
+    //another mode. Returns the prefix nonterminals of the modes.
+    fn add_more_prefixes(&mut self, ast: &GrammarAst) -> HashMap<String, String> {
+        let more_tokens = ast.rules
+            .iter()
+            .filter(|rule| rule.is_lexer_rule() && !rule.is_skipped())
+            .filter(|rule| self.modes.tokens[&rule.name].more)
+            .map(|rule| (rule.name.clone(), self.modes.tokens[&rule.name].clone()))
+            .collect::<Vec<_>>();
+        let mut prefixes = HashMap::new();
+        let mut repetitions = HashMap::new();
+        let mut modes = vec![];
+        for &(_, ref info) in more_tokens.iter() {
+            let mode = match info.enters {
+                Some(ref mode) => mode,
+                None => &info.mode,
+            };
+            if !prefixes.contains_key(mode) {
+                let key = format!("<prefix {}>", mode);
+                let name = format!("{}_PREFIX", mode.to_uppercase());
+                prefixes.insert(mode.clone(), self.add_special_nonterm(&key, &name));
+                modes.push(mode.clone());
+            }
+            if info.enters.is_none() && !repetitions.contains_key(mode) {
+                let key = format!("<more {}>", mode);
+                let name = format!("{}_MORE", mode.to_uppercase());
+                repetitions.insert(mode.clone(), self.add_special_nonterm(&key, &name));
+            }
+        }
+        let reference = |nonterm: Option<&String>| match nonterm {
+            Some(nonterm) => format!("{{{}}}", nonterm),
+            None => String::new(),
+        };
+        for mode in modes.iter() {
+            let more = reference(repetitions.get(mode));
+            for &(ref token, ref info) in more_tokens.iter() {
+                let token = format!("{{{}}}", self.replace_with_new_name(token));
+                if info.enters.as_ref() == Some(mode) {
+                    let def = format!("{}{}{}", reference(prefixes.get(&info.mode)), token, more);
+                    self.rules.push((prefixes[mode].clone(), def));
+                } else if info.enters.is_none() && info.mode == *mode {
+                    self.rules.push((repetitions[mode].clone(), format!("{}{}", token, more)));
                 }
             }
+            self.rules.push((prefixes[mode].clone(), more.clone()));
+            if let Some(repetition) = repetitions.get(mode) {
+                self.rules.push((repetition.clone(), String::new()));
+            }
         }
+        return prefixes;
+    }
 
-        //Iterate through all Rules:
-        for rule in body.split(';') {
-            if rule.contains(":") {
-                //Progress the rule definition
-                let mut name_and_definition = rule.splitn(2, ':'); //splitn because there could be another ':' in a rule definition
-                let name = name_and_definition.next().expect("RAND_1336718586").trim();
-                let definition = name_and_definition.next().expect("RAND_2265765111").trim();
-                let mut definitions = self.parse_definition(&definition, name);
-                for def in definitions.iter_mut() {
-                    self.print_string(name, def);
+    fn lower_alternatives(
+        &mut self,
+        ast: &GrammarAst,
+        alternatives: &[Alternative],
+        name: &str,
+    ) -> Result<Vec<String>, ParseError> {
+        let mut definitions = vec![];
+        let mut seen = HashSet::new();
+        for alternative in alternatives.iter().filter(|alt| !alt.is_skipped()) {
+            for def in self.lower_sequence(ast, &alternative.elements, name)? {
+                if seen.insert(def.clone()) {
+                    definitions.push(def);
                 }
             }
         }
+        return Ok(definitions);
     }
 
-    //This function removes fragment form the beginning of a string
-    fn remove_fragment(&self, mut string: String) -> String {
-        if string.starts_with("fragment ") {
-            return string.split_off(9);
+    fn lower_sequence(
+        &mut self,
+        ast: &GrammarAst,
+        elements: &[Element],
+        name: &str,
+    ) -> Result<Vec<String>, ParseError> {
+        let mut definitions = vec![String::new()];
+        for (i, element) in elements.iter().enumerate() {
+            let mut options = self.lower_element(ast, element, name)?;
+            if elements.len() > 1 && options.len() > 1
+                && definitions.len() * options.len() > MAX_INLINE_ALTERNATIVES
+            {
+                options = vec![self.apply_block_subrule(options, name, "BLOCK")];
+            }
+            if i > 0 && self.modes.can_separate(&elements[i - 1], element) {
+                options = options
+                    .into_iter()
+                    .map(|option| self.separated(option))
+                    .collect();
+            }
+            definitions = self.combine_vectors(&definitions, &options);
         }
-        return string;
+        return Ok(definitions);
     }
 
-    //This function removes single line comments from a line
-    fn remove_single_line_comment(&self, string: String) -> String {
-        let mut current = String::new();
-        let mut last_char = 'a';
-        let mut in_quotes = false; //Variable to "count" "'"
-        for character in string.chars() {
-            match character {
-                '\'' if last_char != '\\' => {
-                    current.push('\'');
-                    in_quotes ^= true
-                }
-                '/' if !in_quotes && (last_char == '/') => {
-                    return current;
+    fn lower_element(
+        &mut self,
+        ast: &GrammarAst,
+        element: &Element,
+        name: &str,
+    ) -> Result<Vec<String>, ParseError> {
+        let mut options = self.lower_atom(ast, &element.atom, element.pos, name)?;
+        let separate = self.modes.can_separate(element, element);
+        return Ok(match element.suffix.map(|suffix| suffix.repeat) {
+            None => options,
+            Some(Repeat::Optional) => {
+                if !options.contains(&String::new()) {
+                    options.push(String::new());
                 }
-                '/' if !in_quotes && (last_char != '/') => {}
-                _ if last_char == '/' => {
-                    current.push('/');
-                    current.push(character);
-                }
-                _ => current.push(character),
+                options
             }
-            last_char = character;
-        }
-        return current;
+            Some(Repeat::Star) => {
+                let lower = self.options.repetition.min;
+                self.apply_repetition(options, name, lower, separate)
+            }
+            Some(Repeat::Plus) => {
+                let lower = cmp::max(self.options.repetition.min, 1);
+                self.apply_repetition(options, name, lower, separate)
+            }
+        });
     }
 
-    //check for *, +, ?, *?, +? after words without brackets around them
-    //Works on a single line or rule
-    fn tokenize_subrules(&self, string: &str) -> String {
-        let mut current = String::new();
-        let mut current_word = String::new();
-        let mut last_char = 'a';
-        let mut in_quotes = false; //Variable to "count" "'"
-        for character in string.chars() {
-            match character {
-                '\'' if last_char != '\\' => {
-                    current_word.push('\'');
-                    in_quotes ^= true
+    fn lower_atom(
+        &mut self,
+        ast: &GrammarAst,
+        atom: &Atom,
+        pos: Pos,
+        name: &str,
+    ) -> Result<Vec<String>, ParseError> {
+        return match atom {
+            &Atom::Literal(ref literal) => Ok(vec![self.terminal(literal)]),
+            &Atom::Set(ref ranges) => Ok(chars_in(ranges)
+                .map(|c| self.terminal(&c.to_string()))
+                .collect()),
+            &Atom::Not(ref atoms) => {
+                let excluded = self.set_chars(ast, atoms, pos)?;
+                Ok(self.alphabet
+                    .clone()
+                    .into_iter()
+                    .filter(|c| !excluded.contains(c))
+                    .map(|c| self.terminal(&c.to_string()))
+                    .collect())
+            }
+            &Atom::Wildcard => Ok(self.alphabet
+                .clone()
+                .into_iter()
+                .map(|c| self.terminal(&c.to_string()))
+                .collect()),
+            &Atom::Ref(ref rule) if rule == "EOF" => Ok(vec![String::new()]),
+            &Atom::Ref(ref rule) => {
+                if !self.is_nonterm(rule) {
+                    return Err(ParseError {
+                        pos,
+                        message: format!("undefined rule {}", rule),
+                    });
                 }
-                ' ' | '\n' | '\r' | '\t' | ';' if !in_quotes => {
-                    if (!current_word.ends_with(")*")
-                        && !current_word.ends_with("]*")
-                        && !current_word.ends_with(")+")
-                        && !current_word.ends_with("]+")
-                        && !current_word.ends_with(")?")
-                        && !current_word.ends_with("]?")
-                        && !current_word.ends_with("*?")
-                        && !current_word.ends_with("+?"))
-                        && (current_word.ends_with("*")
-                            || current_word.ends_with("+")
-                            || current_word.ends_with("?"))
-                    {
-                        let subrule = current_word
-                            .pop()
-                            .expect("String did not contain any characters");
-                        current.push('(');
-                        current.push_str(&current_word);
-                        current.push(')');
-                        current.push(subrule);
-                    } else if (!current_word.ends_with(")*?")
-                        && !current_word.ends_with("]*?")
-                        && !current_word.ends_with(")+?")
-                        && !current_word.ends_with("]+?"))
-                        && (current_word.ends_with("*?") || current_word.ends_with("+?"))
-                    {
-                        current_word.pop();
-                        let subrule = current_word
-                            .pop()
-                            .expect("String did not contain any characters");
-                        current.push('(');
-                        current.push_str(&current_word);
-                        current.push(')');
-                        current.push(subrule);
-                        current.push('?');
-                    } else {
-                        current.push_str(&current_word);
+                Ok(vec![format!("{{{}}}", self.replace_with_new_name(rule))])
+            }
+            &Atom::Block(ref alternatives) => self.lower_alternatives(ast, alternatives, name),
+        };
+    }
+
+    fn terminal(&mut self, literal: &str) -> String {
+        return rule_format::literal_to_format(literal, || {
+            if !self.is_nonterm("<backslash>") {
+                let new_nonterm = self.add_special_nonterm("<backslash>", "BACKSLASH");
+                self.rules.push((new_nonterm, "\\".to_string()));
+            }
+            self.replace_with_new_name("<backslash>").to_string()
+        });
+    }
+
+    //Collects the characters of a negated set. Tokens like `~QUOTE` name a rule that matches a
+    //single character.
+    fn set_chars(&self, ast: &GrammarAst, atoms: &[Atom], pos: Pos) -> Result<HashSet<char>, ParseError> {
+        let mut chars = HashSet::new();
+        for atom in atoms {
+            match atom {
+                &Atom::Literal(ref literal) => chars.extend(literal.chars()),
+                &Atom::Set(ref ranges) => chars.extend(chars_in(ranges)),
+                &Atom::Ref(ref rule) => match ast.get_rule(rule) {
+                    Some(rule) => for alternative in rule.alternatives.iter() {
+                        let atoms = alternative
+                            .elements
+                            .iter()
+                            .map(|element| element.atom.clone())
+                            .collect::<Vec<_>>();
+                        chars.extend(self.set_chars(ast, &atoms, pos)?);
+                    },
+                    None => {
+                        return Err(ParseError {
+                            pos,
+                            message: format!("undefined rule {}", rule),
+                        })
                     }
-                    current.push(' ');
-                    current_word = String::new();
+                },
+                _ => {
+                    return Err(ParseError {
+                        pos,
+                        message: "only characters can be negated".to_string(),
+                    })
                 }
-                _ => current_word.push(character),
-            }
-            last_char = character;
-        }
-        if (!current_word.ends_with(")*")
-            && !current_word.ends_with("]*")
-            && !current_word.ends_with(")+")
-            && !current_word.ends_with("]+")
-            && !current_word.ends_with(")?")
-            && !current_word.ends_with("]?")
-            && !current_word.ends_with("*?")
-            && !current_word.ends_with("+?"))
-            && (current_word.ends_with("*")
-                || current_word.ends_with("+")
-                || current_word.ends_with("?"))
-        {
-            let subrule = current_word
-                .pop()
-                .expect("String did not contain any characters");
-            current.push('(');
-            current.push_str(&current_word);
-            current.push(')');
-            current.push(subrule);
-        } else if (!current_word.ends_with(")*?")
-            && !current_word.ends_with("]*?")
-            && !current_word.ends_with(")+?")
-            && !current_word.ends_with("]+?"))
-            && (current_word.ends_with("*?") || current_word.ends_with("+?"))
-        {
-            current_word.pop();
-            let subrule = current_word
-                .pop()
-                .expect("String did not contain any characters");
-            current.push('(');
-            current.push_str(&current_word);
-            current.push(')');
-            current.push(subrule);
-            current.push('?');
-        } else {
-            current.push_str(&current_word);
-        }
-        current.push(' ');
-        current_word = String::new();
-        if current_word.len() > 0 {
-            current.push_str(&current_word);
-        }
-        return current;
+            }
+        }
+        return Ok(chars);
     }
 
     //This function checks if a word is a nonterm
@@ -260,442 +682,175 @@ impl AntlrParser {
         return false;
     }
 
-    //First remove forms like "id=nterm" and form them into "nterm" and escape basic stuff
-    fn basic_editing(&self, string: &str) -> String {
-        let mut current = String::new();
-        let mut current_word = String::new();
-        let mut last_char = 'a';
-        let mut in_quotes = false; //Variable to "count" "'"
-        let mut in_backslash_escape = false; //Variable to "count" "\"
-        let mut in_brackets = false; //Varuiable to "count" "["
-        for character in string.chars() {
-            match character {
-                '\'' if !in_backslash_escape && !in_brackets => {
-                    current_word.push('\'');
-                    in_quotes ^= true
-                }
-                '\\' if in_quotes => {
-                    in_backslash_escape ^= true;
-                    if !in_backslash_escape {
-                        current_word.push_str("&escaped_backslash");
-                    }
-                }
-                '=' if !in_quotes => {
-                    current_word = String::new();
-                } //remove the part before the "="
-                ' ' if in_quotes => {
-                    current_word.push_str(" &space ");
-                }
-                'n' if in_quotes && (last_char == '\\') => {
-                    in_backslash_escape ^= true;
-                    current_word.push_str("&escaped_n");
-                }
-                'r' if in_quotes && (last_char == '\\') => {
-                    in_backslash_escape ^= true;
-                    current_word.push_str("&escaped_r");
-                }
-                //'?' if !in_quotes => {}, //remove ?
-                '{' if in_quotes => {
-                    current_word.push_str("&escaped_curly_bracked_open");
-                }
-                '}' if in_quotes => {
-                    current_word.push_str("&escaped_curly_bracked_closed");
-                }
-                '[' if !in_quotes && !in_backslash_escape => {
-                    current_word.push('[');
-                    in_brackets ^= true
-                }
-                ']' if !in_quotes && !in_backslash_escape && in_brackets => {
-                    current_word.push(']');
-                    in_brackets ^= true
-                }
-                ';' if in_quotes => {
-                    current_word.push_str("&escaped_semicolon");
-                }
-                ' ' => {
-                    current_word.push(' ');
-                    current.push_str(&current_word);
-                    current_word = String::new();
-                }
-                _ if in_backslash_escape => {
-                    current_word.push('\\');
-                    current_word.push(character);
-                    in_backslash_escape ^= true;
-                }
-                _ => current_word.push(character),
-            }
-            last_char = character;
-        }
-        if current_word.len() > 0 {
-            current.push_str(&current_word);
-        }
-        return current;
-    }
-
-    //This function takes a String and returns a Vec<String>
-    fn parse_definition(&mut self, string: &str, name: &str) -> Vec<String> {
-        let mut definitions: Vec<String> = vec![];
-        let mut praeposition: Vec<String> = vec![];
-        let mut current = String::new();
-        let mut last_char = 'a';
-        let mut in_quotes = false; //Variable to "count" "'"
-        let mut in_backslash_escape = false; //Variable to "count" "\"
-        let mut state = State::ReadNextChar;
-        for character in string.chars() {
-            match state {
-                State::ReadNextChar => match character {
-                    '\'' if !in_backslash_escape => {
-                        in_quotes ^= true;
-                    }
-                    '\'' if in_quotes && in_backslash_escape => {
-                        current.push_str("&escaped_single_quote");
-                        in_backslash_escape ^= true;
-                    }
-                    '\\' if in_quotes => {
-                        in_backslash_escape ^= true;
-                        if in_backslash_escape == false {
-                            current.push_str("&escaped_backslash");
-                        }
-                    }
-                    '|' if !in_quotes => {
-                        if praeposition.len() == 0 {
-                            definitions.push(current.clone());
-                        }
-                        for definition in praeposition {
-                            definitions.push(definition + &current);
-                        }
-                        praeposition = vec![];
-                        current = String::new();
-                    }
-                    '[' if !in_quotes => {
-                        if current.len() > 0 {
-                            praeposition =
-                                self.combine_vectors(&praeposition, &vec![current.clone()]);
-                            current = String::new();
-                        }
-                        state = State::InRegex;
-                    }
-                    '(' if !in_quotes => {
-                        if current.len() > 0 {
-                            praeposition =
-                                self.combine_vectors(&praeposition, &vec![current.clone()]);
-                            current = String::new();
-                        }
-                        state = State::InBrackets { depth: 1 };
-                    }
-                    '{' if !in_quotes => {
-                        state = State::InAction;
-                    }
-                    '.' if !in_quotes => {
-                        state = State::FoundDot;
-                    }
-                    '~' if !in_quotes => {
-                        if current.len() > 0 {
-                            praeposition =
-                                self.combine_vectors(&praeposition, &vec![current.clone()]);
-                            current = String::new();
-                        }
-                        state = State::FoundNot { depth: 0 };
-                    }
-                    _ if in_backslash_escape => {
-                        current.push('\\');
-                        current.push(character);
-                        in_backslash_escape ^= true;
-                    }
-                    _ => current.push(character),
-                },
-                State::FoundDot => {
-                    match character {
-                        '.' => {
-                            state = State::FoundTwoDots;
-                        }
-                        _ => {
-                            if current.len() > 0 {
-                                praeposition =
-                                    self.combine_vectors(&praeposition, &vec![current.clone()]);
-                                current = String::new();
-                            }
-                            //let definition_helper = self.all_chars();
-                            let definition_helper = vec!["Not(\"\")".to_string()];
-                            praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                            state = State::ReadNextChar;
-                        }
-                    }
-                }
-                State::FoundTwoDots => match character {
-                    '\'' => {
-                        if in_quotes {
-                            let mut regex = current.clone() + "-";
-                            regex.push(last_char);
-                            let definition_helper = self.parse_regex(&regex);
-                            praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                            current = String::new();
-                            state = State::ReadNextChar;
-                        }
-                        in_quotes ^= true;
-                    }
-                    _ => {}
-                },
-                State::FoundNot { depth: x } => {
-                    match character {
-                        /*'\'' if last_char != '\\' => {in_quotes ^= true;current.push(character);},
-						'(' if !in_quotes => {
-							state = State::FoundNot{depth: x+1};
-							if x > 0 {current.push(character);}
-						},
-						')' if !in_quotes => {
-							//if x == 1 {
-								//let mut definition_helper = self.all_chars();
-								//for string in self.parse_definition(&current, name) {
-								//	definition_helper.remove_item(&string);	
-								//}
-								//current = String::new();
-								//praeposition = self.combine_vectors(&praeposition, &definition_helper);
-								//state = State::ReadNextChar;
-							//}
-							//else {
-							//	state = State::FoundNot{depth: x-1};
-							//	current.push(')');
-							//}
-						},
-						_ => current.push(character)*/
-                        '\'' if last_char != '\\' => {
-                            in_quotes ^= true;
-                            current.push(character);
-                        }
-                        '(' if !in_quotes => {
-                            state = State::FoundNot { depth: x + 1 };
-                            current.push(character);
-                        }
-                        ')' if !in_quotes => {
-                            if x == 1 {
-                                let mut not_string = String::from(" &spaceNot(");
-                                for string in self.parse_definition(&current, name) {
-                                    not_string.push_str(&string);
-                                }
-                                not_string.push_str(")&space)");
-                                praeposition =
-                                    self.combine_vectors(&praeposition, &vec![not_string]);
-                                current = String::new();
-                                state = State::ReadNextChar;
-                            } else {
-                                state = State::FoundNot { depth: x - 1 };
-                                current.push(')');
-                            }
-                        }
-                        _ => current.push(character),
-                    }
-                }
-                State::InAction => match character {
-                    '\'' if last_char != '\\' => {
-                        in_quotes ^= true;
-                    }
-                    '}' if !in_quotes => state = State::ReadNextChar,
-                    _ => {}
-                },
-                State::InBrackets { depth: x } => match character {
-                    '\'' if last_char != '\\' => {
-                        in_quotes ^= true;
-                        current.push(character);
-                    }
-                    '(' if !in_quotes => {
-                        state = State::InBrackets { depth: x + 1 };
-                        current.push(character);
-                    }
-                    ')' if !in_quotes => {
-                        if x == 1 {
-                            state = State::CheckForSubrulesNormal;
-                        } else {
-                            state = State::InBrackets { depth: x - 1 };
-                            current.push(')');
-                        }
-                    }
-                    _ => current.push(character),
-                },
-                State::CheckForSubrulesNormal => {
-                    match character {
-                        '*' => {
-                            let definition_helper = self.parse_definition(&current, name);
-                            let new_rule_name = self.apply_mul_subrule(definition_helper, name);
-                            current = String::new();
-                            if praeposition.len() == 0 {
-                                praeposition.push(String::new());
-                            }
-                            for prae in praeposition.iter_mut() {
-                                prae.push_str(&(" ".to_string() + &new_rule_name + " "));
-                            }
-                            state = State::ReadNextChar;
-                        }
-                        '?' => {
-                            let mut definition_helper = self.parse_definition(&current, name);
-                            definition_helper.push(String::new());
-                            current = String::new();
-                            praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                            state = State::ReadNextChar;
-                        }
-                        '+' => {
-                            let definition_helper = self.parse_definition(&current, name);
-                            let new_rule_name = self.apply_plus_subrule(definition_helper, name);
-                            current = String::new();
-                            if praeposition.len() == 0 {
-                                praeposition.push(String::new());
-                            }
-                            for prae in praeposition.iter_mut() {
-                                prae.push_str(&(" ".to_string() + &new_rule_name + " "));
-                            }
-                            state = State::ReadNextChar;
-                        }
-                        '(' => {
-                            let definition_helper = self.parse_definition(&current, name);
-                            current = String::new();
-                            praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                            state = State::InBrackets { depth: 1 };
-                        }
-                        '[' => {
-                            let definition_helper = self.parse_definition(&current, name);
-                            current = String::new();
-                            praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                            state = State::InRegex;
-                        }
-                        _ => {
-                            let definition_helper = self.parse_definition(&current, name);
-                            //println!("{}: length: {}", current, definition_helper.len());
-                            current = character.to_string();
-                            praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                            state = State::ReadNextChar;
-                        }
-                    }
-                }
-                State::CheckForSubrulesRegex => match character {
-                    '*' => {
-                        let definition_helper = self.parse_regex(&current);
-                        let new_rule_name = self.apply_mul_subrule(definition_helper, name);
-                        current = String::new();
-                        if praeposition.len() == 0 {
-                            praeposition.push(String::new());
-                        }
-                        for prae in praeposition.iter_mut() {
-                            prae.push_str(&(" ".to_string() + &new_rule_name + " "));
-                        }
-                        state = State::ReadNextChar;
-                    }
-                    '?' => {
-                        let mut definition_helper = self.parse_regex(&current);
-                        definition_helper.push(String::new());
-                        current = String::new();
-                        praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                        state = State::ReadNextChar;
-                    }
-                    '+' => {
-                        let definition_helper = self.parse_regex(&current);
-                        let new_rule_name = self.apply_plus_subrule(definition_helper, name);
-                        current = String::new();
-                        if praeposition.len() == 0 {
-                            praeposition.push(String::new());
-                        }
-                        for prae in praeposition.iter_mut() {
-                            prae.push_str(&(" ".to_string() + &new_rule_name + " "));
-                        }
-                        state = State::ReadNextChar;
-                    }
-                    '(' => {
-                        let definition_helper = self.parse_definition(&current, name);
-                        current = String::new();
-                        praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                        state = State::InBrackets { depth: 1 };
-                    }
-                    '[' => {
-                        let definition_helper = self.parse_regex(&current);
-                        current = String::new();
-                        praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                        state = State::InRegex;
-                    }
-                    _ => {
-                        let definition_helper = self.parse_regex(&current);
-                        current = character.to_string();
-                        praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                        state = State::ReadNextChar;
-                    }
-                },
-                State::InRegex => match character {
-                    ']' if last_char != '\\' => {
-                        state = State::CheckForSubrulesRegex;
-                    }
-                    _ => current.push(character),
-                },
-            }
-            last_char = character;
+    //Puts the separator in front of a non-empty part of a parser rule
+    fn separated(&self, format: String) -> String {
+        return match self.separator {
+            Some(ref separator) if !format.is_empty() => format!("{}{}", separator, format),
+            _ => format,
+        };
+    }
+
+    //Adds a nonterminal that doesn't stand for an ANTLR rule. The key is used instead of the
+    //original name and can't clash with one.
+    fn add_special_nonterm(&mut self, key: &str, name: &str) -> String {
+        let mut new_nonterm = name.to_string();
+        while self.is_nonterm_name(&new_nonterm) || self.is_nonterm(&new_nonterm) {
+            new_nonterm.push('1');
         }
-        match state {
-            State::CheckForSubrulesNormal => {
-                let definition_helper = self.parse_definition(&current, name);
-                praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                current = String::new();
-            }
-            State::CheckForSubrulesRegex => {
-                let definition_helper = self.parse_regex(&current);
-                praeposition = self.combine_vectors(&praeposition, &definition_helper);
-                current = String::new();
-            }
-            _ => {}
+        self.nonterminals.push((key.to_string(), new_nonterm.clone()));
+        return new_nonterm;
+    }
+
+    //Adds a nonterminal for a part of the rule `name`, e.g. PROG_STAR for a `*` in prog. More
+    //helpers of the same kind are numbered: PROG_STAR2, PROG_STAR3, ...
+    fn add_helper_nonterm(&mut self, name: &str, kind: &str) -> String {
+        let base = format!("{}_{}", self.replace_with_new_name(name), kind);
+        let mut new_nonterm = base.clone();
+        let mut i = 1;
+        while self.is_nonterm_name(&new_nonterm) || self.is_nonterm(&new_nonterm) {
+            i += 1;
+            new_nonterm = format!("{}{}", base, i);
         }
-        if praeposition.len() == 0 {
-            definitions.push(current.clone());
+        self.nonterminals
+            .push((format!("<{}>", new_nonterm), new_nonterm.clone()));
+        if let Some(span) = self.rule_span {
+            self.spans.insert(new_nonterm.clone(), span);
         }
-        for definition in praeposition {
-            definitions.push(definition + &current);
+        return new_nonterm;
+    }
+
+    fn apply_block_subrule(
+        &mut self,
+        definition_helper: Vec<String>,
+        name: &str,
+        kind: &str,
+    ) -> String {
+        let new_rule_name = self.add_helper_nonterm(name, kind);
+        for rule_definition in definition_helper {
+            self.rules.push((new_rule_name.clone(), rule_definition));
         }
-        return definitions;
+        return format!("{{{}}}", new_rule_name);
     }
 
-    //
-    fn apply_plus_subrule(&mut self, mut definition_helper: Vec<String>, name: &str) -> String {
-        let mut new_rule_name = name.to_string();
-        loop {
-            new_rule_name.push('1');
-            if !self.is_nonterm(&new_rule_name) {
-                break;
+    //Lowers a repetition of at least `lower` elements according to the repetition options
+    fn apply_repetition(
+        &mut self,
+        definition_helper: Vec<String>,
+        name: &str,
+        lower: usize,
+        separate: bool,
+    ) -> Vec<String> {
+        if let Some(max) = self.options.repetition.max {
+            let item = self.repetition_item(definition_helper, name);
+            let mut alternatives = vec![];
+            for count in lower..cmp::max(lower, max) + 1 {
+                alternatives.push(self.joined(vec![item.clone(); count], separate));
             }
+            if alternatives.len() == 1 {
+                return alternatives;
+            }
+            return vec![self.apply_block_subrule(alternatives, name, "REP")];
         }
-        self.add_nonterm(new_rule_name.clone());
-        for rule_definition in definition_helper.iter() {
-            self.print_string(&new_rule_name, &rule_definition);
+        if lower == 0 {
+            return vec![self.apply_mul_subrule(definition_helper, name, separate)];
         }
-        for def in definition_helper.iter_mut() {
-            def.push_str(&(" ".to_string() + &new_rule_name + " "));
+        if lower == 1 {
+            return vec![self.apply_plus_subrule(definition_helper, name, separate)];
         }
-        for rule_definition in definition_helper {
-            self.print_string(&new_rule_name, &rule_definition);
+        //The elements that are always there are put in front of a `+`
+        let item = self.repetition_item(definition_helper.clone(), name);
+        let mut items = vec![item; lower - 1];
+        items.push(self.apply_plus_subrule(definition_helper, name, separate));
+        return vec![self.joined(items, separate)];
+    }
+
+    //A single element of a repetition that is unrolled
+    fn repetition_item(&mut self, definition_helper: Vec<String>, name: &str) -> String {
+        if definition_helper.len() == 1 {
+            return definition_helper[0].clone();
         }
-        return new_rule_name;
+        return self.apply_block_subrule(definition_helper, name, "ITEM");
     }
 
-    fn apply_mul_subrule(&mut self, mut definition_helper: Vec<String>, name: &str) -> String {
-        let mut new_rule_name = name.to_string();
-        loop {
-            new_rule_name.push('1');
-            if !self.is_nonterm(&new_rule_name) {
-                break;
+    fn joined(&self, items: Vec<String>, separate: bool) -> String {
+        let mut format = String::new();
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 && separate {
+                format.push_str(&self.separated(item));
+            } else {
+                format.push_str(&item);
+            }
+        }
+        return format;
+    }
+
+    fn apply_plus_subrule(
+        &mut self,
+        definition_helper: Vec<String>,
+        name: &str,
+        separate: bool,
+    ) -> String {
+        let style = self.options.repetition.style;
+        let kind = match style {
+            RepetitionStyle::Balanced => "LIST",
+            _ => "PLUS",
+        };
+        let new_rule_name = self.add_helper_nonterm(name, kind);
+        let recursion = format!("{{{}}}", new_rule_name);
+        for rule_definition in definition_helper.iter() {
+            self.rules.push((new_rule_name.clone(), rule_definition.clone()));
+        }
+        let definitions = match style {
+            RepetitionStyle::Right => definition_helper
+                .into_iter()
+                .map(|def| self.joined(vec![def, recursion.clone()], separate))
+                .collect(),
+            RepetitionStyle::Left => definition_helper
+                .into_iter()
+                .map(|def| self.joined(vec![recursion.clone(), def], separate))
+                .collect(),
+            RepetitionStyle::Balanced => {
+                vec![self.joined(vec![recursion.clone(), recursion.clone()], separate)]
             }
+        };
+        for rule_definition in definitions {
+            self.rules.push((new_rule_name.clone(), rule_definition));
         }
-        self.add_nonterm(new_rule_name.clone());
-        for def in definition_helper.iter_mut() {
-            def.push_str(&(" ".to_string() + &new_rule_name + " "));
+        return recursion;
+    }
+
+    fn apply_mul_subrule(
+        &mut self,
+        definition_helper: Vec<String>,
+        name: &str,
+        separate: bool,
+    ) -> String {
+        let style = self.options.repetition.style;
+        if style == RepetitionStyle::Balanced {
+            let list = self.apply_plus_subrule(definition_helper, name, separate);
+            return self.apply_block_subrule(vec![list, String::new()], name, "STAR");
         }
-        definition_helper.push(String::new());
+        let new_rule_name = self.add_helper_nonterm(name, "STAR");
+        let recursion = format!("{{{}}}", new_rule_name);
         for rule_definition in definition_helper {
-            self.print_string(&new_rule_name, &rule_definition);
+            let items = match style {
+                RepetitionStyle::Left => vec![recursion.clone(), rule_definition],
+                _ => vec![rule_definition, recursion.clone()],
+            };
+            let rule_definition = self.joined(items, separate);
+            self.rules.push((new_rule_name.clone(), rule_definition));
         }
-        return new_rule_name;
+        self.rules.push((new_rule_name.clone(), String::new()));
+        return recursion;
     }
 
     //This function combines two vectors. The resulting vector contains all combinations
-    fn combine_vectors(&self, vec1: &Vec<String>, vec2: &Vec<String>) -> Vec<String> {
-        if vec1.len() == 0 {
-            return vec2.clone();
+    fn combine_vectors(&self, vec1: &[String], vec2: &[String]) -> Vec<String> {
+        if vec1.is_empty() {
+            return vec2.to_vec();
         }
-        if vec2.len() == 0 {
-            return vec1.clone();
+        if vec2.is_empty() {
+            return vec1.to_vec();
         }
         let mut result_vec: Vec<String> = vec![];
         for string1 in vec1.iter() {
@@ -708,79 +863,6 @@ impl AntlrParser {
         return result_vec;
     }
 
-    //Parse regex
-    fn parse_regex(&self, string: &str) -> Vec<String> {
-        let mut return_vec: Vec<String> = vec![];
-        let mut last_char = 'a';
-        let mut in_range = false;
-        let mut from = ' ';
-        for character in string.chars() {
-            match character {
-                '-' if last_char != '\\' => {
-                    in_range = true;
-                    from = last_char;
-                }
-                'n' if last_char == '\\' => {
-                    return_vec.push(String::from("\n"));
-                }
-                'r' if last_char == '\\' => {
-                    return_vec.push(String::from("\r"));
-                }
-                't' if last_char == '\\' => {
-                    return_vec.push(String::from("\t"));
-                }
-                'f' if last_char == '\\' => {
-                    return_vec.push(String::from("\x0C"));
-                }
-                '"' => {
-                    return_vec.push(String::from("\""));
-                }
-                '{' => {
-                    return_vec.push(String::from("&escaped_curly_bracked_open"));
-                }
-                '}' => {
-                    return_vec.push(String::from("&escaped_curly_bracked_closed"));
-                }
-                '\\' if last_char == '\\' => {
-                    return_vec.push(String::from("\\"));
-                }
-                _ => {
-                    if in_range {
-                        for x in (from as u32) + 1..(character as u32) + 1 {
-                            return_vec.push(
-                                char::from_u32(x)
-                                    .expect("u32 could not be converted to char")
-                                    .to_string(),
-                            );
-                        }
-                        in_range = false;
-                    } else {
-                        return_vec.push(character.to_string());
-                    }
-                }
-            }
-            last_char = character;
-        }
-
-        return return_vec;
-    }
-
-    fn print_string(&mut self, name: &str, def: &str) {
-        let mut string = def.to_string();
-        string = self.rename_nterms(&string);
-        string = string.replace("&escaped_single_quote", "'");
-        string = string.replace("&escaped_backslash", "\\\\");
-        string = string.trim().to_string();
-        string = string.replace("&space", " ");
-        string = string.replace("&escaped_n", "\n");
-        string = string.replace("&escaped_r", "\r");
-        string = string.replace("&escaped_curly_bracked_open", "\\{");
-        string = string.replace("&escaped_curly_bracked_closed", "\\}");
-        string = string.replace("&escaped_semicolon", ";");
-        let name = self.replace_with_new_name(name.trim()).to_string();
-        self.rules.push((name, string));
-    }
-
     fn replace_with_new_name(&self, string: &str) -> &str {
         for nt in &self.nonterminals {
             if nt.0 == string {
@@ -792,152 +874,385 @@ impl AntlrParser {
             string
         ); //should never happen
     }
+}
 
-    //This function searches a string for nonterms, places {} around them and replaces their names with their upper_case names
-    fn rename_nterms(&self, string: &str) -> String {
-        let mut result = String::new();
-        for word in string.split_whitespace() {
-            // print!("{}: ", word);
-            if self.is_nonterm(word) {
-                result.push('{');
-                result.push_str(self.replace_with_new_name(word));
-                result.push_str("}");
-            // print!("is nterm!\n");
-            // result.push(' ');
-            } else {
-                result.push_str(word);
-                // print!("is NOT nterm!\n");
-                result.push(' ');
-            }
-        }
-        return result.trim().to_string();
-    }
-
-    //This function returns all possible characters
-    #[allow(dead_code)]
-    fn all_chars(&self) -> Vec<String> {
-        let mut chars = vec![];
-        for character in 0..0xD800 {
-            if char::from_u32(character).expect("RAND_2188530336") == '{' {
-                chars.push(String::from("&escaped_curly_bracked_open"));
-            } else if char::from_u32(character).expect("RAND_2728928412") == '}' {
-                chars.push(String::from("&escaped_curly_bracked_closed"));
-            } else {
-                chars.push(
-                    char::from_u32(character)
-                        .expect("RAND_3329310204")
-                        .to_string(),
-                );
-            }
+//The rule a grammar is meant to be parsed with: the parser rule that ends with EOF, or else the
+//first parser rule that isn't used by another rule. Returns None to keep the first rule.
+fn find_start_rule(rules: &[&RuleAst]) -> Option<usize> {
+    let parser_rules = rules.iter().take_while(|rule| !rule.is_lexer_rule());
+    let mut used = HashSet::new();
+    for rule in parser_rules.clone() {
+        if rule_references(&rule.alternatives).contains("EOF") {
+            return rules.iter().position(|other| other.name == rule.name);
         }
-        for character in 0xE000..0x11000 {
-            if char::from_u32(character).expect("RAND_1752472326") == '{' {
-                chars.push(String::from("&escaped_curly_bracked_open"));
-            } else if char::from_u32(character).expect("RAND_1794224051") == '}' {
-                chars.push(String::from("&escaped_curly_bracked_closed"));
-            } else {
-                chars.push(
-                    char::from_u32(character)
-                        .expect("RAND_3621278981")
-                        .to_string(),
-                );
+        for name in rule_references(&rule.alternatives) {
+            if name != rule.name {
+                used.insert(name);
             }
         }
-        return chars;
     }
+    return parser_rules
+        .clone()
+        .position(|rule| !used.contains(&rule.name));
+}
 
-    //This function decodes all unicode characters
-    fn replace_unicode(&self, string: String) -> String {
-        let mut return_string = String::new();
-        let mut last_char = ' ';
-        let mut found_unicode = 0;
-        let mut current_unicode = 0;
-        for character in string.chars() {
-            if found_unicode == 0 {
-                match character {
-                    'u' if last_char == '\\' => {
-                        found_unicode = 4;
-                    }
-                    '\\' if last_char != '\\' => {}
-                    '\\' => {
-                        return_string.push('\\');
-                    }
-                    _ if last_char == '\\' => {
-                        return_string.push('\\');
-                        return_string.push(character);
-                    }
-                    _ => {
-                        return_string.push(character);
-                    }
-                }
-            } else {
-                current_unicode =
-                    current_unicode * 16 + character.to_digit(16).expect("RAND_1674350288");
-                found_unicode = found_unicode - 1;
-                if found_unicode == 0 {
-                    //println!("{:x}", current_unicode);
-                    return_string.push(char::from_u32(current_unicode).expect("RAND_1605623195"));
-                    current_unicode = 0;
-                }
+fn rule_references(alternatives: &[Alternative]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for element in alternatives.iter().flat_map(|alt| alt.elements.iter()) {
+        match element.atom {
+            Atom::Ref(ref name) => {
+                names.insert(name.clone());
             }
-            last_char = character;
+            Atom::Block(ref alternatives) => names.extend(rule_references(alternatives)),
+            _ => {}
         }
-        return return_string;
     }
+    return names;
+}
+
+fn chars_in<'a>(ranges: &'a [(char, char)]) -> impl Iterator<Item = char> + 'a {
+    return ranges
+        .iter()
+        .flat_map(|&(from, to)| (from as u32..to as u32 + 1).filter_map(char::from_u32));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
     use std::fs;
+    use std::fs::File;
     use std::io::prelude::*;
 
     #[test]
     fn check_is_nonterm_function() {
-        let mut my_parser = AntlrParser {
-            nonterminals: vec![],
-            rules: vec![],
-        };
+        let mut my_parser = AntlrParser::new();
         let file_path = "/tmp/tmp_grammar1.g4";
         let mut file = File::create(file_path).expect("Could not create file");
         file.write_all(
             b"grammar test;\nprog: 'test';\n LITERAL: 'test2';\nBREAK: 'test3';\nBla: 'test4';",
         ).expect("Could not write to file");
-        my_parser.parse_antlr_grammar(file_path);
+        my_parser.parse_antlr_grammar(file_path).expect("RAND_2817406193");
         assert!(my_parser.is_nonterm("prog"));
         assert!(my_parser.is_nonterm("LITERAL"));
         assert!(my_parser.is_nonterm("BREAK"));
         assert!(my_parser.is_nonterm("Bla"));
         fs::remove_file(file_path).expect("Could not remove file");
+        let err = my_parser.parse_antlr_grammar(file_path).expect_err("RAND_3029475161");
+        assert_eq!(err.file, Some(PathBuf::from(file_path)));
     }
 
     #[test]
     fn check_combine_vectors_function() {
-        let my_parser = AntlrParser {
-            nonterminals: vec![],
-            rules: vec![],
-        };
+        let my_parser = AntlrParser::new();
         let vec = my_parser.combine_vectors(
-            &vec!["test".to_string(), "a".to_string()],
-            &vec!["lulz".to_string(), "b".to_string()],
+            &["test".to_string(), "a".to_string()],
+            &["lulz".to_string(), "b".to_string()],
         );
         assert_eq!(vec, vec!["testlulz", "testb", "alulz", "ab"]);
     }
 
+    fn lower_with(src: &str, separator: Separator) -> Vec<(String, String)> {
+        let mut options = LoweringOptions::new();
+        options.separator = separator;
+        let mut my_parser = AntlrParser::with_options(options);
+        let ast = parser::parse(src).expect("RAND_1407920006");
+        my_parser.lower_grammar(&ast).expect("RAND_3760932565");
+        return my_parser.rules;
+    }
+
+    fn lower(src: &str) -> Vec<(String, String)> {
+        return lower_with(src, Separator::None);
+    }
+
+    fn rules_for<'a>(rules: &'a [(String, String)], nt: &str) -> Vec<&'a str> {
+        return rules
+            .iter()
+            .filter(|&&(ref name, _)| name == nt)
+            .map(|&(_, ref format)| format.as_str())
+            .collect();
+    }
+
     #[test]
-    fn check_rename_nterms_function() {
-        let mut my_parser = AntlrParser {
-            nonterminals: vec![],
-            rules: vec![],
+    fn check_doubled_backslash() {
+        let rules = lower("grammar test;\nprog : '\\\\\\\\' ID | 'a\\\\\\\\\\\\' ;\nID : 'x' ;");
+        assert_eq!(
+            rules_for(&rules, "PROG"),
+            vec!["{BACKSLASH}{BACKSLASH}{ID}", "a{BACKSLASH}{BACKSLASH}{BACKSLASH}"]
+        );
+        assert_eq!(rules_for(&rules, "BACKSLASH"), vec!["\\"]);
+    }
+
+    #[test]
+    fn check_lowering() {
+        let rules = lower(
+            "grammar test;\n\
+             prog : stat (';' stat)* EOF ;\n\
+             stat : ID '=' v=value # Assign\n\
+                  | 'print' '{' value? '}' # Print\n\
+                  ;\n\
+             value : ID | INT+ ;\n\
+             ID : [a-c] ;\n\
+             INT : '0'..'1' ;\n\
+             ESC : '\\\\' ID | 'x\\\\' ;\n\
+             WS : [ \\t]+ -> skip ;\n\
+             COMMENT : '/*' .*? '*/' -> channel(HIDDEN) ;",
+        );
+        assert_eq!(rules[0], ("PROG".to_string(), "{STAT}{PROG_STAR}".to_string()));
+        assert_eq!(rules_for(&rules, "PROG_STAR"), vec![";{STAT}{PROG_STAR}", ""]);
+        assert_eq!(
+            rules_for(&rules, "STAT"),
+            vec!["{ID}={VALUE}", "print\\{{VALUE}\\}", "print\\{\\}"]
+        );
+        assert_eq!(rules_for(&rules, "VALUE"), vec!["{ID}", "{VALUE_PLUS}"]);
+        assert_eq!(rules_for(&rules, "VALUE_PLUS"), vec!["{INT}", "{INT}{VALUE_PLUS}"]);
+        assert_eq!(rules_for(&rules, "ID"), vec!["a", "b", "c"]);
+        assert_eq!(rules_for(&rules, "INT"), vec!["0", "1"]);
+        assert_eq!(rules_for(&rules, "ESC"), vec!["{BACKSLASH}{ID}", "x{BACKSLASH}"]);
+        assert_eq!(rules_for(&rules, "BACKSLASH"), vec!["\\"]);
+        assert_eq!(rules_for(&rules, "WS").len(), 0);
+        assert_eq!(rules_for(&rules, "COMMENT_STAR").len(), 98 + 1);
+
+        let rules = lower("grammar test;\ntokens { T }\nprog : T 'x' ;");
+        assert_eq!(rules, vec![
+            ("PROG".to_string(), "{T}x".to_string()),
+            ("T".to_string(), "".to_string()),
+        ]);
+
+        //Big combinations get their own nonterminal instead of being multiplied out
+        let rules = lower("grammar test;\nHEX : '0x' [0-9a-f] [0-9a-f] ;");
+        assert_eq!(rules_for(&rules, "HEX").len(), 16);
+        assert_eq!(rules_for(&rules, "HEX")[10], "0xa{HEX_BLOCK}");
+        assert_eq!(rules_for(&rules, "HEX_BLOCK").len(), 16);
+    }
+
+    #[test]
+    fn check_separators() {
+        let src = "grammar test;\n\
+                   prog : 'return' ID? ('+' ID)* ;\n\
+                   ID : [a-b]+ ;\n\
+                   WS : ' ' -> skip ;\n\
+                   COMMENT : '#' -> channel(HIDDEN) ;";
+        let rules = lower_with(src, Separator::Text(" ".to_string()));
+        assert_eq!(
+            rules_for(&rules, "PROG"),
+            vec!["return{SEPARATOR}{ID}{SEPARATOR}{PROG_STAR}", "return{SEPARATOR}{PROG_STAR}"]
+        );
+        assert_eq!(
+            rules_for(&rules, "PROG_STAR"),
+            vec!["+{SEPARATOR}{ID}{SEPARATOR}{PROG_STAR}", ""]
+        );
+        assert_eq!(rules_for(&rules, "SEPARATOR"), vec![" "]);
+        assert_eq!(rules_for(&rules, "WS").len(), 0);
+
+        let rules = lower_with(src, Separator::Skipped);
+        assert_eq!(rules_for(&rules, "SEPARATOR"), vec!["{WS}", "{COMMENT}"]);
+        assert_eq!(rules_for(&rules, "WS"), vec![" "]);
+        //Lexer rules don't get separators
+        assert_eq!(rules_for(&rules, "ID_PLUS"), vec!["a", "b", "a{ID_PLUS}", "b{ID_PLUS}"]);
+
+        let rules = lower_with("grammar test;\nprog : 'a' 'b' ;", Separator::Skipped);
+        assert_eq!(rules_for(&rules, "PROG"), vec!["a{SEPARATOR}b"]);
+        assert_eq!(rules_for(&rules, "SEPARATOR"), vec![" "]);
+    }
+
+    fn lower_repetitions(
+        style: RepetitionStyle,
+        min: usize,
+        max: Option<usize>,
+    ) -> Vec<(String, String)> {
+        let mut options = LoweringOptions::new();
+        options.separator = Separator::Text(" ".to_string());
+        options.repetition = Repetition { style, min, max };
+        let mut my_parser = AntlrParser::with_options(options);
+        let src = "grammar test;\nprog : 'a'* ('b' | 'c')+ ;";
+        let ast = parser::parse(src).expect("RAND_3573014090");
+        my_parser.lower_grammar(&ast).expect("RAND_1590237383");
+        return my_parser.rules;
+    }
+
+    #[test]
+    fn check_repetitions() {
+        let rules = lower_repetitions(RepetitionStyle::Left, 0, None);
+        assert_eq!(rules_for(&rules, "PROG"), vec!["{PROG_STAR}{SEPARATOR}{PROG_PLUS}"]);
+        assert_eq!(rules_for(&rules, "PROG_STAR"), vec!["{PROG_STAR}{SEPARATOR}a", ""]);
+        assert_eq!(
+            rules_for(&rules, "PROG_PLUS"),
+            vec!["b", "c", "{PROG_PLUS}{SEPARATOR}b", "{PROG_PLUS}{SEPARATOR}c"]
+        );
+
+        let rules = lower_repetitions(RepetitionStyle::Balanced, 0, None);
+        assert_eq!(rules_for(&rules, "PROG"), vec!["{PROG_STAR}{SEPARATOR}{PROG_LIST2}"]);
+        assert_eq!(rules_for(&rules, "PROG_STAR"), vec!["{PROG_LIST}", ""]);
+        assert_eq!(rules_for(&rules, "PROG_LIST"), vec!["a", "{PROG_LIST}{SEPARATOR}{PROG_LIST}"]);
+        assert_eq!(rules_for(&rules, "PROG_LIST2").len(), 3);
+
+        //At least two elements: the first one is unrolled
+        let rules = lower_repetitions(RepetitionStyle::Right, 2, None);
+        assert_eq!(
+            rules_for(&rules, "PROG"),
+            vec!["a{SEPARATOR}{PROG_PLUS}{SEPARATOR}{PROG_ITEM}{SEPARATOR}{PROG_PLUS2}"]
+        );
+        assert_eq!(rules_for(&rules, "PROG_ITEM"), vec!["b", "c"]);
+
+        let rules = lower_repetitions(RepetitionStyle::Right, 0, Some(2));
+        assert_eq!(rules_for(&rules, "PROG_REP"), vec!["", "a", "a{SEPARATOR}a"]);
+        assert_eq!(
+            rules_for(&rules, "PROG_REP2"),
+            vec!["{PROG_ITEM}", "{PROG_ITEM}{SEPARATOR}{PROG_ITEM}"]
+        );
+        let rules = lower_repetitions(RepetitionStyle::Right, 0, Some(1));
+        assert_eq!(rules_for(&rules, "PROG"), vec!["{PROG_REP}{SEPARATOR}{PROG_ITEM}"]);
+    }
+
+    #[test]
+    fn check_lexer_modes() {
+        let src = "grammar test;\n\
+                   prog : tpl ID STRING ;\n\
+                   tpl : BackTick Atom* BackTickInside ;\n\
+                   StringStart : '\"' -> more, pushMode(STR) ;\n\
+                   ID : 'x' ;\n\
+                   KW : 'kw' -> type(ID) ;\n\
+                   KW2 : 'kw2' -> type(KEYWORD) ;\n\
+                   BackTick : '`' -> pushMode(TEMPLATE) ;\n\
+                   mode TEMPLATE;\n\
+                   BackTickInside : '`' -> popMode ;\n\
+                   Atom : ~[`] ;\n\
+                   mode STR;\n\
+                   STRING : '\"' -> popMode ;\n\
+                   Text : ~[\"] -> more ;";
+        let rules = lower_with(src, Separator::Text(" ".to_string()));
+        assert_eq!(rules_for(&rules, "PROG"), vec!["{TPL}{SEPARATOR}{ID}{STRING}"]);
+        assert_eq!(rules_for(&rules, "TPL"), vec!["{BACKTICK}{TPL_STAR}{BACKTICKINSIDE}"]);
+        assert_eq!(rules_for(&rules, "TPL_STAR"), vec!["{ATOM}{TPL_STAR}", ""]);
+        assert_eq!(rules_for(&rules, "ATOM").len(), 97);
+        assert_eq!(rules_for(&rules, "ID"), vec!["x", "{KW}"]);
+        assert_eq!(rules_for(&rules, "KEYWORD"), vec!["{KW2}"]);
+        assert_eq!(rules_for(&rules, "STRING"), vec!["{STR_PREFIX}\""]);
+        assert_eq!(
+            rules_for(&rules, "STR_PREFIX"),
+            vec!["{STRINGSTART}{STR_MORE}", "{STR_MORE}"]
+        );
+        assert_eq!(rules_for(&rules, "STR_MORE"), vec!["{TEXT}{STR_MORE}", ""]);
+    }
+
+    #[test]
+    fn check_alphabets() {
+        assert_eq!(Alphabet::Ascii.chars().len(), 98);
+        assert_eq!(Alphabet::Latin1.chars().len(), 98 + 96);
+        let unicode = Alphabet::Unicode(50).chars();
+        assert_eq!(unicode.len(), 98 + 50);
+        assert_eq!(unicode, Alphabet::Unicode(50).chars());
+
+        let mut options = LoweringOptions::new();
+        options.alphabet = Alphabet::Latin1;
+        let mut my_parser = AntlrParser::with_options(options);
+        let ast = parser::parse("grammar test;\nA : ~[a-z] ;").expect("RAND_2519323434");
+        my_parser.lower_grammar(&ast).expect("RAND_3196813813");
+        assert_eq!(rules_for(&my_parser.rules, "A").len(), 98 + 96 - 26);
+        assert!(my_parser.rules.contains(&("A".to_string(), "\u{e9}".to_string())));
+    }
+
+    #[test]
+    fn check_parse_str() {
+        let my_parser = AntlrParser::new();
+        let grammar = my_parser
+            .parse_str(
+                "grammar test;\n\
+                 value : INT | list ;\n\
+                 list : '[' value* ']' ;\n\
+                 file : value EOF ;\n\
+                 INT : [0-9] ;",
+            )
+            .expect("RAND_3887390405");
+        assert_eq!(grammar.name, "test");
+        assert_eq!(grammar.start, "FILE");
+        assert_eq!(grammar.rules[0], ("FILE".to_string(), "{VALUE}".to_string()));
+        assert_eq!(grammar.rules_with_start()[0], ("START".to_string(), "{FILE}".to_string()));
+        assert_eq!(grammar.nonterminals["list"], "LIST");
+        assert!(!grammar.nonterminals.contains_key("<separator>"));
+        let span = Span {
+            start: Pos { line: 3, column: 1 },
+            end: Pos { line: 3, column: 23 },
         };
-        let file_path = "/tmp/tmp_grammar2.g4";
-        let mut file = File::create(file_path).expect("Could not create file");
-        file.write_all(b"grammar test;\nprog: 'test';\n LITERAL: 'test2';")
-            .expect("Could not write to file");
-        my_parser.parse_antlr_grammar(file_path);
-        let string = my_parser.rename_nterms("prog asfas LITERALasd LITERAL");
-        assert_eq!(string, "{PROG}asfas LITERALasd {LITERAL}");
-        fs::remove_file(file_path).expect("Could not remove file");
+        assert_eq!(grammar.spans["LIST"], span);
+        assert_eq!(grammar.spans["LIST_STAR"], span);
+        assert!(!grammar.spans.contains_key("SEPARATOR"));
+
+        //Without EOF the first rule that isn't used by another one is the start rule
+        let grammar = my_parser
+            .parse_str("grammar test;\na : 'x' ;\nb : a a ;")
+            .expect("RAND_609377325");
+        assert_eq!(grammar.start, "B");
+
+        let err = my_parser
+            .parse_str("grammar test;\na : b ;")
+            .expect_err("RAND_1978813097");
+        assert_eq!((err.line(), err.column()), (Some(2), Some(5)));
+        assert_eq!(err.to_string(), "2:5: undefined rule b");
+        let err = my_parser
+            .parse_str("parser grammar test;\nimport Other;\na : 'x' ;")
+            .expect_err("RAND_3116049617");
+        assert_eq!(err.to_string(), "cannot find grammar Other");
+        let srcs = ["parser grammar P;\nimport L;\na : X ;", "lexer grammar L;\nX : 'x' ;"];
+        let grammar = my_parser.parse_strs(&srcs).expect("RAND_1141387658");
+        assert_eq!(&grammar.rules[..2], &[
+            ("A".to_string(), "{X}".to_string()),
+            ("X".to_string(), "x".to_string()),
+        ]);
     }
 
+    //Checks that every referenced nonterminal is defined and every format can be tokenized
+    fn check_bundled_grammar(path: &str, start: &str) {
+        let mut my_parser = AntlrParser::new();
+        my_parser.parse_antlr_grammar(path).expect("RAND_1352097864");
+        assert_eq!(my_parser.rules[0].0, start);
+        let defined = my_parser
+            .rules
+            .iter()
+            .map(|&(ref nt, _)| nt.clone())
+            .collect::<HashSet<_>>();
+        for &(_, ref format) in my_parser.rules.iter() {
+            let (nonterms, literal) = tokenize(format);
+            for nt in nonterms {
+                assert!(defined.contains(&nt), "{} uses undefined {}", path, nt);
+            }
+            //an escaped brace in front of a nonterminal turns it into text
+            for nt in defined.iter() {
+                assert!(
+                    !literal.contains(&format!("{{{}}}", nt)),
+                    "{} derives {} as text in {}",
+                    path,
+                    nt,
+                    format
+                );
+            }
+        }
+    }
+
+    //Splits a format like Rule::tokenize does: a backslash escapes a directly following brace,
+    //unescaped braces enclose a nonterminal. Returns the nonterminals and the literal text.
+    fn tokenize(format: &str) -> (Vec<String>, String) {
+        let mut nonterms = vec![];
+        let mut literal = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'{') || chars.peek() == Some(&'}') => {
+                    literal.push(chars.next().expect("RAND_2463702779"));
+                }
+                '{' => nonterms.push(chars.by_ref().take_while(|c| *c != '}').collect()),
+                _ => literal.push(c),
+            }
+        }
+        return (nonterms, literal);
+    }
+
+    #[test]
+    fn check_bundled_grammars() {
+        check_bundled_grammar("src/calculator.g4", "EQUATION");
+        check_bundled_grammar("src/JavaScript.g4", "PROGRAM");
+        check_bundled_grammar("src/ruby_antlr_old.g4", "PROG");
+        check_bundled_grammar("src/ruby_new_antlr_grammar.g4", "RUBY");
+    }
 }
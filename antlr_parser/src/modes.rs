@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+
+use ast::{Atom, Element, GrammarAst, Repeat, RuleAst};
+
+pub const DEFAULT_MODE: &str = "DEFAULT_MODE";
+
+//What the lexer commands of a token do
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenInfo {
+    pub mode: String,
+    //`more`: the text becomes the prefix of the next token
+    pub more: bool,
+    //`pushMode(X)` or `mode(X)`
+    pub enters: Option<String>,
+    pub pops: bool,
+    //`type(X)`: the token is handed to the parser as X
+    pub token_type: Option<String>,
+}
+
+impl TokenInfo {
+    fn new(rule: &RuleAst) -> Self {
+        let mut info = TokenInfo {
+            mode: rule.mode.clone().unwrap_or(DEFAULT_MODE.to_string()),
+            more: false,
+            enters: None,
+            pops: false,
+            token_type: None,
+        };
+        //ANTLR only allows commands at the end of the outermost alternatives, a command used by
+        //any of them is assumed to hold for the whole rule
+        for alternative in rule.alternatives.iter() {
+            for cmd in alternative.commands.iter() {
+                match (cmd.name.as_str(), &cmd.arg) {
+                    ("more", _) => info.more = true,
+                    ("pushMode", &Some(ref mode)) | ("mode", &Some(ref mode)) => {
+                        info.enters = Some(mode.clone())
+                    }
+                    ("popMode", _) => info.pops = true,
+                    ("type", &Some(ref token_type)) => info.token_type = Some(token_type.clone()),
+                    _ => {}
+                }
+            }
+        }
+        return info;
+    }
+
+    //Whether the lexer is in another mode than the default mode after this token. A popMode
+    //in the default mode returns to the mode that pushed it, e.g. the `}` closing `${` in a
+    //template string.
+    fn ends_in_mode(&self) -> bool {
+        return match self.enters {
+            Some(ref mode) => mode != DEFAULT_MODE,
+            None if self.pops => self.mode == DEFAULT_MODE,
+            None => self.mode != DEFAULT_MODE,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Boundary {
+    first: HashSet<String>,
+    last: HashSet<String>,
+    nullable: bool,
+}
+
+//Knows which tokens are lexed in other modes than the default mode. No separator may be put next
+//to them, as the rules the lexer skips (i.e. whitespace) usually only exist in the default mode.
+#[derive(Debug, Clone, Default)]
+pub struct LexerModes {
+    pub tokens: HashMap<String, TokenInfo>,
+    rules: HashMap<String, Boundary>,
+}
+
+impl LexerModes {
+    pub fn new(ast: &GrammarAst) -> Self {
+        let mut modes = LexerModes::default();
+        for rule in ast.rules.iter().filter(|rule| rule.is_lexer_rule()) {
+            modes.tokens.insert(rule.name.clone(), TokenInfo::new(rule));
+        }
+        //The first and last tokens of the parser rules, computed until they don't change anymore
+        let parser_rules = ast.rules
+            .iter()
+            .filter(|rule| !rule.is_lexer_rule())
+            .collect::<Vec<_>>();
+        let mut something_changed = true;
+        while something_changed {
+            something_changed = false;
+            for rule in parser_rules.iter() {
+                let mut boundary = Boundary::default();
+                for alternative in rule.alternatives.iter() {
+                    let alt = modes.sequence_boundary(&alternative.elements);
+                    boundary.first.extend(alt.first);
+                    boundary.last.extend(alt.last);
+                    boundary.nullable |= alt.nullable;
+                }
+                let old = modes.rules.entry(rule.name.clone()).or_insert(Boundary::default());
+                if old.first.len() != boundary.first.len() || old.last.len() != boundary.last.len()
+                    || old.nullable != boundary.nullable
+                {
+                    *old = boundary;
+                    something_changed = true;
+                }
+            }
+        }
+        return modes;
+    }
+
+    pub fn has_modes(&self) -> bool {
+        return self.tokens.values().any(|info| info.mode != DEFAULT_MODE || info.enters.is_some());
+    }
+
+    //Whether a separator can be put between two adjacent elements of a parser rule
+    pub fn can_separate(&self, left: &Element, right: &Element) -> bool {
+        let left = self.element_boundary(left);
+        let right = self.element_boundary(right);
+        return !left.last.iter().any(|token| self.ends_in_mode(token))
+            && !right.first.iter().any(|token| self.starts_in_mode(token));
+    }
+
+    fn starts_in_mode(&self, token: &str) -> bool {
+        return self.tokens
+            .get(token)
+            .map_or(false, |info| info.mode != DEFAULT_MODE);
+    }
+
+    fn ends_in_mode(&self, token: &str) -> bool {
+        return self.tokens.get(token).map_or(false, |info| info.ends_in_mode());
+    }
+
+    fn sequence_boundary(&self, elements: &[Element]) -> Boundary {
+        let mut boundary = Boundary {
+            first: HashSet::new(),
+            last: HashSet::new(),
+            nullable: true,
+        };
+        for element in elements.iter() {
+            let next = self.element_boundary(element);
+            if boundary.nullable {
+                boundary.first.extend(next.first.iter().cloned());
+            }
+            if !next.nullable {
+                boundary.last.clear();
+            }
+            boundary.last.extend(next.last);
+            boundary.nullable &= next.nullable;
+        }
+        return boundary;
+    }
+
+    fn element_boundary(&self, element: &Element) -> Boundary {
+        let mut boundary = match element.atom {
+            Atom::Ref(ref name) if self.tokens.contains_key(name) => {
+                let tokens = [name.clone()].iter().cloned().collect::<HashSet<_>>();
+                Boundary {
+                    first: tokens.clone(),
+                    last: tokens,
+                    nullable: false,
+                }
+            }
+            Atom::Ref(ref name) => self.rules.get(name).cloned().unwrap_or(Boundary {
+                first: HashSet::new(),
+                last: HashSet::new(),
+                nullable: true,
+            }),
+            Atom::Block(ref alternatives) => {
+                let mut boundary = Boundary::default();
+                for alternative in alternatives.iter() {
+                    let alt = self.sequence_boundary(&alternative.elements);
+                    boundary.first.extend(alt.first);
+                    boundary.last.extend(alt.last);
+                    boundary.nullable |= alt.nullable;
+                }
+                boundary
+            }
+            //Literals in parser rules are implicit tokens of the default mode
+            Atom::Literal(ref literal) => Boundary {
+                first: HashSet::new(),
+                last: HashSet::new(),
+                nullable: literal.is_empty(),
+            },
+            _ => Boundary::default(),
+        };
+        match element.suffix.map(|suffix| suffix.repeat) {
+            Some(Repeat::Optional) | Some(Repeat::Star) => boundary.nullable = true,
+            _ => {}
+        }
+        return boundary;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+
+    #[test]
+    fn check_mode_boundaries() {
+        let ast = parse(
+            "lexer grammar t;\n\
+             BackTick : '`' -> pushMode(TEMPLATE) ;\n\
+             CloseBrace : '}' -> popMode ;\n\
+             Id : [a-z]+ ;\n\
+             mode TEMPLATE;\n\
+             BackTickInside : '`' -> popMode ;\n\
+             StartExpression : '${' -> pushMode(DEFAULT_MODE) ;\n\
+             Atom : ~[`] -> more ;",
+        ).expect("RAND_1316011355");
+        let modes = LexerModes::new(&ast);
+        assert!(modes.has_modes());
+        assert!(modes.tokens["Atom"].more);
+        assert!(modes.ends_in_mode("BackTick") && !modes.starts_in_mode("BackTick"));
+        assert!(modes.ends_in_mode("CloseBrace"));
+        assert!(!modes.ends_in_mode("Id") && !modes.starts_in_mode("Id"));
+        assert!(!modes.ends_in_mode("BackTickInside") && modes.starts_in_mode("BackTickInside"));
+        assert!(!modes.ends_in_mode("StartExpression"));
+    }
+}
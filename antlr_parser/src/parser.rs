@@ -0,0 +1,685 @@
+use std::char;
+use std::fmt;
+
+use ast::{
+    Alternative, Atom, Element, GrammarAst, GrammarKind, LexerCommand, Pos, Repeat, RuleAst,
+    Suffix,
+};
+use lexer::{tokenize, Token};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.message)
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    idx: usize,
+    mode: Option<String>,
+    in_lexer_rule: bool,
+}
+
+//Parses the text of an ANTLR4 grammar file
+pub fn parse(src: &str) -> Result<GrammarAst, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        idx: 0,
+        mode: None,
+        in_lexer_rule: false,
+    };
+    return parser.parse_grammar();
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> &Token {
+        //tokenize always ends the list with Eof
+        let last = self.tokens.len() - 1;
+        return &self.tokens[(self.idx + offset).min(last)].0;
+    }
+
+    fn pos(&self) -> Pos {
+        return self.tokens[self.idx].1;
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.peek(0).clone();
+        if self.idx < self.tokens.len() - 1 {
+            self.idx += 1;
+        }
+        return token;
+    }
+
+    fn is_keyword(&self, offset: usize, keyword: &str) -> bool {
+        return match self.peek(offset) {
+            &Token::Ident(ref ident) => ident == keyword,
+            _ => false,
+        };
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        return Err(ParseError {
+            pos: self.pos(),
+            message: message.to_string(),
+        });
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ParseError> {
+        return self.error(&format!("expected {}, found {}", expected, describe(self.peek(0))));
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), ParseError> {
+        if *self.peek(0) != token {
+            return self.unexpected(expected);
+        }
+        self.bump();
+        return Ok(());
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        if let &Token::Ident(ref ident) = self.peek(0) {
+            let ident = ident.clone();
+            self.bump();
+            return Ok(ident);
+        }
+        return self.unexpected("a name");
+    }
+
+    fn expect_action(&mut self) -> Result<String, ParseError> {
+        if let &Token::Action(ref action) = self.peek(0) {
+            let action = action.clone();
+            self.bump();
+            return Ok(action);
+        }
+        return self.unexpected("{");
+    }
+
+    fn expect_char_set(&mut self) -> Result<String, ParseError> {
+        if let &Token::CharSet(ref raw) = self.peek(0) {
+            let raw = raw.clone();
+            self.bump();
+            return Ok(raw);
+        }
+        return self.unexpected("[");
+    }
+
+    fn parse_grammar(&mut self) -> Result<GrammarAst, ParseError> {
+        let kind = if self.is_keyword(0, "lexer") {
+            self.bump();
+            GrammarKind::Lexer
+        } else if self.is_keyword(0, "parser") {
+            self.bump();
+            GrammarKind::Parser
+        } else {
+            GrammarKind::Combined
+        };
+        if !self.is_keyword(0, "grammar") {
+            return self.unexpected("grammar");
+        }
+        self.bump();
+        let name = self.expect_ident()?;
+        self.expect(Token::Semi, ";")?;
+        let mut ast = GrammarAst {
+            kind,
+            name,
+            options: vec![],
+            imports: vec![],
+            tokens: vec![],
+            rules: vec![],
+        };
+        loop {
+            let is_action = |token: &Token| match token {
+                &Token::Action(_) => true,
+                _ => false,
+            };
+            if *self.peek(0) == Token::Eof {
+                return Ok(ast);
+            } else if self.is_keyword(0, "options") && is_action(self.peek(1)) {
+                self.bump();
+                let options = self.expect_action()?;
+                ast.options.extend(parse_options(&options));
+            } else if (self.is_keyword(0, "tokens") || self.is_keyword(0, "channels"))
+                && is_action(self.peek(1))
+            {
+                let is_tokens = self.is_keyword(0, "tokens");
+                self.bump();
+                let names = self.expect_action()?;
+                if is_tokens {
+                    ast.tokens.extend(
+                        names
+                            .split(',')
+                            .map(|name| name.trim().to_string())
+                            .filter(|name| !name.is_empty()),
+                    );
+                }
+            } else if self.is_keyword(0, "import") {
+                self.bump();
+                loop {
+                    let mut name = self.expect_ident()?;
+                    if *self.peek(0) == Token::Assign {
+                        self.bump();
+                        name = self.expect_ident()?;
+                    }
+                    ast.imports.push(name);
+                    if *self.peek(0) != Token::Comma {
+                        break;
+                    }
+                    self.bump();
+                }
+                self.expect(Token::Semi, ";")?;
+            } else if *self.peek(0) == Token::At {
+                self.parse_named_action()?;
+            } else if self.is_keyword(0, "mode") && *self.peek(2) == Token::Semi {
+                self.bump();
+                self.mode = Some(self.expect_ident()?);
+                self.bump();
+            } else {
+                let rule = self.parse_rule()?;
+                ast.rules.push(rule);
+            }
+        }
+    }
+
+    //@members { ... }, @parser::header { ... } or @init { ... }
+    fn parse_named_action(&mut self) -> Result<(), ParseError> {
+        self.expect(Token::At, "@")?;
+        self.expect_ident()?;
+        if *self.peek(0) == Token::ColonColon {
+            self.bump();
+            self.expect_ident()?;
+        }
+        self.expect_action()?;
+        return Ok(());
+    }
+
+    fn parse_rule(&mut self) -> Result<RuleAst, ParseError> {
+        let pos = self.pos();
+        let fragment = self.is_keyword(0, "fragment");
+        if fragment {
+            self.bump();
+        }
+        let name = self.expect_ident()?;
+        self.in_lexer_rule = name.chars().next().map_or(false, |c| c.is_uppercase());
+        loop {
+            match self.peek(0).clone() {
+                Token::CharSet(_) => {
+                    self.bump();
+                }
+                Token::Ident(ref keyword) if keyword == "returns" || keyword == "locals" => {
+                    self.bump();
+                    self.expect_char_set()?;
+                }
+                Token::Ident(ref keyword) if keyword == "throws" => {
+                    self.bump();
+                    self.expect_ident()?;
+                    while *self.peek(0) == Token::Comma {
+                        self.bump();
+                        self.expect_ident()?;
+                    }
+                }
+                Token::Ident(ref keyword) if keyword == "options" => {
+                    self.bump();
+                    self.expect_action()?;
+                }
+                Token::At => self.parse_named_action()?,
+                _ => break,
+            }
+        }
+        self.expect(Token::Colon, ":")?;
+        let alternatives = self.parse_alternatives()?;
+        let end = self.pos();
+        self.expect(Token::Semi, ";")?;
+        loop {
+            if self.is_keyword(0, "catch") {
+                self.bump();
+                self.expect_char_set()?;
+                self.expect_action()?;
+            } else if self.is_keyword(0, "finally") {
+                self.bump();
+                self.expect_action()?;
+            } else {
+                break;
+            }
+        }
+        let mode = if self.in_lexer_rule { self.mode.clone() } else { None };
+        return Ok(RuleAst {
+            name,
+            fragment,
+            mode,
+            alternatives,
+            pos,
+            end,
+        });
+    }
+
+    fn parse_alternatives(&mut self) -> Result<Vec<Alternative>, ParseError> {
+        let mut alternatives = vec![self.parse_alternative()?];
+        while *self.peek(0) == Token::Or {
+            self.bump();
+            alternatives.push(self.parse_alternative()?);
+        }
+        return Ok(alternatives);
+    }
+
+    fn parse_alternative(&mut self) -> Result<Alternative, ParseError> {
+        let mut alternative = Alternative {
+            label: None,
+            elements: vec![],
+            commands: vec![],
+        };
+        self.skip_element_options()?;
+        loop {
+            match *self.peek(0) {
+                Token::Semi | Token::Or | Token::RParen | Token::Pound | Token::Arrow => break,
+                Token::Eof => return self.unexpected(";"),
+                _ => {
+                    if let Some(element) = self.parse_element()? {
+                        alternative.elements.push(element);
+                    }
+                }
+            }
+        }
+        if *self.peek(0) == Token::Pound {
+            self.bump();
+            alternative.label = Some(self.expect_ident()?);
+        }
+        if *self.peek(0) == Token::Arrow {
+            self.bump();
+            loop {
+                let name = self.expect_ident()?;
+                let mut arg = None;
+                if *self.peek(0) == Token::LParen {
+                    self.bump();
+                    arg = match self.bump() {
+                        Token::Ident(arg) | Token::Int(arg) => Some(arg),
+                        _ => return self.error("expected the argument of a lexer command"),
+                    };
+                    self.expect(Token::RParen, ")")?;
+                }
+                alternative.commands.push(LexerCommand { name, arg });
+                if *self.peek(0) != Token::Comma {
+                    break;
+                }
+                self.bump();
+            }
+        }
+        return Ok(alternative);
+    }
+
+    //Returns None for actions and semantic predicates
+    fn parse_element(&mut self) -> Result<Option<Element>, ParseError> {
+        let pos = self.pos();
+        if let &Token::Action(_) = self.peek(0) {
+            self.bump();
+            if *self.peek(0) == Token::Question {
+                self.bump();
+            }
+            return Ok(None);
+        }
+        //Labels like `x=expr` or `xs+=expr` only name the element
+        if let &Token::Ident(_) = self.peek(0) {
+            if *self.peek(1) == Token::Assign || *self.peek(1) == Token::PlusAssign {
+                self.bump();
+                self.bump();
+            }
+        }
+        let atom = self.parse_atom()?;
+        let suffix = self.parse_suffix();
+        return Ok(Some(Element { atom, suffix, pos }));
+    }
+
+    fn parse_atom(&mut self) -> Result<Atom, ParseError> {
+        let pos = self.pos();
+        match *self.peek(0) {
+            Token::StringLit(_)
+            | Token::Ident(_)
+            | Token::CharSet(_)
+            | Token::Dot
+            | Token::Tilde
+            | Token::LParen => {}
+            _ => return self.error(&format!("unexpected {}", describe(self.peek(0)))),
+        }
+        let atom = match self.bump() {
+            Token::StringLit(raw) => {
+                let literal = decode_literal(&raw, pos)?;
+                if *self.peek(0) == Token::Range {
+                    self.bump();
+                    let to_pos = self.pos();
+                    let to = match self.bump() {
+                        Token::StringLit(raw) => decode_literal(&raw, to_pos)?,
+                        _ => return self.error("expected the end of the character range"),
+                    };
+                    Atom::Set(vec![(single_char(&literal, pos)?, single_char(&to, to_pos)?)])
+                } else {
+                    Atom::Literal(literal)
+                }
+            }
+            Token::Ident(name) => {
+                //Arguments of parser rules, `expr[0]`
+                if !self.in_lexer_rule {
+                    if let &Token::CharSet(_) = self.peek(0) {
+                        self.bump();
+                    }
+                }
+                Atom::Ref(name)
+            }
+            Token::CharSet(raw) => Atom::Set(parse_char_set(&raw, pos)?),
+            Token::Dot => Atom::Wildcard,
+            Token::Tilde => Atom::Not(self.parse_not_set()?),
+            Token::LParen => {
+                if self.is_keyword(0, "options") {
+                    self.bump();
+                    self.expect_action()?;
+                    self.expect(Token::Colon, ":")?;
+                }
+                let alternatives = self.parse_alternatives()?;
+                self.expect(Token::RParen, ")")?;
+                Atom::Block(alternatives)
+            }
+            _ => unreachable!(),
+        };
+        self.skip_element_options()?;
+        return Ok(atom);
+    }
+
+    fn parse_not_set(&mut self) -> Result<Vec<Atom>, ParseError> {
+        if *self.peek(0) != Token::LParen {
+            return Ok(vec![self.parse_set_element()?]);
+        }
+        self.bump();
+        let mut elements = vec![self.parse_set_element()?];
+        while *self.peek(0) == Token::Or {
+            self.bump();
+            elements.push(self.parse_set_element()?);
+        }
+        self.expect(Token::RParen, ")")?;
+        return Ok(elements);
+    }
+
+    fn parse_set_element(&mut self) -> Result<Atom, ParseError> {
+        return match self.peek(0).clone() {
+            Token::StringLit(_) | Token::CharSet(_) | Token::Ident(_) => self.parse_atom(),
+            _ => self.unexpected("a character set"),
+        };
+    }
+
+    fn parse_suffix(&mut self) -> Option<Suffix> {
+        let repeat = match *self.peek(0) {
+            Token::Question => Repeat::Optional,
+            Token::Star => Repeat::Star,
+            Token::Plus => Repeat::Plus,
+            _ => return None,
+        };
+        self.bump();
+        let greedy = *self.peek(0) != Token::Question;
+        if !greedy {
+            self.bump();
+        }
+        return Some(Suffix { repeat, greedy });
+    }
+
+    //Element options like `<assoc=right>` only matter to ANTLR's parser generator
+    fn skip_element_options(&mut self) -> Result<(), ParseError> {
+        if *self.peek(0) != Token::Lt {
+            return Ok(());
+        }
+        while *self.peek(0) != Token::Gt {
+            if *self.peek(0) == Token::Eof {
+                return self.unexpected(">");
+            }
+            self.bump();
+        }
+        self.bump();
+        return Ok(());
+    }
+}
+
+fn describe(token: &Token) -> String {
+    return match token {
+        &Token::Ident(ref ident) => format!("'{}'", ident),
+        &Token::StringLit(ref raw) => format!("'{}'", raw),
+        &Token::CharSet(ref raw) => format!("[{}]", raw),
+        &Token::Action(_) => "an action".to_string(),
+        &Token::Int(ref int) => int.clone(),
+        &Token::Colon => ":".to_string(),
+        &Token::ColonColon => "::".to_string(),
+        &Token::Semi => ";".to_string(),
+        &Token::Or => "|".to_string(),
+        &Token::LParen => "(".to_string(),
+        &Token::RParen => ")".to_string(),
+        &Token::Star => "*".to_string(),
+        &Token::Plus => "+".to_string(),
+        &Token::Question => "?".to_string(),
+        &Token::Tilde => "~".to_string(),
+        &Token::Dot => ".".to_string(),
+        &Token::Range => "..".to_string(),
+        &Token::Arrow => "->".to_string(),
+        &Token::Assign => "=".to_string(),
+        &Token::PlusAssign => "+=".to_string(),
+        &Token::Pound => "#".to_string(),
+        &Token::Comma => ",".to_string(),
+        &Token::Lt => "<".to_string(),
+        &Token::Gt => ">".to_string(),
+        &Token::At => "@".to_string(),
+        &Token::Eof => "end of file".to_string(),
+    };
+}
+
+//Reads `key = value;` pairs from the body of an options block
+fn parse_options(body: &str) -> Vec<(String, String)> {
+    return body
+        .split(';')
+        .filter_map(|option| {
+            let mut parts = option.splitn(2, '=');
+            let key = parts.next()?.trim();
+            let value = parts.next()?.trim().trim_matches(|c| c == '\'' || c == '"');
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect();
+}
+
+fn single_char(literal: &str, pos: Pos) -> Result<char, ParseError> {
+    let mut chars = literal.chars();
+    return match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(ParseError {
+            pos,
+            message: format!("'{}' is not a single character", literal),
+        }),
+    };
+}
+
+enum SetItem {
+    Char(char),
+    Class(Vec<(char, char)>),
+}
+
+//Decodes the escape sequence that starts after the backslash at chars[idx]
+fn read_escape(chars: &[char], idx: usize, pos: Pos) -> Result<(SetItem, usize), ParseError> {
+    let err = |message: &str| {
+        Err(ParseError {
+            pos,
+            message: message.to_string(),
+        })
+    };
+    let c = match chars.get(idx) {
+        Some(c) => *c,
+        None => return err("incomplete escape sequence"),
+    };
+    let simple = match c {
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        'b' => Some('\x08'),
+        'f' => Some('\x0C'),
+        'u' | 'p' | 'P' => None,
+        c => Some(c),
+    };
+    if let Some(simple) = simple {
+        return Ok((SetItem::Char(simple), idx + 1));
+    }
+    let braced = chars.get(idx + 1) == Some(&'{');
+    let (body, next) = if braced {
+        match chars[idx + 2..].iter().position(|c| *c == '}') {
+            Some(len) => (
+                chars[idx + 2..idx + 2 + len].iter().collect::<String>(),
+                idx + 3 + len,
+            ),
+            None => return err("unterminated escape sequence"),
+        }
+    } else if c == 'u' && idx + 5 <= chars.len() {
+        (chars[idx + 1..idx + 5].iter().collect::<String>(), idx + 5)
+    } else {
+        return err("incomplete escape sequence");
+    };
+    if c == 'u' {
+        return match u32::from_str_radix(&body, 16).ok().and_then(char::from_u32) {
+            Some(c) => Ok((SetItem::Char(c), next)),
+            None => err(&format!("invalid unicode escape \\u{}", body)),
+        };
+    }
+    //Unicode properties are approximated by their ASCII members
+    let class = match body.as_str() {
+        "L" | "Letter" | "Alpha" | "Alphabetic" | "Lu" | "Ll" | "ID_Start" => {
+            vec![('a', 'z'), ('A', 'Z')]
+        }
+        "N" | "Nd" | "Digit" => vec![('0', '9')],
+        "White_Space" | "Zs" => vec![(' ', ' '), ('\t', '\t')],
+        _ => vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+    };
+    return Ok((SetItem::Class(class), next));
+}
+
+fn decode_literal(raw: &str, pos: Pos) -> Result<String, ParseError> {
+    let chars = raw.chars().collect::<Vec<_>>();
+    let mut literal = String::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        if chars[idx] != '\\' {
+            literal.push(chars[idx]);
+            idx += 1;
+            continue;
+        }
+        match read_escape(&chars, idx + 1, pos)? {
+            (SetItem::Char(c), next) => {
+                literal.push(c);
+                idx = next;
+            }
+            (SetItem::Class(_), _) => {
+                return Err(ParseError {
+                    pos,
+                    message: "unicode properties are only allowed in character sets".to_string(),
+                })
+            }
+        }
+    }
+    return Ok(literal);
+}
+
+//Turns the body of `[a-z_À]` into inclusive character ranges
+pub fn parse_char_set(raw: &str, pos: Pos) -> Result<Vec<(char, char)>, ParseError> {
+    let chars = raw.chars().collect::<Vec<_>>();
+    let read = |idx: usize| -> Result<(SetItem, usize), ParseError> {
+        if chars[idx] == '\\' {
+            return read_escape(&chars, idx + 1, pos);
+        }
+        return Ok((SetItem::Char(chars[idx]), idx + 1));
+    };
+    let mut ranges = vec![];
+    let mut idx = 0;
+    while idx < chars.len() {
+        let (from, next) = match read(idx)? {
+            (SetItem::Char(from), next) => (from, next),
+            (SetItem::Class(class), next) => {
+                ranges.extend(class);
+                idx = next;
+                continue;
+            }
+        };
+        //A '-' at the end of the set is just a character
+        if next + 1 < chars.len() && chars[next] == '-' {
+            if let (SetItem::Char(to), after) = read(next + 1)? {
+                if to < from {
+                    return Err(ParseError {
+                        pos,
+                        message: format!("invalid character range {:?}-{:?}", from, to),
+                    });
+                }
+                ranges.push((from, to));
+                idx = after;
+                continue;
+            }
+        }
+        ranges.push((from, from));
+        idx = next;
+    }
+    return Ok(ranges);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_rule_syntax() {
+        let ast = parse(
+            "/** doc */ grammar t;\n\
+             options { language = Java; }\n\
+             @members { int x = 0; }\n\
+             tokens { A, B }\n\
+             prog : x=stmt (ys+=stmt)* EOF # Program\n\
+                  | {p(\"x\")}? ID <assoc=right> {x++;} # Other\n\
+                  ;\n\
+             stmt[int i] returns [int v] @init { } : 'a\\'\\u0041' | ~[;\\]]+? ;\n\
+             fragment ID : 'a'..'z' [0-9;-]* ;\n\
+             WS : [ \\t\\r\\n]+ -> channel(HIDDEN), skip ;",
+        ).expect("RAND_2208914606");
+        assert_eq!(ast.kind, GrammarKind::Combined);
+        assert_eq!(ast.get_option("language"), Some("Java"));
+        assert_eq!(ast.tokens, vec!["A", "B"]);
+        assert_eq!(ast.rules.len(), 4);
+
+        let prog = &ast.rules[0];
+        assert_eq!(prog.alternatives[0].label, Some("Program".to_string()));
+        let elements = &prog.alternatives[0].elements;
+        assert_eq!(elements[0].atom, Atom::Ref("stmt".to_string()));
+        assert_eq!(elements[1].suffix.expect("RAND_3061593473").repeat, Repeat::Star);
+        assert_eq!(elements[2].atom, Atom::Ref("EOF".to_string()));
+        assert_eq!(prog.alternatives[1].elements.len(), 1);
+        assert_eq!(prog.end, Pos { line: 7, column: 1 });
+
+        let stmt = &ast.rules[1];
+        assert_eq!(stmt.pos, Pos { line: 8, column: 1 });
+        assert_eq!(stmt.alternatives[0].elements[0].atom, Atom::Literal("a'A".to_string()));
+        let not = &stmt.alternatives[1].elements[0];
+        assert_eq!(not.atom, Atom::Not(vec![Atom::Set(vec![(';', ';'), (']', ']')])]));
+        assert_eq!(not.suffix, Some(Suffix { repeat: Repeat::Plus, greedy: false }));
+
+        let id = ast.get_rule("ID").expect("RAND_1860226521");
+        assert!(id.fragment && id.is_lexer_rule());
+        assert_eq!(id.alternatives[0].elements[0].atom, Atom::Set(vec![('a', 'z')]));
+        assert_eq!(
+            id.alternatives[0].elements[1].atom,
+            Atom::Set(vec![('0', '9'), (';', ';'), ('-', '-')])
+        );
+        let ws = ast.get_rule("WS").expect("RAND_1467347316");
+        assert!(ws.is_skipped());
+        assert_eq!(ws.alternatives[0].commands[0].arg, Some("HIDDEN".to_string()));
+    }
+
+    #[test]
+    fn check_parse_errors() {
+        let err = parse("grammar t;\na : 'x' |\n  ( b ;").expect_err("RAND_2624003108");
+        assert_eq!(err.pos, Pos { line: 3, column: 7 });
+        assert_eq!(err.to_string(), "3:7: expected ), found ;");
+        let err = parse("a : b ;").expect_err("RAND_1522658066");
+        assert_eq!(err.pos, Pos { line: 1, column: 1 });
+        assert!(parse("grammar t;\nA : [z-a] ;").is_err());
+    }
+}
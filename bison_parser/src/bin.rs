@@ -0,0 +1,47 @@
+extern crate bison_parser;
+extern crate serde_json;
+
+use bison_parser::BisonOptions;
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str =
+    "Usage: bison_parser [--tokens MAP.json] [--separator TEXT] GRAMMAR.y OUTPUT.json";
+
+fn main() {
+    let mut options = BisonOptions::new();
+    let mut args = vec![];
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        if arg == "--tokens" {
+            let path = argv.next().expect("token map missing");
+            options.token_texts = match bison_parser::read_token_map(Path::new(&path)) {
+                Ok(token_texts) => token_texts,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            };
+        } else if arg == "--separator" {
+            options.separator = argv.next().expect("separator missing");
+        } else {
+            args.push(arg);
+        }
+    }
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+    let grammar = match bison_parser::parse_file(Path::new(&args[0]), &options) {
+        Ok(grammar) => grammar,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let of = File::create(&args[1]).expect("cannot create output file");
+    serde_json::to_writer(&of, &grammar.rules).expect("Can not write to output file");
+}
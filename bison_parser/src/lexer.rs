@@ -0,0 +1,340 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    //`%token`, `%left`, `%define`, ... without the percent sign
+    Directive(String),
+    //A quoted character like 'x' or '\n', already decoded
+    Char(String),
+    //A string like "if", used as the alias of a token
+    Str(String),
+    //`{ ... }` code, `%{ ... %}` prologues and `%union` bodies. Only their extent matters.
+    Code,
+    //`<type>`
+    Tag,
+    //`[name]` of a named reference
+    Name,
+    Int,
+    Colon,
+    Pipe,
+    Semi,
+    //`%%`
+    Separator,
+    Other(char),
+    Eof,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    idx: usize,
+    pos: Pos,
+}
+
+//Splits a Yacc/Bison grammar into tokens. The epilogue after the second `%%` is C code and is
+//not tokenized.
+pub fn tokenize(src: &str) -> Result<Vec<(Token, Pos)>, ParseError> {
+    let mut lexer = Lexer {
+        chars: src.chars().collect(),
+        idx: 0,
+        pos: Pos { line: 1, column: 1 },
+    };
+    let mut tokens = vec![];
+    let mut separators = 0;
+    loop {
+        lexer.skip_whitespace_and_comments()?;
+        let pos = lexer.pos;
+        let token = match lexer.peek(0) {
+            None => Token::Eof,
+            Some(_) if separators == 2 => Token::Eof,
+            Some(c) => lexer.read_token(c)?,
+        };
+        if token == Token::Separator {
+            separators += 1;
+        }
+        tokens.push((token.clone(), pos));
+        if token == Token::Eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+impl Lexer {
+    fn peek(&self, offset: usize) -> Option<char> {
+        return self.chars.get(self.idx + offset).cloned();
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.idx += 1;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        return Some(c);
+    }
+
+    fn error<T>(&self, pos: Pos, message: &str) -> Result<T, ParseError> {
+        return Err(ParseError {
+            pos,
+            message: message.to_string(),
+        });
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => while self.peek(0).is_some_and(|c| c != '\n') {
+                    self.bump();
+                },
+                (Some('/'), Some('*')) => self.skip_block_comment()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.bump();
+        self.bump();
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some('*'), Some('/')) => break,
+                (None, _) => return self.error(start, "unterminated comment"),
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+        self.bump();
+        self.bump();
+        return Ok(());
+    }
+
+    fn read_token(&mut self, c: char) -> Result<Token, ParseError> {
+        let start = self.pos;
+        if c.is_alphabetic() || c == '_' || c == '.' {
+            return Ok(Token::Ident(self.read_ident()));
+        }
+        if c.is_ascii_digit() {
+            while self.peek(0).is_some_and(|c| c.is_ascii_alphanumeric()) {
+                self.bump();
+            }
+            return Ok(Token::Int);
+        }
+        self.bump();
+        let token = match c {
+            '%' => match self.peek(0) {
+                Some('%') => {
+                    self.bump();
+                    Token::Separator
+                }
+                Some('{') => {
+                    self.bump();
+                    self.skip_prologue(start)?;
+                    Token::Code
+                }
+                Some(c) if c.is_alphabetic() => Token::Directive(self.read_ident()),
+                _ => return self.error(start, "expected a directive after %"),
+            },
+            '\'' => Token::Char(self.read_quoted('\'', start)?),
+            '"' => Token::Str(self.read_quoted('"', start)?),
+            '{' => {
+                self.skip_code(start)?;
+                Token::Code
+            }
+            '<' => {
+                self.skip_until('>', start, "unterminated type tag")?;
+                Token::Tag
+            }
+            '[' => {
+                self.skip_until(']', start, "unterminated name")?;
+                Token::Name
+            }
+            ':' => Token::Colon,
+            '|' => Token::Pipe,
+            ';' => Token::Semi,
+            c => Token::Other(c),
+        };
+        return Ok(token);
+    }
+
+    fn read_ident(&mut self) -> String {
+        let mut ident = String::new();
+        while let Some(c) = self.peek(0) {
+            if !c.is_alphanumeric() && c != '_' && c != '.' && c != '-' {
+                break;
+            }
+            ident.push(c);
+            self.bump();
+        }
+        return ident;
+    }
+
+    fn skip_until(&mut self, end: char, start: Pos, message: &str) -> Result<(), ParseError> {
+        loop {
+            match self.bump() {
+                Some(c) if c == end => return Ok(()),
+                Some(_) => {}
+                None => return self.error(start, message),
+            }
+        }
+    }
+
+    //Reads a character or string literal and decodes the C escape sequences in it
+    fn read_quoted(&mut self, quote: char, start: Pos) -> Result<String, ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(text),
+                Some('\\') => {
+                    let decoded = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('a') => '\x07',
+                        Some('b') => '\x08',
+                        Some('f') => '\x0c',
+                        Some('v') => '\x0b',
+                        Some('x') => {
+                            let mut value = 0;
+                            while let Some(digit) = self.peek(0).and_then(|c| c.to_digit(16)) {
+                                value = value * 16 + digit;
+                                self.bump();
+                            }
+                            match ::std::char::from_u32(value) {
+                                Some(c) => c,
+                                None => return self.error(start, "invalid escape sequence"),
+                            }
+                        }
+                        Some(c) => c,
+                        None => return self.error(start, "unterminated literal"),
+                    };
+                    text.push(decoded);
+                }
+                Some('\n') | None => return self.error(start, "unterminated literal"),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    //Skips C code up to the matching closing brace. Braces in strings, character literals and
+    //comments don't count.
+    fn skip_code(&mut self, start: Pos) -> Result<(), ParseError> {
+        let mut depth = 1;
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (None, _) => return self.error(start, "unterminated code block"),
+                (Some('/'), Some('*')) => self.skip_block_comment()?,
+                (Some('/'), Some('/')) => while self.peek(0).is_some_and(|c| c != '\n') {
+                    self.bump();
+                },
+                (Some(quote), _) if quote == '"' || quote == '\'' => {
+                    let literal_start = self.pos;
+                    self.bump();
+                    self.read_quoted(quote, literal_start)?;
+                }
+                (Some(c), _) => {
+                    self.bump();
+                    if c == '{' {
+                        depth += 1;
+                    } else if c == '}' {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn skip_prologue(&mut self, start: Pos) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some('%'), Some('}')) => {
+                    self.bump();
+                    self.bump();
+                    return Ok(());
+                }
+                (None, _) => return self.error(start, "unterminated %{ block"),
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        return tokenize(src)
+            .expect("RAND_2797479336")
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+    }
+
+    #[test]
+    fn check_tokens() {
+        assert_eq!(
+            tokens(
+                "%{ int x = '}'; %}\n\
+                 %token <id> tIDENT 258 \"identifier\"\n\
+                 %%\n\
+                 expr.list : expr[e] '+' /* } */ { if (x) { y = \"}\"; } } | '\\n' ;\n\
+                 %%\n\
+                 int main() { return '%'; }"
+            ),
+            vec![
+                Token::Code,
+                Token::Directive("token".to_string()),
+                Token::Tag,
+                Token::Ident("tIDENT".to_string()),
+                Token::Int,
+                Token::Str("identifier".to_string()),
+                Token::Separator,
+                Token::Ident("expr.list".to_string()),
+                Token::Colon,
+                Token::Ident("expr".to_string()),
+                Token::Name,
+                Token::Char("+".to_string()),
+                Token::Code,
+                Token::Pipe,
+                Token::Char("\n".to_string()),
+                Token::Semi,
+                Token::Separator,
+                Token::Eof,
+            ]
+        );
+        let err = tokenize("%%\na : { b ;").expect_err("RAND_2023853235");
+        assert_eq!(err.pos, Pos { line: 2, column: 5 });
+    }
+}
@@ -0,0 +1,410 @@
+extern crate serde_json;
+extern crate rule_format;
+
+pub mod lexer;
+pub mod parser;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use lexer::{ParseError, Pos};
+use parser::{GrammarAst, Symbol};
+
+//The token Bison uses for error recovery. Alternatives containing it are dropped.
+const ERROR_TOKEN: &str = "error";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BisonError {
+    pub file: Option<PathBuf>,
+    //None if the error isn't about a place in the grammar, e.g. if the file can't be read
+    pub pos: Option<Pos>,
+    pub message: String,
+}
+
+impl BisonError {
+    fn new(pos: Option<Pos>, message: String) -> Self {
+        return BisonError {
+            file: None,
+            pos,
+            message,
+        };
+    }
+}
+
+impl From<ParseError> for BisonError {
+    fn from(err: ParseError) -> Self {
+        return BisonError::new(Some(err.pos), err.message);
+    }
+}
+
+impl fmt::Display for BisonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(pos) = self.pos {
+            write!(f, "{}:{}:", pos.line, pos.column)?;
+        }
+        if self.file.is_some() || self.pos.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for BisonError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BisonOptions {
+    //The texts a token can stand for, keyed by the token name or its alias. Tokens that aren't
+    //in here produce their alias if they have one and their name otherwise.
+    pub token_texts: HashMap<String, Vec<String>>,
+    //Put between the symbols of a rule, since the scanner usually skips whitespace between tokens
+    pub separator: String,
+}
+
+impl BisonOptions {
+    pub fn new() -> Self {
+        return BisonOptions {
+            token_texts: HashMap::new(),
+            separator: " ".to_string(),
+        };
+    }
+}
+
+impl Default for BisonOptions {
+    fn default() -> Self {
+        return BisonOptions::new();
+    }
+}
+
+//A Bison grammar converted to grammartec rules. Rule names are upper case, the start rule is the
+//one given by `%start` or else the first rule of the grammar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grammar {
+    pub start: String,
+    pub rules: Vec<(String, String)>,
+}
+
+//Reads the texts of the tokens from a JSON object like
+//`{"tIDENT": ["foo", "bar"], "tINTEGER": "1337"}`
+pub fn read_token_map(path: &Path) -> Result<HashMap<String, Vec<String>>, BisonError> {
+    let in_file = |message: String| {
+        let mut err = BisonError::new(None, message);
+        err.file = Some(path.to_path_buf());
+        return err;
+    };
+    let file = File::open(path).map_err(|e| in_file(e.to_string()))?;
+    let value: serde_json::Value =
+        serde_json::from_reader(file).map_err(|e| in_file(e.to_string()))?;
+    let object = match value.as_object() {
+        Some(object) => object,
+        None => return Err(in_file("the token map has to be a JSON object".to_string())),
+    };
+    let mut token_texts = HashMap::new();
+    for (token, texts) in object.iter() {
+        let texts = match texts {
+            &serde_json::Value::String(ref text) => vec![text.clone()],
+            &serde_json::Value::Array(ref texts) => texts
+                .iter()
+                .map(|text| text.as_str().map(|text| text.to_string()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| in_file(format!("the texts of {} have to be strings", token)))?,
+            _ => {
+                return Err(in_file(format!(
+                    "the texts of {} have to be a string or a list of strings",
+                    token
+                )))
+            }
+        };
+        token_texts.insert(token.clone(), texts);
+    }
+    return Ok(token_texts);
+}
+
+pub fn parse_file(path: &Path, options: &BisonOptions) -> Result<Grammar, BisonError> {
+    let mut src = String::new();
+    let in_file = |mut err: BisonError| {
+        err.file = Some(path.to_path_buf());
+        return err;
+    };
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut src))
+        .map_err(|e| in_file(BisonError::new(None, e.to_string())))?;
+    return parse_str(&src, options).map_err(in_file);
+}
+
+pub fn parse_str(src: &str, options: &BisonOptions) -> Result<Grammar, BisonError> {
+    let ast = parser::parse(src)?;
+    if ast.rules.is_empty() {
+        return Err(BisonError::new(None, "grammar does not contain any rules".to_string()));
+    }
+    let mut lowering = Lowering::new(&ast, options);
+    let start = match ast.start {
+        Some(ref start) => match lowering.rule_names.get(start) {
+            Some(name) => name.clone(),
+            None => {
+                return Err(BisonError::new(None, format!("start rule {} is not defined", start)))
+            }
+        },
+        None => lowering.rule_names[&ast.rules[0].name].clone(),
+    };
+    for rule in ast.rules.iter() {
+        let name = lowering.rule_names[&rule.name].clone();
+        let mut num_definitions = 0;
+        for alternative in rule.alternatives.iter() {
+            if let Some(def) = lowering.lower_alternative(alternative)? {
+                lowering.rules.push((name.clone(), def));
+                num_definitions += 1;
+            }
+        }
+        if num_definitions == 0 {
+            return Err(BisonError::new(
+                Some(rule.pos),
+                format!("every alternative of {} uses the error token", rule.name),
+            ));
+        }
+    }
+    let mut rules = lowering.rules;
+    rules.extend(lowering.token_rules);
+    return Ok(Grammar { start, rules });
+}
+
+//Turns a Bison name like `expr.list` or `tIDENT` into a nonterminal name like EXPR_LIST or TIDENT
+fn nonterm_name(name: &str) -> String {
+    let mut nonterm = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect::<String>();
+    if !nonterm.starts_with(|c: char| c.is_ascii_uppercase()) {
+        nonterm.insert_str(0, "NT_");
+    }
+    return nonterm;
+}
+
+struct Lowering<'a> {
+    options: &'a BisonOptions,
+    nonterminals: HashSet<String>,
+    //Bison rule name -> nonterminal
+    rule_names: HashMap<String, String>,
+    //Token name -> alias
+    aliases: HashMap<String, Option<String>>,
+    //Token name -> nonterminal, for the tokens that were used so far
+    token_names: HashMap<String, String>,
+    rules: Vec<(String, String)>,
+    //The rules of the tokens, which are placed behind all other rules
+    token_rules: Vec<(String, String)>,
+    backslash: Option<String>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(ast: &GrammarAst, options: &'a BisonOptions) -> Self {
+        let mut lowering = Lowering {
+            options,
+            nonterminals: HashSet::new(),
+            rule_names: HashMap::new(),
+            aliases: ast
+                .tokens
+                .iter()
+                .map(|token| (token.name.clone(), token.alias.clone()))
+                .collect(),
+            token_names: HashMap::new(),
+            rules: vec![],
+            token_rules: vec![],
+            backslash: None,
+        };
+        for rule in ast.rules.iter() {
+            let name = lowering.add_nonterm(&nonterm_name(&rule.name));
+            lowering.rule_names.insert(rule.name.clone(), name);
+        }
+        return lowering;
+    }
+
+    //Returns None if the alternative uses the error token
+    fn lower_alternative(&mut self, symbols: &[Symbol]) -> Result<Option<String>, BisonError> {
+        let mut parts = vec![];
+        for symbol in symbols.iter() {
+            let part = match symbol {
+                &Symbol::Ident(ref name, _) if name == ERROR_TOKEN => return Ok(None),
+                &Symbol::Ident(ref name, pos) => {
+                    if let Some(nonterm) = self.rule_names.get(name) {
+                        format!("{{{}}}", nonterm)
+                    } else if self.aliases.contains_key(name)
+                        || self.options.token_texts.contains_key(name)
+                    {
+                        self.token(name)
+                    } else {
+                        return Err(BisonError::new(
+                            Some(pos),
+                            format!("symbol {} is neither a rule nor a token", name),
+                        ));
+                    }
+                }
+                &Symbol::Char(ref text) => match self.options.token_texts.get(text) {
+                    Some(_) => self.token(text),
+                    None => self.terminal(text),
+                },
+                &Symbol::Str(ref alias, _) => {
+                    let token = self
+                        .aliases
+                        .iter()
+                        .find(|&(_, token_alias)| token_alias.as_ref() == Some(alias))
+                        .map(|(name, _)| name.clone());
+                    match token {
+                        Some(name) => self.token(&name),
+                        None if self.options.token_texts.contains_key(alias) => self.token(alias),
+                        None => self.terminal(alias),
+                    }
+                }
+            };
+            parts.push(part);
+        }
+        let separator = self.terminal(&self.options.separator.clone());
+        return Ok(Some(parts.join(&separator)));
+    }
+
+    //Returns a reference to the nonterminal of a token, which is added on first use
+    fn token(&mut self, name: &str) -> String {
+        if let Some(nonterm) = self.token_names.get(name) {
+            return format!("{{{}}}", nonterm);
+        }
+        let nonterm = self.add_nonterm(&nonterm_name(name));
+        self.token_names.insert(name.to_string(), nonterm.clone());
+        let alias = self.aliases.get(name).cloned().unwrap_or(None);
+        let texts = match self.options.token_texts.get(name) {
+            Some(texts) => texts.clone(),
+            None => match alias.as_ref().and_then(|alias| self.options.token_texts.get(alias)) {
+                Some(texts) => texts.clone(),
+                None => vec![alias.unwrap_or_else(|| name.to_string())],
+            },
+        };
+        for text in texts {
+            let def = self.terminal(&text);
+            self.token_rules.push((nonterm.clone(), def));
+        }
+        return format!("{{{}}}", nonterm);
+    }
+
+    fn terminal(&mut self, literal: &str) -> String {
+        return rule_format::literal_to_format(literal, || {
+            if self.backslash.is_none() {
+                let backslash = self.add_nonterm("BACKSLASH");
+                self.token_rules.push((backslash.clone(), "\\".to_string()));
+                self.backslash = Some(backslash);
+            }
+            self.backslash.clone().expect("RAND_597375230")
+        });
+    }
+
+    //Rules and tokens whose names only differ in case or punctuation get numbered: EXPR, EXPR2
+    fn add_nonterm(&mut self, base: &str) -> String {
+        let mut new_nonterm = base.to_string();
+        let mut i = 1;
+        while self.nonterminals.contains(&new_nonterm) {
+            i += 1;
+            new_nonterm = format!("{}{}", base, i);
+        }
+        self.nonterminals.insert(new_nonterm.clone());
+        return new_nonterm;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_for<'a>(grammar: &'a Grammar, nt: &str) -> Vec<&'a str> {
+        return grammar
+            .rules
+            .iter()
+            .filter(|&&(ref name, _)| name == nt)
+            .map(|&(_, ref format)| format.as_str())
+            .collect();
+    }
+
+    const CALC: &str = "\
+%token NUM
+%token tIDENT \"identifier\"
+%token kwLET \"let\"
+%left '+' '-'
+%%
+input: %empty | input line ;
+line: '\\n' | exp '\\n' { printf(\"%d\", $1); }
+    | \"let\" tIDENT '=' exp '\\n'
+    | error '\\n'
+    ;
+exp: NUM | tIDENT | exp '+' exp | exp '-' exp | '{' exp '}' | exp '\\\\' ;
+%%
+";
+
+    #[test]
+    fn check_lowering() {
+        let mut options = BisonOptions::new();
+        options
+            .token_texts
+            .insert("NUM".to_string(), vec!["0".to_string(), "1337".to_string()]);
+        let grammar = parse_str(CALC, &options).expect("RAND_3823741847");
+        assert_eq!(grammar.start, "INPUT");
+        assert_eq!(rules_for(&grammar, "INPUT"), vec!["", "{INPUT} {LINE}"]);
+        assert_eq!(
+            rules_for(&grammar, "LINE"),
+            vec!["\n", "{EXP} \n", "{KWLET} {TIDENT} = {EXP} \n"]
+        );
+        assert_eq!(
+            rules_for(&grammar, "EXP"),
+            vec![
+                "{NUM}",
+                "{TIDENT}",
+                "{EXP} + {EXP}",
+                "{EXP} - {EXP}",
+                "\\{ {EXP} \\}",
+                "{EXP} {BACKSLASH}",
+            ]
+        );
+        assert_eq!(rules_for(&grammar, "NUM"), vec!["0", "1337"]);
+        assert_eq!(rules_for(&grammar, "TIDENT"), vec!["identifier"]);
+        assert_eq!(rules_for(&grammar, "KWLET"), vec!["let"]);
+        assert_eq!(rules_for(&grammar, "BACKSLASH"), vec!["\\"]);
+        //The tokens come after the rules
+        assert_eq!(grammar.rules[11], ("KWLET".to_string(), "let".to_string()));
+    }
+
+    #[test]
+    fn check_names_and_options() {
+        let mut options = BisonOptions::new();
+        options.separator = "".to_string();
+        options
+            .token_texts
+            .insert("identifier".to_string(), vec!["x\\\\".to_string()]);
+        let grammar = parse_str(
+            "%token Expr tIDENT \"identifier\"\n%start expr\n%%\n\
+             stmt.list : expr ;\n\
+             expr : Expr tIDENT '.' _tail ;\n\
+             _tail : %empty ;",
+            &options,
+        ).expect("RAND_4053347072");
+        assert_eq!(grammar.start, "EXPR");
+        assert_eq!(rules_for(&grammar, "STMT_LIST"), vec!["{EXPR}"]);
+        assert_eq!(rules_for(&grammar, "EXPR"), vec!["{EXPR2}{TIDENT}.{NT__TAIL}"]);
+        assert_eq!(rules_for(&grammar, "EXPR2"), vec!["Expr"]);
+        assert_eq!(rules_for(&grammar, "TIDENT"), vec!["x{BACKSLASH}{BACKSLASH}"]);
+    }
+
+    #[test]
+    fn check_errors() {
+        let options = BisonOptions::new();
+        let err = parse_str("%%\nexpr : NUM ;", &options).expect_err("RAND_3810339397");
+        assert_eq!(err.to_string(), "2:8: symbol NUM is neither a rule nor a token");
+        let err = parse_str("%start prog\n%%\nexpr : ;", &options).expect_err("RAND_147269671");
+        assert_eq!(err.message, "start rule prog is not defined");
+        let err = parse_str("%%\nexpr : error ;", &options).expect_err("RAND_4158037889");
+        assert_eq!(err.pos, Some(Pos { line: 2, column: 1 }));
+    }
+}
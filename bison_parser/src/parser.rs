@@ -0,0 +1,288 @@
+use lexer::{tokenize, ParseError, Pos, Token};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarAst {
+    pub tokens: Vec<TokenDecl>,
+    //Set by `%start`
+    pub start: Option<String>,
+    pub rules: Vec<RuleAst>,
+}
+
+//A token declared by `%token` or one of the precedence declarations
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenDecl {
+    pub name: String,
+    //The string in `%token IF "if"`, which the rules can use instead of the name
+    pub alias: Option<String>,
+}
+
+//Rules with the same left-hand side are merged, so this holds all alternatives of a nonterminal
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleAst {
+    pub name: String,
+    pub alternatives: Vec<Vec<Symbol>>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Symbol {
+    //A nonterminal or a named token
+    Ident(String, Pos),
+    //A character token like '+'
+    Char(String),
+    //The alias of a token, like "if"
+    Str(String, Pos),
+}
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    idx: usize,
+}
+
+//Parses the declarations and the rules of a Yacc/Bison grammar. Actions, precedence
+//declarations and everything else that doesn't change the language are dropped.
+pub fn parse(src: &str) -> Result<GrammarAst, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        idx: 0,
+    };
+    let mut ast = GrammarAst {
+        tokens: vec![],
+        start: None,
+        rules: vec![],
+    };
+    parser.parse_declarations(&mut ast)?;
+    parser.parse_rules(&mut ast)?;
+    return Ok(ast);
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> &Token {
+        //tokenize always ends the list with Eof
+        let last = self.tokens.len() - 1;
+        return &self.tokens[(self.idx + offset).min(last)].0;
+    }
+
+    fn pos(&self) -> Pos {
+        return self.tokens[self.idx].1;
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.peek(0).clone();
+        if self.idx < self.tokens.len() - 1 {
+            self.idx += 1;
+        }
+        return token;
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        return Err(ParseError {
+            pos: self.pos(),
+            message: message.to_string(),
+        });
+    }
+
+    fn parse_declarations(&mut self, ast: &mut GrammarAst) -> Result<(), ParseError> {
+        loop {
+            match self.peek(0).clone() {
+                Token::Separator => {
+                    self.bump();
+                    return Ok(());
+                }
+                Token::Code | Token::Semi => {
+                    self.bump();
+                }
+                Token::Directive(name) => {
+                    self.bump();
+                    match name.as_str() {
+                        "token" | "left" | "right" | "nonassoc" | "precedence" => {
+                            self.parse_token_list(ast)
+                        }
+                        "start" => match self.peek(0).clone() {
+                            Token::Ident(start) => {
+                                self.bump();
+                                ast.start = Some(start);
+                            }
+                            _ => return self.error("expected the name of the start rule"),
+                        },
+                        //Everything up to the next directive belongs to this one
+                        _ => while !self.at_declaration_end() {
+                            self.bump();
+                        },
+                    }
+                }
+                Token::Eof => return self.error("expected %% before the rules"),
+                _ => return self.error("expected a declaration"),
+            }
+        }
+    }
+
+    fn at_declaration_end(&self) -> bool {
+        return matches!(self.peek(0), &Token::Directive(_) | &Token::Separator | &Token::Eof);
+    }
+
+    //`%token <type> NAME 258 "alias" OTHER ...`
+    fn parse_token_list(&mut self, ast: &mut GrammarAst) {
+        //The index of the token an alias belongs to
+        let mut last = None;
+        while !self.at_declaration_end() {
+            match self.bump() {
+                Token::Ident(name) => {
+                    last = ast.tokens.iter().position(|token| token.name == name);
+                    if last.is_none() {
+                        last = Some(ast.tokens.len());
+                        ast.tokens.push(TokenDecl { name, alias: None });
+                    }
+                }
+                //The token number doesn't change the language
+                Token::Int => {}
+                Token::Str(alias) => {
+                    if let Some(i) = last.take() {
+                        ast.tokens[i].alias = Some(alias);
+                    }
+                }
+                _ => last = None,
+            }
+        }
+    }
+
+    //Whether the next tokens start a new rule, i.e. are `name :` or `name [ref] :`
+    fn at_rule_start(&self) -> bool {
+        return matches!(
+            (self.peek(0), self.peek(1), self.peek(2)),
+            (&Token::Ident(_), &Token::Colon, _) | (&Token::Ident(_), &Token::Name, &Token::Colon)
+        );
+    }
+
+    fn parse_rules(&mut self, ast: &mut GrammarAst) -> Result<(), ParseError> {
+        loop {
+            match self.peek(0) {
+                &Token::Separator | &Token::Eof => return Ok(()),
+                &Token::Semi => {
+                    self.bump();
+                    continue;
+                }
+                _ => {}
+            }
+            if !self.at_rule_start() {
+                return self.error("expected a rule");
+            }
+            let pos = self.pos();
+            let name = match self.bump() {
+                Token::Ident(name) => name,
+                _ => unreachable!(),
+            };
+            if *self.peek(0) == Token::Name {
+                self.bump();
+            }
+            self.bump();
+            let mut alternatives = vec![self.parse_alternative()?];
+            while *self.peek(0) == Token::Pipe {
+                self.bump();
+                alternatives.push(self.parse_alternative()?);
+            }
+            match ast.rules.iter_mut().find(|rule| rule.name == name) {
+                Some(rule) => rule.alternatives.extend(alternatives),
+                None => ast.rules.push(RuleAst {
+                    name,
+                    alternatives,
+                    pos,
+                }),
+            }
+        }
+    }
+
+    fn parse_alternative(&mut self) -> Result<Vec<Symbol>, ParseError> {
+        let mut symbols = vec![];
+        loop {
+            if self.at_rule_start() {
+                return Ok(symbols);
+            }
+            let pos = self.pos();
+            match self.peek(0).clone() {
+                Token::Pipe | Token::Semi | Token::Separator | Token::Eof => return Ok(symbols),
+                Token::Ident(name) => symbols.push(Symbol::Ident(name, pos)),
+                Token::Char(text) => symbols.push(Symbol::Char(text)),
+                Token::Str(text) => symbols.push(Symbol::Str(text, pos)),
+                //Actions, the types of mid-rule actions and named references
+                Token::Code | Token::Tag | Token::Name => {}
+                Token::Directive(ref name) => match name.as_str() {
+                    "empty" => {}
+                    //The argument is the precedence token, the priority or the merge function
+                    "prec" | "dprec" | "merge" => {
+                        self.bump();
+                    }
+                    _ => return self.error(&format!("unexpected %{} in a rule", name)),
+                },
+                _ => return self.error("unexpected symbol in a rule"),
+            }
+            self.bump();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_grammar_syntax() {
+        let ast = parse(
+            "%{\n#include <stdio.h>\n%}\n\
+             %union { int num; }\n\
+             %define api.pure full\n\
+             %token <num> tINTEGER 258 \"integer\" keyword_if \"if\"\n\
+             %left '+' tPLUS\n\
+             %type <num> expr\n\
+             %start program\n\
+             %%\n\
+             program: stmts\n\
+             stmts : %empty | stmts[s] stmt { $$ = $1; }\n\
+             stmt: keyword_if expr ';' | expr %prec tPLUS ;\n\
+             expr : tINTEGER | expr '+' <num>{ $$ = 0; } expr | \"if\";\n\
+             stmts: error\n\
+             %%\n\
+             int main() {}",
+        ).expect("RAND_3384950358");
+        assert_eq!(ast.start, Some("program".to_string()));
+        assert_eq!(
+            ast.tokens,
+            vec![
+                TokenDecl {
+                    name: "tINTEGER".to_string(),
+                    alias: Some("integer".to_string()),
+                },
+                TokenDecl {
+                    name: "keyword_if".to_string(),
+                    alias: Some("if".to_string()),
+                },
+                TokenDecl {
+                    name: "tPLUS".to_string(),
+                    alias: None,
+                },
+            ]
+        );
+        let names = ast.rules.iter().map(|rule| rule.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["program", "stmts", "stmt", "expr"]);
+        assert_eq!(ast.rules[1].alternatives.len(), 3);
+        assert_eq!(ast.rules[1].alternatives[0], vec![]);
+        assert_eq!(ast.rules[1].pos, Pos { line: 12, column: 1 });
+        assert_eq!(
+            ast.rules[3].alternatives[1],
+            vec![
+                Symbol::Ident("expr".to_string(), Pos { line: 14, column: 19 }),
+                Symbol::Char("+".to_string()),
+                Symbol::Ident("expr".to_string(), Pos { line: 14, column: 45 }),
+            ]
+        );
+        assert_eq!(ast.rules[2].alternatives[1].len(), 1);
+    }
+
+    #[test]
+    fn check_parse_errors() {
+        let err = parse("%token A\nexpr : A ;").expect_err("RAND_881391785");
+        assert_eq!(err.to_string(), "2:11: expected %% before the rules");
+        let err = parse("%%\nexpr : A %foo B ;").expect_err("RAND_995851830");
+        assert_eq!(err.pos, Pos { line: 2, column: 10 });
+    }
+}
@@ -0,0 +1,73 @@
+//Stand-in for a target built with an AFL compiler, used by the tests of forksrv::afl. It
+//implements the forkserver side of the protocol by hand. In "options" mode it announces a larger
+//map and an autodictionary like an AFL++ runtime does, in "shdmem" mode it offers to take the
+//inputs from shared memory and falls back to stdin if the fuzzer declines.
+#include<signal.h>
+#include<stdio.h>
+#include<stdint.h>
+#include<stdlib.h>
+#include<string.h>
+#include<sys/shm.h>
+#include<sys/wait.h>
+#include<unistd.h>
+
+#define FORKSRV_FD 198
+#define FS_OPT_ENABLED 0x80000001
+#define FS_OPT_MAPSIZE 0x40000000
+#define FS_OPT_SHDMEM_FUZZ 0x01000000
+#define FS_OPT_AUTODICT 0x10000000
+#define FS_OPT_SET_MAPSIZE(x) (((x)-1) << 1)
+
+static uint8_t* trace_bits;
+static uint32_t map_size = 1 << 16;
+
+static void forkserver(const char* mode){
+  uint32_t msg = 0;
+  if (strcmp(mode, "options") == 0) {
+    map_size = 1 << 17;
+    msg = FS_OPT_ENABLED | FS_OPT_MAPSIZE | FS_OPT_SET_MAPSIZE(map_size) | FS_OPT_AUTODICT;
+  }
+  if (strcmp(mode, "shdmem") == 0) msg = FS_OPT_ENABLED | FS_OPT_SHDMEM_FUZZ;
+  if (write(FORKSRV_FD + 1, &msg, 4) != 4) exit(1);
+  if (msg != 0) {
+    //we offered a dictionary or shared memory, the fuzzer answers what it wants. A real runtime
+    //would map the input shared memory if FS_OPT_SHDMEM_FUZZ was set, we always read stdin.
+    if (read(FORKSRV_FD, &msg, 4) != 4) exit(1);
+    if (msg != FS_OPT_ENABLED) exit(1);
+  }
+  while (1) {
+    uint32_t was_killed;
+    int status;
+    if (read(FORKSRV_FD, &was_killed, 4) != 4) exit(0);
+    pid_t child = fork();
+    if (child < 0) exit(1);
+    if (child == 0) {
+      close(FORKSRV_FD);
+      close(FORKSRV_FD + 1);
+      return;
+    }
+    if (write(FORKSRV_FD + 1, &child, 4) != 4) exit(1);
+    if (waitpid(child, &status, 0) < 0) exit(1);
+    if (write(FORKSRV_FD + 1, &status, 4) != 4) exit(1);
+  }
+}
+
+int main(int argc, char** argv){
+  char input[16] = {0};
+  char* shm_id = getenv("__AFL_SHM_ID");
+  if (!shm_id || argc < 2) return 1;
+  trace_bits = shmat(atoi(shm_id), NULL, 0);
+  if (trace_bits == (void*)-1) return 1;
+  forkserver(argv[1]);
+
+  if (read(0, input, sizeof(input) - 1) < 0) return 1;
+  trace_bits[1] = 1;
+  if (input[0] == 'a') trace_bits[2] = 1;
+  if (strcmp(input, "crash") == 0) {
+    fprintf(stderr, "crashing\n");
+    abort();
+  }
+  if (strcmp(input, "hang") == 0) sleep(10);
+  trace_bits[map_size - 1] = 1;
+  return 0;
+}
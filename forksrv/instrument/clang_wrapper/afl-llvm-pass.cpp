@@ -82,6 +82,15 @@ bool AFLCoverage::runOnModule(Module &M) {
                          GlobalValue::ExternalLinkage, 0, "__rofl_area_ptr");
   }
 
+  /* Edge ids are smaller than the map size, the runtime makes sure its map is at least as large */
+
+  unsigned int map_size = ROFL_MAP_SIZE;
+  char *map_size_str = getenv("ROFL_MAP_SIZE");
+  if (map_size_str) map_size = rofl_map_size(strtoull(map_size_str, NULL, 0));
+
+  new GlobalVariable(M, Int32Ty, true, GlobalValue::WeakAnyLinkage,
+                     ConstantInt::get(Int32Ty, map_size), "__rofl_compiled_map_size");
+
   GlobalVariable *AFLPrevLoc = new GlobalVariable(
       M, Int32Ty, false, GlobalValue::ExternalLinkage, 0, "__rofl_prev_loc",
       0, GlobalVariable::GeneralDynamicTLSModel, 0, false);
@@ -99,7 +108,7 @@ bool AFLCoverage::runOnModule(Module &M) {
 
       /* Make up cur_loc */
 
-      unsigned int cur_loc = rand()%ROFL_MAP_SIZE;
+      unsigned int cur_loc = rand()%map_size;
       ConstantInt *CurLoc = ConstantInt::get(Int32Ty, cur_loc);
 
       /* Load prev_loc */
@@ -0,0 +1,51 @@
+//Target for the tests of forksrv, built together with rt/common.c. The first byte of the bitmap
+//counts the runs done by the current child, the next two are the first byte of the input read
+//from stdin and the length returned by __rofl_input. Some inputs write to stdout and stderr.
+#include<signal.h>
+#include<stdio.h>
+#include<stdlib.h>
+#include<string.h>
+#include<unistd.h>
+
+#include "rt/runtime.h"
+
+int main(){
+  unsigned int runs = 0;
+  char input[16];
+  while(__ROFL_LOOP(1000)){
+    size_t len = 0;
+    memset(input, 0, sizeof(input));
+    if(fread(input, 1, sizeof(input) - 1, stdin) == 0) return 1;
+    runs++;
+    __rofl_area_ptr[0] = runs;
+    __rofl_area_ptr[1] = input[0];
+    if(__rofl_input(&len)) __rofl_area_ptr[2] = len;
+    if(strcmp(input, "print") == 0){
+      printf("out\n");
+      fflush(stdout);
+      fprintf(stderr, "err\n");
+    }
+    if(strcmp(input, "spam") == 0){
+      char line[1024];
+      memset(line, 'x', sizeof(line));
+      line[sizeof(line) - 1] = '\n';
+      //more than fits into a pipe, every write has to succeed
+      for(int i = 0; i < 256; i++) if(write(2, line, sizeof(line)) != sizeof(line)) abort();
+      fprintf(stderr, "end\n");
+    }
+    if(strcmp(input, "crash") == 0){
+      fprintf(stderr, "crashing\n");
+      abort();
+    }
+    if(strcmp(input, "hang") == 0) sleep(10);
+    if(strcmp(input, "stuck") == 0){
+      //the timer signal of the runtime never arrives, only the watchdog of the fuzzer helps
+      sigset_t set;
+      sigemptyset(&set);
+      sigaddset(&set, SIGALRM);
+      sigprocmask(SIG_BLOCK, &set, NULL);
+      sleep(10);
+    }
+  }
+  return 0;
+}
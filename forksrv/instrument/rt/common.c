@@ -5,29 +5,86 @@
 #include <fcntl.h> //Specified in man 2 open
 #include <errno.h>
 #include <sys/mman.h>
+#include <sys/stat.h>
 #include <stdlib.h> 
 #include <string.h>
 #include <sys/wait.h>
 #include <sys/time.h>
+#ifdef __linux__
+#include <sys/prctl.h>
+#endif
 #include <stdio.h>
 
 #include "runtime_types.h"
 
 feedback_data_t* rofl_feedback_data;
+rofl_input_t* rofl_input;
+//replaces stdin if the input is delivered through shared memory
+static FILE* __rofl_input_stream = NULL;
+//the original stdin, reads the input file for inputs that don't fit into the shared memory
+static FILE* __rofl_file_stdin = NULL;
 
 //so other constructors don't fail before we run our setup
-uint8_t __rofl_pre_init_bitmap[ROFL_MAP_SIZE]; 
+uint8_t __rofl_pre_init_bitmap[ROFL_MAX_MAP_SIZE]; 
 uint8_t* __rofl_area_ptr = __rofl_pre_init_bitmap;
+uint32_t __rofl_map_size = ROFL_MAP_SIZE;
+//defined by the instrumentation pass, edge ids of the target are smaller than this
+extern const uint32_t __rofl_compiled_map_size __attribute__((weak));
 
 __thread uint32_t __rofl_prev_loc;
 
+//wall clock time a single run may take, set by the fuzzer
+static long __rofl_timeout_ms = 70;
+//set if the fuzzer lets __ROFL_LOOP run several inputs in the same child
+static int __rofl_persistent = 0;
+//set by SIGUSR1, the fuzzer wants a fresh child instead of the persistent one
+static volatile sig_atomic_t __rofl_restart_child = 0;
+
 void __rofl_init(){
-  memset(__rofl_area_ptr, 0, ROFL_MAP_SIZE);
+  memset(__rofl_area_ptr, 0, __rofl_map_size);
   __rofl_prev_loc = 0;
 }
 
+const uint8_t* __rofl_input(size_t* len){
+  if(!rofl_input || rofl_input->len == ROFL_INPUT_IN_FILE){
+    return NULL;
+  }
+  *len = rofl_input->len;
+  return rofl_input->data;
+}
+
+static void __rofl_open_input(){
+  if(!rofl_input){
+    return;
+  }
+  if(__rofl_input_stream){
+    fclose(__rofl_input_stream);
+    __rofl_input_stream = NULL;
+  }
+  if(rofl_input->len == ROFL_INPUT_IN_FILE){
+    stdin = __rofl_file_stdin;
+    fseek(stdin, 0, SEEK_SET);
+    clearerr(stdin);
+    return;
+  }
+  //older glibc versions don't support empty memory streams
+  if(rofl_input->len){
+    __rofl_input_stream = fmemopen(rofl_input->data, rofl_input->len, "r");
+  } else {
+    __rofl_input_stream = fopen("/dev/null", "r");
+  }
+  stdin = __rofl_input_stream;
+}
+
+static void __rofl_clear_feedback(){
+  rofl_feedback_data->magic = 0;
+  rofl_feedback_data->status = 0;
+  memset(rofl_feedback_data->run_bitmap, 0x0, __rofl_map_size);
+}
+
 void __rofl_reset(){
-    memset(rofl_feedback_data, 0x0, sizeof(feedback_data_t));
+    __rofl_clear_feedback();
+    __rofl_open_input();
     if(getenv("ROFL_OUT_PATH")){
       int fd = fileno(fopen(getenv("ROFL_OUT_PATH"),"w+"));
       dup2(fd, 1);
@@ -39,10 +96,58 @@ void __rofl_reset(){
     }
 }
 
+//trigger timeout signal (SIGALRM) after timer expires, also while blocked in a syscall. 0 disarms
+//the timer
+static void __rofl_set_timer(long timeout_ms){
+  struct itimerval timer;
+  timer.it_value.tv_sec     = timeout_ms / 1000;
+  timer.it_value.tv_usec    = (timeout_ms % 1000) * 1000;
+  timer.it_interval.tv_sec  = 0;
+  timer.it_interval.tv_usec = 0;
+  setitimer (ITIMER_REAL, &timer, NULL);
+}
+
+static void __rofl_handle_restart(int sig){
+  __rofl_restart_child = 1;
+}
+
+//Used through __ROFL_LOOP. The first call returns 1 for the input the child was forked for. Every
+//further call reports the last run as finished, stops the child until the fuzzer provides the
+//next input and returns 1 again. Returns 0 after max runs or if the fuzzer doesn't use persistent
+//mode, so the target exits and the forkserver forks a fresh child.
+int __rofl_persistent_loop(unsigned int max){
+  static unsigned int runs = 0;
+  if(runs == 0){
+    runs = 1;
+    return 1;
+  }
+  if(!__rofl_persistent || runs >= max){
+    return 0;
+  }
+  runs++;
+  __rofl_set_timer(0);
+  raise(SIGSTOP);
+  __rofl_set_timer(__rofl_timeout_ms);
+  __rofl_clear_feedback();
+  __rofl_prev_loc = 0;
+  if(rofl_input){
+    __rofl_open_input();
+  } else {
+    //the fuzzer rewrote the input file
+    fseek(stdin, 0, SEEK_SET);
+    clearerr(stdin);
+  }
+  return 1;
+}
+
 uint8_t* get_shm(size_t size){
     if(getenv("ROFL_SHM_FD") != NULL){
       int shm_fd = atoi(getenv("ROFL_SHM_FD"));
-      ftruncate(shm_fd, size);
+      struct stat st;
+      //the fuzzer maps the file with the size it asked for, it must not shrink
+      if(fstat(shm_fd, &st) != 0 || st.st_size < size){
+        ftruncate(shm_fd, size);
+      }
       void* addr = NULL;
       void* shm = mmap(addr, size, PROT_READ | PROT_WRITE, MAP_SHARED, shm_fd, 0);
       if(shm == (void*)-1){
@@ -58,45 +163,94 @@ uint8_t* get_shm(size_t size){
 void __rofl_forkserver(){
     fsync(0);
     if(getenv("ROFL_SHM_FD")){
+      if(getenv("ROFL_TIMEOUT_MS")){
+        __rofl_timeout_ms = atol(getenv("ROFL_TIMEOUT_MS"));
+      }
+      if(getenv("ROFL_PERSISTENT")){
+        __rofl_persistent = 1;
+        signal(SIGUSR1, __rofl_handle_restart);
+      }
       printf("running forkserver\n");
+      if(!getenv("ROFL_OUT_PATH")){
+        //stdout is captured by the fuzzer, don't leave the message in the buffer every child
+        //inherits and flushes into the output of its run
+        fflush(stdout);
+      }
+      //persistent child that waits for the next input, 0 if there is none
+      int pid = 0;
       while(1){
-        uint8_t buffer;
-        int pid;
+        int status;
         //stop ourself, so that the forkserver can continue us when needed
         kill(getpid(),  SIGSTOP);
 
-        //fork the next running instance
-        if((pid = fork()) < 0) {
+        if(pid && __rofl_restart_child){
+          kill(pid, SIGKILL);
+          waitpid(pid, &status, 0);
+          pid = 0;
+        }
+        __rofl_restart_child = 0;
+
+        if(pid){
+          kill(pid, SIGCONT);
+        } else if((pid = fork()) < 0) {
             fprintf(stderr, "Could not fork... %s\n", strerror(errno));
+            pid = 0;
+            continue;
         } else if(pid == 0) {
-             struct itimerval timer;
-             //trigger timeout signal after timer expires (20 ms)
-             timer.it_value.tv_sec     = 0;
-             timer.it_value.tv_usec    = 70000;
-             timer.it_interval.tv_sec  = 0;
-             timer.it_interval.tv_usec = 0;
-             setitimer (ITIMER_VIRTUAL, &timer, NULL);
+            signal(SIGUSR1, SIG_DFL);
+#ifdef __linux__
+            //a stopped persistent child would outlive a killed forkserver forever
+            if(__rofl_persistent){
+              prctl(PR_SET_PDEATHSIG, SIGKILL);
+            }
+#endif
+            __rofl_set_timer(__rofl_timeout_ms);
             __rofl_reset();
             return;
+        }
+        /* Elternprozess */
+        waitpid(pid, &status, WUNTRACED);
+        if(WIFSTOPPED(status)){
+          //the persistent child finished a run and waits for the next input
+          status = 0;
         } else {
-          /* Elternprozess */
-          int status;
-          waitpid(pid, &status, 0);
-          rofl_feedback_data->magic = 0x5a5a55464c464f52; //"ROFLFUZZ"
-          rofl_feedback_data->status = status;
+          pid = 0;
         }
+        rofl_feedback_data->magic = 0x5a5a55464c464f52; //"ROFLFUZZ"
+        rofl_feedback_data->status = status;
       }
     }
 }
 
 void __attribute__ ((constructor)) get_shm_autorun(){
-  size_t size = sizeof(feedback_data_t);
+  if(getenv("ROFL_MAP_SIZE")){
+    __rofl_map_size = rofl_map_size(strtoull(getenv("ROFL_MAP_SIZE"), NULL, 0));
+  }
+  if(&__rofl_compiled_map_size && __rofl_compiled_map_size > __rofl_map_size){
+    __rofl_map_size = __rofl_compiled_map_size;
+  }
+  size_t size = sizeof(feedback_data_t) + __rofl_map_size;
   uint8_t* shm = get_shm(size);
   if(!shm){ 
     shm = malloc(size);
   }
 
   rofl_feedback_data = (feedback_data_t*)shm;
+  rofl_feedback_data->map_size = __rofl_map_size;
+
+  if(getenv("ROFL_INPUT_SHM_FD")){
+    int input_fd = atoi(getenv("ROFL_INPUT_SHM_FD"));
+    struct stat st;
+    if(fstat(input_fd, &st) == 0){
+      void* input = mmap(NULL, st.st_size, PROT_READ | PROT_WRITE, MAP_SHARED, input_fd, 0);
+      if(input != (void*)-1){
+        rofl_input = (rofl_input_t*)input;
+        __rofl_file_stdin = stdin;
+      } else {
+        fprintf(stderr, "Could not mmap input... %s\n", strerror(errno));
+      }
+    }
+  }
   __rofl_area_ptr = &(rofl_feedback_data->run_bitmap[0]);
 
   __rofl_init();
@@ -6,6 +6,18 @@
 extern feedback_data_t* redqueen_feedback_data;
 
 extern uint8_t* __rofl_area_ptr;
+extern uint32_t __rofl_map_size;
 extern __thread uint32_t __rofl_prev_loc;
 
+int __rofl_persistent_loop(unsigned int max);
+
+//Input of the current run if the fuzzer delivers it through shared memory, NULL otherwise. stdin
+//reads from the same buffer in that case. Inputs too large for the shared memory are only
+//available from stdin.
+const uint8_t* __rofl_input(size_t* len);
+
+//Persistent mode: while(__ROFL_LOOP(1000)){ read stdin and process it } runs up to 1000 inputs
+//in the same process. The state of the target has to be reset at the start of every iteration.
+#define __ROFL_LOOP(max) __rofl_persistent_loop(max)
+
 #endif
@@ -1,6 +1,24 @@
 #ifndef __GUARD_ROFL_CONFIG__
 #define __GUARD_ROFL_CONFIG__
 
+#include <stdint.h>
+
+//default size of the coverage map, both the fuzzer (ROFL_MAP_SIZE in the environment of the
+//target) and the instrumentation (ROFL_MAP_SIZE in the environment of the compiler) can ask for a
+//different one
 #define ROFL_MAP_SIZE (1<<15)
+#define ROFL_MAX_MAP_SIZE (1<<24)
+
+//map sizes are powers of two, so that the edge ids (cur_loc ^ prev_loc >> 1) stay in the map
+static inline uint32_t rofl_map_size(uint64_t requested){
+  uint32_t size = 1;
+  if(requested == 0){
+    return ROFL_MAP_SIZE;
+  }
+  while(size < requested && size < ROFL_MAX_MAP_SIZE){
+    size <<= 1;
+  }
+  return size;
+}
 
 #endif
@@ -5,14 +5,26 @@
 #include "./runtime_config.h"
 
 //one instance of this struct is mapped to shared memory for communication
-//content of this struct will be memset to zero before each run
+//content of this struct except for map_size will be memset to zero before each run
 
 typedef struct feedback_data_s{
-  uint8_t run_bitmap[ROFL_MAP_SIZE];
   uint64_t magic;
-  int status;
+  int32_t status;
+  //size of run_bitmap, reported to the fuzzer by the runtime
+  uint32_t map_size;
+  uint8_t run_bitmap[];
 } feedback_data_t;
 
+//if the fuzzer delivers inputs through shared memory, an instance of this struct is mapped from
+//ROFL_INPUT_SHM_FD. It holds the input of the current run
+typedef struct rofl_input_s{
+  uint64_t len;
+  uint8_t data[];
+} rofl_input_t;
+
+//len of inputs that don't fit into the shared memory, they are in the input file (stdin) instead
+#define ROFL_INPUT_IN_FILE UINT64_MAX
+
 void __afl_init();
 void __afl_forkserver();
 void __afl_reset();
@@ -0,0 +1,433 @@
+//Backend for targets built with afl-clang-fast, afl-gcc and friends. Their runtime maps the
+//coverage map from the SysV shared memory named by __AFL_SHM_ID, reads commands from fd 198 and
+//reports the pid and the status of every run on fd 199.
+
+use libc;
+use nix::fcntl::*;
+use nix::sys::signal::kill;
+use nix::sys::signal::Signal::SIGKILL;
+use nix::sys::wait::*;
+use nix::unistd::*;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::process;
+use std::ptr;
+use std::slice;
+
+use error::{descr_err, SpawnError, SubprocessError};
+use exitreason::ExitReason;
+use exec_args;
+use wait_readable;
+use ForkServerOptions;
+use OutputCapture;
+use tempfile;
+
+//Size of the coverage map if the target doesn't tell us otherwise
+pub const AFL_MAP_SIZE: usize = 1 << 16;
+
+//The forkserver reads from FORKSRV_FD and writes to FORKSRV_FD + 1
+const FORKSRV_FD: i32 = 198;
+
+//Options of the AFL++ handshake
+const FS_OPT_ENABLED: u32 = 0x80000001;
+const FS_OPT_MAPSIZE: u32 = 0x40000000;
+const FS_OPT_SHDMEM_FUZZ: u32 = 0x01000000;
+const FS_OPT_AUTODICT: u32 = 0x10000000;
+
+fn fs_opt_get_mapsize(status: u32) -> usize {
+    return (((status & 0x00fffffe) >> 1) + 1) as usize;
+}
+
+#[derive(Debug)]
+pub struct AflForkServer {
+    child_pid: i32,
+    ctl_fd: i32,
+    st_fd: i32,
+    shm_id: i32,
+    trace_bits: *mut u8,
+    map_size: usize,
+    inp_file: tempfile::NamedTempFile,
+    stdout_capture: Option<OutputCapture>,
+    stderr_capture: Option<OutputCapture>,
+    pub out_path: String,
+    pub err_path: String,
+    pub timeout_ms: u64,
+    last_run_killed: bool,
+}
+
+impl AflForkServer {
+    //See ForkServer::new, only timeout_ms and capture_output of the options are used
+    pub fn new(
+        path: &String,
+        args: &Vec<String>,
+        out_path: String,
+        err_path: String,
+        options: &ForkServerOptions,
+    ) -> Result<Self, SubprocessError> {
+        let inp_file = tempfile::NamedTempFile::new()?;
+        let (stdout_capture, stderr_capture) = if options.capture_output {
+            (Some(OutputCapture::new()?), Some(OutputCapture::new()?))
+        } else {
+            (None, None)
+        };
+        let (shm_id, trace_bits) = AflForkServer::create_shm(AFL_MAP_SIZE)?;
+        let mut res = AflForkServer {
+            child_pid: 0,
+            ctl_fd: -1,
+            st_fd: -1,
+            shm_id,
+            trace_bits,
+            map_size: AFL_MAP_SIZE,
+            inp_file,
+            stdout_capture,
+            stderr_capture,
+            out_path,
+            err_path,
+            timeout_ms: options.timeout_ms,
+            last_run_killed: false,
+        };
+        if let Some(map_size) = res.start(path, args)? {
+            //the target uses a larger map than we provided, so start it again with one that fits
+            res.stop();
+            AflForkServer::remove_shm(res.shm_id, res.trace_bits);
+            let (shm_id, trace_bits) = AflForkServer::create_shm(map_size)?;
+            res.shm_id = shm_id;
+            res.trace_bits = trace_bits;
+            res.map_size = map_size;
+            if res.start(path, args)?.is_some() {
+                return descr_err("target changed its map size");
+            }
+        }
+        //the target has attached the map, so it goes away with the last process using it, even if
+        //we never get to drop the forkserver
+        unsafe {
+            libc::shmctl(res.shm_id, libc::IPC_RMID, ptr::null_mut());
+        }
+        return Ok(res);
+    }
+
+    //The coverage map of the last run
+    pub fn bitmap(&self) -> &[u8] {
+        unsafe { return slice::from_raw_parts(self.trace_bits, self.map_size) }
+    }
+
+    pub fn bitmap_mut(&mut self) -> &mut [u8] {
+        unsafe { return slice::from_raw_parts_mut(self.trace_bits, self.map_size) }
+    }
+
+    //Sets the input of the following runs
+    pub fn set_input<I: AsRef<[u8]>>(&mut self, data: &I) -> Result<(), SubprocessError> {
+        let mut inp_file = self.inp_file.as_file();
+        inp_file.set_len(0)?;
+        inp_file.seek(SeekFrom::Start(0))?;
+        inp_file.write_all(data.as_ref())?;
+        return Ok(());
+    }
+
+    //What the last run wrote to stdout, empty if the output isn't captured
+    pub fn last_stdout(&mut self) -> Result<&[u8], SubprocessError> {
+        match self.stdout_capture {
+            Some(ref mut capture) => return capture.output(),
+            None => return Ok(&[]),
+        }
+    }
+
+    //What the last run wrote to stderr, e.g. the report of a sanitizer
+    pub fn last_stderr(&mut self) -> Result<&[u8], SubprocessError> {
+        match self.stderr_capture {
+            Some(ref mut capture) => return capture.output(),
+            None => return Ok(&[]),
+        }
+    }
+
+    pub fn run_on<I: AsRef<[u8]>>(&mut self, data: &I) -> Result<ExitReason, SubprocessError> {
+        self.set_input(data)?;
+        return self.run();
+    }
+
+    //Like run_on, with the input of the last run
+    pub fn run(&mut self) -> Result<ExitReason, SubprocessError> {
+        unsafe {
+            ptr::write_bytes(self.trace_bits, 0, self.map_size);
+        }
+        self.inp_file.as_file().seek(SeekFrom::Start(0))?;
+        for capture in self.stdout_capture.iter_mut().chain(self.stderr_capture.iter_mut()) {
+            capture.clear()?;
+        }
+        write_u32(self.ctl_fd, self.last_run_killed as u32)?;
+        self.last_run_killed = false;
+        let pid = read_u32(self.st_fd)? as i32;
+        if pid <= 0 {
+            return descr_err("forkserver failed to fork");
+        }
+        if !wait_readable(self.st_fd, self.timeout_ms)? {
+            let _ = kill(pid, SIGKILL);
+            self.last_run_killed = true;
+            read_u32(self.st_fd)?;
+            return Ok(ExitReason::Timeouted);
+        }
+        let status = read_u32(self.st_fd)?;
+        return Ok(ExitReason::from_int(status as i32));
+    }
+
+    //private functions
+
+    fn create_shm(size: usize) -> Result<(i32, *mut u8), SubprocessError> {
+        let shm_id = unsafe {
+            libc::shmget(
+                libc::IPC_PRIVATE,
+                size,
+                libc::IPC_CREAT | libc::IPC_EXCL | 0o600,
+            )
+        };
+        if shm_id < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let trace_bits = unsafe { libc::shmat(shm_id, ptr::null(), 0) };
+        if trace_bits as isize == -1 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::shmctl(shm_id, libc::IPC_RMID, ptr::null_mut());
+            }
+            return Err(err.into());
+        }
+        return Ok((shm_id, trace_bits as *mut u8));
+    }
+
+    fn remove_shm(shm_id: i32, trace_bits: *mut u8) {
+        unsafe {
+            libc::shmdt(trace_bits as *const libc::c_void);
+            libc::shmctl(shm_id, libc::IPC_RMID, ptr::null_mut());
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.child_pid > 0 {
+            let _ = kill(-self.child_pid, SIGKILL);
+            let _ = waitpid(self.child_pid, None);
+            self.child_pid = 0;
+        }
+        for fd in &[self.ctl_fd, self.st_fd] {
+            if *fd >= 0 {
+                let _ = close(*fd);
+            }
+        }
+        self.ctl_fd = -1;
+        self.st_fd = -1;
+    }
+
+    //Starts the forkserver and does the handshake. Returns the map size the target asked for, if
+    //it is larger than the current map.
+    fn start(
+        &mut self,
+        path: &String,
+        args: &Vec<String>,
+    ) -> Result<Option<usize>, SubprocessError> {
+        let (ctl_read, ctl_write) = pipe()?;
+        let (st_read, st_write) = pipe()?;
+        let out_file = match self.stdout_capture {
+            Some(ref capture) => capture.as_file().try_clone()?,
+            None => File::create(&self.out_path)?,
+        };
+        let err_file = match self.stderr_capture {
+            Some(ref capture) => capture.as_file().try_clone()?,
+            None => File::create(&self.err_path)?,
+        };
+        match fork().expect("fork failed") {
+            ForkResult::Parent { child } => {
+                close(ctl_read)?;
+                close(st_write)?;
+                self.child_pid = child;
+                self.ctl_fd = ctl_write;
+                self.st_fd = st_read;
+            }
+            ForkResult::Child => {
+                let _ = close(ctl_write);
+                let _ = close(st_read);
+                let res = self.run_process(
+                    path,
+                    args,
+                    (ctl_read, st_write),
+                    &out_file,
+                    &err_file,
+                );
+                print!("Executing Target failed {:?}\n", res.err());
+                process::exit(0x0f00);
+            }
+        }
+
+        //the runtime says hello, possibly with AFL++ options
+        if !wait_readable(self.st_fd, self.timeout_ms * 10)? {
+            self.stop();
+            return descr_err("timeout during forkserver handshake");
+        }
+        let status = match read_u32(self.st_fd) {
+            Ok(status) => status,
+            Err(_) => {
+                let res = waitpid(self.child_pid, None);
+                self.child_pid = 0;
+                self.stop();
+                return descr_err(&format!(
+                    "forkserver handshake failed, is the target instrumented? {:?}",
+                    res
+                ));
+            }
+        };
+        if status & FS_OPT_ENABLED != FS_OPT_ENABLED {
+            return Ok(None);
+        }
+        if status & (FS_OPT_SHDMEM_FUZZ | FS_OPT_AUTODICT) != 0 {
+            //the target waits for the options we accept. We neither deliver inputs in shared
+            //memory, then the target reads them from stdin, nor do we ask for the dictionary.
+            write_u32(self.ctl_fd, FS_OPT_ENABLED)?;
+        }
+        if status & FS_OPT_MAPSIZE != 0 {
+            let map_size = fs_opt_get_mapsize(status);
+            if map_size > self.map_size {
+                return Ok(Some(map_size));
+            }
+            self.map_size = map_size;
+        }
+        return Ok(None);
+    }
+
+    fn run_process(
+        &self,
+        path: &String,
+        args: &Vec<String>,
+        (ctl_read, st_write): (i32, i32),
+        out_file: &File,
+        err_file: &File,
+    ) -> Result<(), SpawnError> {
+        let inp_file_path = self
+            .inp_file
+            .path()
+            .to_str()
+            .expect("temp path should be unicode!")
+            .to_string();
+        let (cpath, cargs) = exec_args(path, args, &inp_file_path)?;
+        let env = vec![
+            CString::new("LD_BIND_NOW=1").expect("RAND_4113552462"),
+            CString::new(format!("__AFL_SHM_ID={}", self.shm_id)).expect("RAND_2449033181"),
+            CString::new("ASAN_OPTIONS=exitcode=223").expect("RAND_1139700035"),
+            CString::new("UBSAN_OPTIONS=halt_on_error=1:exitcode=223:print_stacktrace=1")
+                .expect("RAND_2106968759"),
+        ];
+        dup2(ctl_read, FORKSRV_FD)?;
+        dup2(st_write, FORKSRV_FD + 1)?;
+        for fd in &[FORKSRV_FD, FORKSRV_FD + 1] {
+            fcntl(*fd, F_SETFD(FdFlag::from_bits(0).expect("RAND_2929299958")))?;
+        }
+        dup2(self.inp_file.as_file().as_raw_fd(), 0)?;
+        dup2(out_file.as_raw_fd(), 1)?;
+        dup2(err_file.as_raw_fd(), 2)?;
+        //lead our own process group, so stop() also takes down anything the target spawned
+        unsafe {
+            libc::setpgid(0, 0);
+        }
+        execve(&cpath, &cargs, &env)?;
+        unreachable!()
+    }
+}
+
+impl Drop for AflForkServer {
+    fn drop(&mut self) {
+        self.stop();
+        unsafe {
+            libc::shmdt(self.trace_bits as *const libc::c_void);
+        }
+    }
+}
+
+fn read_u32(fd: i32) -> Result<u32, SubprocessError> {
+    let mut buf = [0u8; 4];
+    let mut len = 0;
+    while len < 4 {
+        let res = unsafe {
+            libc::read(fd, buf[len..].as_mut_ptr() as *mut libc::c_void, 4 - len)
+        };
+        if res == 0 {
+            return descr_err("forkserver closed the status pipe");
+        }
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+            continue;
+        }
+        len += res as usize;
+    }
+    return Ok(u32::from_ne_bytes(buf));
+}
+
+fn write_u32(fd: i32, value: u32) -> Result<(), SubprocessError> {
+    let buf = value.to_ne_bytes();
+    if write(fd, &buf)? != 4 {
+        return descr_err("short write to the forkserver");
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn compile_test_target(dir: &tempfile::TempDir) -> String {
+        let target = dir.path().join("afl_test_target");
+        let status = Command::new("cc")
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/instrument/afl_test_target.c"))
+            .arg("-o")
+            .arg(&target)
+            .status()
+            .expect("RAND_733761337");
+        assert!(status.success());
+        return target.to_str().expect("RAND_450417772").to_string();
+    }
+
+    #[test]
+    fn check_afl_forkserver() {
+        let dir = tempfile::tempdir().expect("RAND_296443829");
+        let target = compile_test_target(&dir);
+        for &(mode, map_size) in &[
+            ("classic", AFL_MAP_SIZE),
+            ("options", 1 << 17),
+            ("shdmem", AFL_MAP_SIZE),
+        ] {
+            let options = ForkServerOptions {
+                timeout_ms: 200,
+                capture_output: true,
+                ..ForkServerOptions::default()
+            };
+            let mut fs = AflForkServer::new(
+                &target,
+                &vec![mode.to_string()],
+                "/dev/null".into(),
+                "/dev/null".into(),
+                &options,
+            ).expect("RAND_107644188");
+            assert_eq!(fs.bitmap().len(), map_size);
+
+            assert_eq!(fs.run_on(&"a").expect("RAND_3980577820"), ExitReason::Normal(0));
+            assert_eq!(fs.bitmap()[1], 1);
+            assert_eq!(fs.bitmap()[2], 1);
+            assert_eq!(fs.bitmap()[map_size - 1], 1);
+
+            assert_eq!(fs.run_on(&"b").expect("RAND_3130412857"), ExitReason::Normal(0));
+            assert_eq!(fs.bitmap()[1], 1);
+            assert_eq!(fs.bitmap()[2], 0);
+
+            assert_eq!(
+                fs.run_on(&"crash").expect("RAND_2320051635"),
+                ExitReason::Signaled(libc::SIGABRT)
+            );
+            assert_eq!(fs.last_stderr().expect("RAND_1154803267"), b"crashing\n");
+            assert_eq!(fs.run_on(&"hang").expect("RAND_2433378028"), ExitReason::Timeouted);
+            assert_eq!(fs.run_on(&"a").expect("RAND_3196232305"), ExitReason::Normal(0));
+            assert_eq!(fs.bitmap()[2], 1);
+        }
+    }
+}
@@ -14,10 +14,10 @@ impl ExitReason {
     pub fn from_int(status: i32) -> ExitReason {
         if unsafe { libc::WIFSIGNALED(status) } {
             let sigi = unsafe { libc::WTERMSIG(status) };
-            if Signal::from_c_int(sigi).expect("RAND_2991405959") != signal::SIGVTALRM {
-                return ExitReason::Signaled(sigi);
+            match Signal::from_c_int(sigi).expect("RAND_2991405959") {
+                signal::SIGALRM | signal::SIGVTALRM => return ExitReason::Timeouted,
+                _ => return ExitReason::Signaled(sigi),
             }
-            return ExitReason::Timeouted;
         }
         if unsafe { libc::WIFSTOPPED(status) } {
             return ExitReason::Stopped(unsafe { libc::WSTOPSIG(status) });
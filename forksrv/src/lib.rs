@@ -6,101 +6,663 @@ extern crate quick_error;
 extern crate serde_derive;
 extern crate tempfile;
 
+pub mod afl;
 pub mod error;
 pub mod exitreason;
 
 use nix::fcntl::*;
 use nix::sys::mman::*;
 use nix::sys::signal::kill;
-use nix::sys::signal::Signal::SIGCONT;
+use nix::sys::signal::Signal::{SIGCONT, SIGKILL, SIGUSR1};
 use nix::sys::signal::*;
 use nix::sys::wait::WaitStatus::*;
 use nix::sys::wait::*;
 use nix::unistd::*;
 use std::ffi::CString;
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::collections::VecDeque;
 use std::mem;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::Path;
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub use error::{descr_err, SubprocessError};
 use error::{path_err, SpawnError};
+use exitreason::ExitReason;
+
+//Map size used by the runtime if the fuzzer doesn't ask for another one
+pub const DEFAULT_MAP_SIZE: usize = 1 << 15;
+
+//The runtime sets the magic after every run
+const MAGIC_BEFORE_RUN: u64 = 0x1337133713371337;
+const MAGIC_AFTER_RUN: u64 = 0x5a5a55464c464f52; //"ROFLFUZZ"
+
+fn get_filename(path: &String) -> Result<String, SpawnError> {
+    let path_obj = Path::new(&path);
+
+    if !path_obj.is_absolute() {
+        return path_err("should be absolute");
+    }
+
+    let name = match path_obj.file_name() {
+        Some(name) => name,
+        _ => return path_err("should be a file"),
+    };
+
+    match name.to_os_string().into_string() {
+        Ok(res) => return Ok(res),
+        _ => return path_err("should be unicode"),
+    }
+}
+
+//The path and argv of the target, "@@" in the arguments is replaced by the path of the input file
+fn exec_args(
+    path: &String,
+    args: &Vec<String>,
+    inp_file_path: &String,
+) -> Result<(CString, Vec<CString>), SpawnError> {
+    let filename = get_filename(path)?;
+    let cpath = CString::new(path.clone())?;
+    let args_iter = args
+        .iter()
+        .map(|arg| if arg == "@@" { inp_file_path } else { arg });
+    let args_iter = Some(&filename).into_iter().chain(args_iter); //add filename as argv[0]
+    let cargs = args_iter
+        .map(|s| Ok(CString::new(s.clone())?))
+        .collect::<Result<Vec<CString>, SpawnError>>()?; //convert all String args to CStrin args
+    return Ok((cpath, cargs));
+}
+
+//Capacity of the shared memory inputs are delivered through, larger inputs are passed in the
+//input file instead
+pub const INPUT_SHM_SIZE: usize = 1 << 20;
+
+//Length that tells the runtime to read the input of the run from the input file, see
+//ROFL_INPUT_IN_FILE in the runtime
+const INPUT_IN_FILE: u64 = u64::max_value();
+
+//Shared memory that holds the input instead of the input file: the length as u64 followed by the
+//bytes, see rofl_input_t in the runtime
+#[derive(Debug)]
+struct InputShm {
+    file: File,
+    len: *mut u64,
+    data: *mut u8,
+}
+
+impl InputShm {
+    fn new() -> Result<Self, SubprocessError> {
+        let size = mem::size_of::<u64>() + INPUT_SHM_SIZE;
+        let file = tempfile::tempfile()?;
+        file.set_len(size as u64)?;
+        let ptr = mmap(
+            0 as *mut nix::c_void,
+            size,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )?;
+        return Ok(InputShm {
+            file,
+            len: ptr as *mut u64,
+            data: unsafe { (ptr as *mut u8).offset(mem::size_of::<u64>() as isize) },
+        });
+    }
+
+    //The input of the following runs is in the input file
+    fn set_in_file(&mut self) {
+        unsafe {
+            *self.len = INPUT_IN_FILE;
+        }
+    }
+
+    fn writer<'a>(&'a mut self) -> InputWriter<'a> {
+        unsafe {
+            *self.len = 0;
+            return InputWriter {
+                len: &mut *self.len,
+                data: std::slice::from_raw_parts_mut(self.data, INPUT_SHM_SIZE),
+            };
+        }
+    }
+}
+
+//Writes the input of the next run directly into the shared memory of the target. Writes fail
+//once the input gets larger than INPUT_SHM_SIZE, such inputs have to be passed to set_input.
+pub struct InputWriter<'a> {
+    len: &'a mut u64,
+    data: &'a mut [u8],
+}
+
+impl<'a> Write for InputWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = *self.len as usize;
+        if buf.len() > self.data.len() - start {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "input doesn't fit into the shared memory",
+            ));
+        }
+        self.data[start..start + buf.len()].copy_from_slice(buf);
+        *self.len += buf.len() as u64;
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+//Upper bound of the output kept per run, of longer output only the end is kept
+pub const CAPTURE_SIZE: usize = 1 << 16;
+
+//How long the thread of an OutputCapture waits for output before it checks for shutdown
+const CAPTURE_POLL_MS: u64 = 50;
 
 #[derive(Debug)]
-pub struct ForkServer<T> {
+struct CaptureState {
+    //non blocking, only read while holding tail
+    read: File,
+    tail: Mutex<VecDeque<u8>>,
+    shutdown: AtomicBool,
+}
+
+impl CaptureState {
+    //Reads what is in the pipe right now, keeping the last CAPTURE_SIZE bytes
+    fn drain(&self, tail: &mut VecDeque<u8>) -> Result<(), SubprocessError> {
+        let mut chunk = [0; 4096];
+        loop {
+            match (&self.read).read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(n) => {
+                    tail.extend(chunk[..n].iter());
+                    let excess = tail.len().saturating_sub(CAPTURE_SIZE);
+                    tail.drain(..excess);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn watch(&self) {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            match wait_readable(self.read.as_raw_fd(), CAPTURE_POLL_MS) {
+                Ok(true) => {
+                    let mut tail = self.tail.lock().expect("RAND_1046358797");
+                    if self.drain(&mut tail).is_err() {
+                        return;
+                    }
+                }
+                Ok(false) => {}
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+//Pipe the stdout or stderr of the target is redirected to instead of a file. A thread empties it
+//while the target runs, so the target never blocks on a full pipe or loses output to EAGAIN, and
+//only the end of long output is kept.
+#[derive(Debug)]
+pub struct OutputCapture {
+    write: File,
+    state: Arc<CaptureState>,
+    thread: Option<thread::JoinHandle<()>>,
+    buf: Vec<u8>,
+}
+
+impl OutputCapture {
+    pub fn new() -> Result<Self, SubprocessError> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let read = unsafe { File::from_raw_fd(fds[0]) };
+        let write = unsafe { File::from_raw_fd(fds[1]) };
+        //the target blocks until the thread caught up, we never block on an empty pipe
+        if unsafe { libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let state = Arc::new(CaptureState {
+            read,
+            tail: Mutex::new(VecDeque::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        let state_clone = state.clone();
+        let thread = thread::Builder::new()
+            .name("forksrv output capture".to_string())
+            .spawn(move || state_clone.watch())?;
+        return Ok(OutputCapture {
+            write,
+            state,
+            thread: Some(thread),
+            buf: vec![],
+        });
+    }
+
+    //The end the target writes to
+    pub fn as_file(&self) -> &File {
+        return &self.write;
+    }
+
+    //Drops everything written so far
+    pub fn clear(&mut self) -> Result<(), SubprocessError> {
+        let mut tail = self.state.tail.lock().expect("RAND_2214760358");
+        self.state.drain(&mut tail)?;
+        tail.clear();
+        return Ok(());
+    }
+
+    //The last CAPTURE_SIZE bytes written since the last clear. The target must not be running,
+    //otherwise some of its output may still be on the way.
+    pub fn output(&mut self) -> Result<&[u8], SubprocessError> {
+        {
+            let mut tail = self.state.tail.lock().expect("RAND_3880713442");
+            self.state.drain(&mut tail)?;
+            self.buf.clear();
+            self.buf.extend(tail.iter());
+        }
+        return Ok(&self.buf);
+    }
+}
+
+impl Drop for OutputCapture {
+    fn drop(&mut self) {
+        self.state.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+//Returns false if nothing arrived within timeout_ms
+fn wait_readable(fd: i32, timeout_ms: u64) -> Result<bool, SubprocessError> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        let res = unsafe { libc::poll(&mut pollfd, 1, timeout_ms as libc::c_int) };
+        if res >= 0 {
+            return Ok(res > 0);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct WatchdogState {
+    //The forkserver (and process group) of the current run and when to kill it
+    deadline: Option<(i32, Instant)>,
+    fired: bool,
+    shutdown: bool,
+}
+
+//Kills runs that take longer than their deadline, even if the target blocks or ignores the
+//timer signal of the runtime
+#[derive(Debug, Default)]
+struct Watchdog {
+    state: Mutex<WatchdogState>,
+    cond: Condvar,
+}
+
+impl Watchdog {
+    fn arm(&self, pid: i32, timeout: Duration) {
+        let mut state = self.state.lock().expect("RAND_1402284167");
+        state.deadline = Some((pid, Instant::now() + timeout));
+        state.fired = false;
+        self.cond.notify_one();
+    }
+
+    //returns true if the run was killed
+    fn disarm(&self) -> bool {
+        let mut state = self.state.lock().expect("RAND_2869911735");
+        state.deadline = None;
+        return state.fired;
+    }
+
+    fn shutdown(&self) {
+        self.state.lock().expect("RAND_3346123599").shutdown = true;
+        self.cond.notify_one();
+    }
+
+    fn watch(&self) {
+        let mut state = self.state.lock().expect("RAND_1791340856");
+        while !state.shutdown {
+            match state.deadline {
+                None => state = self.cond.wait(state).expect("RAND_2281700935"),
+                Some((pid, deadline)) => {
+                    let now = Instant::now();
+                    if now < deadline {
+                        state = self
+                            .cond
+                            .wait_timeout(state, deadline - now)
+                            .expect("RAND_4120624394")
+                            .0;
+                        continue;
+                    }
+                    //the forkserver is the leader of its own process group, which also
+                    //contains the stuck child
+                    let _ = kill(-pid, SIGKILL);
+                    state.deadline = None;
+                    state.fired = true;
+                }
+            }
+        }
+    }
+}
+
+//Start of the shared memory, the coverage map follows. See feedback_data_t in the runtime
+#[repr(C)]
+#[derive(Debug)]
+struct FeedbackHeader {
+    magic: u64,
+    status: i32,
+    map_size: u32,
+}
+
+//How ForkServer and AflForkServer run the target. AflForkServer ignores persistent_runs,
+//shm_input and map_size, AFL targets tell it the size of their map.
+#[derive(Debug, Clone)]
+pub struct ForkServerOptions {
+    //The wall clock time a single run may take. The runtime of the target kills runs after that
+    //time with SIGALRM, if that doesn't work, the watchdog kills the whole forkserver after twice
+    //the time and starts a new one.
+    pub timeout_ms: u64,
+    //If larger than 1, targets that use __ROFL_LOOP run up to that many inputs in the same child
+    //before the forkserver has to fork a fresh one
+    pub persistent_runs: u64,
+    //Pass inputs in shared memory instead of the input file. The target has to read them from
+    //stdin or __rofl_input, "@@" and reading fd 0 don't work in that case.
+    pub shm_input: bool,
+    //The size of the coverage map we ask the runtime for. It uses a larger one if the target was
+    //compiled for that, bitmap() has the size the runtime reported.
+    pub map_size: usize,
+    //stdout and stderr go to pipes instead of out_path and err_path and the output of the last
+    //run is available from last_stdout and last_stderr
+    pub capture_output: bool,
+}
+
+impl Default for ForkServerOptions {
+    fn default() -> Self {
+        return ForkServerOptions {
+            timeout_ms: 1000,
+            persistent_runs: 1,
+            shm_input: false,
+            map_size: DEFAULT_MAP_SIZE,
+            capture_output: false,
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct ForkServer {
     child_pid: i32,
+    path: String,
+    args: Vec<String>,
     shm_file: File,
     inp_file: tempfile::NamedTempFile,
+    input_shm: Option<InputShm>,
+    stdout_capture: Option<OutputCapture>,
+    stderr_capture: Option<OutputCapture>,
     pub out_path: String,
     pub err_path: String,
-    shared_data: *mut T,
+    pub timeout_ms: u64,
+    persistent_runs: u64,
+    runs_in_child: u64,
+    watchdog: Arc<Watchdog>,
+    watchdog_thread: Option<thread::JoinHandle<()>>,
+    shared_data: *mut FeedbackHeader,
+    shm_len: usize,
+    map_size: usize,
 }
 
-impl<T> ForkServer<T> {
+impl ForkServer {
+    //Starts the target at path with args, see ForkServerOptions for how it is run. Without
+    //capture_output its stdout and stderr go to out_path and err_path.
     pub fn new(
         path: &String,
         args: &Vec<String>,
         out_path: String,
         err_path: String,
+        options: &ForkServerOptions,
     ) -> Result<Self, SubprocessError> {
-        let (shm_file, shared_data) = ForkServer::<T>::create_shm()?;
+        let timeout_ms = options.timeout_ms;
+        let persistent_runs = options.persistent_runs;
+        let map_size = options.map_size;
+        let shm_len = mem::size_of::<FeedbackHeader>() + map_size;
+        let (shm_file, shared_data) = ForkServer::create_shm(shm_len)?;
         let inp_file = tempfile::NamedTempFile::new()?;
+        let input_shm = if options.shm_input {
+            Some(InputShm::new()?)
+        } else {
+            None
+        };
+        let (stdout_capture, stderr_capture) = if options.capture_output {
+            (Some(OutputCapture::new()?), Some(OutputCapture::new()?))
+        } else {
+            (None, None)
+        };
         let inp_file_path = inp_file
             .path()
             .to_str()
             .expect("temp path should be unicode!")
             .to_string();
-        let child = ForkServer::<T>::start(
+        let child = ForkServer::start(
             path,
             args,
+            map_size,
             &shm_file,
             &inp_file.as_file(),
+            input_shm.as_ref().map(|input_shm| &input_shm.file),
+            stdout_capture.as_ref().map(|capture| capture.as_file()),
+            stderr_capture.as_ref().map(|capture| capture.as_file()),
             &out_path,
             &err_path,
             &inp_file_path,
+            timeout_ms,
+            persistent_runs > 1,
         )?;
-        return Ok(ForkServer {
+        let watchdog = Arc::new(Watchdog::default());
+        let watchdog_clone = watchdog.clone();
+        let watchdog_thread = thread::Builder::new()
+            .name("forksrv watchdog".to_string())
+            .spawn(move || watchdog_clone.watch())?;
+        let mut res = ForkServer {
             child_pid: child,
+            path: path.clone(),
+            args: args.clone(),
             shm_file,
             inp_file,
+            input_shm,
+            stdout_capture,
+            stderr_capture,
             out_path,
             err_path,
+            timeout_ms,
+            persistent_runs,
+            runs_in_child: 0,
+            watchdog,
+            watchdog_thread: Some(watchdog_thread),
             shared_data,
-        });
+            shm_len,
+            map_size,
+        };
+        res.update_map_size()?;
+        return Ok(res);
     }
 
-    pub fn get_shared_mut<'a>(&'a mut self) -> &'a mut T {
-        unsafe { return &mut *self.shared_data }
+    //The coverage map of the last run
+    pub fn bitmap(&self) -> &[u8] {
+        unsafe {
+            let map = (self.shared_data as *const u8).offset(mem::size_of::<FeedbackHeader>() as isize);
+            return std::slice::from_raw_parts(map, self.map_size);
+        }
     }
 
-    pub fn get_shared<'a>(&'a self) -> &'a T {
-        unsafe { return &*self.shared_data }
+    pub fn bitmap_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            let map = (self.shared_data as *mut u8).offset(mem::size_of::<FeedbackHeader>() as isize);
+            return std::slice::from_raw_parts_mut(map, self.map_size);
+        }
     }
 
-    pub fn run_on<I: AsRef<[u8]>>(&mut self, data: &I) -> Result<(), SubprocessError> {
+    //Sets the input of the following runs. Inputs larger than INPUT_SHM_SIZE go to the input file
+    //even if inputs are passed in shared memory.
+    pub fn set_input<I: AsRef<[u8]>>(&mut self, data: &I) -> Result<(), SubprocessError> {
+        if let Some(ref mut input_shm) = self.input_shm {
+            if data.as_ref().len() <= INPUT_SHM_SIZE {
+                input_shm.writer().write_all(data.as_ref())?;
+                return Ok(());
+            }
+            input_shm.set_in_file();
+        }
         let mut inp_file = self.inp_file.as_file();
         inp_file.set_len(0)?;
         inp_file.seek(SeekFrom::Start(0))?;
-        inp_file.write(data.as_ref())?;
-        inp_file.seek(SeekFrom::Start(0))?;
+        inp_file.write_all(data.as_ref())?;
+        return Ok(());
+    }
+
+    //Clears the input and returns a writer for the input of the following runs, if inputs are
+    //passed in shared memory. This saves a copy of the input, unless it turns out to be too large.
+    pub fn input_writer<'a>(&'a mut self) -> Option<InputWriter<'a>> {
+        return self.input_shm.as_mut().map(|input_shm| input_shm.writer());
+    }
+
+    //What the last run wrote to stdout, empty if the output isn't captured
+    pub fn last_stdout(&mut self) -> Result<&[u8], SubprocessError> {
+        match self.stdout_capture {
+            Some(ref mut capture) => return capture.output(),
+            None => return Ok(&[]),
+        }
+    }
+
+    //What the last run wrote to stderr, e.g. the report of a sanitizer
+    pub fn last_stderr(&mut self) -> Result<&[u8], SubprocessError> {
+        match self.stderr_capture {
+            Some(ref mut capture) => return capture.output(),
+            None => return Ok(&[]),
+        }
+    }
+
+    pub fn run_on<I: AsRef<[u8]>>(&mut self, data: &I) -> Result<ExitReason, SubprocessError> {
+        self.set_input(data)?;
+        return self.run();
+    }
+
+    //Like run_on, with the input of the last run or what was written to the input_writer
+    pub fn run(&mut self) -> Result<ExitReason, SubprocessError> {
+        //with shared memory inputs the file is only used for the ones that don't fit
+        self.inp_file.as_file().seek(SeekFrom::Start(0))?;
+        for capture in self.stdout_capture.iter_mut().chain(self.stderr_capture.iter_mut()) {
+            capture.clear()?;
+        }
+        if self.persistent_runs > 1 {
+            //crashes and timeouts end the persistent child anyway, every persistent_runs runs we
+            //ask the forkserver to replace it. The signal is handled once the forkserver continues.
+            if self.runs_in_child >= self.persistent_runs {
+                kill(self.child_pid, SIGUSR1)?;
+                self.runs_in_child = 0;
+            }
+            self.runs_in_child += 1;
+        }
+        unsafe {
+            (*self.shared_data).magic = MAGIC_BEFORE_RUN;
+        }
+        self.watchdog
+            .arm(self.child_pid, Duration::from_millis(self.timeout_ms * 2));
         kill(self.child_pid, SIGCONT)?;
         let res = waitpid(self.child_pid, Some(WUNTRACED))
             .expect("waitpid failed - this shouldn't happen");
+        if self.watchdog.disarm() {
+            if let Stopped(..) = res {
+                //the run finished just before the watchdog fired, but the kill still arrives
+                waitpid(self.child_pid, None).expect("RAND_3896505305");
+            }
+            self.restart()?;
+            return Ok(ExitReason::Timeouted);
+        }
         match res {
             Exited(_, exitcode) => {
                 return descr_err(&format!("Parent died on run with exitcode {}", exitcode))
             }
-            Stopped(_, sig) if sig == SIGSTOP => return Ok(()),
+            Stopped(_, sig) if sig == SIGSTOP => {}
             _ => return descr_err(&format!("Parent died on run {:?}", res)),
         };
+        let header = unsafe { &*self.shared_data };
+        if header.magic != MAGIC_AFTER_RUN {
+            return descr_err("Failed to get magic value from subprocess");
+        }
+        return Ok(ExitReason::from_int(header.status));
     }
 
     //private functions
 
-    fn create_shm() -> Result<(File, *mut T), SubprocessError> {
-        let len = mem::size_of::<T>();
+    fn restart(&mut self) -> Result<(), SubprocessError> {
+        let inp_file_path = self
+            .inp_file
+            .path()
+            .to_str()
+            .expect("temp path should be unicode!")
+            .to_string();
+        self.child_pid = ForkServer::start(
+            &self.path,
+            &self.args,
+            self.map_size,
+            &self.shm_file,
+            &self.inp_file.as_file(),
+            self.input_shm.as_ref().map(|input_shm| &input_shm.file),
+            self.stdout_capture.as_ref().map(|capture| capture.as_file()),
+            self.stderr_capture.as_ref().map(|capture| capture.as_file()),
+            &self.out_path,
+            &self.err_path,
+            &inp_file_path,
+            self.timeout_ms,
+            self.persistent_runs > 1,
+        )?;
+        self.runs_in_child = 0;
+        return self.update_map_size();
+    }
+
+    //The runtime of a freshly started forkserver reports its map size and grows the shared memory
+    //if needed
+    fn update_map_size(&mut self) -> Result<(), SubprocessError> {
+        let map_size = unsafe { (*self.shared_data).map_size } as usize;
+        if map_size == 0 {
+            return descr_err("target didn't report a map size, is it built with the current runtime?");
+        }
+        let shm_len = mem::size_of::<FeedbackHeader>() + map_size;
+        if shm_len > self.shm_len {
+            munmap(self.shared_data as *mut nix::c_void, self.shm_len)?;
+            let ptr = mmap(
+                0 as *mut nix::c_void,
+                shm_len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                self.shm_file.as_raw_fd(),
+                0,
+            )?;
+            self.shared_data = ptr as *mut FeedbackHeader;
+            self.shm_len = shm_len;
+        }
+        self.map_size = map_size;
+        return Ok(());
+    }
+
+    fn create_shm(len: usize) -> Result<(File, *mut FeedbackHeader), SubprocessError> {
         let shm_file = tempfile::tempfile()?;
         shm_file.set_len(len as u64)?;
         let prot = PROT_READ | PROT_WRITE;
@@ -113,55 +675,60 @@ impl<T> ForkServer<T> {
             shm_file.as_raw_fd(),
             0,
         )?;
-        return Ok((shm_file, ptr as *mut T));
-    }
-
-    fn get_filename(path: &String) -> Result<String, SpawnError> {
-        let path_obj = Path::new(&path);
-
-        if !path_obj.is_absolute() {
-            return path_err("should be absolute");
-        }
-
-        let name = match path_obj.file_name() {
-            Some(name) => name,
-            _ => return path_err("should be a file"),
-        };
-
-        match name.to_os_string().into_string() {
-            Ok(res) => return Ok(res),
-            _ => return path_err("should be unicode"),
-        }
+        return Ok((shm_file, ptr as *mut FeedbackHeader));
     }
 
     fn run_process(
         path: &String,
         args: &Vec<String>,
+        map_size: usize,
         shm_file: &File,
         inp_file: &File,
+        input_shm_file: Option<&File>,
+        stdout_capture: Option<&File>,
+        stderr_capture: Option<&File>,
         out_file: &String,
         err_file: &String,
         inp_file_path: &String,
+        timeout_ms: u64,
+        persistent: bool,
     ) -> Result<(), SpawnError> {
-        let filename = ForkServer::<T>::get_filename(path)?;
-        let cpath = CString::new(path.clone())?;
-        let args_iter = args
-            .iter()
-            .map(|arg| if arg == "@@" { inp_file_path } else { arg });
-        let args_iter = Some(&filename).into_iter().chain(args_iter); //add filename as argv[0]
-        let cargs = args_iter
-            .map(|s| Ok(CString::new(s.clone())?))
-            .collect::<Result<Vec<CString>, SpawnError>>()?; //convert all String args to CStrin args
+        let (cpath, cargs) = exec_args(path, args, inp_file_path)?;
         let shm_fd = shm_file.as_raw_fd();
         let inp_fd = inp_file.as_raw_fd();
-        let env = vec![
+        let mut env = vec![
             CString::new("LD_BIND_NOW=1").expect("RAND_508190816"),
             CString::new(format!("ROFL_SHM_FD={}", shm_fd)).expect("RAND_3630438482"),
             CString::new(format!("ROFL_INP_FD={}", inp_fd)).expect("RAND_734314699"),
-            CString::new(format!("ROFL_OUT_PATH={}", out_file)).expect("RAND_2015012392"),
-            CString::new(format!("ROFL_ERR_PATH={}", err_file)).expect("RAND_3568988286"),
+            CString::new(format!("ROFL_TIMEOUT_MS={}", timeout_ms)).expect("RAND_1931027580"),
+            CString::new(format!("ROFL_MAP_SIZE={}", map_size)).expect("RAND_1916992402"),
             CString::new("ASAN_OPTIONS=exitcode=223,abort_on_erro=true").expect("RAND_2089158993"),
+            //UBSAN only reports by default, exit like ASAN does so the fuzzer sees the bug
+            CString::new("UBSAN_OPTIONS=halt_on_error=1:exitcode=223:print_stacktrace=1")
+                .expect("RAND_2640176939"),
         ];
+        //the runtime opens the output files for every child, captured output goes to the pipes
+        //the forkserver inherits instead
+        if stdout_capture.is_none() {
+            env.push(CString::new(format!("ROFL_OUT_PATH={}", out_file)).expect("RAND_2015012392"));
+        }
+        if stderr_capture.is_none() {
+            env.push(CString::new(format!("ROFL_ERR_PATH={}", err_file)).expect("RAND_3568988286"));
+        }
+        if persistent {
+            env.push(CString::new("ROFL_PERSISTENT=1").expect("RAND_3326120407"));
+        }
+        if let Some(input_shm_file) = input_shm_file {
+            let input_shm_fd = input_shm_file.as_raw_fd();
+            env.push(
+                CString::new(format!("ROFL_INPUT_SHM_FD={}", input_shm_fd))
+                    .expect("RAND_4077749081"),
+            );
+            fcntl(
+                input_shm_fd,
+                F_SETFD(FdFlag::from_bits(0).expect("RAND_1023866527")),
+            )?;
+        }
         fcntl(
             shm_fd,
             F_SETFD(FdFlag::from_bits(0).expect("RAND_22127389")),
@@ -172,6 +739,17 @@ impl<T> ForkServer<T> {
         )?;
         //use inp_fd instead of the original stdin
         dup2(inp_fd, 0)?;
+        if let Some(capture) = stdout_capture {
+            dup2(capture.as_raw_fd(), 1)?;
+        }
+        if let Some(capture) = stderr_capture {
+            dup2(capture.as_raw_fd(), 2)?;
+        }
+        //become the leader of a new process group, so the watchdog can kill the forkserver
+        //together with all its children
+        unsafe {
+            libc::setpgid(0, 0);
+        }
         execve(&cpath, &cargs, &env)?;
         unreachable!()
     }
@@ -179,11 +757,17 @@ impl<T> ForkServer<T> {
     fn start(
         path: &String,
         args: &Vec<String>,
+        map_size: usize,
         shm_file: &File,
         inp_file: &File,
+        input_shm_file: Option<&File>,
+        stdout_capture: Option<&File>,
+        stderr_capture: Option<&File>,
         out_file: &String,
         err_file: &String,
         inp_file_path: &String,
+        timeout_ms: u64,
+        persistent: bool,
     ) -> Result<(i32), SubprocessError> {
         match fork().expect("fork failed") {
             ForkResult::Parent { child } => {
@@ -208,14 +792,20 @@ impl<T> ForkServer<T> {
             }
             ForkResult::Child => {
                 {
-                    let res = ForkServer::<T>::run_process(
+                    let res = ForkServer::run_process(
                         path,
                         args,
+                        map_size,
                         shm_file,
                         inp_file,
+                        input_shm_file,
+                        stdout_capture,
+                        stderr_capture,
                         out_file,
                         err_file,
                         inp_file_path,
+                        timeout_ms,
+                        persistent,
                     );
                     let err = res.err();
                     print!("Executing Target failed {:?}\n", err)
@@ -225,3 +815,205 @@ impl<T> ForkServer<T> {
         }
     }
 }
+
+impl Drop for ForkServer {
+    fn drop(&mut self) {
+        //don't leave the stopped forkserver and its children behind
+        let _ = kill(-self.child_pid, SIGKILL);
+        let _ = waitpid(self.child_pid, None);
+        let _ = munmap(self.shared_data as *mut nix::c_void, self.shm_len);
+        self.watchdog.shutdown();
+        if let Some(watchdog_thread) = self.watchdog_thread.take() {
+            let _ = watchdog_thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn compile_test_target(dir: &tempfile::TempDir) -> String {
+        let target = dir.path().join("rofl_test_target");
+        let instrument = concat!(env!("CARGO_MANIFEST_DIR"), "/instrument");
+        let status = Command::new("cc")
+            .arg(format!("{}/rofl_test_target.c", instrument))
+            .arg(format!("{}/rt/common.c", instrument))
+            .arg("-o")
+            .arg(&target)
+            .status()
+            .expect("RAND_3215632797");
+        assert!(status.success());
+        return target.to_str().expect("RAND_462270282").to_string();
+    }
+
+    fn start_with_map(
+        target: &String,
+        persistent_runs: u64,
+        shm_input: bool,
+        map_size: usize,
+    ) -> ForkServer {
+        let options = ForkServerOptions {
+            timeout_ms: 200,
+            persistent_runs,
+            shm_input,
+            map_size,
+            capture_output: false,
+        };
+        return ForkServer::new(target, &vec![], "/dev/null".into(), "/dev/null".into(), &options)
+            .expect("RAND_638794806");
+    }
+
+    fn start(target: &String, persistent_runs: u64, shm_input: bool) -> ForkServer {
+        return start_with_map(target, persistent_runs, shm_input, DEFAULT_MAP_SIZE);
+    }
+
+    //The first bytes of the bitmap
+    fn result(fs: &ForkServer) -> (u8, u8, u8) {
+        let bitmap = fs.bitmap();
+        return (bitmap[0], bitmap[1], bitmap[2]);
+    }
+
+    fn run(fs: &mut ForkServer, input: &str) -> (ExitReason, u8) {
+        let exitreason = fs.run_on(&input).expect("RAND_3710683060");
+        return (exitreason, result(fs).0);
+    }
+
+    #[test]
+    fn check_persistent_mode() {
+        let dir = tempfile::tempdir().expect("RAND_2654776252");
+        let target = compile_test_target(&dir);
+
+        let mut fs = start(&target, 4, false);
+        let normal = ExitReason::Normal(0);
+        assert_eq!(run(&mut fs, "a"), (normal, 1));
+        assert_eq!(run(&mut fs, "b"), (normal, 2));
+        assert_eq!(run(&mut fs, "c"), (normal, 3));
+        assert_eq!(run(&mut fs, "d"), (normal, 4));
+        //the fuzzer asked for a fresh child
+        assert_eq!(run(&mut fs, "e"), (normal, 1));
+        assert_eq!(run(&mut fs, "crash"), (ExitReason::Signaled(libc::SIGABRT), 2));
+        assert_eq!(run(&mut fs, "f"), (normal, 1));
+        assert_eq!(run(&mut fs, "hang"), (ExitReason::Timeouted, 2));
+        assert_eq!(run(&mut fs, "g"), (normal, 1));
+
+        let mut fs = start(&target, 1, false);
+        assert_eq!(run(&mut fs, "a"), (normal, 1));
+        assert_eq!(run(&mut fs, "b"), (normal, 1));
+    }
+
+    #[test]
+    fn check_watchdog() {
+        let dir = tempfile::tempdir().expect("RAND_3527460914");
+        let target = compile_test_target(&dir);
+        let normal = ExitReason::Normal(0);
+        for &persistent_runs in &[1, 4] {
+            let mut fs = start(&target, persistent_runs, false);
+            assert_eq!(run(&mut fs, "a"), (normal, 1));
+            //the target blocks SIGALRM, the watchdog kills it after twice the timeout
+            let started = Instant::now();
+            assert_eq!(fs.run_on(&"stuck").expect("RAND_2165824178"), ExitReason::Timeouted);
+            let elapsed = started.elapsed();
+            assert!(elapsed >= Duration::from_millis(400) && elapsed < Duration::from_secs(5));
+            //the forkserver was replaced by a fresh one
+            assert_eq!(run(&mut fs, "b"), (normal, 1));
+            let runs = if persistent_runs > 1 { 2 } else { 1 };
+            assert_eq!(run(&mut fs, "hang"), (ExitReason::Timeouted, runs));
+            assert_eq!(run(&mut fs, "c"), (normal, 1));
+        }
+    }
+
+    #[test]
+    fn check_shm_input() {
+        let dir = tempfile::tempdir().expect("RAND_839362770");
+        let target = compile_test_target(&dir);
+        let normal = ExitReason::Normal(0);
+
+        //without shared memory __rofl_input returns NULL
+        let mut fs = start(&target, 1, false);
+        assert!(fs.input_writer().is_none());
+        assert_eq!(fs.run_on(&"abc").expect("RAND_1609523063"), normal);
+        assert_eq!(result(&fs), (1, b'a', 0));
+
+        for &persistent_runs in &[1, 4] {
+            let mut fs = start(&target, persistent_runs, true);
+            assert_eq!(fs.run_on(&"abc").expect("RAND_3774427162"), normal);
+            assert_eq!(result(&fs), (1, b'a', 3));
+            fs.input_writer()
+                .expect("RAND_1092091291")
+                .write_all(b"xy")
+                .expect("RAND_715021199");
+            assert_eq!(fs.run().expect("RAND_3384121084"), normal);
+            let persistent = persistent_runs > 1;
+            assert_eq!(result(&fs), (if persistent { 2 } else { 1 }, b'x', 2));
+            //the input stays in place
+            assert_eq!(fs.run().expect("RAND_2525541109"), normal);
+            assert_eq!(result(&fs), (if persistent { 3 } else { 1 }, b'x', 2));
+            //inputs that don't fit are read from the input file, __rofl_input returns NULL
+            let large = vec![b'z'; INPUT_SHM_SIZE + 1];
+            assert!(fs.input_writer().expect("RAND_3001684772").write_all(&large).is_err());
+            assert_eq!(fs.run_on(&large).expect("RAND_1477290263"), normal);
+            assert_eq!(result(&fs), (if persistent { 4 } else { 1 }, b'z', 0));
+            assert_eq!(fs.run().expect("RAND_2603318151"), normal);
+            assert_eq!(result(&fs), (1, b'z', 0));
+            assert_eq!(fs.run_on(&"abc").expect("RAND_864209125"), normal);
+            assert_eq!(result(&fs), (if persistent { 2 } else { 1 }, b'a', 3));
+        }
+    }
+
+    #[test]
+    fn check_map_size() {
+        let dir = tempfile::tempdir().expect("RAND_308037426");
+        let target = compile_test_target(&dir);
+        //the runtime rounds up to a power of two
+        for &(requested, map_size) in &[(DEFAULT_MAP_SIZE, DEFAULT_MAP_SIZE), (100000, 1 << 17)] {
+            let mut fs = start_with_map(&target, 1, false, requested);
+            assert_eq!(fs.bitmap().len(), map_size);
+            assert_eq!(fs.run_on(&"a").expect("RAND_2845886964"), ExitReason::Normal(0));
+            assert_eq!(result(&fs), (1, b'a', 0));
+            assert_eq!(fs.bitmap()[map_size - 1], 0);
+        }
+        //the shared memory grows if the runtime uses a larger map than we asked for
+        let mut fs = start_with_map(&target, 1, false, 1000);
+        assert_eq!(fs.bitmap().len(), 1024);
+        assert_eq!(fs.run_on(&"b").expect("RAND_2577686303"), ExitReason::Normal(0));
+        assert_eq!(result(&fs), (1, b'b', 0));
+    }
+
+    #[test]
+    fn check_output_capture() {
+        let dir = tempfile::tempdir().expect("RAND_1417036785");
+        let target = compile_test_target(&dir);
+        for &persistent_runs in &[1, 4] {
+            let options = ForkServerOptions {
+                timeout_ms: 200,
+                persistent_runs,
+                capture_output: true,
+                ..ForkServerOptions::default()
+            };
+            let mut fs =
+                ForkServer::new(&target, &vec![], "/dev/null".into(), "/dev/null".into(), &options)
+                    .expect("RAND_1720946566");
+            assert_eq!(fs.run_on(&"print").expect("RAND_1983403935"), ExitReason::Normal(0));
+            assert_eq!(fs.last_stdout().expect("RAND_2357315185"), b"out\n");
+            assert_eq!(fs.last_stderr().expect("RAND_4066569716"), b"err\n");
+            //reading the output doesn't consume it
+            assert_eq!(fs.last_stderr().expect("RAND_3425787990"), b"err\n");
+            //every run starts with empty buffers
+            assert_eq!(fs.run_on(&"a").expect("RAND_1013771290"), ExitReason::Normal(0));
+            assert_eq!(fs.last_stdout().expect("RAND_2620454658"), b"");
+            assert_eq!(fs.last_stderr().expect("RAND_3196808014"), b"");
+            let crash = ExitReason::Signaled(libc::SIGABRT);
+            assert_eq!(fs.run_on(&"crash").expect("RAND_3066373484"), crash);
+            assert_eq!(fs.last_stderr().expect("RAND_1502245707"), b"crashing\n");
+            //the target doesn't block on a full pipe, only the end of its output is kept
+            assert_eq!(fs.run_on(&"spam").expect("RAND_2750683226"), ExitReason::Normal(0));
+            let stderr = fs.last_stderr().expect("RAND_3840451738");
+            assert_eq!(stderr.len(), CAPTURE_SIZE);
+            assert!(stderr.ends_with(b"xxxx\nend\n"));
+            assert_eq!(fs.run_on(&"print").expect("RAND_2283870373"), ExitReason::Normal(0));
+            assert_eq!(fs.last_stderr().expect("RAND_2436400081"), b"err\n");
+        }
+    }
+}
@@ -8,12 +8,12 @@ extern crate time;
 #[macro_use]
 extern crate lazy_static;
 
-use forksrv::{descr_err, ForkServer, SubprocessError};
+use forksrv::exitreason::ExitReason;
+use forksrv::{ForkServer, ForkServerOptions, SubprocessError};
 use nix::sys::signal::Signal;
 use rand::Rng;
 use regex::Regex;
 use std::env;
-use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -219,27 +219,13 @@ impl ObjectSpace {
     }
 }
 
-#[repr(C)]
-struct FeedbackData {
-    run_bitmap: [u8; 1 << 15],
-    magic: u64,
-    status: i32,
-}
-
-impl fmt::Debug for FeedbackData {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Feedback {{ run_bitmap [...] magic: {}, status: {} }}",
-            self.magic, self.status
-        )
-    }
-}
+const TIMEOUT_MS: u64 = 70;
 
 struct Fuzzer {
-    forksrv: ForkServer<FeedbackData>,
-    infosrv: ForkServer<FeedbackData>,
-    bitmap: [u8; 1 << 15],
+    forksrv: ForkServer,
+    infosrv: ForkServer,
+    bitmap: Vec<u8>,
+    last_exit: ExitReason,
     objs: ObjectSpace,
     pub target_path: String,
     pub target_args: Vec<String>,
@@ -247,15 +233,31 @@ struct Fuzzer {
 
 impl Fuzzer {
     pub fn new(path: String, args: Vec<String>) -> Result<Self, SubprocessError> {
-        let fs =
-            ForkServer::<FeedbackData>::new(&path, &args, "/dev/null".into(), "/dev/null".into())?;
-        let is =
-            ForkServer::<FeedbackData>::new(&path, &args, "/tmp/out".into(), "/tmp/err".into())?;
+        let options = ForkServerOptions {
+            timeout_ms: TIMEOUT_MS,
+            ..ForkServerOptions::default()
+        };
+        let fs = ForkServer::new(
+            &path,
+            &args,
+            "/dev/null".into(),
+            "/dev/null".into(),
+            &options,
+        )?;
+        let is = ForkServer::new(
+            &path,
+            &args,
+            "/tmp/out".into(),
+            "/tmp/err".into(),
+            &options,
+        )?;
         let objs = ObjectSpace::new();
+        let bitmap = vec![0; fs.bitmap().len()];
         return Ok(Fuzzer {
             forksrv: fs,
             infosrv: is,
-            bitmap: [0; 1 << 15],
+            bitmap,
+            last_exit: ExitReason::Normal(0),
             objs,
             target_path: path,
             target_args: args,
@@ -263,21 +265,17 @@ impl Fuzzer {
     }
 
     pub fn run_on(&mut self, input: &[u8]) -> Result<(), SubprocessError> {
-        self.forksrv.get_shared_mut().magic = 0x1337133713371337;
-        self.forksrv.run_on(&input)?;
-        if self.forksrv.get_shared().magic != 0x5a5a55464c464f52 {
-            return descr_err("Failed to get magic value from subprocess");
-        }
+        self.last_exit = self.forksrv.run_on(&input)?;
         return Ok(());
     }
 
     pub fn term_signal_i8(&self) -> Option<i8> {
-        let status = self.forksrv.get_shared().status;
-        //WIFSIGNALED
-        if ((((status & 0x7f) + 1) as i8) >> 1) > 0 {
-            return Some((status & 0x7f) as i8);
+        match self.last_exit {
+            ExitReason::Signaled(sig) => return Some(sig as i8),
+            //runs that take too long are killed with SIGALRM
+            ExitReason::Timeouted => return Some(Signal::SIGALRM as i8),
+            _ => return None,
         }
-        return None;
     }
 
     pub fn term_signal(&self) -> Option<Signal> {
@@ -287,7 +285,7 @@ impl Fuzzer {
 
     pub fn has_new_bit(&mut self) -> bool {
         let mut res = false;
-        let run_bitmap = self.forksrv.get_shared().run_bitmap;
+        let run_bitmap = self.forksrv.bitmap();
         for (i, elem) in self.bitmap.iter_mut().enumerate() {
             if (*elem | run_bitmap[i]) != *elem {
                 *elem |= run_bitmap[i];
@@ -5,7 +5,7 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 
 use loaded_dice::LoadedDiceSampler;
-use rand::{sample, thread_rng, Rng, StdRng};
+use rand::{thread_rng, Rng, StdRng};
 
 use newtypes::{NTermID, RuleID};
 use rule::Rule;
@@ -26,6 +26,7 @@ pub struct Context {
     rhs_and_n_to_count: HashMap<(Vec<NTermID>, usize), u16>,
     rhs_and_n_to_count_u32: HashMap<(Vec<NTermID>, usize), u32>,
     rule_id_to_possible_lens: HashMap<RuleID, Vec<usize>>,
+    rules_to_weight: HashMap<RuleID, f64>,
     max_len: usize,
     dumb: bool,
 }
@@ -42,6 +43,8 @@ pub struct SerializableContext {
     rhs_and_n_to_count: HashMap<(Vec<NTermID>, usize), u16>,
     rhs_and_n_to_count_u32: HashMap<(Vec<NTermID>, usize), u32>,
     rule_id_to_possible_lens: HashMap<RuleID, Vec<usize>>,
+    #[serde(default)]
+    rules_to_weight: HashMap<RuleID, f64>,
     max_len: usize,
     pub hash_of_original: u64,
     pub dumb: bool,
@@ -66,6 +69,7 @@ impl Context {
             nt_and_n_to_count: HashMap::new(),
             rhs_and_n_to_count: HashMap::new(),
             rule_id_to_possible_lens: HashMap::new(),
+            rules_to_weight: HashMap::new(),
             max_len: 0,
             dumb,
         };
@@ -92,6 +96,7 @@ impl Context {
             nt_and_n_to_count: self.nt_and_n_to_count.clone(),
             rhs_and_n_to_count: self.rhs_and_n_to_count.clone(),
             rule_id_to_possible_lens: self.rule_id_to_possible_lens.clone(),
+            rules_to_weight: self.rules_to_weight.clone(),
             max_len: self.max_len,
             hash_of_original,
             dumb: self.dumb,
@@ -117,6 +122,7 @@ impl Context {
             nt_and_n_to_count: saved_context.nt_and_n_to_count,
             rhs_and_n_to_count: saved_context.rhs_and_n_to_count,
             rule_id_to_possible_lens: saved_context.rule_id_to_possible_lens,
+            rules_to_weight: saved_context.rules_to_weight,
             max_len,
             dumb,
         };
@@ -138,6 +144,10 @@ impl Context {
         return self.get_rule(r).nonterm();
     }
 
+    pub fn get_num_rules(&self) -> usize {
+        return self.rules.len();
+    }
+
     pub fn get_num_children(&self, r: RuleID) -> usize{
         return self.get_rule(r).number_of_nonterms();
     }
@@ -154,6 +164,16 @@ impl Context {
         return rid;
     }
 
+    //Rules with a higher weight are picked more often during generation. The default weight is 1.
+    pub fn set_rule_weight(&mut self, r: RuleID, weight: f64) {
+        assert!(weight > 0.0, "rule weights have to be positive");
+        self.rules_to_weight.insert(r, weight);
+    }
+
+    pub fn get_rule_weight(&self, r: RuleID) -> f64 {
+        return *self.rules_to_weight.get(&r).unwrap_or(&1.0);
+    }
+
     pub fn add_term_rule(&mut self, nt: &str, term: &Vec<u8>) -> RuleID {
         let rid = self.rules.len().into();
         let ntid = self.aquire_nt_id(nt);
@@ -289,7 +309,7 @@ impl Context {
                     .get(&(*nterm, i))
                     .expect("RAND_2374448501") != &0
                 {
-                    let mut norm_factor: u64 = 0;
+                    let mut norm_factor: f64 = 0.0;
                     let rules_for_nt = self
                         .nts_to_rules
                         .get(&nterm)
@@ -298,17 +318,16 @@ impl Context {
                     let mut rule_probabilities: Vec<f64> = vec![0.0; rules_for_nt.len()];
                     for (x, rule_id) in rules_for_nt.iter().enumerate() {
                         let nterms = self.get_rule(rule_id.clone()).nonterms().clone();
-                        rule_probabilities[x] +=
-                            self.count_possibilities_rule(&nterms, i - 1)
-                                .to_u32()
-                                .unwrap_or(u32::max_value()) as f64;
-                        norm_factor +=
+                        let weighted_count =
                             self.count_possibilities_rule(&nterms, i - 1)
                                 .to_u32()
-                                .unwrap_or(u32::max_value()) as u64;
+                                .unwrap_or(u32::max_value()) as f64
+                                * self.get_rule_weight(*rule_id);
+                        rule_probabilities[x] += weighted_count;
+                        norm_factor += weighted_count;
                     }
                     for x in 0..rule_probabilities.len() {
-                        rule_probabilities[x] /= norm_factor as f64;
+                        rule_probabilities[x] /= norm_factor;
                     }
                     // println!("Sampler: Nterm: {};\tDepth: {};\t\tRule probabilities: {:?}", self.nt_ids_to_name.get(&nterm).expect("RAND_1038242446"), i, rule_probabilities);
                     let sampler = LoadedDiceSampler::new(
@@ -559,12 +578,22 @@ impl Context {
         }
     }
 
+    //Picks one of the rules that fit into max_len, rules with a higher weight more often
     fn dumb_get_random_rule_for_nt(&self, nt: NTermID, max_len: usize) -> RuleID {
         let applicable_rules = self.nts_to_rules[&nt]
             .iter()
-            .take_while(|r| self.rules_to_min_size[r] <= max_len);
-        match sample(&mut thread_rng(), applicable_rules, 1).pop() {
-            Some(rule) => return *rule,
+            .take_while(|r| self.rules_to_min_size[r] <= max_len)
+            .collect::<Vec<_>>();
+        let total_weight: f64 = applicable_rules.iter().map(|r| self.get_rule_weight(**r)).sum();
+        let mut choice = thread_rng().gen::<f64>() * total_weight;
+        for rule in applicable_rules.iter() {
+            choice -= self.get_rule_weight(**rule);
+            if choice < 0.0 {
+                return **rule;
+            }
+        }
+        match applicable_rules.last() {
+            Some(rule) => return **rule,
             None => panic!(
                 "there is no way to derive {} within {} steps",
                 self.nt_ids_to_name[&nt], max_len
@@ -641,6 +670,11 @@ mod tests {
         assert_eq!(r.nonterms()[0], ctx.nt_id("A"));
         assert_eq!(r.nonterms()[1], ctx.nt_id("B"));
         assert_eq!(r.nonterms()[2], ctx.nt_id("C"));
+
+        let r = Rule::from_format(&mut ctx, "F", "{A:len=u16be}{B:b}{C:num=zigzag}");
+        assert_eq!(r.children().len(), 5);
+        assert_eq!(r.nonterms().len(), 3);
+        assert_eq!(r.to_format(&ctx), "{A:len=u16be}{B}{C:num=zigzag}");
     }
 
     #[test]
@@ -772,6 +806,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rule_weights() {
+        for dumb in [false, true].iter() {
+            let mut ctx = Context::with_dump(*dumb);
+            let _ = ctx.add_rule("S", "a");
+            let r1 = ctx.add_rule("S", "b");
+            ctx.set_rule_weight(r1, 50.0);
+            ctx.initialize(5, false);
+            assert_eq!(ctx.get_rule_weight(r1), 50.0);
+
+            let mut num_b = 0;
+            for _ in 0..100 {
+                let tree = ctx.generate_tree_from_nt(ctx.nt_id("S"), 1);
+                let mut data: Vec<u8> = vec![];
+                tree.unparse_to(&ctx, &mut data).expect("RAND_729560476");
+                if data == b"b" {
+                    num_b += 1;
+                }
+            }
+            assert!(num_b > 75, "dumb: {}, num_b: {}", dumb, num_b);
+        }
+    }
+
     #[test]
     fn test_context_serialization() {
         let mut ctx = Context::new();
@@ -0,0 +1,177 @@
+use std::fmt;
+
+//How a number is written by an encoded nonterminal
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum NumberFormat {
+    //LEB128 as used by protobuf, negative numbers take ten bytes
+    Varint,
+    //Varint of the zigzag encoded number (protobuf sint32/sint64)
+    Zigzag,
+    U8,
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+    U64Le,
+    U64Be,
+    //IEEE 754 single and double precision, little endian
+    Float,
+    Double,
+    //ASCII digits, e.g. for Content-Length
+    Decimal,
+}
+
+static FORMAT_NAMES: [(&str, NumberFormat); 12] = [
+    ("varint", NumberFormat::Varint),
+    ("zigzag", NumberFormat::Zigzag),
+    ("u8", NumberFormat::U8),
+    ("u16le", NumberFormat::U16Le),
+    ("u16be", NumberFormat::U16Be),
+    ("u32le", NumberFormat::U32Le),
+    ("u32be", NumberFormat::U32Be),
+    ("u64le", NumberFormat::U64Le),
+    ("u64be", NumberFormat::U64Be),
+    ("float", NumberFormat::Float),
+    ("double", NumberFormat::Double),
+    ("decimal", NumberFormat::Decimal),
+];
+
+impl NumberFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        return FORMAT_NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, f)| f);
+    }
+
+    pub fn name(&self) -> &'static str {
+        return FORMAT_NAMES
+            .iter()
+            .find(|&&(_, f)| f == *self)
+            .map(|&(n, _)| n)
+            .expect("RAND_1502231785");
+    }
+
+    //Values that don't fit into the format are truncated
+    pub fn encode_int(&self, value: i64) -> Vec<u8> {
+        return match *self {
+            NumberFormat::Varint => varint(value as u64),
+            NumberFormat::Zigzag => varint(((value << 1) ^ (value >> 63)) as u64),
+            NumberFormat::U8 => vec![value as u8],
+            NumberFormat::U16Le => (value as u16).to_le_bytes().to_vec(),
+            NumberFormat::U16Be => (value as u16).to_be_bytes().to_vec(),
+            NumberFormat::U32Le => (value as u32).to_le_bytes().to_vec(),
+            NumberFormat::U32Be => (value as u32).to_be_bytes().to_vec(),
+            NumberFormat::U64Le => (value as u64).to_le_bytes().to_vec(),
+            NumberFormat::U64Be => (value as u64).to_be_bytes().to_vec(),
+            NumberFormat::Float => (value as f32).to_le_bytes().to_vec(),
+            NumberFormat::Double => (value as f64).to_le_bytes().to_vec(),
+            NumberFormat::Decimal => value.to_string().into_bytes(),
+        };
+    }
+
+    //Parses a decimal number (integers up to u64::MAX, floats for Float and Double)
+    pub fn encode_text(&self, text: &str) -> Option<Vec<u8>> {
+        let text = text.trim();
+        match *self {
+            NumberFormat::Float => return text.parse::<f32>().ok().map(|f| f.to_le_bytes().to_vec()),
+            NumberFormat::Double => {
+                return text.parse::<f64>().ok().map(|f| f.to_le_bytes().to_vec())
+            }
+            _ => {}
+        }
+        let value = match text.parse::<i64>() {
+            Ok(value) => value,
+            Err(_) => text.parse::<u64>().ok()? as i64,
+        };
+        return Some(self.encode_int(value));
+    }
+}
+
+fn varint(mut value: u64) -> Vec<u8> {
+    let mut res = vec![];
+    while value >= 0x80 {
+        res.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    res.push(value as u8);
+    return res;
+}
+
+//Written as {NT:len=FORMAT} or {NT:num=FORMAT} in a rule format. The encoding applies to the
+//output of the nonterminal it is attached to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum Encoding {
+    //The length of the output in bytes, followed by the output itself
+    Length(NumberFormat),
+    //The output is a decimal number which is replaced by its encoding. Outputs that are no number
+    //(e.g. after a mutation) are written unchanged.
+    Number(NumberFormat),
+}
+
+impl Encoding {
+    //Returns Ok(None) for descriptions that aren't encodings
+    pub fn from_description(descr: &str) -> Result<Option<Self>, String> {
+        let (kind, name) = match descr.find('=') {
+            Some(i) => (&descr[..i], &descr[i + 1..]),
+            None => return Ok(None),
+        };
+        let format = NumberFormat::from_name(name);
+        return match (kind, format) {
+            ("len", Some(format)) => Ok(Some(Encoding::Length(format))),
+            ("num", Some(format)) => Ok(Some(Encoding::Number(format))),
+            ("len", None) | ("num", None) => Err(format!("unknown number format {:?}", name)),
+            _ => Err(format!("unknown encoding {:?}, expected len or num", kind)),
+        };
+    }
+
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        return match *self {
+            Encoding::Length(format) => {
+                let mut res = format.encode_int(data.len() as i64);
+                res.extend_from_slice(data);
+                res
+            }
+            Encoding::Number(format) => String::from_utf8(data.to_vec())
+                .ok()
+                .and_then(|text| format.encode_text(&text))
+                .unwrap_or(data.to_vec()),
+        };
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            &Encoding::Length(format) => write!(f, "len={}", format.name()),
+            &Encoding::Number(format) => write!(f, "num={}", format.name()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_encodings() {
+        let len = Encoding::from_description("len=varint").expect("RAND_3377946310");
+        assert_eq!(len, Some(Encoding::Length(NumberFormat::Varint)));
+        let data = vec![b'a'; 300];
+        assert_eq!(&len.expect("RAND_2395452592").apply(&data)[..3], &[0xac, 0x02, b'a']);
+
+        let num = Encoding::Number(NumberFormat::Zigzag);
+        assert_eq!(num.apply(b"-2"), vec![3]);
+        assert_eq!(num.to_string(), "num=zigzag");
+        assert_eq!(Encoding::Number(NumberFormat::Varint).apply(b"-1").len(), 10);
+        assert_eq!(
+            Encoding::Number(NumberFormat::Varint).apply(b"18446744073709551615"),
+            Encoding::Number(NumberFormat::Varint).apply(b"-1")
+        );
+        assert_eq!(Encoding::Number(NumberFormat::U16Be).apply(b"258"), vec![1, 2]);
+        assert_eq!(Encoding::Number(NumberFormat::Float).apply(b"1.5"), vec![0, 0, 0xc0, 0x3f]);
+        assert_eq!(Encoding::Number(NumberFormat::U8).apply(b"x"), b"x".to_vec());
+        assert_eq!(Encoding::Length(NumberFormat::Decimal).apply(b"ab"), b"2ab".to_vec());
+
+        assert_eq!(Encoding::from_description("a"), Ok(None));
+        assert!(Encoding::from_description("len=u24").is_err());
+        assert!(Encoding::from_description("size=u8").is_err());
+    }
+}
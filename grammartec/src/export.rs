@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+
+use context::Context;
+use newtypes::{NTermID, RuleID};
+use rule::RuleChild;
+
+static ANTLR_KEYWORDS: [&str; 14] = [
+    "catch", "channels", "finally", "fragment", "grammar", "import", "lexer", "locals", "mode",
+    "options", "parser", "returns", "throws", "tokens",
+];
+
+//Writes the rules as the JSON list of [nonterminal, format] pairs that the loaders read
+pub fn to_json<W: Write>(ctx: &Context, w: &mut W) -> Result<(), Error> {
+    let rules = rules_in_order(ctx);
+    //Literals ending with a backslash right before a nonterminal need a rule for the backslash
+    let names = rules.iter().map(|&(nt, _)| ctx.nt_id_to_s(nt)).collect::<HashSet<_>>();
+    let backslash = unused_name(&names, "BACKSLASH");
+    let mut needs_backslash = false;
+    let mut first = true;
+    w.write_all(b"[\n")?;
+    for (_, rule_ids) in rules {
+        for rule_id in rule_ids {
+            if !first {
+                w.write_all(b",\n")?;
+            }
+            first = false;
+            let rule = ctx.get_rule(rule_id);
+            let format = rule.to_format_with(ctx, || {
+                needs_backslash = true;
+                backslash.clone()
+            });
+            write!(
+                w,
+                "  [{}, {}]",
+                json_string(&ctx.nt_id_to_s(rule.nonterm())),
+                json_string(&format)
+            )?;
+        }
+    }
+    if needs_backslash {
+        write!(w, ",\n  [{}, {}]", json_string(&backslash), json_string("\\"))?;
+    }
+    w.write_all(b"\n]\n")?;
+    return Ok(());
+}
+
+//Returns base, or base with the smallest number >= 2 appended that isn't in names
+pub fn unused_name(names: &HashSet<String>, base: &str) -> String {
+    if !names.contains(base) {
+        return base.to_string();
+    }
+    let mut i = 2;
+    while names.contains(&format!("{}{}", base, i)) {
+        i += 1;
+    }
+    return format!("{}{}", base, i);
+}
+
+//Writes a human readable BNF version of the grammar, one alternative per line
+pub fn to_bnf<W: Write>(ctx: &Context, w: &mut W) -> Result<(), Error> {
+    for (nt, rules) in rules_in_order(ctx) {
+        let lhs = format!("<{}> ::=", ctx.nt_id_to_s(nt));
+        let indent = " ".repeat(lhs.len() - 1);
+        for (i, rule_id) in rules.iter().enumerate() {
+            let mut symbols = vec![];
+            let mut encoding = None;
+            for child in ctx.get_rule(*rule_id).children().iter() {
+                match child {
+                    &RuleChild::Term(ref data) | &RuleChild::CustomTerm(ref data) => {
+                        symbols.push(bnf_literal(&String::from_utf8_lossy(data)))
+                    }
+                    &RuleChild::NTerm(n) => match encoding.take() {
+                        Some(enc) => symbols.push(format!("<{}:{}>", ctx.nt_id_to_s(n), enc)),
+                        None => symbols.push(format!("<{}>", ctx.nt_id_to_s(n))),
+                    },
+                    &RuleChild::Encode(enc) => encoding = Some(enc),
+                }
+            }
+            if symbols.len() == 0 {
+                symbols.push("\"\"".to_string());
+            }
+            if i == 0 {
+                writeln!(w, "{} {}", lhs, symbols.join(" "))?;
+            } else {
+                writeln!(w, "{}| {}", indent, symbols.join(" "))?;
+            }
+        }
+        writeln!(w, "")?;
+    }
+    return Ok(());
+}
+
+//Writes the grammar as a combined ANTLR4 grammar. Every nonterminal becomes a parser rule, so the
+//names are lowercased (and made unique) as ANTLR requires.
+pub fn to_antlr<W: Write>(ctx: &Context, grammar_name: &str, w: &mut W) -> Result<(), Error> {
+    let rules = rules_in_order(ctx);
+    let names = antlr_names(ctx, &rules);
+    writeln!(w, "grammar {};", grammar_name)?;
+    for (nt, rule_ids) in rules.iter() {
+        writeln!(w, "")?;
+        writeln!(w, "{}", names[nt])?;
+        for (i, rule_id) in rule_ids.iter().enumerate() {
+            let mut symbols = vec![];
+            for child in ctx.get_rule(*rule_id).children().iter() {
+                match child {
+                    &RuleChild::Term(ref data) | &RuleChild::CustomTerm(ref data) => {
+                        if data.len() > 0 {
+                            symbols.push(antlr_literal(&String::from_utf8_lossy(data)))
+                        }
+                    }
+                    &RuleChild::NTerm(n) => symbols.push(names[&n].clone()),
+                    //ANTLR can't compute lengths, the nonterminal is exported without encoding
+                    &RuleChild::Encode(_) => {}
+                }
+            }
+            let sep = if i == 0 { ":" } else { "|" };
+            if symbols.len() == 0 {
+                writeln!(w, "   {}", sep)?;
+            } else {
+                writeln!(w, "   {} {}", sep, symbols.join(" "))?;
+            }
+        }
+        writeln!(w, "   ;")?;
+    }
+    return Ok(());
+}
+
+//Writes the grammar to path in the format given by its extension: .json, .bnf or .g4. The grammar
+//of an ANTLR export is named after the file.
+pub fn write_by_extension<P: AsRef<Path>>(ctx: &Context, path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if !["json", "bnf", "g4"].contains(&extension) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown grammar format {:?}, use .json, .bnf or .g4", path),
+        ));
+    }
+    let mut file = File::create(path)?;
+    match extension {
+        "json" => return to_json(ctx, &mut file),
+        "bnf" => return to_bnf(ctx, &mut file),
+        _ => {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            return to_antlr(ctx, &name, &mut file);
+        }
+    }
+}
+
+//Returns all nonterminals (in the order their first rule was added) with their rules (in the order
+//they were added). The loaders wrap the grammar in an artificial START -> {first} rule. This
+//wrapper is dropped and the wrapped nonterminal moved to the front, such that loading the export
+//again yields the same grammar instead of START -> {START}.
+fn rules_in_order(ctx: &Context) -> Vec<(NTermID, Vec<RuleID>)> {
+    let mut order = vec![];
+    let mut nts_to_rules: HashMap<NTermID, Vec<RuleID>> = HashMap::new();
+    for i in 0..ctx.get_num_rules() {
+        let rule_id = RuleID::from(i);
+        let nt = ctx.get_nt(rule_id);
+        if !nts_to_rules.contains_key(&nt) {
+            order.push(nt);
+        }
+        nts_to_rules.entry(nt).or_insert_with(|| vec![]).push(rule_id);
+    }
+    if let Some((start, inner)) = find_start_wrapper(ctx, &order, &nts_to_rules) {
+        order.retain(|nt| *nt != start && *nt != inner);
+        order.insert(0, inner);
+        nts_to_rules.remove(&start);
+    }
+    return order
+        .into_iter()
+        .map(|nt| {
+            let rules = nts_to_rules.remove(&nt).expect("RAND_2779237453");
+            (nt, rules)
+        })
+        .collect();
+}
+
+fn find_start_wrapper(
+    ctx: &Context,
+    order: &Vec<NTermID>,
+    nts_to_rules: &HashMap<NTermID, Vec<RuleID>>,
+) -> Option<(NTermID, NTermID)> {
+    let start = *order.iter().find(|nt| ctx.nt_id_to_s(**nt) == "START")?;
+    let start_rules = &nts_to_rules[&start];
+    if start_rules.len() != 1 {
+        return None;
+    }
+    let inner = match ctx.get_rule(start_rules[0]).children().as_slice() {
+        &[RuleChild::NTerm(inner)] if inner != start => inner,
+        _ => return None,
+    };
+    if !nts_to_rules.contains_key(&inner) {
+        return None;
+    }
+    for i in 0..ctx.get_num_rules() {
+        if ctx.get_rule(RuleID::from(i)).nonterms().contains(&start) {
+            return None;
+        }
+    }
+    return Some((start, inner));
+}
+
+fn antlr_names(
+    ctx: &Context,
+    rules: &Vec<(NTermID, Vec<RuleID>)>,
+) -> HashMap<NTermID, String> {
+    //Nonterminals without rules still need a name if they are referenced somewhere
+    let mut nts = rules.iter().map(|&(nt, _)| nt).collect::<Vec<_>>();
+    for &(_, ref rule_ids) in rules.iter() {
+        for rule_id in rule_ids.iter() {
+            for nt in ctx.get_rule(*rule_id).nonterms().iter() {
+                if !nts.contains(nt) {
+                    nts.push(*nt);
+                }
+            }
+        }
+    }
+    let mut names = HashMap::new();
+    let mut used = HashSet::new();
+    for nt in nts {
+        let mut name = ctx.nt_id_to_s(nt).to_lowercase().replace("-", "_");
+        while used.contains(&name) || ANTLR_KEYWORDS.contains(&name.as_str()) {
+            name.push('1');
+        }
+        used.insert(name.clone());
+        names.insert(nt, name);
+    }
+    return names;
+}
+
+fn json_string(string: &str) -> String {
+    let mut res = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            _ if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+    return res;
+}
+
+fn bnf_literal(string: &str) -> String {
+    let mut res = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            _ if c.is_control() => res.push_str(&format!("\\x{:02x}", c as u32)),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+    return res;
+}
+
+fn antlr_literal(string: &str) -> String {
+    let mut res = String::from("'");
+    for c in string.chars() {
+        match c {
+            '\'' => res.push_str("\\'"),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            '\x08' => res.push_str("\\b"),
+            '\x0C' => res.push_str("\\f"),
+            _ if c.is_control() => res.push_str(&format!("\\u{:04X}", c as u32)),
+            _ => res.push(c),
+        }
+    }
+    res.push('\'');
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use context::Context;
+    use newtypes::RuleID;
+    use export::{to_antlr, to_bnf, to_json, write_by_extension};
+    use std::fs;
+    use tempfile;
+
+    fn example_context() -> Context {
+        let mut ctx = Context::new();
+        let _ = ctx.add_rule("START", "{PROG}");
+        let _ = ctx.add_rule("PROG", "{STMT}\n{PROG}");
+        let _ = ctx.add_rule("PROG", "");
+        let _ = ctx.add_rule("STMT", "x = \\{'a' => {VAL}\\}");
+        let _ = ctx.add_rule("VAL", "\"1\"");
+        return ctx;
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let ctx = example_context();
+        let mut data = vec![];
+        to_json(&ctx, &mut data).expect("RAND_4225515249");
+        let json = String::from_utf8(data.clone()).expect("RAND_3909198640");
+        assert_eq!(
+            json,
+            "[\n  [\"PROG\", \"{STMT}\\n{PROG}\"],\n  [\"PROG\", \"\"],\n  \
+             [\"STMT\", \"x = \\\\{'a' => {VAL}\\\\}\"],\n  [\"VAL\", \"\\\"1\\\"\"]\n]\n"
+        );
+
+        let mut ctx2 = Context::new();
+        let _ = ctx2.add_rule("START", "{PROG}");
+        let _ = ctx2.add_rule("PROG", "{STMT}\n{PROG}");
+        let _ = ctx2.add_rule("PROG", "");
+        let _ = ctx2.add_rule("STMT", &ctx.get_rule(RuleID::from(3)).to_format(&ctx));
+        let _ = ctx2.add_rule("VAL", "\"1\"");
+        let mut data2 = vec![];
+        to_json(&ctx2, &mut data2).expect("RAND_1956629463");
+        assert_eq!(data, data2);
+    }
+
+    #[test]
+    fn bnf_output() {
+        let ctx = example_context();
+        let mut data = vec![];
+        to_bnf(&ctx, &mut data).expect("RAND_2368515012");
+        let bnf = String::from_utf8(data).expect("RAND_1787472450");
+        assert!(bnf.contains("<PROG> ::= <STMT> \"\\n\" <PROG>\n         | \"\"\n"));
+        assert!(bnf.contains("<VAL> ::= \"\\\"1\\\"\"\n"));
+        assert!(!bnf.contains("<START>"));
+    }
+
+    #[test]
+    fn antlr_output() {
+        let mut ctx = example_context();
+        let _ = ctx.add_rule("MODE", "{VAL}");
+        let _ = ctx.add_rule("Mode", "a");
+        let mut data = vec![];
+        to_antlr(&ctx, "example", &mut data).expect("RAND_720140927");
+        let g4 = String::from_utf8(data).expect("RAND_3492516171");
+        assert!(g4.starts_with("grammar example;\n\nprog\n   : stmt '\\n' prog\n   |\n   ;\n"));
+        assert!(g4.contains("stmt\n   : 'x = {\\'a\\' => ' val '}'\n   ;\n"));
+        assert!(g4.contains("mode1\n   : val\n   ;\n"));
+        assert!(g4.contains("mode11\n   : 'a'\n   ;\n"));
+    }
+
+    #[test]
+    fn check_write_by_extension() {
+        let ctx = example_context();
+        let tmp = tempfile::tempdir().expect("RAND_1760282365");
+        let mut json = vec![];
+        to_json(&ctx, &mut json).expect("RAND_2980416233");
+        write_by_extension(&ctx, tmp.path().join("g.json")).expect("RAND_3619014770");
+        assert_eq!(fs::read(tmp.path().join("g.json")).expect("RAND_411835427"), json);
+        let mut antlr = vec![];
+        to_antlr(&ctx, "Calc", &mut antlr).expect("RAND_1325741509");
+        write_by_extension(&ctx, tmp.path().join("Calc.g4")).expect("RAND_2493866207");
+        assert_eq!(fs::read(tmp.path().join("Calc.g4")).expect("RAND_3093175246"), antlr);
+        assert!(write_by_extension(&ctx, tmp.path().join("g.txt")).is_err());
+        assert!(!tmp.path().join("g.txt").exists());
+    }
+}
@@ -4,17 +4,31 @@
 extern crate lazy_static;
 #[macro_use]
 extern crate serde_derive;
+extern crate abnf_parser;
 extern crate afl_mutator;
+extern crate antlr_parser;
 extern crate forksrv;
 extern crate loaded_dice;
 extern crate num;
 extern crate rand;
 extern crate regex;
+extern crate ron;
+extern crate rule_format;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+#[cfg(test)]
+extern crate tempfile;
+extern crate toml;
 
 pub mod chunkstore;
 pub mod context;
+pub mod encoding;
+pub mod export;
+pub mod loader;
 pub mod mutator;
 pub mod newtypes;
 pub mod rule;
+pub mod transform;
 pub mod tree;
 pub mod recursion_info;
@@ -0,0 +1,1104 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use abnf_parser;
+use antlr_parser::{AntlrParser, LoweringOptions};
+use regex::{Captures, Regex};
+use ron;
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json;
+use serde_yaml;
+use toml;
+
+use context::{Context, SerializableContext};
+use encoding::Encoding;
+use newtypes::RuleID;
+
+//Grammars can be written in the following formats (chosen by file extension):
+//
+//ANTLR (.g4): converted by antlr_parser, the start symbol is the parser rule that ends with EOF or
+//else the first parser rule no other rule uses. Imported grammars and the lexer grammar named by
+//tokenVocab are read from the same directory. Tokens are concatenated without a separator unless
+//the LoweringOptions passed to load_grammar_with (ContextConfig.antlr for load_context) set one.
+//
+//ABNF (.abnf): converted by abnf_parser, the first rule is the start symbol. The core rules of
+//RFC 5234 (ALPHA, DIGIT, CRLF, ...) can be used without defining them. Unlike ANTLR grammars,
+//ABNF grammars can be included by the other formats.
+//
+//JSON (.json): a list of [nonterminal, format] pairs. Additionally the following directives can be
+//used:
+//  ["@include", "path"]              adds all rules of another grammar file
+//  ["@include", "path", "Namespace"]  same, but every nonterminal defined by that file is renamed
+//                                     to Namespace-NAME. Nonterminals the included file only
+//                                     references keep their name, so the including grammar can
+//                                     fill them in.
+//  ["@override", "NT", "format"]     replaces all included rules of NT (repeat to add more)
+//  ["@extend", "NT", "format"]       adds another rule to an included NT
+//
+//YAML (.yaml/.yml), TOML (.toml) and RON (.ron): a document with the optional keys start, comment,
+//include (list of {path, namespace}), rules, override and extend. The last three map from a
+//nonterminal to one alternative or a list of alternatives. An alternative is either a format
+//string or a map {format, weight, tags, comment}. tags and comment are kept in the LoadedGrammar
+//but don't influence the Context. Includes are processed first, then rules, override and extend.
+//For example in YAML:
+//  start: PROG
+//  include:
+//    - {path: strings.yaml, namespace: Str}
+//  rules:
+//    PROG: "{STMT}"
+//    STMT:
+//      - "print({Str-STRING})"
+//      - format: |
+//          while {COND} do
+//            {STMT}
+//          end
+//        weight: 0.5
+//        tags: [loop]
+//
+//Paths are relative to the file containing the include, included files can use any format.
+//
+//load_context turns a grammar into an initialized Context. As this can take a while for large
+//grammars, the result is cached in <grammar>.gfc and reused as long as the hash of the loaded rules
+//and the tree size stay the same.
+
+#[derive(Debug)]
+pub enum LoaderError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    UnknownFormat(PathBuf),
+    IncludeCycle(Vec<PathBuf>),
+    NameCollision {
+        nt: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    UnknownNonterm(PathBuf, String),
+    UndefinedNonterm {
+        nt: String,
+        used_by: String,
+    },
+    InvalidNonterm(String),
+    InvalidEncoding {
+        nt: String,
+        used_by: String,
+        message: String,
+    },
+    UnproductiveNonterm(String),
+    UnknownStart(String),
+    TreeSizeTooSmall {
+        needed: usize,
+        max_tree_size: usize,
+    },
+}
+
+impl fmt::Display for LoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LoaderError::Io(ref path, ref err) => {
+                write!(f, "cannot read grammar file {}: {}", path.display(), err)
+            }
+            &LoaderError::Parse(ref path, ref err) => {
+                write!(f, "cannot parse grammar file {}: {}", path.display(), err)
+            }
+            &LoaderError::UnknownFormat(ref path) => write!(
+                f,
+                "unknown grammar format of {} (expected .g4, .abnf, .json, .yaml, .yml, .toml, .ron)",
+                path.display()
+            ),
+            &LoaderError::IncludeCycle(ref paths) => {
+                let chain = paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "include cycle: {}", chain.join(" -> "))
+            }
+            &LoaderError::NameCollision {
+                ref nt,
+                ref first,
+                ref second,
+            } => write!(
+                f,
+                "nonterminal {} is defined in {} and {} (use a namespace, override or extend)",
+                nt,
+                first.display(),
+                second.display()
+            ),
+            &LoaderError::UnknownNonterm(ref path, ref nt) => write!(
+                f,
+                "{}: cannot override or extend {}, it is not defined by an included grammar",
+                path.display(),
+                nt
+            ),
+            &LoaderError::UndefinedNonterm {
+                ref nt,
+                ref used_by,
+            } => write!(
+                f,
+                "nonterminal {} is used by a rule of {}, but there are no rules for it",
+                nt, used_by
+            ),
+            &LoaderError::InvalidNonterm(ref nt) => write!(
+                f,
+                "invalid nonterminal name {:?} (names start with A-Z and contain only \
+                 a-z, A-Z, 0-9, _ and -)",
+                nt
+            ),
+            &LoaderError::InvalidEncoding {
+                ref nt,
+                ref used_by,
+                ref message,
+            } => write!(
+                f,
+                "invalid encoding of nonterminal {} in a rule of {}: {}",
+                nt, used_by, message
+            ),
+            &LoaderError::UnproductiveNonterm(ref nt) => write!(
+                f,
+                "nonterminal {} can never be derived to a string without nonterminals",
+                nt
+            ),
+            &LoaderError::UnknownStart(ref nt) => {
+                write!(f, "start symbol {} is not defined by the grammar", nt)
+            }
+            &LoaderError::TreeSizeTooSmall {
+                needed,
+                max_tree_size,
+            } => write!(
+                f,
+                "the grammar needs a maximum tree size of at least {} (got {})",
+                needed, max_tree_size
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrammarRule {
+    #[serde(skip)]
+    pub nt: String,
+    pub format: String,
+    #[serde(default)]
+    pub weight: Option<f64>,
+    //tags and comment are only documentation for the grammar's authors and for tools reading the
+    //LoadedGrammar. They are parsed so grammars can carry them, but add_to_context ignores them
+    //and the exports don't write them.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl GrammarRule {
+    pub fn new(nt: &str, format: &str) -> Self {
+        return GrammarRule {
+            nt: nt.to_string(),
+            format: format.to_string(),
+            weight: None,
+            tags: vec![],
+            comment: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedGrammar {
+    pub start: String,
+    pub comment: Option<String>,
+    pub rules: Vec<GrammarRule>,
+}
+
+impl LoadedGrammar {
+    pub fn set_start(&mut self, start: &str) -> Result<(), LoaderError> {
+        if !self.rules.iter().any(|r| r.nt == start) {
+            return Err(LoaderError::UnknownStart(start.to_string()));
+        }
+        self.start = start.to_string();
+        return Ok(());
+    }
+
+    //Checks everything that would otherwise make Context panic: invalid names, nonterminals
+    //without rules and nonterminals that can't be derived to a string
+    pub fn validate(&self) -> Result<(), LoaderError> {
+        lazy_static! {
+            static ref NT_NAME: Regex = Regex::new(r"^[A-Z][a-zA-Z_\-0-9]*$").expect("RAND_2714483306");
+        }
+        let defined = self.rules.iter().map(|r| &r.nt).collect::<HashSet<_>>();
+        if !defined.contains(&self.start) {
+            return Err(LoaderError::UnknownStart(self.start.clone()));
+        }
+        for rule in self.rules.iter() {
+            if !NT_NAME.is_match(&rule.nt) {
+                return Err(LoaderError::InvalidNonterm(rule.nt.clone()));
+            }
+            for nt in nonterms_in_format(&rule.format) {
+                if !NT_NAME.is_match(&nt) {
+                    return Err(LoaderError::InvalidNonterm(nt));
+                }
+                if !defined.contains(&nt) {
+                    return Err(LoaderError::UndefinedNonterm {
+                        nt,
+                        used_by: rule.nt.clone(),
+                    });
+                }
+            }
+            for cap in TOKENIZER.captures_iter(&rule.format) {
+                if let (Some(nt), Some(descr)) = (cap.get(2), cap.get(3)) {
+                    if let Err(message) = Encoding::from_description(&descr.as_str()[1..]) {
+                        return Err(LoaderError::InvalidEncoding {
+                            nt: nt.as_str().to_string(),
+                            used_by: rule.nt.clone(),
+                            message,
+                        });
+                    }
+                }
+            }
+        }
+        let mut productive = HashSet::new();
+        let mut something_changed = true;
+        while something_changed {
+            something_changed = false;
+            for rule in self.rules.iter() {
+                if !productive.contains(&rule.nt)
+                    && nonterms_in_format(&rule.format)
+                        .iter()
+                        .all(|nt| productive.contains(nt))
+                {
+                    productive.insert(rule.nt.clone());
+                    something_changed = true;
+                }
+            }
+        }
+        for rule in self.rules.iter() {
+            if !productive.contains(&rule.nt) {
+                return Err(LoaderError::UnproductiveNonterm(rule.nt.clone()));
+            }
+        }
+        return Ok(());
+    }
+
+    //Hash of everything that ends up in the Context, used to detect stale cached contexts
+    pub fn content_hash(&self) -> u64 {
+        let mut s = DefaultHasher::new();
+        self.start.hash(&mut s);
+        for rule in self.rules.iter() {
+            rule.nt.hash(&mut s);
+            rule.format.hash(&mut s);
+            rule.weight.map(|w| w.to_bits()).hash(&mut s);
+        }
+        return s.finish();
+    }
+
+    //Adds the START -> {start} rule followed by all rules
+    pub fn add_to_context(&self, ctx: &mut Context) {
+        ctx.add_rule("START", &format!("{{{}}}", self.start));
+        for rule in self.rules.iter() {
+            let rid = ctx.add_rule(&rule.nt, &rule.format);
+            if let Some(weight) = rule.weight {
+                ctx.set_rule_weight(rid, weight);
+            }
+        }
+    }
+}
+
+enum Entry {
+    Include(String, Option<String>),
+    Rule(GrammarRule),
+    Override(GrammarRule),
+    Extend(GrammarRule),
+}
+
+//The content of a single grammar file, before includes are resolved
+struct GrammarFile {
+    start: Option<String>,
+    comment: Option<String>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StructuredGrammarFile {
+    #[serde(default)]
+    start: Option<String>,
+    #[serde(default)]
+    comment: Option<String>,
+    #[serde(default)]
+    include: Vec<IncludeSpec>,
+    #[serde(default)]
+    rules: OrderedRules,
+    #[serde(default, rename = "override")]
+    overrides: OrderedRules,
+    #[serde(default)]
+    extend: OrderedRules,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludeSpec {
+    path: String,
+    #[serde(default)]
+    namespace: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AlternativeSpec {
+    Format(String),
+    Rule(GrammarRule),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AlternativesSpec {
+    One(AlternativeSpec),
+    Many(Vec<AlternativeSpec>),
+}
+
+//A map from nonterminals to their alternatives that keeps the order of the file, such that the
+//default start symbol is the first nonterminal of the file
+#[derive(Default)]
+struct OrderedRules(Vec<GrammarRule>);
+
+impl<'de> Deserialize<'de> for OrderedRules {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RulesVisitor;
+        impl<'de> Visitor<'de> for RulesVisitor {
+            type Value = OrderedRules;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map from nonterminals to rules")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<OrderedRules, A::Error> {
+                let mut rules = vec![];
+                while let Some((nt, alternatives)) =
+                    map.next_entry::<String, AlternativesSpec>()?
+                {
+                    let alternatives = match alternatives {
+                        AlternativesSpec::One(alternative) => vec![alternative],
+                        AlternativesSpec::Many(alternatives) => alternatives,
+                    };
+                    for alternative in alternatives.into_iter() {
+                        let mut rule = match alternative {
+                            AlternativeSpec::Format(format) => GrammarRule::new(&nt, &format),
+                            AlternativeSpec::Rule(rule) => rule,
+                        };
+                        rule.nt = nt.clone();
+                        rules.push(rule);
+                    }
+                }
+                return Ok(OrderedRules(rules));
+            }
+        }
+        return deserializer.deserialize_map(RulesVisitor);
+    }
+}
+
+impl From<abnf_parser::Grammar> for GrammarFile {
+    fn from(grammar: abnf_parser::Grammar) -> Self {
+        return GrammarFile {
+            start: Some(grammar.start),
+            comment: None,
+            entries: grammar
+                .rules
+                .iter()
+                .map(|&(ref nt, ref format)| Entry::Rule(GrammarRule::new(nt, format)))
+                .collect(),
+        };
+    }
+}
+
+impl From<StructuredGrammarFile> for GrammarFile {
+    fn from(file: StructuredGrammarFile) -> Self {
+        let mut entries = vec![];
+        for inc in file.include.into_iter() {
+            entries.push(Entry::Include(inc.path, inc.namespace));
+        }
+        entries.extend(file.rules.0.into_iter().map(Entry::Rule));
+        entries.extend(file.overrides.0.into_iter().map(Entry::Override));
+        entries.extend(file.extend.0.into_iter().map(Entry::Extend));
+        return GrammarFile {
+            start: file.start,
+            comment: file.comment,
+            entries,
+        };
+    }
+}
+
+fn parse_json(content: &str) -> Result<GrammarFile, String> {
+    let json_entries: Vec<Vec<String>> =
+        serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let mut entries = vec![];
+    for entry in json_entries.into_iter() {
+        let parsed = match (entry.first().map(|d| d.as_str()).unwrap_or(""), entry.len()) {
+            ("@include", 2) => Entry::Include(entry[1].clone(), None),
+            ("@include", 3) => Entry::Include(entry[1].clone(), Some(entry[2].clone())),
+            ("@override", 3) => Entry::Override(GrammarRule::new(&entry[1], &entry[2])),
+            ("@extend", 3) => Entry::Extend(GrammarRule::new(&entry[1], &entry[2])),
+            (directive, 2) if !directive.starts_with("@") => {
+                Entry::Rule(GrammarRule::new(&entry[0], &entry[1]))
+            }
+            _ => return Err(format!("invalid entry {:?}", entry)),
+        };
+        entries.push(parsed);
+    }
+    return Ok(GrammarFile {
+        start: None,
+        comment: None,
+        entries,
+    });
+}
+
+fn parse_grammar_file(path: &Path, content: &str) -> Result<GrammarFile, LoaderError> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or(String::new());
+    let parsed = match extension.as_str() {
+        "json" => parse_json(content),
+        "yaml" | "yml" => serde_yaml::from_str::<StructuredGrammarFile>(content)
+            .map(GrammarFile::from)
+            .map_err(|e| e.to_string()),
+        "toml" => toml::from_str::<StructuredGrammarFile>(content)
+            .map(GrammarFile::from)
+            .map_err(|e| e.to_string()),
+        "ron" => ron::de::from_str::<StructuredGrammarFile>(content)
+            .map(GrammarFile::from)
+            .map_err(|e| e.to_string()),
+        "abnf" => abnf_parser::parse_str(content)
+            .map(GrammarFile::from)
+            .map_err(|e| e.to_string()),
+        _ => return Err(LoaderError::UnknownFormat(path.to_path_buf())),
+    };
+    let file = parsed.map_err(|e| LoaderError::Parse(path.to_path_buf(), e))?;
+    for entry in file.entries.iter() {
+        match entry {
+            &Entry::Rule(ref rule) | &Entry::Override(ref rule) | &Entry::Extend(ref rule) => {
+                if rule.weight.map(|w| !(w > 0.0)).unwrap_or(false) {
+                    return Err(LoaderError::Parse(
+                        path.to_path_buf(),
+                        format!(
+                            "weight of rule {} -> {:?} has to be positive",
+                            rule.nt, rule.format
+                        ),
+                    ));
+                }
+            }
+            &Entry::Include(..) => {}
+        }
+    }
+    return Ok(file);
+}
+
+pub struct ContextConfig {
+    //Overrides the start symbol chosen by the grammar
+    pub start: Option<String>,
+    pub max_tree_size: usize,
+    pub dumb: bool,
+    pub verbose: bool,
+    //Read and write <grammar>.gfc
+    pub use_cache: bool,
+    //How ANTLR grammars are converted
+    pub antlr: LoweringOptions,
+}
+
+impl ContextConfig {
+    pub fn new(max_tree_size: usize) -> Self {
+        return ContextConfig {
+            start: None,
+            max_tree_size,
+            dumb: false,
+            verbose: false,
+            use_cache: false,
+            antlr: LoweringOptions::new(),
+        };
+    }
+}
+
+//Loads the grammar at path and returns an initialized Context. NTermID 1 is always START.
+pub fn load_context<P: AsRef<Path>>(path: P, config: &ContextConfig) -> Result<Context, LoaderError> {
+    let mut grammar = load_grammar_with(path.as_ref(), &config.antlr)?;
+    if let Some(ref start) = config.start {
+        grammar.set_start(start)?;
+    }
+    grammar.validate()?;
+
+    let mut s = DefaultHasher::new();
+    grammar.content_hash().hash(&mut s);
+    config.max_tree_size.hash(&mut s);
+    config.antlr.hash(&mut s);
+    let hash = s.finish();
+    let mut cache_path = path.as_ref().as_os_str().to_owned();
+    cache_path.push(".gfc");
+    let cache_path = PathBuf::from(cache_path);
+
+    if config.use_cache {
+        if let Some(saved) = read_cached_context(&cache_path, hash) {
+            return Ok(Context::from_serialized_context(
+                saved,
+                config.verbose,
+                config.dumb,
+            ));
+        }
+    }
+    let mut ctx = Context::with_dump(config.dumb);
+    grammar.add_to_context(&mut ctx);
+    ctx.calc_min_len();
+    let needed = (0..ctx.get_num_rules())
+        .map(|i| {
+            let rule = ctx.get_rule(RuleID::from(i));
+            rule.nonterms()
+                .iter()
+                .fold(1, |sum, nt| sum + ctx.get_min_len_for_nt(*nt))
+        })
+        .max()
+        .unwrap_or(0);
+    //Context::initialize requires every rule to fit into max_tree_size + 2
+    if needed >= config.max_tree_size + 2 {
+        return Err(LoaderError::TreeSizeTooSmall {
+            needed: needed - 1,
+            max_tree_size: config.max_tree_size,
+        });
+    }
+    ctx.initialize(config.max_tree_size, config.verbose);
+    if config.use_cache {
+        //The cache only saves time, failing to write it is not an error
+        let serialized = ron::ser::to_string(&ctx.create_serializable_context(hash));
+        if let Ok(serialized) = serialized {
+            let _ = File::create(&cache_path).and_then(|mut f| f.write_all(serialized.as_bytes()));
+        }
+    }
+    return Ok(ctx);
+}
+
+fn read_cached_context(cache_path: &Path, hash: u64) -> Option<SerializableContext> {
+    let mut content = String::new();
+    File::open(cache_path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .ok()?;
+    let saved: SerializableContext = ron::de::from_str(&content).ok()?;
+    if saved.hash_of_original != hash {
+        return None;
+    }
+    return Some(saved);
+}
+
+pub fn load_grammar<P: AsRef<Path>>(path: P) -> Result<LoadedGrammar, LoaderError> {
+    return load_grammar_with(path, &LoweringOptions::new());
+}
+
+//Same as load_grammar, ANTLR grammars are converted with the given options
+pub fn load_grammar_with<P: AsRef<Path>>(
+    path: P,
+    antlr: &LoweringOptions,
+) -> Result<LoadedGrammar, LoaderError> {
+    if path.as_ref().extension().map(|e| e == "g4").unwrap_or(false) {
+        return load_antlr_grammar(path.as_ref(), antlr);
+    }
+    let mut stack = vec![];
+    let mut start = None;
+    let mut comment = None;
+    let rules = load_rules(path.as_ref(), &mut stack, &mut start, &mut comment)?;
+    let start = match start.or_else(|| rules.first().map(|r| r.rule.nt.clone())) {
+        Some(start) => start,
+        None => {
+            return Err(LoaderError::Parse(
+                path.as_ref().to_path_buf(),
+                "grammar does not contain any rules".to_string(),
+            ))
+        }
+    };
+    return Ok(LoadedGrammar {
+        start,
+        comment,
+        rules: rules.into_iter().map(|r| r.rule).collect(),
+    });
+}
+
+fn load_antlr_grammar(path: &Path, options: &LoweringOptions) -> Result<LoadedGrammar, LoaderError> {
+    let grammar = AntlrParser::with_options(options.clone())
+        .parse_files(&[path])
+        .map_err(|e| LoaderError::Parse(path.to_path_buf(), e.to_string()))?;
+    let rules = grammar
+        .rules
+        .iter()
+        .map(|&(ref nt, ref format)| GrammarRule::new(nt, format))
+        .collect::<Vec<_>>();
+    return Ok(LoadedGrammar {
+        start: grammar.start,
+        comment: None,
+        rules,
+    });
+}
+
+struct LoadedRule {
+    rule: GrammarRule,
+    origin: PathBuf,
+}
+
+//start and comment are taken from the outermost file. If it doesn't set a start symbol, the first
+//nonterminal defined by the file itself is used.
+fn load_rules(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    start: &mut Option<String>,
+    comment: &mut Option<String>,
+) -> Result<Vec<LoadedRule>, LoaderError> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| LoaderError::Io(path.to_path_buf(), e))?;
+    if let Some(pos) = stack.iter().position(|p| *p == path) {
+        let mut cycle = stack[pos..].to_vec();
+        cycle.push(path);
+        return Err(LoaderError::IncludeCycle(cycle));
+    }
+    let is_outermost = stack.len() == 0;
+    let mut content = String::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .map_err(|e| LoaderError::Io(path.clone(), e))?;
+    let file = parse_grammar_file(&path, &content)?;
+    if is_outermost {
+        *start = file.start;
+        *comment = file.comment;
+    }
+    stack.push(path.clone());
+
+    let mut rules: Vec<LoadedRule> = vec![];
+    //nonterminal -> file that defined it, for everything that came from an include
+    let mut included: HashMap<String, PathBuf> = HashMap::new();
+    let mut defined_here = HashSet::new();
+    let mut overridden = HashSet::new();
+    for entry in file.entries.into_iter() {
+        match entry {
+            Entry::Include(inc, namespace) => {
+                let inc_path = path.parent().expect("RAND_1293721242").join(&inc);
+                let mut inc_rules = load_rules(&inc_path, stack, &mut None, &mut None)?;
+                if let Some(namespace) = namespace {
+                    add_namespace(&mut inc_rules, &namespace);
+                }
+                let mut definers: HashMap<String, PathBuf> = HashMap::new();
+                for r in inc_rules.iter() {
+                    definers
+                        .entry(r.rule.nt.clone())
+                        .or_insert_with(|| r.origin.clone());
+                }
+                let mut already_present = HashSet::new();
+                for (nt, definer) in definers.into_iter() {
+                    if defined_here.contains(&nt) {
+                        return Err(LoaderError::NameCollision {
+                            nt,
+                            first: path.clone(),
+                            second: definer,
+                        });
+                    }
+                    let first = included.get(&nt).cloned();
+                    match first {
+                        //The same file was included twice (e.g. by two different includes)
+                        Some(ref first) if *first == definer => {
+                            already_present.insert(nt);
+                        }
+                        Some(first) => {
+                            return Err(LoaderError::NameCollision {
+                                nt,
+                                first,
+                                second: definer,
+                            })
+                        }
+                        None => {
+                            included.insert(nt, definer);
+                        }
+                    }
+                }
+                rules.extend(
+                    inc_rules
+                        .into_iter()
+                        .filter(|r| !already_present.contains(&r.rule.nt)),
+                );
+            }
+            Entry::Override(rule) => {
+                if !included.contains_key(&rule.nt) {
+                    return Err(LoaderError::UnknownNonterm(path.clone(), rule.nt));
+                }
+                if !overridden.contains(&rule.nt) {
+                    rules.retain(|r| r.rule.nt != rule.nt);
+                    overridden.insert(rule.nt.clone());
+                }
+                rules.push(LoadedRule {
+                    rule,
+                    origin: path.clone(),
+                });
+            }
+            Entry::Extend(rule) => {
+                if !included.contains_key(&rule.nt) {
+                    return Err(LoaderError::UnknownNonterm(path.clone(), rule.nt));
+                }
+                rules.push(LoadedRule {
+                    rule,
+                    origin: path.clone(),
+                });
+            }
+            Entry::Rule(rule) => {
+                if let Some(first) = included.get(&rule.nt) {
+                    return Err(LoaderError::NameCollision {
+                        nt: rule.nt,
+                        first: first.clone(),
+                        second: path.clone(),
+                    });
+                }
+                if is_outermost && start.is_none() {
+                    *start = Some(rule.nt.clone());
+                }
+                defined_here.insert(rule.nt.clone());
+                rules.push(LoadedRule {
+                    rule,
+                    origin: path.clone(),
+                });
+            }
+        }
+    }
+    stack.pop();
+    return Ok(rules);
+}
+
+lazy_static! {
+    //Same tokenizer as Rule::tokenize, group 2 is the name of a nonterminal
+    static ref TOKENIZER: Regex =
+        Regex::new(r"(\{([^}\\:]+)(:[^}\\]*)?\})|((?:[^{\\]|\\\{|\\\}|\\)+)").expect("RAND_1196597661");
+}
+
+fn nonterms_in_format(format: &str) -> Vec<String> {
+    return TOKENIZER
+        .captures_iter(format)
+        .filter_map(|cap| cap.get(2).map(|nt| nt.as_str().to_string()))
+        .collect();
+}
+
+//Renames every nonterminal defined by rules (and all references to it) to namespace-NAME
+fn add_namespace(rules: &mut Vec<LoadedRule>, namespace: &str) {
+    let defined = rules
+        .iter()
+        .map(|r| r.rule.nt.clone())
+        .collect::<HashSet<_>>();
+    let rename = |nt: &str| {
+        if defined.contains(nt) {
+            format!("{}-{}", namespace, nt)
+        } else {
+            nt.to_string()
+        }
+    };
+    for r in rules.iter_mut() {
+        r.rule.format = TOKENIZER
+            .replace_all(&r.rule.format, |cap: &Captures| match cap.get(2) {
+                Some(nt) => format!(
+                    "{{{}{}}}",
+                    rename(nt.as_str()),
+                    cap.get(3).map(|d| d.as_str()).unwrap_or("")
+                ),
+                None => cap[0].to_string(),
+            })
+            .to_string();
+        r.rule.nt = rename(&r.rule.nt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use context::Context;
+    use antlr_parser::Separator;
+    use loader::{load_context, load_grammar, load_grammar_with, ContextConfig, LoaderError};
+    use newtypes::NTermID;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use tempfile::{self, TempDir};
+
+    fn write_grammars(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().expect("RAND_1705871043");
+        for &(name, content) in files.iter() {
+            let mut f = File::create(dir.path().join(name)).expect("RAND_2065248235");
+            f.write_all(content.as_bytes()).expect("RAND_851196469");
+        }
+        return dir;
+    }
+
+    #[test]
+    fn includes() {
+        let tmp = write_grammars(
+            &[
+                (
+                    "main.json",
+                    r#"[
+                    ["PROG", "{STMT}"],
+                    ["@include", "strings.json", "Str"],
+                    ["@include", "numbers.json"],
+                    ["@include", "common.json"],
+                    ["STMT", "print({Str-STRING})"],
+                    ["STMT", "print({NUMBER})"],
+                    ["@extend", "NUMBER", "0x{DIGIT}"],
+                    ["@override", "DIGIT", "7"],
+                    ["@override", "DIGIT", "8"]
+                ]"#,
+                ),
+                (
+                    "strings.json",
+                    r#"[["STRING", "\"{CHAR}\\{{ESCAPE}\\}\""], ["CHAR", "a"], ["CHAR", "{STMT}"]]"#,
+                ),
+                ("numbers.json", r#"[["@include", "digits.json"], ["NUMBER", "{DIGIT}"]]"#),
+                ("common.json", r#"[["@include", "digits.json"]]"#),
+                ("digits.json", r#"[["DIGIT", "0"], ["DIGIT", "1"]]"#),
+            ],
+        );
+        let dir = tmp.path();
+        let grammar = load_grammar(dir.join("main.json")).expect("RAND_3336706382");
+        assert_eq!(grammar.start, "PROG");
+        let rules = grammar
+            .rules
+            .iter()
+            .map(|r| (r.nt.as_str(), r.format.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            vec![
+                ("PROG", "{STMT}"),
+                ("Str-STRING", "\"{Str-CHAR}\\{{ESCAPE}\\}\""),
+                ("Str-CHAR", "a"),
+                ("Str-CHAR", "{STMT}"),
+                ("NUMBER", "{DIGIT}"),
+                ("STMT", "print({Str-STRING})"),
+                ("STMT", "print({NUMBER})"),
+                ("NUMBER", "0x{DIGIT}"),
+                ("DIGIT", "7"),
+                ("DIGIT", "8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn include_errors() {
+        let tmp = write_grammars(
+            &[
+                ("a.json", r#"[["A", "{B}"], ["@include", "b.json"]]"#),
+                ("b.json", r#"[["@include", "a.json"]]"#),
+                ("collision.json", r#"[["@include", "c.json"], ["@include", "d.json"]]"#),
+                ("c.json", r#"[["A", "c"]]"#),
+                ("d.json", r#"[["A", "d"]]"#),
+                ("local.json", r#"[["A", "x"], ["@include", "c.json"]]"#),
+                ("unknown.json", r#"[["@extend", "A", "x"]]"#),
+            ],
+        );
+        let dir = tmp.path();
+        match load_grammar(dir.join("a.json")) {
+            Err(LoaderError::IncludeCycle(paths)) => assert_eq!(paths.len(), 3),
+            res => panic!("expected include cycle, got {:?}", res),
+        }
+        match load_grammar(dir.join("collision.json")) {
+            Err(LoaderError::NameCollision { nt, first, second }) => {
+                assert_eq!(nt, "A");
+                assert!(first.ends_with("c.json"));
+                assert!(second.ends_with("d.json"));
+            }
+            res => panic!("expected name collision, got {:?}", res),
+        }
+        match load_grammar(dir.join("local.json")) {
+            Err(LoaderError::NameCollision { nt, .. }) => assert_eq!(nt, "A"),
+            res => panic!("expected name collision, got {:?}", res),
+        }
+        match load_grammar(dir.join("unknown.json")) {
+            Err(LoaderError::UnknownNonterm(_, nt)) => assert_eq!(nt, "A"),
+            res => panic!("expected unknown nonterminal, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn structured_formats() {
+        let tmp = write_grammars(
+            &[
+                (
+                    "main.yaml",
+                    "start: PROG\n\
+                     comment: test grammar\n\
+                     include:\n  - {path: values.toml, namespace: Val}\n\
+                     rules:\n\
+                     \x20 STMT: \"x = {Val-VALUE};\"\n\
+                     \x20 PROG:\n\
+                     \x20   - \"{STMT}\"\n\
+                     \x20   - format: |\n\
+                     \x20       while 1 do\n\
+                     \x20         {STMT}\n\
+                     \x20       end\n\
+                     \x20     weight: 0.5\n\
+                     \x20     tags: [loop]\n\
+                     extend:\n\
+                     \x20 Val-NUMBER: \"0\"\n",
+                ),
+                (
+                    "values.toml",
+                    "[rules]\n\
+                     VALUE = [\"{NUMBER}\", \"'\\\\{}'\"]\n\
+                     [[rules.NUMBER]]\n\
+                     format = \"1\"\n\
+                     weight = 3.0\n\
+                     comment = \"one\"\n",
+                ),
+                (
+                    "other.ron",
+                    r#"(
+                        include: [(path: "values.toml")],
+                        rules: {"OTHER": ["2", {"format": "3", "tags": ["three"]}]},
+                        extend: {"VALUE": "{OTHER}{OTHER}"},
+                    )"#,
+                ),
+                ("bad_weight.yaml", "rules: {A: {format: a, weight: 0}}"),
+                ("bad_key.yaml", "rule: {A: a}"),
+                ("grammar.txt", "A"),
+            ],
+        );
+        let dir = tmp.path();
+        let grammar = load_grammar(dir.join("main.yaml")).expect("RAND_1874294695");
+        assert_eq!(grammar.start, "PROG");
+        assert_eq!(grammar.comment, Some("test grammar".to_string()));
+        let rules = grammar
+            .rules
+            .iter()
+            .map(|r| (r.nt.as_str(), r.format.as_str(), r.weight))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            vec![
+                ("Val-VALUE", "{Val-NUMBER}", None),
+                ("Val-VALUE", "'\\{}'", None),
+                ("Val-NUMBER", "1", Some(3.0)),
+                ("STMT", "x = {Val-VALUE};", None),
+                ("PROG", "{STMT}", None),
+                ("PROG", "while 1 do\n  {STMT}\nend\n", Some(0.5)),
+                ("Val-NUMBER", "0", None),
+            ]
+        );
+        assert_eq!(grammar.rules[2].comment, Some("one".to_string()));
+        assert_eq!(grammar.rules[5].tags, vec!["loop".to_string()]);
+
+        let grammar = load_grammar(dir.join("other.ron")).expect("RAND_388063820");
+        assert_eq!(grammar.start, "OTHER");
+        assert_eq!(grammar.rules.len(), 6);
+        assert_eq!(grammar.rules[4].tags, vec!["three".to_string()]);
+        assert_eq!(grammar.rules[5].format, "{OTHER}{OTHER}");
+
+        let mut ctx = Context::new();
+        grammar.add_to_context(&mut ctx);
+        ctx.initialize(10, false);
+
+        match load_grammar(dir.join("bad_weight.yaml")) {
+            Err(LoaderError::Parse(_, err)) => assert!(err.contains("positive")),
+            res => panic!("expected parse error, got {:?}", res),
+        }
+        match load_grammar(dir.join("bad_key.yaml")) {
+            Err(LoaderError::Parse(_, err)) => assert!(err.contains("rule")),
+            res => panic!("expected parse error, got {:?}", res),
+        }
+        match load_grammar(dir.join("grammar.txt")) {
+            Err(LoaderError::UnknownFormat(_)) => {}
+            res => panic!("expected unknown format, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn contexts() {
+        let tmp = write_grammars(
+            &[
+                ("g.json", r#"[["A", "a{B}"], ["B", "b"], ["B", "{C}"], ["C", "c"]]"#),
+                ("undefined.json", r#"[["A", "a{B}"], ["B", "{D}"]]"#),
+                ("invalid.json", r#"[["A", "{b}"], ["b", "b"]]"#),
+                ("unproductive.json", r#"[["A", "a{A}"]]"#),
+                ("encoding.json", r#"[["A", "{B:len=u24}"], ["B", "b"]]"#),
+            ],
+        );
+        let dir = tmp.path();
+        let mut config = ContextConfig::new(10);
+        config.use_cache = true;
+        let ctx = load_context(dir.join("g.json"), &config).expect("RAND_2456777623");
+        assert_eq!(ctx.nt_id("START"), NTermID::from(1));
+        assert_eq!(ctx.get_rule(ctx.get_rules_for_nt(ctx.nt_id("START"))[0]).to_format(&ctx), "{A}");
+        assert!(dir.join("g.json.gfc").is_file());
+        let cached = load_context(dir.join("g.json"), &config).expect("RAND_3599420566");
+        assert_eq!(cached.get_num_rules(), ctx.get_num_rules());
+
+        config.start = Some("B".to_string());
+        let ctx = load_context(dir.join("g.json"), &config).expect("RAND_1296772041");
+        assert_eq!(ctx.nt_id("START"), NTermID::from(1));
+        assert_eq!(ctx.get_rule(ctx.get_rules_for_nt(ctx.nt_id("START"))[0]).to_format(&ctx), "{B}");
+
+        config.start = Some("D".to_string());
+        match load_context(dir.join("g.json"), &config) {
+            Err(LoaderError::UnknownStart(nt)) => assert_eq!(nt, "D"),
+            _ => panic!("expected unknown start symbol"),
+        }
+        config.start = None;
+        match load_context(dir.join("undefined.json"), &config) {
+            Err(LoaderError::UndefinedNonterm { nt, used_by }) => {
+                assert_eq!(nt, "D");
+                assert_eq!(used_by, "B");
+            }
+            _ => panic!("expected undefined nonterminal"),
+        }
+        match load_context(dir.join("invalid.json"), &config) {
+            Err(LoaderError::InvalidNonterm(nt)) => assert_eq!(nt, "b"),
+            _ => panic!("expected invalid nonterminal"),
+        }
+        match load_context(dir.join("unproductive.json"), &config) {
+            Err(LoaderError::UnproductiveNonterm(nt)) => assert_eq!(nt, "A"),
+            _ => panic!("expected unproductive nonterminal"),
+        }
+        match load_context(dir.join("encoding.json"), &config) {
+            Err(LoaderError::InvalidEncoding { nt, used_by, .. }) => {
+                assert_eq!(nt, "B");
+                assert_eq!(used_by, "A");
+            }
+            _ => panic!("expected invalid encoding"),
+        }
+        config.max_tree_size = 1;
+        match load_context(dir.join("g.json"), &config) {
+            Err(LoaderError::TreeSizeTooSmall { needed, .. }) => assert_eq!(needed, 2),
+            _ => panic!("expected too small tree size"),
+        }
+        match load_context(dir.join("missing.json"), &config) {
+            Err(LoaderError::Io(..)) => {}
+            _ => panic!("expected io error"),
+        }
+
+        let grammar = load_grammar("../antlr_parser/src/calculator.g4").expect("RAND_4048710865");
+        assert!(grammar.rules.len() > 0);
+        assert_eq!(grammar.start, grammar.rules[0].nt);
+        assert!(grammar.rules.iter().all(|r| !r.format.contains(' ')));
+        //The cached context of the grammar isn't reused for other ANTLR options
+        let tmp = tempfile::tempdir().expect("RAND_2086415973");
+        let g4 = tmp.path().join("calculator.g4");
+        fs::copy("../antlr_parser/src/calculator.g4", &g4).expect("RAND_1733260548");
+        let mut config = ContextConfig::new(200);
+        config.use_cache = true;
+        let concatenated = load_context(&g4, &config).expect("RAND_3917045216");
+        config.antlr.separator = Separator::Text(" ".to_string());
+        let separated = load_context(&g4, &config).expect("RAND_502968117");
+        assert_eq!(separated.get_num_rules(), concatenated.get_num_rules() + 1);
+        let grammar = load_grammar_with(&g4, &config.antlr).expect("RAND_4170628325");
+        assert!(grammar.rules.iter().any(|r| r.format.contains("{SEPARATOR}")));
+
+        let tmp = write_grammars(
+            &[
+                ("uri.abnf", "uri = scheme \":\" 1*DIGIT\nscheme = \"http\" / \"ftp\""),
+                ("g.json", "[[\"URL\", \"<{Uri-URI}>\"], [\"@include\", \"uri.abnf\", \"Uri\"]]"),
+            ],
+        );
+        let dir = tmp.path();
+        let grammar = load_grammar(dir.join("uri.abnf")).expect("RAND_1838208147");
+        assert_eq!(grammar.start, "URI");
+        assert_eq!(grammar.rules[0].format, "{SCHEME}:{URI_PLUS}");
+        let config = ContextConfig::new(10);
+        let ctx = load_context(dir.join("g.json"), &config).expect("RAND_1790262935");
+        assert_eq!(ctx.get_rules_for_nt(ctx.nt_id("Uri-DIGIT")).len(), 10);
+    }
+}
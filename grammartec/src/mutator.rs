@@ -27,6 +27,8 @@ impl Mutator {
     }
 
     //Return value indicates if minimization is complete: true: complete, false: not complete
+    //Subtrees are only replaced if tester accepts the smaller tree for bits, the fuzzer checks
+    //that every bit is still hit with the same hit count bucket
     pub fn minimize_tree<F>(
         &mut self,
         tree: &mut Tree,
@@ -97,6 +99,125 @@ impl Mutator {
         return Ok(true);
     }
 
+    //Hierarchical delta debugging: level by level, replaces as many subtrees as possible with
+    //minimal subtrees of the same nonterminal at once. Candidates are passed to tester as a
+    //TreeMutation without repl and postfix
+    pub fn minimize_hdd<F>(
+        &mut self,
+        tree: &mut Tree,
+        bits: &HashSet<usize>,
+        ctx: &Context,
+        tester: &mut F,
+    ) -> Result<(), SubprocessError>
+    where
+        F: FnMut(&TreeMutation, &HashSet<usize>, &Context) -> Result<bool, SubprocessError>,
+    {
+        let mut level = 0;
+        loop {
+            let depths = Mutator::node_depths(tree);
+            if !depths.iter().any(|d| *d >= level) {
+                return Ok(());
+            }
+            let mut nodes = vec![];
+            let mut min_trees = vec![];
+            for i in 0..tree.size() {
+                let n = NodeID::from(i);
+                let nt = tree.get_rule(n, ctx).nonterm();
+                if depths[i] == level && tree.subtree_size(n) > ctx.get_min_len_for_nt(nt) {
+                    self.scratchpad
+                        .generate_from_nt(nt, ctx.get_min_len_for_nt(nt), ctx);
+                    nodes.push(n);
+                    min_trees.push(self.scratchpad.rules.clone());
+                }
+            }
+            if !nodes.is_empty() {
+                let keep = Mutator::ddmin(tree, &nodes, &min_trees, bits, ctx, tester)?;
+                let rules = Mutator::replace_subtrees(tree, &nodes, &min_trees, &keep);
+                *tree = Tree::from_rule_vec(rules, ctx);
+            }
+            level += 1;
+        }
+    }
+
+    //Returns the indices into nodes of the subtrees that have to be kept
+    fn ddmin<F>(
+        tree: &Tree,
+        nodes: &[NodeID],
+        min_trees: &[Vec<NormalOrCustomRule>],
+        bits: &HashSet<usize>,
+        ctx: &Context,
+        tester: &mut F,
+    ) -> Result<Vec<usize>, SubprocessError>
+    where
+        F: FnMut(&TreeMutation, &HashSet<usize>, &Context) -> Result<bool, SubprocessError>,
+    {
+        let mut keep: Vec<usize> = (0..nodes.len()).collect();
+        let mut granularity = 1;
+        while !keep.is_empty() {
+            let chunk_size = (keep.len() + granularity - 1) / granularity;
+            let mut reduced = false;
+            for start in (0..keep.len()).step_by(chunk_size) {
+                let mut candidate = keep[..start].to_vec();
+                candidate.extend_from_slice(&keep[(start + chunk_size).min(keep.len())..]);
+                let rules = Mutator::replace_subtrees(tree, nodes, min_trees, &candidate);
+                let mutation = TreeMutation {
+                    prefix: &rules,
+                    repl: &[],
+                    postfix: &[],
+                };
+                if tester(&mutation, bits, ctx)? {
+                    keep = candidate;
+                    granularity = granularity.saturating_sub(1).max(1);
+                    reduced = true;
+                    break;
+                }
+            }
+            if !reduced {
+                if granularity >= keep.len() {
+                    break;
+                }
+                granularity = (granularity * 2).min(keep.len());
+            }
+        }
+        return Ok(keep);
+    }
+
+    //Replaces the subtrees of nodes by min_trees, except those whose index is in keep
+    fn replace_subtrees(
+        tree: &Tree,
+        nodes: &[NodeID],
+        min_trees: &[Vec<NormalOrCustomRule>],
+        keep: &[usize],
+    ) -> Vec<NormalOrCustomRule> {
+        let mut rules = Vec::with_capacity(tree.size());
+        let mut next = 0;
+        let mut i = 0;
+        while i < tree.size() {
+            if next < nodes.len() && nodes[next].to_i() == i {
+                if keep.contains(&next) {
+                    rules.push(tree.rules[i].clone());
+                    i += 1;
+                } else {
+                    rules.extend_from_slice(&min_trees[next]);
+                    i += tree.subtree_size(nodes[next]);
+                }
+                next += 1;
+            } else {
+                rules.push(tree.rules[i].clone());
+                i += 1;
+            }
+        }
+        return rules;
+    }
+
+    fn node_depths(tree: &Tree) -> Vec<usize> {
+        let mut depths = vec![0; tree.size()];
+        for i in 1..tree.size() {
+            depths[i] = depths[tree.paren[i].to_i()] + 1;
+        }
+        return depths;
+    }
+
     pub fn mut_rules<F>(
         &mut self,
         tree: &Tree,
@@ -529,6 +650,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_minimize_hdd() {
+        let mut ctx = Context::new();
+        let r_s = ctx.add_rule("S", "{L}");
+        let r_lc = ctx.add_rule("L", "{E},{L}");
+        let r_l = ctx.add_rule("L", "{E}");
+        let r_a = ctx.add_rule("E", "a");
+        let r_c = ctx.add_rule("E", "{C}");
+        let r_br = ctx.add_rule("E", "[{L}]");
+        let r_crash = ctx.add_rule("C", "crash");
+        ctx.initialize(20, false);
+        let rules = vec![
+            r_s, r_lc, r_a, r_lc, r_br, r_lc, r_a, r_l, r_c, r_crash, r_lc, r_br, r_lc, r_a, r_l,
+            r_a, r_l, r_a,
+        ];
+        let mut tree = Tree::from_rule_vec(
+            rules
+                .into_iter()
+                .map(|r| NormalOrCustomRule::NormalRule(r))
+                .collect(),
+            &ctx,
+        );
+        assert_eq!(tree.unparse_to_vec(&ctx), b"a,[a,crash],[a,a],a".to_vec());
+        let mut mutator = Mutator::new(&ctx);
+        {
+            let mut tester = |tree_mut: &TreeMutation, _bits: &HashSet<usize>, ctx: &Context| {
+                return Ok(String::from_utf8(tree_mut.unparse_to_vec(&ctx))
+                    .expect("RAND_1867160714")
+                    .contains("crash"));
+            };
+            mutator
+                .minimize_hdd(&mut tree, &HashSet::new(), &ctx, &mut tester)
+                .expect("RAND_3163216189");
+        }
+        let unparse = String::from_utf8(tree.unparse_to_vec(&ctx)).expect("RAND_1136105540");
+        assert_eq!(unparse, "a,[a,crash],a");
+    }
+
     #[test]
     fn deterministic_rule() {
         let mut ctx = Context::new();
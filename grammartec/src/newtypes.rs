@@ -127,7 +127,7 @@ mod tests {
         let i1: usize = r1.into();
         assert_eq!(i1, 1337);
         let i2: usize = 1338;
-        assert_eq!(i2, r2.into());
+        assert_eq!(i2, Into::<usize>::into(r2));
         let r3 = r2 + 3;
         assert_eq!(r3, 1341.into());
     }
@@ -139,7 +139,7 @@ mod tests {
         let i1: usize = r1.into();
         assert_eq!(i1, 1337);
         let i2: usize = 1338;
-        assert_eq!(i2, r2.into());
+        assert_eq!(i2, Into::<usize>::into(r2));
         let r3 = r2 + 3;
         assert_eq!(r3, 1341.into());
     }
@@ -151,7 +151,7 @@ mod tests {
         let i1: usize = r1.into();
         assert_eq!(i1, 1337);
         let i2: usize = 1338;
-        assert_eq!(i2, r2.into());
+        assert_eq!(i2, Into::<usize>::into(r2));
         let r3 = r2 + 3;
         assert_eq!(r3, 1341.into());
     }
@@ -3,8 +3,10 @@ use num::Zero;
 use std::io::Write;
 
 use context::Context;
+use encoding::Encoding;
 use newtypes::{NTermID, NodeID, RuleID};
 use regex::Regex;
+use rule_format::escape_literal;
 use std::io::Error;
 use tree::{Tree, TreeLike};
 
@@ -13,6 +15,8 @@ pub enum RuleChild {
     Term(Vec<u8>),
     CustomTerm(Vec<u8>),
     NTerm(NTermID),
+    //Always directly followed by the NTerm whose output it encodes
+    Encode(Encoding),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -38,6 +42,18 @@ impl RuleChild {
         return RuleChild::NTerm(ctx.aquire_nt_id(&nonterm));
     }
 
+    //{A:len=varint} becomes [Encode, NTerm], any other nonterminal just NTerm
+    fn from_nt_with_encoding(nt: &str, ctx: &mut Context) -> Vec<Self> {
+        let (_, descr) = RuleChild::split_nt_description(nt);
+        let encoding = Encoding::from_description(&descr)
+            .unwrap_or_else(|err| panic!("invalid nonterminal {}: {}", nt, err));
+        return encoding
+            .map(RuleChild::Encode)
+            .into_iter()
+            .chain(Some(RuleChild::from_nt(nt, ctx)))
+            .collect();
+    }
+
     pub fn unparse<W: Write, T: TreeLike>(
         &self,
         tree: &T,
@@ -55,6 +71,8 @@ impl RuleChild {
             &RuleChild::NTerm(_) => {
                 cur = tree.unparse(cur + 1, ctx, w)?;
             }
+            //Applied by Rule::unparse
+            &RuleChild::Encode(_) => {}
         }
         return Ok(cur);
     }
@@ -62,14 +80,14 @@ impl RuleChild {
     fn split_nt_description(nonterm: &str) -> (String, String) {
         lazy_static! {
             static ref SPLITTER: Regex = Regex::new(
-                r"^\{([A-Z][a-zA-Z_\-0-9]*)(?::([a-zA-Z_\-0-9]*))?\}$"
+                r"^\{([A-Z][a-zA-Z_\-0-9]*)(?::([a-zA-Z_\-0-9=]*))?\}$"
             ).expect("RAND_1363289094");
         }
 
         //splits {A:a} or {A} into A and maybe a
         let descr = SPLITTER.captures(nonterm).expect("RAND_3427632992");
-        //let name = descr.get(2).map(|m| m.as_str().into()).unwrap_or(default.to_string()));
-        return (descr[1].into(), "".into());
+        let name = descr.get(2).map(|m| m.as_str().into()).unwrap_or(String::new());
+        return (descr[1].into(), name);
     }
 }
 
@@ -128,14 +146,15 @@ impl Rule {
 
         return TOKENIZER
             .captures_iter(format)
-            .map(|cap| {
+            .flat_map(|cap| {
                 if let Some(sub) = cap.get(1) {
                     //println!("cap.get(1): {}", sub.as_str());
-                    RuleChild::from_nt(sub.as_str(), ctx)
+                    RuleChild::from_nt_with_encoding(sub.as_str(), ctx)
                 } else if let Some(sub) = cap.get(2) {
                     //println!("String: {}, cap.get(2): {}", format, sub.as_str());
                     //println!("String: {}, cap.get(2): {}", format, sub.as_str().replace("\\{", "{").replace("\\}", "}"));
-                    RuleChild::from_lit(&(sub.as_str().replace("\\{", "{").replace("\\}", "}")))
+                    let lit = sub.as_str().replace("\\{", "{").replace("\\}", "}");
+                    vec![RuleChild::from_lit(&lit)]
                 } else {
                     unreachable!()
                 }
@@ -143,6 +162,46 @@ impl Rule {
             .collect::<Vec<_>>();
     }
 
+    //Inverse of from_format: returns a format string that tokenizes back into the same children.
+    //Formats can't have a backslash right before a nonterminal, as it would escape the brace.
+    //These backslashes are written as {BACKSLASH}, see to_format_with.
+    pub fn to_format(&self, ctx: &Context) -> String {
+        return self.to_format_with(ctx, || "BACKSLASH".to_string());
+    }
+
+    //Like to_format, backslashes right before a nonterminal are written as the nonterminal
+    //returned by backslash, which has to derive a single backslash
+    pub fn to_format_with<F: FnMut() -> String>(&self, ctx: &Context, mut backslash: F) -> String {
+        let mut res = String::new();
+        let mut encoding = None;
+        for (i, child) in self.children.iter().enumerate() {
+            match child {
+                &RuleChild::Term(ref data) | &RuleChild::CustomTerm(ref data) => {
+                    let (lit, backslashes) = escape_literal(&String::from_utf8_lossy(data));
+                    res.push_str(&lit);
+                    match self.children.get(i + 1) {
+                        Some(&RuleChild::NTerm(_)) | Some(&RuleChild::Encode(_)) => {
+                            for _ in 0..backslashes {
+                                res.push_str(&format!("{{{}}}", backslash()));
+                            }
+                        }
+                        _ => res.push_str(&"\\".repeat(backslashes)),
+                    }
+                }
+                &RuleChild::NTerm(nt) => {
+                    res.push('{');
+                    res.push_str(&ctx.nt_id_to_s(nt));
+                    if let Some(enc) = encoding.take() {
+                        res.push_str(&format!(":{}", enc));
+                    }
+                    res.push('}');
+                }
+                &RuleChild::Encode(enc) => encoding = Some(enc),
+            }
+        }
+        return res;
+    }
+
     pub fn unparse<W: Write, T: TreeLike>(
         &self,
         tree: &T,
@@ -150,8 +209,20 @@ impl Rule {
         ctx: &Context,
         w: &mut W,
     ) -> Result<NodeID, Error> {
+        let mut encoding = None;
         for child in self.children.iter() {
-            id = child.unparse(tree, id, ctx, w)?;
+            if let &RuleChild::Encode(enc) = child {
+                encoding = Some(enc);
+                continue;
+            }
+            match encoding.take() {
+                Some(enc) => {
+                    let mut data = vec![];
+                    id = child.unparse(tree, id, ctx, &mut data)?;
+                    w.write_all(&enc.apply(&data))?;
+                }
+                None => id = child.unparse(tree, id, ctx, w)?,
+            }
         }
         return Ok(id);
     }
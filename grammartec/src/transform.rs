@@ -0,0 +1,422 @@
+use std::collections::{HashMap, HashSet};
+
+use context::Context;
+use encoding::Encoding;
+use export::unused_name;
+use newtypes::RuleID;
+use rule::RuleChild;
+use rule_format::escape_literal;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Symbol {
+    Term(Vec<u8>),
+    NTerm(String),
+    //Always directly followed by the NTerm it applies to
+    Encode(Encoding),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarStats {
+    pub num_rules: usize,
+    pub num_nts: usize,
+    //min size (number of nodes) of a tree for the start symbol, None if it is unproductive
+    pub min_size: Option<usize>,
+    //largest min size of all productive nonterminals
+    pub max_min_size: usize,
+}
+
+//Simplifies a grammar while preserving its language (except for cap_recursive_branching, which
+//removes alternatives). The rules are stored by name, such that nonterminals can be removed and
+//replaced freely before a new Context is built with to_context.
+pub struct Transformer {
+    start: String,
+    rules: Vec<(String, Vec<Symbol>)>,
+}
+
+impl Transformer {
+    pub fn from_context(ctx: &Context, start: &str) -> Self {
+        let mut rules = vec![];
+        for i in 0..ctx.get_num_rules() {
+            let rule = ctx.get_rule(RuleID::from(i));
+            let symbols = rule
+                .children()
+                .iter()
+                .map(|child| match child {
+                    &RuleChild::Term(ref data) | &RuleChild::CustomTerm(ref data) => {
+                        Symbol::Term(data.clone())
+                    }
+                    &RuleChild::NTerm(nt) => Symbol::NTerm(ctx.nt_id_to_s(nt)),
+                    &RuleChild::Encode(enc) => Symbol::Encode(enc),
+                })
+                .collect();
+            rules.push((ctx.nt_id_to_s(rule.nonterm()), symbols));
+        }
+        return Transformer {
+            start: start.to_string(),
+            rules,
+        };
+    }
+
+    //Builds a new (uninitialized) Context containing the transformed rules
+    pub fn to_context(&self, dumb: bool) -> Context {
+        let mut ctx = Context::with_dump(dumb);
+        //Formats can't have a backslash right before a nonterminal, e.g. after inlining a
+        //nonterminal that ends with one. These backslashes are derived by a nonterminal.
+        let names = self.rules.iter().map(|r| r.0.clone()).collect::<HashSet<_>>();
+        let backslash = unused_name(&names, "BACKSLASH");
+        let mut needs_backslash = false;
+        for &(ref nt, ref symbols) in self.rules.iter() {
+            let mut format = String::new();
+            let mut encoding = None;
+            for (i, symbol) in symbols.iter().enumerate() {
+                match symbol {
+                    &Symbol::Term(ref data) => {
+                        let (lit, backslashes) = escape_literal(&String::from_utf8_lossy(data));
+                        format.push_str(&lit);
+                        match symbols.get(i + 1) {
+                            Some(&Symbol::NTerm(_)) | Some(&Symbol::Encode(_)) => {
+                                format.push_str(&format!("{{{}}}", backslash).repeat(backslashes));
+                                needs_backslash |= backslashes > 0;
+                            }
+                            _ => format.push_str(&"\\".repeat(backslashes)),
+                        }
+                    }
+                    &Symbol::NTerm(ref name) => match encoding.take() {
+                        Some(enc) => format.push_str(&format!("{{{}:{}}}", name, enc)),
+                        None => format.push_str(&format!("{{{}}}", name)),
+                    },
+                    &Symbol::Encode(enc) => encoding = Some(enc),
+                }
+            }
+            ctx.add_rule(nt, &format);
+        }
+        if needs_backslash {
+            ctx.add_rule(&backslash, "\\");
+        }
+        return ctx;
+    }
+
+    pub fn stats(&self) -> GrammarStats {
+        let min_sizes = self.calc_min_sizes();
+        let nts = self.rules.iter().map(|r| &r.0).collect::<HashSet<_>>();
+        return GrammarStats {
+            num_rules: self.rules.len(),
+            num_nts: nts.len(),
+            min_size: min_sizes.get(&self.start).cloned(),
+            max_min_size: min_sizes.values().cloned().max().unwrap_or(0),
+        };
+    }
+
+    //Removes all rules whose nonterminal can not be reached from the start symbol. Returns the
+    //number of removed rules.
+    pub fn drop_unreachable(&mut self) -> usize {
+        let mut reachable = HashSet::new();
+        let mut todo = vec![self.start.clone()];
+        while let Some(nt) = todo.pop() {
+            if !reachable.insert(nt.clone()) {
+                continue;
+            }
+            for &(ref lhs, ref symbols) in self.rules.iter() {
+                if *lhs == nt {
+                    for symbol in symbols.iter() {
+                        if let &Symbol::NTerm(ref child) = symbol {
+                            todo.push(child.clone());
+                        }
+                    }
+                }
+            }
+        }
+        let before = self.rules.len();
+        self.rules.retain(|&(ref lhs, _)| reachable.contains(lhs));
+        return before - self.rules.len();
+    }
+
+    //Inlines nonterminals that have exactly one rule and are referenced exactly once in the whole
+    //grammar. Returns the number of inlined nonterminals.
+    pub fn inline_single_use(&mut self) -> usize {
+        let mut inlined = 0;
+        loop {
+            let mut num_rules = HashMap::new();
+            let mut num_uses = HashMap::new();
+            //The output of an encoded nonterminal has to stay the output of a single nonterminal
+            let mut encoded = HashSet::new();
+            for &(ref lhs, ref symbols) in self.rules.iter() {
+                *num_rules.entry(lhs.clone()).or_insert(0) += 1;
+                for (i, symbol) in symbols.iter().enumerate() {
+                    if let &Symbol::NTerm(ref child) = symbol {
+                        *num_uses.entry(child.clone()).or_insert(0) += 1;
+                        if i > 0 && matches!(symbols[i - 1], Symbol::Encode(_)) {
+                            encoded.insert(child.clone());
+                        }
+                    }
+                }
+            }
+            let candidate = self.rules.iter().position(|&(ref lhs, ref symbols)| {
+                *lhs != self.start
+                    && num_rules[lhs] == 1
+                    && num_uses.get(lhs) == Some(&1)
+                    && !encoded.contains(lhs)
+                    && !symbols.contains(&Symbol::NTerm(lhs.clone()))
+            });
+            if let Some(index) = candidate {
+                let (name, replacement) = self.rules.remove(index);
+                let nterm = Symbol::NTerm(name);
+                for &mut (_, ref mut symbols) in self.rules.iter_mut() {
+                    if let Some(pos) = symbols.iter().position(|s| *s == nterm) {
+                        symbols.splice(pos..pos + 1, replacement.iter().cloned());
+                    }
+                }
+                inlined += 1;
+            } else {
+                return inlined;
+            }
+        }
+    }
+
+    //Removes duplicate alternatives and merges nonterminals that consist of the same set of purely
+    //terminal alternatives (e.g. DIGIT and NUMBER_DIGIT both being 0-9) into one. Returns the
+    //number of removed rules.
+    pub fn merge_terminal_alternatives(&mut self) -> usize {
+        let before = self.rules.len();
+        let mut order = vec![];
+        let mut alternatives: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+        let mut non_terminal = HashSet::new();
+        for &(ref lhs, ref symbols) in self.rules.iter() {
+            if !alternatives.contains_key(lhs) {
+                order.push(lhs.clone());
+            }
+            let alts = alternatives.entry(lhs.clone()).or_insert_with(|| vec![]);
+            let mut term = vec![];
+            for symbol in symbols.iter() {
+                match symbol {
+                    &Symbol::Term(ref data) => term.extend_from_slice(data),
+                    &Symbol::NTerm(_) | &Symbol::Encode(_) => {
+                        non_terminal.insert(lhs.clone());
+                    }
+                }
+            }
+            alts.push(term);
+        }
+        let mut replacements = HashMap::new();
+        let mut representatives: HashMap<Vec<Vec<u8>>, String> = HashMap::new();
+        for nt in order {
+            if non_terminal.contains(&nt) || nt == self.start {
+                continue;
+            }
+            let mut alts = alternatives[&nt].clone();
+            alts.sort();
+            alts.dedup();
+            if let Some(repr) = representatives.get(&alts) {
+                replacements.insert(nt, repr.clone());
+                continue;
+            }
+            representatives.insert(alts, nt);
+        }
+        self.rules.retain(|&(ref lhs, _)| !replacements.contains_key(lhs));
+        for &mut (_, ref mut symbols) in self.rules.iter_mut() {
+            for symbol in symbols.iter_mut() {
+                let replacement = match symbol {
+                    &mut Symbol::NTerm(ref name) => replacements.get(name).cloned(),
+                    _ => None,
+                };
+                if let Some(name) = replacement {
+                    *symbol = Symbol::NTerm(name);
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        self.rules.retain(|rule| seen.insert(rule.clone()));
+        return before - self.rules.len();
+    }
+
+    //Keeps at most max_alternatives recursive rules for each recursive nonterminal. Rules with the
+    //smallest min size are kept, non recursive rules are never removed. Therefore the min size of
+    //every nonterminal stays the same. Returns the number of removed rules.
+    pub fn cap_recursive_branching(&mut self, max_alternatives: usize) -> usize {
+        assert!(max_alternatives > 0, "at least one recursive alternative has to be kept");
+        let reach = self.calc_reachability();
+        let min_sizes = self.calc_min_sizes();
+        let mut recursive_rules: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+        for (i, &(ref lhs, ref symbols)) in self.rules.iter().enumerate() {
+            let is_recursive = symbols.iter().any(|s| match s {
+                &Symbol::NTerm(ref child) => reach[child].contains(lhs),
+                _ => false,
+            });
+            if is_recursive {
+                let size = self.calc_min_size_for_rule(symbols, &min_sizes);
+                recursive_rules
+                    .entry(lhs.clone())
+                    .or_insert_with(|| vec![])
+                    .push((size.unwrap_or(usize::max_value()), i));
+            }
+        }
+        let mut to_remove = HashSet::new();
+        for (_, mut rules) in recursive_rules.into_iter() {
+            rules.sort();
+            for &(_, i) in rules.iter().skip(max_alternatives) {
+                to_remove.insert(i);
+            }
+        }
+        let mut i = 0;
+        self.rules.retain(|_| {
+            i += 1;
+            !to_remove.contains(&(i - 1))
+        });
+        return to_remove.len();
+    }
+
+    //For each nonterminal the set of nonterminals that can be reached by applying at least one rule
+    fn calc_reachability(&self) -> HashMap<String, HashSet<String>> {
+        let mut edges: HashMap<String, HashSet<String>> = HashMap::new();
+        for &(ref lhs, ref symbols) in self.rules.iter() {
+            let e = edges.entry(lhs.clone()).or_insert_with(|| HashSet::new());
+            for symbol in symbols.iter() {
+                if let &Symbol::NTerm(ref child) = symbol {
+                    e.insert(child.clone());
+                }
+            }
+        }
+        let mut reach = HashMap::new();
+        for &(ref lhs, ref symbols) in self.rules.iter() {
+            for nt in Some(lhs).into_iter().chain(symbols.iter().filter_map(|s| match s {
+                &Symbol::NTerm(ref child) => Some(child),
+                _ => None,
+            })) {
+                if reach.contains_key(nt) {
+                    continue;
+                }
+                let mut seen = HashSet::new();
+                let mut todo = edges.get(nt).map(|e| e.iter().cloned().collect()).unwrap_or(vec![]);
+                while let Some(next) = todo.pop() {
+                    if seen.insert(next.clone()) {
+                        if let Some(e) = edges.get(&next) {
+                            todo.extend(e.iter().cloned());
+                        }
+                    }
+                }
+                reach.insert(nt.clone(), seen);
+            }
+        }
+        return reach;
+    }
+
+    //Same fixpoint as Context::calc_min_len, but unproductive nonterminals are left out instead of
+    //causing a panic
+    fn calc_min_sizes(&self) -> HashMap<String, usize> {
+        let mut min_sizes = HashMap::new();
+        let mut something_changed = true;
+        while something_changed {
+            something_changed = false;
+            for &(ref lhs, ref symbols) in self.rules.iter() {
+                if let Some(size) = self.calc_min_size_for_rule(symbols, &min_sizes) {
+                    let e = min_sizes.entry(lhs.clone()).or_insert(usize::max_value());
+                    if *e > size {
+                        *e = size;
+                        something_changed = true;
+                    }
+                }
+            }
+        }
+        return min_sizes;
+    }
+
+    fn calc_min_size_for_rule(
+        &self,
+        symbols: &Vec<Symbol>,
+        min_sizes: &HashMap<String, usize>,
+    ) -> Option<usize> {
+        let mut size = 1;
+        for symbol in symbols.iter() {
+            if let &Symbol::NTerm(ref child) = symbol {
+                size += min_sizes.get(child)?;
+            }
+        }
+        return Some(size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use context::Context;
+    use transform::Transformer;
+    use tree::TreeLike;
+
+    #[test]
+    fn transform_steps() {
+        let mut ctx = Context::new();
+        ctx.add_rule("START", "{EXPR}");
+        ctx.add_rule("EXPR", "{EXPR}+{EXPR}");
+        ctx.add_rule("EXPR", "{EXPR}*{EXPR}");
+        ctx.add_rule("EXPR", "{EXPR}-{EXPR}");
+        ctx.add_rule("EXPR", "{PAREN}");
+        ctx.add_rule("EXPR", "{DIGIT}");
+        ctx.add_rule("EXPR", "{DIGIT}");
+        ctx.add_rule("PAREN", "({EXPR})");
+        ctx.add_rule("EXPR", "{NUMBER_DIGIT}");
+        ctx.add_rule("DIGIT", "0");
+        ctx.add_rule("DIGIT", "1");
+        ctx.add_rule("NUMBER_DIGIT", "1");
+        ctx.add_rule("NUMBER_DIGIT", "0");
+        ctx.add_rule("UNUSED", "{DIGIT}");
+
+        let mut t = Transformer::from_context(&ctx, "START");
+        let before = t.stats();
+        assert_eq!(before.num_rules, 14);
+        assert_eq!(before.num_nts, 6);
+        assert_eq!(before.min_size, Some(3));
+
+        assert_eq!(t.drop_unreachable(), 1);
+        assert_eq!(t.inline_single_use(), 1);
+        assert_eq!(t.merge_terminal_alternatives(), 4);
+        let stats = t.stats();
+        assert_eq!(stats.num_rules, 8);
+        assert_eq!(stats.num_nts, 3);
+        assert_eq!(stats.min_size, Some(3));
+
+        assert_eq!(t.cap_recursive_branching(2), 2);
+        assert_eq!(t.stats().min_size, Some(3));
+
+        let mut ctx = t.to_context(false);
+        ctx.initialize(20, false);
+        let expr = ctx.nt_id("EXPR");
+        let formats = ctx
+            .get_rules_for_nt(expr)
+            .iter()
+            .map(|r| ctx.get_rule(*r).to_format(&ctx))
+            .collect::<Vec<_>>();
+        assert_eq!(formats.len(), 3);
+        assert!(formats.contains(&"{DIGIT}".to_string()));
+        assert!(formats.contains(&"({EXPR})".to_string()));
+        assert!(formats.contains(&"{EXPR}+{EXPR}".to_string()));
+    }
+
+    #[test]
+    fn backslash_before_nonterminal() {
+        let mut ctx = Context::new();
+        ctx.add_rule("START", "{A}{B}");
+        ctx.add_rule("A", "x\\");
+        ctx.add_rule("B", "b");
+        ctx.add_rule("B", "c");
+
+        //inlining A puts its trailing backslash right before {B}
+        let mut t = Transformer::from_context(&ctx, "START");
+        assert_eq!(t.inline_single_use(), 1);
+        let mut ctx = t.to_context(false);
+        ctx.initialize(10, false);
+        let formats = |nt: &str| {
+            ctx.get_rules_for_nt(ctx.nt_id(nt))
+                .iter()
+                .map(|r| ctx.get_rule(*r).to_format(&ctx))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(formats("START"), vec!["x{BACKSLASH}{B}"]);
+        assert_eq!(formats("BACKSLASH"), vec!["\\"]);
+        let start = ctx.nt_id("START");
+        assert_eq!(ctx.get_min_len_for_nt(start), 3);
+        for _ in 0..10 {
+            let tree = ctx.generate_tree_from_nt(start, 3);
+            let output = String::from_utf8(tree.unparse_to_vec(&ctx)).expect("RAND_1826694002");
+            assert!(output == "x\\b" || output == "x\\c", "{}", output);
+        }
+    }
+}
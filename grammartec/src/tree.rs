@@ -5,6 +5,7 @@ use std::io::Write;
 use std::marker::Sized;
 
 use context::Context;
+use encoding::Encoding;
 use newtypes::{NTermID, NodeID, RuleID};
 use rule::{NormalOrCustomRule, Rule, RuleChild};
 use std::collections::HashMap;
@@ -23,58 +24,37 @@ where
         return self.get_rule(id, ctx).unparse(self, id, ctx, w);
     }
 
-    fn unparse_iter<W: Write>(&self, id: NodeID, ctx: &Context, w: &mut W) {
+    fn unparse_iter<W: Write>(&self, id: NodeID, ctx: &Context, w: &mut W) -> Result<(), Error> {
         let mut stack: Vec<RuleChild> = Vec::new();
+        let mut encoded: Vec<EncodedOutput> = Vec::new();
         for i in id.to_i()..self.size() {
-            let mut next_nterm = None;
-            while let Some(rule_child) = stack.pop() {
-                match rule_child {
-                    RuleChild::Term(ref data) => {
-                        w.write(data).expect("RAND_482559653");
-                    }
-                    RuleChild::CustomTerm(ref data) => {
-                        w.write(data).expect("RAND_3278316750");
-                    }
-                    RuleChild::NTerm(nterm_id) => {
-                        next_nterm = Some(nterm_id);
-                        break;
-                    }
-                }
-            }
+            let next_nterm = unparse_stack(&mut stack, &mut encoded, w)?;
             let rule = self.get_rule(NodeID::from(i), ctx);
             //sanity check
-            if next_nterm.is_some() {
-                if next_nterm.expect("RAND_1629372917") != rule.nonterm() {
+            if let Some((nterm_id, encoding)) = next_nterm {
+                if nterm_id != rule.nonterm() {
                     panic!("Not a valid tree for unparsing!");
                 }
+                if let Some(encoding) = encoding {
+                    encoded.push(EncodedOutput {
+                        encoding,
+                        depth: stack.len(),
+                        data: vec![],
+                    });
+                }
             }
             for rule_child in rule.children().iter().rev() {
                 stack.push(rule_child.clone());
             }
         }
-        let mut next_nterm = None;
-        while let Some(rule_child) = stack.pop() {
-            match rule_child {
-                RuleChild::Term(ref data) => {
-                    w.write(data).expect("RAND_298992519");
-                }
-                RuleChild::CustomTerm(ref data) => {
-                    w.write(data).expect("RAND_1616900");
-                }
-                RuleChild::NTerm(nterm_id) => {
-                    next_nterm = Some(nterm_id);
-                    break;
-                }
-            }
-        }
-        if next_nterm.is_some() {
+        if unparse_stack(&mut stack, &mut encoded, w)?.is_some() {
             panic!("Not a valid tree for unparsing!");
         }
+        return Ok(());
     }
 
     fn unparse_to<W: Write>(&self, ctx: &Context, w: &mut W) -> Result<(), Error> {
-        self.unparse_iter(NodeID::from(0), ctx, w);
-        return Ok(());
+        return self.unparse_iter(NodeID::from(0), ctx, w);
     }
 
     fn unparse_to_vec(&self, ctx: &Context) -> Vec<u8> {
@@ -83,7 +63,7 @@ where
 
     fn unparse_node_to_vec(&self, n: NodeID, ctx: &Context) -> Vec<u8> {
         let mut data = vec![];
-        self.unparse_iter(n, ctx, &mut data);
+        self.unparse_iter(n, ctx, &mut data).expect("RAND_1561309274");
         return data;
     }
 
@@ -92,6 +72,47 @@ where
     }
 }
 
+//Output of a nonterminal with an encoding. It is complete once the stack shrinks back to depth,
+//i.e. all children of the nonterminal have been popped.
+struct EncodedOutput {
+    encoding: Encoding,
+    depth: usize,
+    data: Vec<u8>,
+}
+
+//Writes the terminals on top of the stack until a nonterminal is popped, which is returned
+//together with its encoding
+fn unparse_stack<W: Write>(
+    stack: &mut Vec<RuleChild>,
+    encoded: &mut Vec<EncodedOutput>,
+    w: &mut W,
+) -> Result<Option<(NTermID, Option<Encoding>)>, Error> {
+    let mut encoding = None;
+    loop {
+        while encoded.last().map_or(false, |e| e.depth == stack.len()) {
+            let output = encoded.pop().expect("RAND_1259893580");
+            let data = output.encoding.apply(&output.data);
+            match encoded.last_mut() {
+                Some(parent) => parent.data.extend_from_slice(&data),
+                None => w.write_all(&data)?,
+            }
+        }
+        let data = match stack.pop() {
+            Some(RuleChild::Term(data)) | Some(RuleChild::CustomTerm(data)) => data,
+            Some(RuleChild::NTerm(nterm_id)) => return Ok(Some((nterm_id, encoding))),
+            Some(RuleChild::Encode(enc)) => {
+                encoding = Some(enc);
+                continue;
+            }
+            None => return Ok(None),
+        };
+        match encoded.last_mut() {
+            Some(output) => output.data.extend_from_slice(&data),
+            None => w.write_all(&data)?,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Tree {
     pub rules: Vec<NormalOrCustomRule>,
@@ -478,11 +499,41 @@ mod tests {
             let mut vec2 = vec![];
             tree.unparse(NodeID::from(0), &ctx, &mut vec1)
                 .expect("RAND_2991612983");
-            tree.unparse_iter(NodeID::from(0), &ctx, &mut vec2);
+            tree.unparse_iter(NodeID::from(0), &ctx, &mut vec2)
+                .expect("RAND_1184036551");
             assert_eq!(vec1, vec2);
         }
     }
 
+    #[test]
+    fn check_unparse_encoded() {
+        let mut ctx = Context::new();
+        let msg = ctx.add_rule("MSG", "\x08{NUM:num=varint}\x12{SUB:len=varint}");
+        let sub = ctx.add_rule("SUB", "\x0a{TEXT:len=u8}{SUB:len=u8}");
+        let empty = ctx.add_rule("SUB", "");
+        let num = ctx.add_rule("NUM", "300");
+        let text = ctx.add_rule("TEXT", "ab");
+        ctx.initialize(50, false);
+        let rules = vec![msg, num, sub, text, sub, text, empty]
+            .into_iter()
+            .map(NormalOrCustomRule::NormalRule)
+            .collect();
+        let mut tree = Tree::from_rule_vec(rules, &ctx);
+        let expected = b"\x08\xac\x02\x12\x0a\x0a\x02ab\x05\x0a\x02ab\x00".to_vec();
+        assert_eq!(tree.unparse_to_vec(&ctx), expected);
+        let mut data = vec![];
+        tree.unparse(NodeID::from(0), &ctx, &mut data).expect("RAND_4169093587");
+        assert_eq!(data, expected);
+        for _ in 0..100 {
+            tree.truncate();
+            //Every tree for MSG has an odd number of nodes
+            tree.generate_from_nt(ctx.nt_id("MSG"), 49, &ctx);
+            let mut vec1 = vec![];
+            tree.unparse(NodeID::from(0), &ctx, &mut vec1).expect("RAND_2034913640");
+            assert_eq!(vec1, tree.unparse_to_vec(&ctx));
+        }
+    }
+
     #[test]
     fn check_find_recursions() {
         let mut ctx = Context::new();
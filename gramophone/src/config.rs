@@ -1,5 +1,3 @@
-pub const BITMAP_SIZE: usize = 1 << 15;
-
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub number_of_threads: u8,
@@ -8,7 +6,7 @@ pub struct Config {
     pub number_of_generate_inputs: u16,
     pub number_of_deterministic_mutations: usize,
     pub max_tree_size: usize,
-    pub bitmap_size: usize,
+    pub bitmap_size: usize, //Coverage map size requested from the target. Targets compiled with a bigger ROFL_MAP_SIZE use theirs
     pub path_to_bin_target: String,
     pub path_to_grammar: String,
     pub path_to_workdir: String,
@@ -17,4 +15,38 @@ pub struct Config {
     pub no_feedback_mode: bool, //When true the fuzzer only uses the generation method and no mutations
     pub dump_mode: bool, //When true the fuzzer saves every input that is tested (up to a maximum of 5000 and then cycling)
     pub arguments: Vec<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64, //Wall clock time in ms a single execution of the target may take. Upper bound for the timeout derived by the calibration
+    #[serde(default = "default_calibration_inputs")]
+    pub calibration_inputs: usize, //Number of generated inputs used to calibrate timeout and stability before fuzzing. 0 disables the calibration
+    #[serde(default)]
+    pub afl_forkserver: bool, //When true the target is driven with the AFL forkserver protocol, e.g. when it was built with afl-clang-fast
+    #[serde(default = "default_persistent_runs")]
+    pub persistent_runs: u64, //Inputs a target using __ROFL_LOOP may run in the same process before it is forked again. 1 forks for every input
+    #[serde(default)]
+    pub shm_input: bool, //When true inputs are passed in shared memory instead of a file. The target has to read them from stdin or __rofl_input, "@@" doesn't work. Inputs larger than 1 MiB still go to the file
+    #[serde(default = "default_minimize_crashes")]
+    pub minimize_crashes: bool, //When true the first input of every new bug is minimized and saved as outputs/crashes/<signature>/minimized
+    #[serde(default = "default_capture_output")]
+    pub capture_output: bool, //When true stdout and stderr of the target are captured, to group crashes by sanitizer report and save the output next to them
+}
+
+fn default_timeout_ms() -> u64 {
+    return 1000;
+}
+
+fn default_calibration_inputs() -> usize {
+    return 8;
+}
+
+fn default_persistent_runs() -> u64 {
+    return 1;
+}
+
+fn default_minimize_crashes() -> bool {
+    return true;
+}
+
+fn default_capture_output() -> bool {
+    return true;
 }
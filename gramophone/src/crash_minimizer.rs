@@ -0,0 +1,144 @@
+#![feature(vec_remove_item)]
+extern crate forksrv;
+extern crate grammartec;
+extern crate time as othertime;
+#[macro_use]
+extern crate serde_derive;
+extern crate clap;
+extern crate ron;
+
+mod config;
+mod fuzzer;
+mod minimization;
+mod queue;
+mod shared_state;
+mod triage;
+
+use config::Config;
+use fuzzer::Fuzzer;
+use grammartec::loader;
+use grammartec::mutator::Mutator;
+use grammartec::tree::{Tree, TreeLike};
+use shared_state::GlobalSharedState;
+
+use clap::{App, Arg};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+fn main() {
+    //Parse parameters
+    let matches = App::new("crash_minimizer")
+        .about("Shrinks a crashing input while it triggers the same bug")
+        .arg(Arg::with_name("config")
+             .short("g")
+             .value_name("CONFIG")
+             .takes_value(true)
+             .help("Path to configuration file")
+             .default_value("config.ron"))
+        .arg(Arg::with_name("start")
+             .long("start")
+             .value_name("NONTERMINAL")
+             .takes_value(true)
+             .help("Start symbol the fuzzer used instead of the one chosen by the grammar"))
+        .arg(Arg::with_name("output")
+             .short("o")
+             .value_name("OUTPUT")
+             .takes_value(true)
+             .help("Where to write the reproducer, its tree goes to OUTPUT.tree. Default TREE without .tree plus .min"))
+        .arg(Arg::with_name("tree")
+             .required(true)
+             .help("Serialized tree of the crashing input, e.g. outputs/crashes/<signature>/id:000000.tree"))
+        .arg(Arg::with_name("grammar")
+             .help("Overwrite the grammar file specified in the CONFIG"))
+        .get_matches();
+
+    let config_file_path = matches.value_of("config")
+        .expect("the path to the configuration file has a default value");
+    let tree_path = matches.value_of("tree").expect("RAND_1811100157");
+    let output_path = match matches.value_of("output") {
+        Some(path) => path.to_string(),
+        None => format!("{}.min", tree_path.trim_end_matches(".tree")),
+    };
+
+    //Set Config
+    let mut config_file = File::open(&config_file_path).expect("cannot read config file");
+    let mut config_file_contents = String::new();
+    config_file
+        .read_to_string(&mut config_file_contents)
+        .expect("RAND_388852773");
+    let mut config: Config = ron::de::from_str(&config_file_contents).expect("Failed to deserialize");
+    //The inputs tried during the minimization aren't worth dumping
+    config.dump_mode = false;
+
+    //The rule ids of the tree are only valid for the grammar the fuzzer used
+    let grammar_path = matches.value_of("grammar")
+        .unwrap_or(&config.path_to_grammar)
+        .to_owned();
+    let mut context_config = loader::ContextConfig::new(config.max_tree_size);
+    context_config.start = matches.value_of("start").map(|s| s.to_string());
+    let ctx = loader::load_context(&grammar_path, &context_config).unwrap_or_else(|e| {
+        eprintln!("cannot load grammar: {}", e);
+        process::exit(1);
+    });
+
+    //Deserialize tree
+    let mut tree_file = File::open(&tree_path).expect("cannot read tree file");
+    let mut tree_as_string = String::new();
+    tree_file
+        .read_to_string(&mut tree_as_string)
+        .expect("RAND_3986652181");
+    let tree: Tree = ron::de::from_str(&tree_as_string).expect("Failed to deserialize tree");
+
+    let global_state = Arc::new(Mutex::new(GlobalSharedState::new(
+        config.path_to_workdir.clone(),
+    )));
+    //Signatures of crashes without a sanitizer report depend on the coverage of the non-crashing
+    //inputs, the fuzzer saves it with save_state
+    let bitmaps_file_path = config.path_to_workdir.to_owned() + "saved_bitmaps.ron";
+    if Path::new(&bitmaps_file_path).is_file() {
+        let mut sf_bitmaps =
+            File::open(&bitmaps_file_path).expect("cannot read saved bitmaps file");
+        let mut bitmap_as_string = String::new();
+        sf_bitmaps
+            .read_to_string(&mut bitmap_as_string)
+            .expect("RAND_2871643290");
+        global_state.lock().expect("RAND_1092735524").bitmaps =
+            ron::de::from_str(&bitmap_as_string).expect("Failed to deserialize bitmap");
+    }
+    let mut fuzzer = Fuzzer::new(&config, global_state).expect("RAND_2053737946");
+
+    let mut mutator = Mutator::new(&ctx);
+    let (signature, minimized) = match minimization::minimize_crash(&mut fuzzer, &mut mutator, &tree, &ctx) {
+        Ok(Some(minimized)) => minimized,
+        Ok(None) => {
+            eprintln!("the input doesn't crash the target");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("cannot run the target: {:?}", e);
+            process::exit(1);
+        }
+    };
+    if minimized.size() >= tree.size() {
+        println!(
+            "Nothing could be removed from {} ({} nodes, {} bytes, {})",
+            tree_path,
+            tree.size(),
+            tree.unparse_to_vec(&ctx).len(),
+            signature
+        );
+        return;
+    }
+    triage::write_reproducer(&output_path, &minimized, &ctx);
+    println!(
+        "Wrote {} ({} nodes, {} bytes, {}) and {}.tree",
+        output_path,
+        minimized.size(),
+        minimized.unparse_to_vec(&ctx).len(),
+        signature,
+        output_path
+    );
+}
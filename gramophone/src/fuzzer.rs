@@ -3,7 +3,6 @@ use othertime::strftime;
 
 use std::collections::HashSet;
 use std::collections::VecDeque;
-use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::stdout;
@@ -11,23 +10,64 @@ use std::io::ErrorKind;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use forksrv::error::{descr_err, SubprocessError};
+use config::Config;
+use forksrv::error::SubprocessError;
 use forksrv::exitreason::ExitReason;
-use forksrv::ForkServer;
+use forksrv::afl::AflForkServer;
+use forksrv::{ForkServer, ForkServerOptions};
 use grammartec::context::Context;
 use grammartec::tree::TreeLike;
 use shared_state::GlobalSharedState;
+use triage;
+use triage::CrashReport;
  use std::collections::HashMap;
 
-use config::BITMAP_SIZE;
+//How often calibrate runs every input
+const CALIBRATION_RUNS: usize = 8;
 
-#[repr(C)]
-struct FeedbackData {
-    run_bitmap: [u8; BITMAP_SIZE],
-    magic: u64,
-    status: i32,
+//Replaces the hit count of every edge by its AFL bucket (1, 2, 3, 4-7, 8-15, 16-31, 32-127, 128+),
+//one bit per bucket. That way edges hit more often are new coverage, while small changes of a loop
+//count are not.
+fn classify_counts(bitmap: &mut [u8]) {
+    for elem in bitmap.iter_mut() {
+        *elem = match *elem {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            3 => 4,
+            4..=7 => 8,
+            8..=15 => 16,
+            16..=31 => 32,
+            32..=127 => 64,
+            _ => 128,
+        };
+    }
+}
+
+//Adds the buckets of run_bitmap that shared_bitmap doesn't have yet and returns their edges. Both
+//bitmaps hold classified counts, so a new bucket of a known edge is new as well. Unstable edges are
+//never new.
+fn merge_new_bits(
+    run_bitmap: &[u8],
+    shared_bitmap: &mut [u8],
+    unstable_bits: &HashSet<usize>,
+) -> Vec<usize> {
+    let mut res = vec![];
+    for (i, elem) in shared_bitmap.iter_mut().enumerate() {
+        if (run_bitmap[i] & !*elem) != 0 && !unstable_bits.contains(&i) {
+            *elem |= run_bitmap[i];
+            res.push(i);
+        }
+    }
+    return res;
+}
+
+//Like AFL: five times the average execution time, rounded up to a multiple of 20 ms
+fn calibrated_timeout_ms(average_us: u64) -> u64 {
+    let timeout_ms = (average_us * 5 + 999) / 1000;
+    return ((timeout_ms + 19) / 20 * 20).max(20);
 }
 
 pub enum ExecutionReason {
@@ -41,18 +81,14 @@ pub enum ExecutionReason {
     Gen,
 }
 
-impl fmt::Debug for FeedbackData {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Feedback {{ run_bitmap [...] magic: {}, status: {} }}",
-            self.magic, self.status
-        )
-    }
+//The forkserver protocol the target was compiled for
+enum Backend {
+    Rofl(ForkServer),
+    Afl(AflForkServer),
 }
 
 pub struct Fuzzer {
-    forksrv: ForkServer<FeedbackData>,
+    forksrv: Backend,
     last_tried_inputs: HashSet<Vec<u8>>,
     last_inputs_ring_buffer: VecDeque<Vec<u8>>,
     pub global_state: Arc<Mutex<GlobalSharedState>>,
@@ -79,18 +115,43 @@ pub struct Fuzzer {
     dump_mode: bool,
     dump_counter: u64,
     work_dir: String,
+    //Output of the last crashing run, saved before the run is repeated
+    crash_stdout: Vec<u8>,
+    crash_stderr: Vec<u8>,
 }
 
 impl Fuzzer {
+    //Starts the target described by the config with the forkserver it asks for
     pub fn new(
-        path: String,
-        args: Vec<String>,
+        config: &Config,
         global_state: Arc<Mutex<GlobalSharedState>>,
-        dump_mode: bool,
-        work_dir: String,
     ) -> Result<Self, SubprocessError> {
-        let fs =
-            ForkServer::<FeedbackData>::new(&path, &args, "/dev/null".into(), "/dev/null".into())?;
+        let path = config.path_to_bin_target.clone();
+        let args = config.arguments.clone();
+        let options = ForkServerOptions {
+            timeout_ms: config.timeout_ms,
+            persistent_runs: config.persistent_runs,
+            shm_input: config.shm_input,
+            map_size: config.bitmap_size,
+            capture_output: config.capture_output,
+        };
+        let fs = if config.afl_forkserver {
+            Backend::Afl(AflForkServer::new(
+                &path,
+                &args,
+                "/dev/null".into(),
+                "/dev/null".into(),
+                &options,
+            )?)
+        } else {
+            Backend::Rofl(ForkServer::new(
+                &path,
+                &args,
+                "/dev/null".into(),
+                "/dev/null".into(),
+                &options,
+            )?)
+        };
         return Ok(Fuzzer {
             forksrv: fs,
             last_tried_inputs: HashSet::new(),
@@ -116,9 +177,11 @@ impl Fuzzer {
             asan_found_by_det: 0,
             asan_found_by_det_afl: 0,
             asan_found_by_gen: 0,
-            dump_mode: dump_mode,
+            dump_mode: config.dump_mode,
             dump_counter: 0,
-            work_dir: work_dir,
+            work_dir: config.path_to_workdir.clone(),
+            crash_stdout: vec![],
+            crash_stderr: vec![],
         });
     }
 
@@ -127,18 +190,30 @@ impl Fuzzer {
         if self.input_is_known(&code){
             return Ok(false);
         }
-        self.run_on(&code, tree, exec_reason, ctx)?;
+        self.run_on(Some(&code), tree, exec_reason, ctx)?;
         return Ok(true);
     }
 
     pub fn run_on_without_dedup<T: TreeLike>(&mut self, tree: &T, exec_reason: ExecutionReason, ctx: &Context) -> Result<(), SubprocessError>{
+        //Without dedup or dumping we don't need a copy, the tree is unparsed directly into the
+        //shared memory of the target. Inputs that don't fit take the usual way to the input file.
+        if !self.dump_mode {
+            if let Backend::Rofl(ref mut fs) = self.forksrv {
+                if let Some(mut writer) = fs.input_writer() {
+                    if tree.unparse_to(ctx, &mut writer).is_ok() {
+                        return self.run_on(None, tree, exec_reason, ctx);
+                    }
+                }
+            }
+        }
         let code = tree.unparse_to_vec(ctx);
-        return self.run_on(&code, tree, exec_reason, ctx);
+        return self.run_on(Some(&code), tree, exec_reason, ctx);
     }
 
+    //code is None if the input was already written with an input_writer
     fn run_on<T: TreeLike>(
         &mut self,
-        code: &Vec<u8>,
+        code: Option<&[u8]>,
         tree: &T,
         exec_reason: ExecutionReason,
         ctx: &Context,
@@ -157,13 +232,7 @@ impl Fuzzer {
                         .expect("RAND_202860771")
                         .last_found_asan = strftime("[%Y-%m-%d] %H:%M:%S", &othertime::now())
                         .expect("RAND_2888070412");
-                    let mut file = File::create(format!(
-                        "{}outputs/signaled/ASAN_{:09}_{}",
-                        self.work_dir,
-                        self.execution_count,
-                        thread::current().name().expect("RAND_4086695190")
-                    )).expect("RAND_3096222153");
-                    tree.unparse_to(ctx, &mut file).expect("RAND_585073586");
+                    self.save_crash(tree, ctx, term_sig);
                 }
                 ExitReason::Normal(_) => {
                     match exec_reason {
@@ -205,7 +274,7 @@ impl Fuzzer {
                     )).expect("RAND_452993103");
                     tree.unparse_to(ctx, &mut file).expect("RAND_2015788039");
                 }
-                ExitReason::Signaled(sig) => {
+                ExitReason::Signaled(_) => {
                     self.global_state
                         .lock()
                         .expect("RAND_1858328446")
@@ -215,11 +284,7 @@ impl Fuzzer {
                         .expect("RAND_4287051369")
                         .last_found_sig =
                         strftime("[%Y-%m-%d] %H:%M:%S", &othertime::now()).expect("RAND_76391000");
-                    let mut file = File::create(format!(
-                        "{}outputs/signaled/{:?}_{:09}",
-                        self.work_dir, sig, self.execution_count
-                    )).expect("RAND_3690294970");
-                    tree.unparse_to(ctx, &mut file).expect("RAND_3072663268");
+                    self.save_crash(tree, ctx, term_sig);
                 }
                 ExitReason::Stopped(_sig) => {}
             }
@@ -228,19 +293,75 @@ impl Fuzzer {
         return Ok(());
     }
 
+    //Files the crashing input under its signature, together with the output of the run. New bugs
+    //are queued for crash minimization
+    fn save_crash<T: TreeLike>(&self, tree: &T, ctx: &Context, exitreason: ExitReason) {
+        let (signature, report, edges) = self.last_crash_signature(exitreason);
+        let code = tree.unparse_to_vec(ctx);
+        let tree = tree.to_tree(ctx);
+        let mut gstate = self.global_state.lock().expect("RAND_3437101725");
+        let is_new = gstate.crashes.add(
+            signature.clone(),
+            exitreason,
+            report,
+            edges,
+            &code,
+            &tree,
+            &self.crash_stdout,
+            &self.crash_stderr,
+        );
+        if is_new {
+            gstate.crashes_to_minimize.push((signature, tree));
+        }
+    }
+
+    //Without a sanitizer report the edges only crashes hit tell bugs apart, see
+    //CrashIndex::coverage_signature. Returns the signature, the report and the identifying edges.
+    fn last_crash_signature(
+        &self,
+        exitreason: ExitReason,
+    ) -> (String, Option<CrashReport>, Vec<usize>) {
+        if let Some(report) = triage::parse_report(&self.crash_stderr) {
+            return (triage::report_signature(&report), Some(report), vec![]);
+        }
+        let gstate = self.global_state.lock().expect("RAND_3637745199");
+        let edges = triage::stable_edges(self.last_bitmap(), &gstate.unstable_bits);
+        let known_bitmap = gstate.bitmaps.get(&false).map_or(&[][..], |bitmap| &bitmap[..]);
+        let (signature, edges) = gstate.crashes.coverage_signature(exitreason, &edges, known_bitmap);
+        return (signature, None, edges);
+    }
+
+    //Runs the tree without touching the queue or the crashes and returns the exit reason and the
+    //report of the crash, None if the target didn't crash. last_bitmap has the edges of the run.
+    pub fn run_crash<T: TreeLike>(
+        &mut self,
+        tree: &T,
+        ctx: &Context,
+    ) -> Result<Option<(ExitReason, Option<CrashReport>)>, SubprocessError> {
+        let code = tree.unparse_to_vec(ctx);
+        let (exitreason, _) = self.exec_raw(&code)?;
+        match exitreason {
+            ExitReason::Normal(223) | ExitReason::Signaled(_) => {}
+            _ => return Ok(None),
+        }
+        self.save_crash_output()?;
+        return Ok(Some((exitreason, triage::parse_report(&self.crash_stderr))));
+    }
+
+    //Checks whether the tree hits the given edges with the same hit count bucket as all_bits
     pub fn has_bits<T: TreeLike>(
         &mut self,
         tree: &T,
         bits: &HashSet<usize>,
+        all_bits: &[u8],
         exec_reason: ExecutionReason,
         ctx: &Context,
     ) -> Result<bool, SubprocessError> {
         self.run_on_without_dedup(tree, exec_reason, ctx)?;
-        let run_bitmap = self.forksrv.get_shared().run_bitmap;
+        let run_bitmap = self.last_bitmap();
         let mut found_all = true;
         for bit in bits.iter() {
-            if run_bitmap[*bit] == 0 {
-                //TODO: handle edge counts properly
+            if run_bitmap[*bit] != all_bits[*bit] {
                 found_all = false;
             }
         }
@@ -249,31 +370,60 @@ impl Fuzzer {
 
 
     pub fn last_bitmap<'a>(&'a self) -> &'a [u8]{
-            return &self.forksrv.get_shared().run_bitmap;
+            match self.forksrv {
+                Backend::Rofl(ref fs) => return fs.bitmap(),
+                Backend::Afl(ref fs) => return fs.bitmap(),
+            }
     }
 
     pub fn exec_raw<'a>(&'a mut self, code: &[u8])-> Result<(ExitReason, u32), SubprocessError> {
+            match self.forksrv {
+                Backend::Rofl(ref mut fs) => fs.set_input(&code)?,
+                Backend::Afl(ref mut fs) => fs.set_input(&code)?,
+            }
+            return self.exec_current();
+    }
 
-            self.execution_count += 1;
+    //Runs the target again on the input of the last run
+    fn exec_current(&mut self) -> Result<(ExitReason, u32), SubprocessError> {
 
-            self.forksrv.get_shared_mut().magic = 0x1337133713371337;
+            self.execution_count += 1;
 
             let start = Instant::now();
-            
-            self.forksrv.run_on(&code)?;
+
+            let exitreason = match self.forksrv {
+                Backend::Rofl(ref mut fs) => fs.run()?,
+                Backend::Afl(ref mut fs) => fs.run()?,
+            };
+            match self.forksrv {
+                Backend::Rofl(ref mut fs) => classify_counts(fs.bitmap_mut()),
+                Backend::Afl(ref mut fs) => classify_counts(fs.bitmap_mut()),
+            }
 
             let execution_time = start.elapsed().subsec_nanos();
 
             self.average_executions_per_sec = self.average_executions_per_sec * 0.9
                 + ((1.0 / (execution_time as f32)) * 1000000000.0) * 0.1;
 
-            if self.forksrv.get_shared().magic != 0x5a5a55464c464f52 {
-                return descr_err("Failed to get magic value from subprocess");
-            }
-            let exitreason = ExitReason::from_int(self.forksrv.get_shared().status);
             return Ok((exitreason,execution_time));
     }
 
+    fn save_crash_output(&mut self) -> Result<(), SubprocessError> {
+        self.crash_stdout.clear();
+        self.crash_stderr.clear();
+        match self.forksrv {
+            Backend::Rofl(ref mut fs) => {
+                self.crash_stdout.extend_from_slice(fs.last_stdout()?);
+                self.crash_stderr.extend_from_slice(fs.last_stderr()?);
+            }
+            Backend::Afl(ref mut fs) => {
+                self.crash_stdout.extend_from_slice(fs.last_stdout()?);
+                self.crash_stderr.extend_from_slice(fs.last_stderr()?);
+            }
+        }
+        return Ok(());
+    }
+
     fn input_is_known(&mut self, code: &[u8]) -> bool{
         if self.last_tried_inputs.contains(code) {
             return true
@@ -294,7 +444,7 @@ impl Fuzzer {
 
     fn exec<T: TreeLike>(
         &mut self,
-        code: &[u8],
+        code: Option<&[u8]>,
         tree_like: &T,
         ctx: &Context,
     ) -> Result<(Option<Vec<usize>>, ExitReason), SubprocessError> {
@@ -306,6 +456,8 @@ impl Fuzzer {
                     self.dump_counter,
                     thread::current().name().expect("RAND_754590218")
                 )).expect("RAND_3752750300");
+                //in dump mode the input is always passed as code
+                let code = code.expect("RAND_3489856788");
                 file.write(&code).expect("Failed to write to dump file");
                 if self.dump_counter < max_files {
                     match fs::remove_file(format!(
@@ -339,21 +491,28 @@ impl Fuzzer {
                 }
             }
 
-            let (exitreason,execution_time) = self.exec_raw(&code)?;
+            let (exitreason,execution_time) = match code {
+                Some(code) => self.exec_raw(code)?,
+                None => self.exec_current()?,
+            };
 
             let is_crash = match exitreason {
                 ExitReason::Normal(223) => true,
                 ExitReason::Signaled(_) => true,
                 _ => false,
             };
+            if is_crash {
+                //the deterministic check below runs the input again
+                self.save_crash_output()?;
+            }
 
             let mut final_bits = None;
             if let Some(mut new_bits) = self.new_bits(is_crash) {
                 //Only if not Timeout
                 if exitreason != ExitReason::Timeouted {
                     //Check for non deterministic bits
-                    let old_bitmap: Vec<u8> = self.forksrv.get_shared().run_bitmap.to_vec();
-                    self.check_deterministic_behaviour(&old_bitmap, &mut new_bits,&code)?;
+                    let old_bitmap: Vec<u8> = self.last_bitmap().to_vec();
+                    self.check_deterministic_behaviour(&old_bitmap, &mut new_bits)?;
                     if new_bits.len() > 0 {
                         let new_bits_clone = new_bits.clone();
                         final_bits = Some(new_bits);
@@ -374,35 +533,83 @@ impl Fuzzer {
         //}
     }
 
-    fn check_deterministic_behaviour(&mut self, old_bitmap: &[u8], new_bits: &mut Vec<usize>, code: &[u8]) -> Result<(), SubprocessError>{
-        for _ in (0..5){
-            let (exit_reason,time) = self.exec_raw(code)?;
-            let run_bitmap = self.forksrv.get_shared().run_bitmap;
-            for (i,&v) in old_bitmap.iter().enumerate(){
-                if run_bitmap[i] != v {
-                    println!("found fucky bit {}", i);
+    //Runs every input CALIBRATION_RUNS times. Edges that are not hit by every run of an input are
+    //added to the unstable bits, which new_bits ignores from then on. Afterwards the inputs are
+    //run like generated ones, so their coverage ends up in the queue. Returns a timeout derived
+    //from the execution times that is at most max_timeout_ms.
+    pub fn calibrate<T: TreeLike>(
+        &mut self,
+        trees: &[T],
+        ctx: &Context,
+        max_timeout_ms: u64,
+    ) -> Result<u64, SubprocessError> {
+        let mut total_time = Duration::from_secs(0);
+        let mut runs = 0;
+        let mut timeouts = 0;
+        for tree in trees.iter() {
+            let code = tree.unparse_to_vec(ctx);
+            let mut first_bitmap: Option<Vec<u8>> = None;
+            for _ in 0..CALIBRATION_RUNS {
+                let start = Instant::now();
+                let (exitreason, _) = self.exec_raw(&code)?;
+                if exitreason == ExitReason::Timeouted {
+                    timeouts += 1;
+                    continue;
                 }
+                total_time += start.elapsed();
+                runs += 1;
+                match first_bitmap {
+                    Some(ref first_bitmap) => self.add_unstable_bits(first_bitmap),
+                    None => first_bitmap = Some(self.last_bitmap().to_vec()),
+                }
+            }
+        }
+        for tree in trees.iter() {
+            self.run_on_without_dedup(tree, ExecutionReason::Gen, ctx)?;
+        }
+        if runs == 0 || timeouts > 0 {
+            return Ok(max_timeout_ms);
+        }
+        let average_us =
+            (total_time.as_secs() * 1000000 + total_time.subsec_micros() as u64) / runs;
+        return Ok(calibrated_timeout_ms(average_us).min(max_timeout_ms));
+    }
+
+    //Adds all edges that were hit in one of the bitmaps but not in the other to the ignore mask
+    fn add_unstable_bits(&mut self, old_bitmap: &[u8]) {
+        let run_bitmap = self.last_bitmap();
+        let mut gstate_lock = self.global_state.lock().expect("RAND_1919827794");
+        for (i, &v) in old_bitmap.iter().enumerate() {
+            if (run_bitmap[i] != 0) != (v != 0) {
+                gstate_lock.unstable_bits.insert(i);
             }
-            new_bits.retain(|&i| run_bitmap[i] != 0);
         }
+    }
+
+    fn check_deterministic_behaviour(&mut self, old_bitmap: &[u8], new_bits: &mut Vec<usize>) -> Result<(), SubprocessError>{
+        for _ in (0..5){
+            let (exit_reason,time) = self.exec_current()?;
+            if exit_reason == ExitReason::Timeouted {
+                continue;
+            }
+            self.add_unstable_bits(old_bitmap);
+            let run_bitmap = self.last_bitmap();
+            new_bits.retain(|&i| run_bitmap[i] == old_bitmap[i]);
+        }
+        let gstate_lock = self.global_state.lock().expect("RAND_3533043450");
+        new_bits.retain(|i| !gstate_lock.unstable_bits.contains(i));
         return Ok(())
     }
 
     pub fn new_bits(&mut self, is_crash: bool) -> Option<Vec<usize>> {
-        let mut res = vec!();
-        let run_bitmap = self.forksrv.get_shared().run_bitmap;
+        let run_bitmap = self.last_bitmap();
         let mut gstate_lock = self.global_state.lock().expect("RAND_2040280272");
-        let shared_bitmap = gstate_lock
+        let gstate = &mut *gstate_lock;
+        let shared_bitmap = gstate
             .bitmaps
             .entry(is_crash)
-            .or_insert_with(|| vec![0; BITMAP_SIZE]);
-        for (i, elem) in shared_bitmap.iter_mut().enumerate() {
-            if (run_bitmap[i] != 0) && (*elem == 0) {
-                *elem |= run_bitmap[i];
-                res.push(i);
-                //println!("Added new bit to bitmap. Is Crash: {:?}; Added bit: {:?}", is_crash, i);
-            }
-        }
+            .or_insert_with(|| vec![0; run_bitmap.len()]);
+        let res = merge_new_bits(run_bitmap, shared_bitmap, &gstate.unstable_bits);
 
         if res.len() > 0 {
             //print!("New path found:\nNew bits: {:?}\n", res);
@@ -411,3 +618,40 @@ impl Fuzzer {
         return None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_classify_counts() {
+        let mut bitmap = [0, 1, 2, 3, 4, 7, 8, 15, 16, 31, 32, 127, 128, 255];
+        classify_counts(&mut bitmap);
+        assert_eq!(bitmap, [0, 1, 2, 4, 8, 8, 16, 16, 32, 32, 64, 64, 128, 128]);
+    }
+
+    #[test]
+    fn check_merge_new_bits() {
+        let unstable = HashSet::new();
+        let mut shared = vec![0, 1, 0];
+        assert_eq!(merge_new_bits(&[0, 1, 0], &mut shared, &unstable), Vec::<usize>::new());
+        //edge 1 is known, but not with 4-7 hits
+        assert_eq!(merge_new_bits(&[0, 8, 0], &mut shared, &unstable), vec![1]);
+        assert_eq!(shared, vec![0, 9, 0]);
+        assert_eq!(merge_new_bits(&[0, 8, 0], &mut shared, &unstable), Vec::<usize>::new());
+        assert_eq!(merge_new_bits(&[2, 1, 0], &mut shared, &unstable), vec![0]);
+        let unstable = [2].iter().cloned().collect();
+        assert_eq!(merge_new_bits(&[0, 1, 1], &mut shared, &unstable), Vec::<usize>::new());
+        assert_eq!(shared, vec![2, 9, 0]);
+    }
+
+    #[test]
+    fn check_calibrated_timeout() {
+        assert_eq!(calibrated_timeout_ms(0), 20);
+        assert_eq!(calibrated_timeout_ms(100), 20);
+        assert_eq!(calibrated_timeout_ms(4000), 20);
+        assert_eq!(calibrated_timeout_ms(4001), 40);
+        assert_eq!(calibrated_timeout_ms(8000), 40);
+        assert_eq!(calibrated_timeout_ms(1000000), 5000);
+    }
+}
@@ -1,22 +1,19 @@
-extern crate antlr_parser;
 #[macro_use]
 extern crate clap;
 extern crate grammartec;
 extern crate ron;
-extern crate serde_json;
 
-use grammartec::context::Context;
-use grammartec::context::SerializableContext;
+use grammartec::export;
+use grammartec::loader;
 use grammartec::newtypes::NTermID;
 use grammartec::tree::TreeLike;
 
 use clap::{App, Arg};
-use std::collections::hash_map::DefaultHasher;
 use std::fs;
 use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::io::{self, Read, Write};
+use std::io::{self, Write};
 use std::path::Path;
+use std::process;
 
 fn main() {
     //Parse parameters
@@ -48,6 +45,16 @@ fn main() {
         .arg(Arg::with_name("verbose")
              .short("v")
              .help("Be verbose"))
+        .arg(Arg::with_name("start")
+             .long("start")
+             .value_name("NONTERMINAL")
+             .takes_value(true)
+             .help("Generate trees for NONTERMINAL instead of the start symbol of the grammar"))
+        .arg(Arg::with_name("export")
+             .short("e")
+             .value_name("FILE")
+             .takes_value(true)
+             .help("Export the grammar instead of generating trees. The format (.json, .bnf or .g4) is chosen by the file extension"))
         .get_matches();
 
 
@@ -61,69 +68,25 @@ fn main() {
     let store = matches.is_present("store");
     let dumb = matches.is_present("dumb");
     let verbose = matches.is_present("verbose");
+    let export_path = matches.value_of("export").map(|p| p.to_string());
 
-    let mut ctx;
-    let serialized_context_path = grammar_path.clone() + ".gfc";
-    let mut maybe_serialized_context = None;
-    //Calculate string of grammar file
-    let mut gf = File::open(grammar_path.clone()).expect("cannot open grammar file");
-    let mut content = String::new();
-    gf.read_to_string(&mut content)
-        .expect("cannot read grammar file");
-    let mut s = DefaultHasher::new();
-    content.hash(&mut s);
-    let hash = s.finish();
-    //Deserialize saved context if the granmmar did not change (hash value still the same)
-    if Path::new(&serialized_context_path).is_file() {
-        let mut cf = File::open(&serialized_context_path).expect("cannot read saved context file");
-        let mut context_as_string = String::new();
-        cf.read_to_string(&mut context_as_string)
-            .expect("RAND_2280042516");
-        let serialized_context: SerializableContext =
-            ron::de::from_str(&context_as_string).expect("Failed to deserialize context");
-        //Check if file changed
-        if hash != serialized_context.hash_of_original {
-        } else {
-            maybe_serialized_context = Some(serialized_context);
-        }
-    }
-    if let Some(serialized_context) = maybe_serialized_context {
-        ctx = Context::from_serialized_context(serialized_context, false, dumb);
-    }
-    //Create new Context and saved it
-    else {
-        let mut my_parser = antlr_parser::AntlrParser::new();
-        ctx = Context::with_dump(dumb);
-        if grammar_path.ends_with(".json") {
-            let gf = File::open(grammar_path).expect("cannot read grammar file");
-            let rules: Vec<Vec<String>> =
-                serde_json::from_reader(&gf).expect("cannot parse grammar file");
-            assert!(rules.len() > 0, "rule file didn_t include any rules");
-            let root = "{".to_string() + &rules[0][0] + "}";
-            ctx.add_rule("START", &root);
-            for rule in rules {
-                
-                ctx.add_rule(&rule[0], &rule[1]);
-            }
-        } else if grammar_path.ends_with(".g4") {
-            my_parser.parse_antlr_grammar(&grammar_path);
-            let root = "{".to_string() + &my_parser.rules[0].0 + "}";
-            ctx.add_rule("START", &root);
-            for rule in my_parser.rules {
-                ctx.add_rule(&rule.0, &rule.1);
-            }
-        } else {
-            panic!("Unknown grammar type");
-        }
-        ctx.initialize(tree_depth, verbose);
-        //Save context
-        let mut cf = File::create(&serialized_context_path).expect("cannot create context file");
-        let serializable_context: SerializableContext = ctx.create_serializable_context(hash);
-        cf.write_all(
-            ron::ser::to_string(&serializable_context)
-                .expect("Serialization of Context failed!")
-                .as_bytes(),
-        ).expect("Writing to context file failed");
+    let mut context_config = loader::ContextConfig::new(tree_depth);
+    context_config.start = matches.value_of("start").map(|s| s.to_string());
+    context_config.dumb = dumb;
+    context_config.verbose = verbose;
+    context_config.use_cache = true;
+    let ctx = loader::load_context(&grammar_path, &context_config).unwrap_or_else(|e| {
+        eprintln!("cannot load grammar: {}", e);
+        process::exit(1);
+    });
+
+    //Export grammar
+    if let Some(export_path) = export_path {
+        export::write_by_extension(&ctx, &export_path).unwrap_or_else(|e| {
+            eprintln!("cannot write {}: {}", export_path, e);
+            process::exit(1);
+        });
+        return;
     }
 
     //Generate Tree
@@ -1,8 +1,6 @@
 #![feature(vec_remove_item)]
-extern crate antlr_parser;
 extern crate forksrv;
 extern crate grammartec;
-extern crate serde_json;
 extern crate time as othertime;
 #[macro_use]
 extern crate serde_derive;
@@ -11,28 +9,30 @@ extern crate ron;
 
 mod config;
 mod fuzzer;
+mod minimization;
 mod queue;
 mod rules;
 mod shared_state;
 mod state;
+mod triage;
 
 use config::Config;
 use forksrv::error::SubprocessError;
 use fuzzer::Fuzzer;
 use grammartec::chunkstore::ChunkStoreWrapper;
 use grammartec::context::{Context, SerializableContext};
+use grammartec::loader;
 use queue::{InputState, QueueItem};
 use shared_state::GlobalSharedState;
 use state::FuzzingState;
 
 use clap::{App, Arg};
 use othertime::strftime;
-use std::collections::hash_map::DefaultHasher;
 use std::fs;
 use std::fs::File;
-use std::hash::{Hash, Hasher};
 use std::io::{Read, Write};
 use std::path::Path;
+use std::process;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
@@ -94,16 +94,7 @@ fn fuzzing_thread(
     ctx: Context,
     cks: Arc<ChunkStoreWrapper>,
 ) {
-    let path_to_bin_target = config.path_to_bin_target.to_owned();
-    let args = config.arguments.clone();
-
-    let fuzzer = Fuzzer::new(
-        path_to_bin_target.clone(),
-        args,
-        global_state.clone(),
-        config.dump_mode,
-        config.path_to_workdir.clone(),
-    ).expect("RAND_3617502350");
+    let fuzzer = Fuzzer::new(&config, global_state.clone()).expect("RAND_3617502350");
     let mut state = FuzzingState::new(fuzzer, config.clone(), cks.clone());
     state.ctx = ctx.clone();
     let mut old_execution_count = 0;
@@ -111,18 +102,28 @@ fn fuzzing_thread(
     //Normal mode
     if config.no_feedback_mode == false {
         loop {
+            if config.minimize_crashes {
+                let crash = global_state
+                    .lock()
+                    .expect("RAND_1285637740")
+                    .crashes_to_minimize
+                    .pop();
+                if let Some((signature, tree)) = crash {
+                    //If subprocess died restart forkserver
+                    if state.minimize_crash(&signature, &tree).is_err() {
+                        let fuzzer = Fuzzer::new(&config, global_state.clone()).expect("RAND_754506396");
+                        state = FuzzingState::new(fuzzer, config.clone(), cks.clone());
+                        state.ctx = ctx.clone();
+                        old_execution_count = 0;
+                        old_executions_per_sec = 0;
+                    }
+                }
+            }
             let inp = global_state.lock().expect("RAND_2191486322").queue.pop();
             if let Some(mut inp) = inp {
                 //If subprocess died restart forkserver
                 if process_input(&mut state, &mut inp, &config).is_err() {
-                    let args = vec![];
-                    let fuzzer = Fuzzer::new(
-                        path_to_bin_target.clone(),
-                        args,
-                        global_state.clone(),
-                        config.dump_mode,
-                        config.path_to_workdir.clone(),
-                    ).expect("RAND_3077320530");
+                    let fuzzer = Fuzzer::new(&config, global_state.clone()).expect("RAND_3077320530");
                     state = FuzzingState::new(fuzzer, config.clone(), cks.clone());
                     state.ctx = ctx.clone();
                     old_execution_count = 0;
@@ -137,14 +138,7 @@ fn fuzzing_thread(
                 for _ in 0..config.number_of_generate_inputs {
                     //If subprocess dies restart forkserver
                     if state.generate_random("START").is_err() {
-                        let args = vec![];
-                        let fuzzer = Fuzzer::new(
-                            path_to_bin_target.clone(),
-                            args,
-                            global_state.clone(),
-                            config.dump_mode,
-                            config.path_to_workdir.clone(),
-                        ).expect("RAND_357619639");
+                        let fuzzer = Fuzzer::new(&config, global_state.clone()).expect("RAND_357619639");
                         state = FuzzingState::new(fuzzer, config.clone(), cks.clone());
                         state.ctx = ctx.clone();
                         old_execution_count = 0;
@@ -202,14 +196,7 @@ fn fuzzing_thread(
         loop {
             //If subprocess dies restart forkserver
             if state.generate_random("START").is_err() {
-                let args = vec![];
-                let fuzzer = Fuzzer::new(
-                    path_to_bin_target.clone(),
-                    args,
-                    global_state.clone(),
-                    config.dump_mode,
-                    config.path_to_workdir.clone(),
-                ).expect("RAND_574815774");
+                let fuzzer = Fuzzer::new(&config, global_state.clone()).expect("RAND_574815774");
                 state = FuzzingState::new(fuzzer, config.clone(), cks.clone());
                 state.ctx = ctx.clone();
                 old_execution_count = 0;
@@ -229,6 +216,30 @@ fn fuzzing_thread(
     }
 }
 
+//Runs the first generated inputs several times to derive the timeout and to find unstable edges
+fn calibrate(config: &Config, ctx: &Context, global_state: Arc<Mutex<GlobalSharedState>>) -> u64 {
+    let mut fuzzer = Fuzzer::new(config, global_state.clone()).expect("RAND_1623314505");
+    let nonterm = ctx.nt_id("START");
+    let trees = (0..config.calibration_inputs)
+        .map(|_| ctx.generate_tree_from_nt(nonterm, ctx.get_random_len_for_nt(&nonterm)))
+        .collect::<Vec<_>>();
+    let timeout_ms = fuzzer
+        .calibrate(&trees, ctx, config.timeout_ms)
+        .expect("RAND_1160750301");
+    let mut shared_state = global_state.lock().expect("RAND_2920284696");
+    shared_state.execution_count += fuzzer.execution_count;
+    shared_state.bits_found_by_gen += fuzzer.bits_found_by_gen;
+    println!(
+        "{} Calibration done, timeout: {} ms, stability: {:.2}%",
+        othertime::now()
+            .strftime("[%Y-%m-%d] %H:%M:%S")
+            .expect("RAND_3057054012"),
+        timeout_ms,
+        shared_state.stability()
+    );
+    return timeout_ms;
+}
+
 fn main() {
     //Parse parameters
     let matches = App::new("gramfuzz")
@@ -242,6 +253,11 @@ fn main() {
         .arg(Arg::with_name("dumb")
              .short("d")
              .help("Don't use fancy calculations to generate trees (dumb mode)"))
+        .arg(Arg::with_name("start")
+             .long("start")
+             .value_name("NONTERMINAL")
+             .takes_value(true)
+             .help("Use NONTERMINAL as start symbol instead of the one chosen by the grammar"))
         .arg(Arg::with_name("grammar")
              .help("Overwrite the grammar file specified in the CONFIG"))
         .get_matches();
@@ -263,7 +279,7 @@ fn main() {
     config_file
         .read_to_string(&mut config_file_contents)
         .expect("RAND_1413661228");
-    let config: Config = ron::de::from_str(&config_file_contents).expect("Failed to deserialize");
+    let mut config: Config = ron::de::from_str(&config_file_contents).expect("Failed to deserialize");
 
     let shared = Arc::new(Mutex::new(GlobalSharedState::new(
         config.path_to_workdir.clone(),
@@ -274,6 +290,25 @@ fn main() {
     let queue_file_path = config.path_to_workdir.to_owned() + "saved_queue.ron";
     let bitmaps_file_path = config.path_to_workdir.to_owned() + "saved_bitmaps.ron";
     let chunkstore_file_path = config.path_to_workdir.to_owned() + "saved_chunkstore.ron";
+    let unstable_bits_file_path = config.path_to_workdir.to_owned() + "saved_unstable_bits.ron";
+    //Edges found to be unstable by an earlier run stay ignored, calibration only adds to them
+    if Path::new(&unstable_bits_file_path).is_file() {
+        print!(
+            "{} Found old unstable bits...",
+            othertime::now()
+                .strftime("[%Y-%m-%d] %H:%M:%S")
+                .expect("RAND_391717361")
+        );
+        let mut sf_unstable_bits =
+            File::open(&unstable_bits_file_path).expect("cannot read saved unstable bits file");
+        let mut unstable_bits_as_string = String::new();
+        sf_unstable_bits
+            .read_to_string(&mut unstable_bits_as_string)
+            .expect("RAND_1903969542");
+        shared.lock().expect("RAND_2790183514").unstable_bits =
+            ron::de::from_str(&unstable_bits_as_string).expect("Failed to deserialize unstable bits");
+        println!("unstable bits loaded");
+    }
     //if Path::new(&queue_file_path).is_file() {
     //    print!(
     //        "{} Found old Queue...",
@@ -328,89 +363,37 @@ fn main() {
     //    println!("chunkstore loaded");
     //}
 
-    //Generate rules using a grammar or deserialize saved context
-    let mut my_context;
+    //Generate rules using a grammar
     let grammar_path = matches.value_of("grammar")
         .unwrap_or(&config.path_to_grammar)
         .to_owned();
-    //let serialized_context_path = grammar_path.clone() + ".gfc";
-    //let mut maybe_serialized_context = None;
-    //Calculate string of grammar file
-    let mut gf = File::open(grammar_path.clone()).expect("cannot open grammar file");
-    let mut content = String::new();
-    gf.read_to_string(&mut content)
-        .expect("cannot read grammar file");
-    let mut s = DefaultHasher::new();
-    content.hash(&mut s);
-    let hash = s.finish();
-    //Deserialize saved context if the granmmar did not change (hash value still the same)
-    //if Path::new(&serialized_context_path).is_file() {
-    //    println!("Found saved context...");
-    //    let mut cf = File::open(&serialized_context_path).expect("cannot read saved context file");
-    //    let mut context_as_string = String::new();
-    //    cf.read_to_string(&mut context_as_string)
-    //        .expect("RAND_33259161");
-    //    let serialized_context: SerializableContext =
-    //        ron::de::from_str(&context_as_string).expect("Failed to deserialize context");
-    //    //Check if file changed
-    //    if hash != serialized_context.hash_of_original {
-    //        println!("Grammar changed! Generating new context...");
-    //    } else {
-    //        maybe_serialized_context = Some(serialized_context);
-    //    }
-    //}
-    //if let Some(serialized_context) = maybe_serialized_context {
-    //    my_context = Context::from_serialized_context(serialized_context, true, dumb);
-    //    println!("imported saved context!")
-    //}
-    //Create new Context and saved it
-    //else {
-        let mut my_parser = antlr_parser::AntlrParser::new();
-        my_context = Context::with_dump(dumb);
-        if grammar_path.ends_with(".json") {
-            let gf = File::open(grammar_path).expect("cannot read grammar file");
-            let rules: Vec<Vec<String>> =
-                serde_json::from_reader(&gf).expect("cannot parse grammar file");
-            let root = "{".to_string() + &rules[0][0] + "}";
-            my_context.add_rule("START", &root);
-            for rule in rules {
-                my_context.add_rule(&rule[0], &rule[1]);
-            }
-        } else if grammar_path.ends_with(".g4") {
-            my_parser.parse_antlr_grammar(&grammar_path);
-            let root = "{".to_string() + &my_parser.rules[0].0 + "}";
-            my_context.add_rule("START", &root);
-            for rule in my_parser.rules {
-                my_context.add_rule(&rule.0, &rule.1);
-            }
-        } else {
-            panic!("Unknown grammar type");
-        }
-        my_context.initialize(config.max_tree_size, true);
-        //Save context
-        //let mut cf = File::create(&serialized_context_path).expect("cannot create context file");
-        //let serializable_context: SerializableContext =
-        //    my_context.create_serializable_context(hash);
-        //cf.write_all(
-        //    ron::ser::to_string(&serializable_context)
-        //        .expect("Serialization of Context failed!")
-        //        .as_bytes(),
-        //).expect("Writing to context file failed");
-    //}
+    let mut context_config = loader::ContextConfig::new(config.max_tree_size);
+    context_config.start = matches.value_of("start").map(|s| s.to_string());
+    context_config.dumb = dumb;
+    context_config.verbose = true;
+    let my_context = loader::load_context(&grammar_path, &context_config).unwrap_or_else(|e| {
+        eprintln!("cannot load grammar: {}", e);
+        process::exit(1);
+    });
 
     //Create output folder
     fs::create_dir_all(format!("{}/outputs", config.path_to_workdir)).expect("Could not create outputs folder");
-    let signaled_dir = config.path_to_workdir.clone() + "outputs/signaled";
+    let crashes_dir = config.path_to_workdir.clone() + "outputs/crashes";
     let queue_dir = config.path_to_workdir.clone() + "outputs/queue";
     let timeout_dir = config.path_to_workdir.clone() + "outputs/timeout";
     let dump_dir = config.path_to_workdir.clone() + "outputs/dumped_inputs";
-    fs::create_dir_all(signaled_dir).expect("Could not create singaled folder");
+    fs::create_dir_all(crashes_dir).expect("Could not create crashes folder");
     fs::create_dir_all(timeout_dir).expect("Could not create timeout folder");
     fs::create_dir_all(queue_dir).expect("Could not create queue folder");
     if config.dump_mode {
         fs::create_dir_all(dump_dir).expect("Could not create queue folder");
     }
 
+    //Calibrate timeout and stability
+    if config.calibration_inputs > 0 {
+        config.timeout_ms = calibrate(&config, &my_context, shared.clone());
+    }
+
     let clone = shared.clone();
     let clone_of_chunkstore = shared_chunkstore.clone();
     let config_clone = config.clone();
@@ -443,6 +426,7 @@ fn main() {
                 loop {
                     let execution_count;
                     let average_executions_per_sec;
+                    let stability;
                     let queue_len;
                     let bits_found_by_gen;
                     let bits_found_by_min;
@@ -457,11 +441,14 @@ fn main() {
                     let last_timeout;
                     let total_found_asan;
                     let total_found_sig;
+                    let unique_asan_bugs;
+                    let unique_sig_bugs;
                     let state_saved;
                     {
                         let shared_state = global_state.lock().expect("RAND_597319831");
                         execution_count = shared_state.execution_count;
                         average_executions_per_sec = shared_state.average_executions_per_sec;
+                        stability = shared_state.stability();
                         queue_len = shared_state.queue.len();
                         bits_found_by_gen = shared_state.bits_found_by_gen;
                         bits_found_by_min = shared_state.bits_found_by_min;
@@ -476,6 +463,8 @@ fn main() {
                         last_timeout = shared_state.last_timeout.clone();
                         total_found_asan = shared_state.total_found_asan;
                         total_found_sig = shared_state.total_found_sig;
+                        unique_asan_bugs = shared_state.unique_asan_bugs();
+                        unique_sig_bugs = shared_state.unique_sig_bugs();
                         state_saved = shared_state.state_saved.clone();
                     }
                     let secs = start_time.elapsed().as_secs();
@@ -501,6 +490,10 @@ fn main() {
                         "Executions per Sec:       {}                              ",
                         average_executions_per_sec
                     );
+                    println!(
+                        "Stability:                {:.2}%                          ",
+                        stability
+                    );
                     if config.no_feedback_mode == false {
                         println!(
                             "Left in queue:            {}                              ",
@@ -535,12 +528,12 @@ fn main() {
                         last_timeout
                     );
                     println!(
-                        "Total ASAN crashes:       {}                              ",
-                        total_found_asan
+                        "Unique ASAN bugs:         {} ({} inputs)                  ",
+                        unique_asan_bugs, total_found_asan
                     );
                     println!(
-                        "Total SIG crashes:        {}                              ",
-                        total_found_sig
+                        "Unique SIG bugs:          {} ({} inputs)                  ",
+                        unique_sig_bugs, total_found_sig
                     );
                     println!("------------------------------------------------------    ");
                     println!(
@@ -621,6 +614,17 @@ fn main() {
                             )
                             .expect("Writing to bitmap file failed");
 
+                        let mut of_unstable_bits = File::create(&unstable_bits_file_path)
+                            .expect("cannot create output file");
+                        of_unstable_bits
+                            .write_all(
+                                ron::ser::to_string(
+                                    &clone.lock().expect("RAND_2044518866").unstable_bits,
+                                ).expect("Serialization of unstable bits failed!")
+                                    .as_bytes(),
+                            )
+                            .expect("Writing to unstable bits file failed");
+
                         let mut of_chunkstore = File::create(
                             &/*(*/chunkstore_file_path, /*.to_owned()+id)*/
                         ).expect("cannot create output file");
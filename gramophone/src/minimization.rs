@@ -0,0 +1,87 @@
+//Shrinks crashing inputs with the grammar: subtrees are replaced by minimal ones, recursions are
+//collapsed and hierarchical delta debugging replaces whole groups of subtrees at once. A smaller
+//tree is only kept if it still triggers the bug of the first run of the original tree: it has the
+//same sanitizer report or, without one, the same exit reason and all edges the bug is identified by.
+
+use std::collections::HashSet;
+
+use forksrv::error::SubprocessError;
+use forksrv::exitreason::ExitReason;
+use fuzzer::Fuzzer;
+use grammartec::context::Context;
+use grammartec::mutator::Mutator;
+use grammartec::tree::{Tree, TreeLike, TreeMutation};
+use triage;
+
+//Every round runs all reductions once, later rounds only happen while the tree shrinks
+const MAX_ROUNDS: usize = 8;
+
+//What a smaller tree has to reproduce
+enum Reference {
+    Report(String),
+    Coverage(ExitReason, Vec<usize>),
+}
+
+//Returns the signature of the first run and the smallest tree that still triggers its bug, None if
+//the tree doesn't crash the target anymore
+pub fn minimize_crash(
+    fuzzer: &mut Fuzzer,
+    mutator: &mut Mutator,
+    tree: &Tree,
+    ctx: &Context,
+) -> Result<Option<(String, Tree)>, SubprocessError> {
+    //Other threads keep adding to both, the bug shouldn't change while we minimize
+    let (known_bitmap, unstable_bits) = {
+        let gstate = fuzzer.global_state.lock().expect("RAND_1566013429");
+        (
+            gstate.bitmaps.get(&false).cloned().unwrap_or_default(),
+            gstate.unstable_bits.clone(),
+        )
+    };
+    let (exitreason, report) = match fuzzer.run_crash(tree, ctx)? {
+        Some(crash) => crash,
+        None => return Ok(None),
+    };
+    let (signature, reference) = match report {
+        Some(ref report) => {
+            let signature = triage::report_signature(report);
+            (signature.clone(), Reference::Report(signature))
+        }
+        None => {
+            let edges = triage::stable_edges(fuzzer.last_bitmap(), &unstable_bits);
+            let gstate = fuzzer.global_state.lock().expect("RAND_2980046616");
+            let (signature, edges) =
+                gstate.crashes.coverage_signature(exitreason, &edges, &known_bitmap);
+            (signature, Reference::Coverage(exitreason, edges))
+        }
+    };
+
+    let mut tree = tree.clone();
+    let no_bits = HashSet::new();
+    let mut tester = |t: &TreeMutation, _bits: &HashSet<usize>, ctx: &Context| {
+        let (candidate, report) = match fuzzer.run_crash(t, ctx)? {
+            Some(crash) => crash,
+            None => return Ok(false),
+        };
+        match (&reference, report) {
+            (&Reference::Report(ref signature), Some(ref report)) => {
+                return Ok(triage::report_signature(report) == *signature);
+            }
+            (&Reference::Coverage(exitreason, ref edges), None) => {
+                let run_edges = triage::stable_edges(fuzzer.last_bitmap(), &unstable_bits);
+                return Ok(candidate == exitreason && triage::hits_all(&run_edges, edges));
+            }
+            _ => return Ok(false),
+        }
+    };
+    for _ in 0..MAX_ROUNDS {
+        let size = tree.size();
+        mutator.minimize_hdd(&mut tree, &no_bits, ctx, &mut tester)?;
+        mutator.minimize_tree(&mut tree, &no_bits, ctx, 0, usize::max_value(), &mut tester)?;
+        mutator.minimize_rec(&mut tree, &no_bits, ctx, 0, usize::max_value(), &mut tester)?;
+        if tree.size() >= size {
+            break;
+        }
+    }
+    return Ok(Some((signature, tree)));
+}
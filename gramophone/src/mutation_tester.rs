@@ -1,16 +1,16 @@
-extern crate antlr_parser;
 extern crate grammartec;
 extern crate ron;
-extern crate serde_json;
 
 use grammartec::chunkstore::ChunkStore;
 use grammartec::context::Context;
+use grammartec::loader;
 use grammartec::mutator::Mutator;
 use grammartec::tree::{Tree, TreeLike, TreeMutation};
 
 use std::env;
 use std::fs::File;
 use std::io::{self, Read};
+use std::process;
 
 enum MutationMethods {
     Havoc,
@@ -38,29 +38,12 @@ fn main() {
                 panic!("Please use havoc, rec, or splice");
             }
         };
-        let mut ctx = Context::new();
-
-        //Generate rules using an antlr grammar:
-        let mut my_parser = antlr_parser::AntlrParser::new();
-        if grammar_path.ends_with(".json") {
-            let gf = File::open(grammar_path).expect("cannot read grammar file");
-            let rules: Vec<(String, String)> =
-                serde_json::from_reader(&gf).expect("cannot parse grammar file");
-            let root = "{".to_string() + &rules[0].0 + "}";
-            ctx.add_rule("START", &root);
-            for rule in rules {
-                ctx.add_rule(&rule.0, &rule.1);
-            }
-        } else if grammar_path.ends_with(".g4") {
-            my_parser.parse_antlr_grammar(&grammar_path);
-            let root = "{".to_string() + &my_parser.rules[0].0 + "}";
-            ctx.add_rule("START", &root);
-            for rule in my_parser.rules {
-                ctx.add_rule(&rule.0, &rule.1);
-            }
-        } else {
-            panic!("Unknown grammar type");
-        }
+        let mut context_config = loader::ContextConfig::new(tree_depth);
+        context_config.use_cache = true;
+        let ctx = loader::load_context(&grammar_path, &context_config).unwrap_or_else(|e| {
+            eprintln!("cannot load grammar: {}", e);
+            process::exit(1);
+        });
 
         //Deserialize tree
         let mut sf = File::open(&tree_path).expect("cannot read tree file");
@@ -70,9 +53,6 @@ fn main() {
         let mut tree: Tree =
             ron::de::from_str(&tree_as_string).expect("Failed to deserialize tree");
 
-        //Initialize Context
-        ctx.initialize(tree_depth, false);
-
         println!(
             "Original tree:\nRules: {:?}\nSizes: {:?}\nParents: {:?}\nUnparsed original tree: ",
             tree.rules, tree.sizes, tree.paren
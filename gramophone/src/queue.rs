@@ -52,6 +52,12 @@ impl QueueItem {
     }
 }
 
+//bit_to_inputs is keyed by edge and hit count bucket, so inputs that only hit a known edge more
+//often are kept as well
+fn bucket_key(edge: usize, bucket: u8) -> usize {
+    return (edge << 3) | bucket.trailing_zeros() as usize;
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Queue {
     pub inputs: Vec<QueueItem>,
@@ -74,7 +80,7 @@ impl Queue {
         if all_bits
             .iter()
             .enumerate()
-            .all(|(i, elem)| (*elem == 0) || self.bit_to_inputs.contains_key(&i))
+            .all(|(i, elem)| (*elem == 0) || self.bit_to_inputs.contains_key(&bucket_key(i, *elem)))
         {
             return;
         }
@@ -82,11 +88,12 @@ impl Queue {
         //Check which bits are new and insert them into fresh_bits
         for (i, elem) in all_bits.iter().enumerate() {
             if *elem != 0 {
-                if !self.bit_to_inputs.contains_key(&i) {
+                let key = bucket_key(i, *elem);
+                if !self.bit_to_inputs.contains_key(&key) {
                     fresh_bits.insert(i.clone());
                 }
                 self.bit_to_inputs
-                    .entry(i)
+                    .entry(key)
                     .or_insert(vec![])
                     .push(self.current_id);
             }
@@ -155,7 +162,7 @@ impl Queue {
             .all_bits
             .iter()
             .enumerate()
-            .all(|(i, elem)| (*elem == 0) || self.bit_to_inputs.contains_key(&i))
+            .all(|(i, elem)| (*elem == 0) || self.bit_to_inputs.contains_key(&bucket_key(i, *elem)))
         {
             //If file was created for this entry, delete it.
             match fs::remove_file(format!(
@@ -174,10 +181,11 @@ impl Queue {
         let mut fresh_bits = HashSet::new();
         for (i, elem) in item.all_bits.iter().enumerate() {
             if *elem != 0 {
-                if !self.bit_to_inputs.contains_key(&i) {
+                let key = bucket_key(i, *elem);
+                if !self.bit_to_inputs.contains_key(&key) {
                     fresh_bits.insert(i.clone());
                 }
-                self.bit_to_inputs.entry(i).or_insert(vec![]).push(item.id);
+                self.bit_to_inputs.entry(key).or_insert(vec![]).push(item.id);
             }
         }
         self.processed.push(item);
@@ -191,3 +199,20 @@ impl Queue {
         self.inputs.append(&mut self.processed);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_bucket_key() {
+        //every bucket of an edge gets its own key, and no key is shared with another edge
+        let keys = (0..3)
+            .flat_map(|edge| (0..8).map(move |bucket| bucket_key(edge, 1 << bucket)))
+            .collect::<HashSet<_>>();
+        assert_eq!(keys.len(), 24);
+        assert_eq!(bucket_key(0, 128), 7);
+        assert_eq!(bucket_key(1, 1), 8);
+        assert_eq!(bucket_key(5, 16), 44);
+    }
+}
@@ -1,10 +1,15 @@
+use forksrv::exitreason::ExitReason;
+use grammartec::tree::Tree;
 use queue::Queue;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use triage::CrashIndex;
 
 pub struct GlobalSharedState {
     pub queue: Queue,
     //false for not crashing input. True for crashing inputs
     pub bitmaps: HashMap<bool, Vec<u8>>,
+    //Edges that are not hit deterministically. They are never reported as new bits
+    pub unstable_bits: HashSet<usize>,
     pub execution_count: u64,
     pub average_executions_per_sec: u32,
     pub bits_found_by_havoc: u64,
@@ -27,17 +32,22 @@ pub struct GlobalSharedState {
     pub last_found_sig: String,
     pub last_timeout: String,
     pub state_saved: String,
+    //Crashing inputs, crashes counts the different bugs
     pub total_found_asan: u64,
     pub total_found_sig: u64,
+    pub crashes: CrashIndex,
+    //First input of every new bug with its signature, waiting for crash minimization
+    pub crashes_to_minimize: Vec<(String, Tree)>,
 }
 
 impl GlobalSharedState {
     pub fn new(work_dir: String) -> Self {
-        let queue = Queue::new(work_dir);
+        let queue = Queue::new(work_dir.clone());
         let bitmaps = HashMap::new();
         return GlobalSharedState {
             queue,
             bitmaps,
+            unstable_bits: HashSet::new(),
             execution_count: 0,
             average_executions_per_sec: 0,
             bits_found_by_havoc: 0,
@@ -62,6 +72,35 @@ impl GlobalSharedState {
             state_saved: String::from("State not saved yet."),
             total_found_asan: 0,
             total_found_sig: 0,
+            crashes: CrashIndex::new(work_dir),
+            crashes_to_minimize: vec![],
         };
     }
+
+    pub fn unique_asan_bugs(&self) -> usize {
+        return self.crashes.unique(|exitreason| exitreason == ExitReason::Normal(223));
+    }
+
+    pub fn unique_sig_bugs(&self) -> usize {
+        return self.crashes.unique(|exitreason| match exitreason {
+            ExitReason::Signaled(_) => true,
+            _ => false,
+        });
+    }
+
+    //Percentage of the edges seen so far that are hit deterministically
+    pub fn stability(&self) -> f32 {
+        let bitmap_size = self.bitmaps.values().map(|bitmap| bitmap.len()).max().unwrap_or(0);
+        let seen = (0..bitmap_size)
+            .filter(|i| {
+                self.unstable_bits.contains(i) || self.bitmaps.values().any(|bitmap| bitmap[*i] != 0)
+            })
+            .count();
+        if seen == 0 {
+            return 100.0;
+        }
+        //bits loaded from an earlier run may lie outside of the current map
+        let unstable = self.unstable_bits.iter().filter(|i| **i < bitmap_size).count();
+        return 100.0 * (seen - unstable) as f32 / seen as f32;
+    }
 }
@@ -7,12 +7,15 @@ use std::time::Instant;
 use grammartec::chunkstore::ChunkStoreWrapper;
 use grammartec::context::Context;
 use grammartec::mutator::Mutator;
-use grammartec::tree::{TreeLike, TreeMutation};
+use grammartec::tree::{Tree, TreeLike, TreeMutation};
 
 use forksrv::error::SubprocessError;
 use fuzzer::{ExecutionReason, Fuzzer};
+use minimization;
+use othertime;
 use queue::QueueItem;
 use config::Config;
+use triage;
 
 pub struct FuzzingState {
     pub cks: Arc<ChunkStoreWrapper>,
@@ -44,6 +47,7 @@ impl FuzzingState {
     ) -> Result<bool, SubprocessError> {
         let ctx = &mut self.ctx;
         let fuzzer = &mut self.fuzzer;
+        let all_bits = &input.all_bits;
 
         
         let min_simple = self.mutator.minimize_tree(
@@ -53,7 +57,7 @@ impl FuzzingState {
             start_index,
             end_index,
             &mut |t: &TreeMutation, fresh_bits: &HashSet<usize>, ctx: &Context| {
-                let res = fuzzer.has_bits(t, fresh_bits, ExecutionReason::Min, ctx)?;
+                let res = fuzzer.has_bits(t, fresh_bits, all_bits, ExecutionReason::Min, ctx)?;
                 Ok(res)
             },
         )?;
@@ -65,7 +69,7 @@ impl FuzzingState {
             start_index,
             end_index,
             &mut |t: &TreeMutation, fresh_bits: &HashSet<usize>, ctx: &Context| {
-                let res = fuzzer.has_bits(t, fresh_bits, ExecutionReason::MinRec, ctx)?;
+                let res = fuzzer.has_bits(t, fresh_bits, all_bits, ExecutionReason::MinRec, ctx)?;
                 Ok(res)
             },
         )?;
@@ -106,6 +110,41 @@ impl FuzzingState {
         return Ok(false);
     }
 
+    //Shrinks the first input of a new bug, the result is saved next to its other inputs. Nothing is
+    //saved if the input doesn't trigger the bug anymore or can't be shrunk.
+    pub fn minimize_crash(&mut self, signature: &str, tree: &Tree) -> Result<(), SubprocessError> {
+        let minimized = minimization::minimize_crash(
+            &mut self.fuzzer,
+            &mut self.mutator,
+            tree,
+            &self.ctx,
+        )?;
+        let reason = match minimized {
+            Some((ref reproduced, ref minimized)) if reproduced == signature => {
+                if minimized.size() < tree.size() {
+                    let path = format!(
+                        "{}outputs/crashes/{}/minimized",
+                        &self.config.path_to_workdir, signature
+                    );
+                    triage::write_reproducer(&path, minimized, &self.ctx);
+                    return Ok(());
+                }
+                "nothing could be removed".to_string()
+            }
+            Some((reproduced, _)) => format!("the input triggers {} now", reproduced),
+            None => "the input doesn't crash the target anymore".to_string(),
+        };
+        println!(
+            "{} Not minimizing {}: {}",
+            othertime::now()
+                .strftime("[%Y-%m-%d] %H:%M:%S")
+                .expect("RAND_1270350861"),
+            signature,
+            reason
+        );
+        return Ok(());
+    }
+
     pub fn deterministic_tree_mutation(
         &mut self,
         input: &mut QueueItem,
@@ -11,10 +11,12 @@ extern crate ron;
 
 mod config;
 mod fuzzer;
+mod minimization;
 mod queue;
 mod rules;
 mod shared_state;
 mod state;
+mod triage;
 
 use std::collections::HashMap;
 
@@ -68,20 +70,11 @@ fn main() {
     let config: Config = ron::de::from_str(&config_file_contents).expect("Failed to deserialize");
 
 
-    let path_to_bin_target = config.path_to_bin_target.to_owned();
-    let args = config.arguments.clone();
-
     let global_state = Arc::new(Mutex::new(GlobalSharedState::new(
         config.path_to_workdir.clone(),
     )));
 
-    let mut fuzzer = Fuzzer::new(
-        path_to_bin_target.clone(),
-        args,
-        global_state.clone(),
-        config.dump_mode,
-        config.path_to_workdir.clone(),
-    ).expect("RAND_3617502350");
+    let mut fuzzer = Fuzzer::new(&config, global_state.clone()).expect("RAND_3617502350");
 
 
     let mut inputs : Vec<(usize, Vec<u8>, Vec<u8>)>= vec!();
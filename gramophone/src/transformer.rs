@@ -0,0 +1,127 @@
+#[macro_use]
+extern crate clap;
+extern crate grammartec;
+
+use grammartec::context::Context;
+use grammartec::export;
+use grammartec::loader;
+use grammartec::transform::{GrammarStats, Transformer};
+
+use clap::{App, Arg};
+use std::process;
+
+fn print_stats(before: &GrammarStats, after: &GrammarStats) {
+    let fmt_min = |min: Option<usize>| match min {
+        Some(min) => format!("{}", min),
+        None => "unproductive".to_string(),
+    };
+    println!("{:<24}{:>14}{:>14}", "", "before", "after");
+    println!("{:<24}{:>14}{:>14}", "rules", before.num_rules, after.num_rules);
+    println!("{:<24}{:>14}{:>14}", "nonterminals", before.num_nts, after.num_nts);
+    println!(
+        "{:<24}{:>14}{:>14}",
+        "min size of START",
+        fmt_min(before.min_size),
+        fmt_min(after.min_size)
+    );
+    println!(
+        "{:<24}{:>14}{:>14}",
+        "largest min size", before.max_min_size, after.max_min_size
+    );
+}
+
+fn main() {
+    //Parse parameters
+    let matches = App::new("transformer")
+        .about("Simplify a grammar and write the result to a new grammar file")
+        .arg(Arg::with_name("grammar_path")
+             .short("g")
+             .value_name("GRAMMAR")
+             .takes_value(true)
+             .required(true)
+             .help("Path to grammar"))
+        .arg(Arg::with_name("output_path")
+             .short("o")
+             .value_name("FILE")
+             .takes_value(true)
+             .required(true)
+             .help("Path of the transformed grammar. The format (.json, .bnf or .g4) is chosen by the file extension"))
+        .arg(Arg::with_name("start")
+             .long("start")
+             .value_name("NONTERMINAL")
+             .takes_value(true)
+             .help("Use NONTERMINAL as start symbol instead of the one chosen by the grammar"))
+        .arg(Arg::with_name("max_recursive")
+             .short("r")
+             .value_name("NUMBER")
+             .takes_value(true)
+             .validator(|v| match v.parse::<usize>() {
+                 Ok(n) if n > 0 => Ok(()),
+                 _ => Err(String::from("NUMBER has to be at least 1")),
+             })
+             .help("Keep at most NUMBER recursive alternatives for each recursive nonterminal"))
+        .arg(Arg::with_name("no_inline")
+             .long("no-inline")
+             .help("Don't inline nonterminals that are used only once"))
+        .arg(Arg::with_name("no_unreachable")
+             .long("no-unreachable")
+             .help("Don't drop rules that are unreachable from START"))
+        .arg(Arg::with_name("no_merge")
+             .long("no-merge")
+             .help("Don't merge equivalent terminal alternatives"))
+        .get_matches();
+
+    let grammar_path = matches.value_of("grammar_path")
+        .expect("grammar_path is a required parameter")
+        .to_string();
+    let output_path = matches.value_of("output_path")
+        .expect("output_path is a required parameter")
+        .to_string();
+    let max_recursive = if matches.is_present("max_recursive") {
+        Some(value_t!(matches, "max_recursive", usize).expect("max_recursive has to be a number"))
+    } else {
+        None
+    };
+
+    //Load grammar
+    let grammar = loader::load_grammar(&grammar_path).and_then(|mut grammar| {
+        if let Some(start) = matches.value_of("start") {
+            grammar.set_start(start)?;
+        }
+        grammar.validate()?;
+        Ok(grammar)
+    }).unwrap_or_else(|e| {
+        eprintln!("cannot load grammar: {}", e);
+        process::exit(1);
+    });
+    let mut ctx = Context::new();
+    grammar.add_to_context(&mut ctx);
+
+    //Transform
+    let mut transformer = Transformer::from_context(&ctx, "START");
+    let before = transformer.stats();
+    if !matches.is_present("no_unreachable") {
+        println!("dropped {} unreachable rules", transformer.drop_unreachable());
+    }
+    if !matches.is_present("no_inline") {
+        println!("inlined {} nonterminals", transformer.inline_single_use());
+    }
+    if !matches.is_present("no_merge") {
+        println!("merged {} terminal rules", transformer.merge_terminal_alternatives());
+    }
+    if let Some(max_recursive) = max_recursive {
+        println!(
+            "removed {} recursive alternatives",
+            transformer.cap_recursive_branching(max_recursive)
+        );
+    }
+    let after = transformer.stats();
+    print_stats(&before, &after);
+
+    //Write transformed grammar
+    let ctx = transformer.to_context(false);
+    export::write_by_extension(&ctx, &output_path).unwrap_or_else(|e| {
+        eprintln!("cannot write {}: {}", output_path, e);
+        process::exit(1);
+    });
+}
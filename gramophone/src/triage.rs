@@ -0,0 +1,463 @@
+//Groups crashing inputs by the bug they trigger. Crashes with a sanitizer report are identified by
+//the bug type and the top frames of the stack, others by the exit reason and the edges they hit
+//that no non-crashing input hit.
+//Every bug gets a folder outputs/crashes/<signature>/ with some of its inputs and their output,
+//outputs/crashes/index.txt lists all of them.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+
+use forksrv::exitreason::ExitReason;
+use grammartec::context::Context;
+use grammartec::tree::{Tree, TreeLike};
+use othertime;
+use ron;
+
+//Frames of the stack that make up the signature
+const TOP_FRAMES: usize = 3;
+//Inputs kept for every signature, the index still counts all of them
+const MAX_INPUTS_PER_CRASH: u64 = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashReport {
+    pub bug_type: String,
+    //"function file:line", or "module+offset" without symbols, innermost frame first
+    pub frames: Vec<String>,
+}
+
+//Frames of the sanitizer runtime are the same for every bug
+fn is_runtime_frame(frame: &str) -> bool {
+    return ["__asan", "__interceptor", "__interception", "__sanitizer", "__ubsan", "__lsan"]
+        .iter()
+        .any(|prefix| frame.starts_with(prefix))
+        || frame.contains("compiler-rt/")
+        || frame.contains("libsanitizer/");
+}
+
+//"/src/foo.c:12:5" -> "foo.c:12"
+fn short_location(location: &str) -> String {
+    let file_name = location.rsplit('/').next().unwrap_or(location);
+    let mut parts = file_name.split(':');
+    let file = parts.next().unwrap_or("");
+    return match parts.next() {
+        Some(line) => format!("{}:{}", file, line),
+        None => file.to_string(),
+    };
+}
+
+//"#1 0x4f5e3a in foo /src/foo.c:12:5" or "#1 0x4f5e3a (/bin/target+0x4f5e3a)"
+fn parse_frame(line: &str) -> Option<String> {
+    let mut tokens = line.trim().split_whitespace();
+    let num = tokens.next()?;
+    if !num.starts_with('#') || num[1..].parse::<u32>().is_err() {
+        return None;
+    }
+    let _address = tokens.next()?;
+    match tokens.next()? {
+        "in" => {
+            let function = tokens.next()?;
+            return match tokens.next() {
+                Some(location) if !location.starts_with('(') => {
+                    Some(format!("{} {}", function, short_location(location)))
+                }
+                _ => Some(function.to_string()),
+            };
+        }
+        module => return Some(short_location(module.trim_matches(|c| c == '(' || c == ')'))),
+    }
+}
+
+//"heap-buffer-overflow on address 0x..." -> "heap-buffer-overflow"
+fn bug_type(description: &str) -> String {
+    let mut words = vec![];
+    for word in description.split_whitespace() {
+        if ["on", "at", "in", "of"].contains(&word) || word.starts_with("0x") || word.starts_with('(') {
+            break;
+        }
+        words.push(word.trim_end_matches(':'));
+        if word.ends_with(':') {
+            break;
+        }
+    }
+    return words.join(" ");
+}
+
+//Parses the first ASAN, MSAN, LSAN or UBSAN report in the output of a run
+pub fn parse_report(stderr: &[u8]) -> Option<CrashReport> {
+    let text = String::from_utf8_lossy(stderr);
+    let mut report: Option<CrashReport> = None;
+    let mut stack_started = false;
+    for line in text.lines() {
+        match report {
+            None => {
+                if let Some(pos) = line.find("Sanitizer: ") {
+                    if line.contains("ERROR: ") || line.contains("WARNING: ") {
+                        report = Some(CrashReport {
+                            bug_type: bug_type(&line[pos + "Sanitizer: ".len()..]),
+                            frames: vec![],
+                        });
+                    }
+                } else if let Some(pos) = line.find(": runtime error: ") {
+                    //UBSAN names the location in the report, a stack only follows with
+                    //print_stacktrace=1
+                    report = Some(CrashReport {
+                        bug_type: bug_type(&line[pos + ": runtime error: ".len()..]),
+                        frames: vec![short_location(line[..pos].trim())],
+                    });
+                }
+            }
+            Some(ref mut report) => match parse_frame(line) {
+                Some(frame) => {
+                    if !stack_started {
+                        //the stack starts at the location of a UBSAN report as well
+                        report.frames.clear();
+                        stack_started = true;
+                    }
+                    if !is_runtime_frame(&frame) && report.frames.len() < TOP_FRAMES {
+                        report.frames.push(frame);
+                    }
+                }
+                //the first stack ends the report, later ones show where memory was allocated
+                None if stack_started => break,
+                None => {}
+            },
+        }
+    }
+    return report;
+}
+
+//64 bit FNV-1a, unlike the hashers of std it stays the same across builds of the fuzzer
+fn fnv1a(data: &[u8], mut hash: u64) -> u64 {
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+//Only characters that are fine in a folder name
+fn sanitize(name: &str) -> String {
+    return name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' })
+        .collect();
+}
+
+fn exit_name(exitreason: ExitReason) -> String {
+    match exitreason {
+        ExitReason::Normal(223) => return "asan".to_string(),
+        ExitReason::Normal(code) => return format!("exit{}", code),
+        ExitReason::Signaled(sig) => return format!("signal{}", sig),
+        ExitReason::Stopped(sig) => return format!("stopped{}", sig),
+        ExitReason::Timeouted => return "timeout".to_string(),
+    }
+}
+
+pub fn report_signature(report: &CrashReport) -> String {
+    let mut hash = fnv1a(report.bug_type.as_bytes(), FNV_OFFSET);
+    for frame in report.frames.iter() {
+        hash = fnv1a(b"\n", hash);
+        hash = fnv1a(frame.as_bytes(), hash);
+    }
+    return format!("{}_{:016x}", sanitize(&report.bug_type), hash);
+}
+
+//The edges a run hit that aren't unstable, in ascending order
+pub fn stable_edges(bitmap: &[u8], unstable_bits: &HashSet<usize>) -> Vec<usize> {
+    return bitmap
+        .iter()
+        .enumerate()
+        .filter(|&(i, elem)| *elem != 0 && !unstable_bits.contains(&i))
+        .map(|(i, _)| i)
+        .collect();
+}
+
+//Whether a run that hit run_edges hit all of the edges, both in ascending order
+pub fn hits_all(run_edges: &[usize], edges: &[usize]) -> bool {
+    return edges.iter().all(|i| run_edges.binary_search(i).is_ok());
+}
+
+//A new bug without a report is identified by the stable edges the crash hit that no non-crashing
+//input hit so far, i.e. the way into the crash. known_bitmap holds the edges of the non-crashing
+//inputs. Returns the signature and the identifying edges.
+pub fn coverage_signature(
+    exitreason: ExitReason,
+    edges: &[usize],
+    known_bitmap: &[u8],
+) -> (String, Vec<usize>) {
+    let name = exit_name(exitreason);
+    let mut hash = fnv1a(name.as_bytes(), FNV_OFFSET);
+    let mut crash_edges = vec![];
+    for &i in edges.iter() {
+        if known_bitmap.get(i).is_none_or(|known| *known == 0) {
+            hash = fnv1a(&(i as u64).to_le_bytes(), hash);
+            crash_edges.push(i);
+        }
+    }
+    return (format!("{}-cov_{:016x}", name, hash), crash_edges);
+}
+
+//Writes the unparsed tree to path and the serialized tree to path.tree
+pub fn write_reproducer(path: &str, tree: &Tree, ctx: &Context) {
+    File::create(path)
+        .and_then(|mut file| file.write_all(&tree.unparse_to_vec(ctx)))
+        .expect("RAND_4109451102");
+    write_tree(path, tree);
+}
+
+fn write_tree(path: &str, tree: &Tree) {
+    let serialized = ron::ser::to_string(tree).expect("Serialization of Tree failed!");
+    File::create(format!("{}.tree", path))
+        .and_then(|mut file| file.write_all(serialized.as_bytes()))
+        .expect("RAND_3458854374");
+}
+
+pub struct CrashEntry {
+    pub exitreason: ExitReason,
+    pub report: Option<CrashReport>,
+    //Without a report: the edges the first input was identified by, see coverage_signature
+    pub edges: Vec<usize>,
+    pub inputs: u64,
+    pub first_found: String,
+}
+
+pub struct CrashIndex {
+    work_dir: String,
+    pub entries: BTreeMap<String, CrashEntry>,
+}
+
+impl CrashIndex {
+    pub fn new(work_dir: String) -> Self {
+        return CrashIndex {
+            work_dir,
+            entries: BTreeMap::new(),
+        };
+    }
+
+    //Number of different bugs that ended with one of the exit reasons
+    pub fn unique<F: Fn(ExitReason) -> bool>(&self, filter: F) -> usize {
+        return self
+            .entries
+            .values()
+            .filter(|entry| filter(entry.exitreason))
+            .count();
+    }
+
+    //The signature of a crash without a report that hit the stable edges. It belongs to a known
+    //bug if it hit all edges the bug was identified by, the one with the most of them if there are
+    //several. The known bitmap grows while fuzzing, so the edges of a new bug are only computed
+    //once. Returns the signature and the identifying edges of the bug.
+    pub fn coverage_signature(
+        &self,
+        exitreason: ExitReason,
+        edges: &[usize],
+        known_bitmap: &[u8],
+    ) -> (String, Vec<usize>) {
+        let known_bug = self
+            .entries
+            .iter()
+            .filter(|&(_, entry)| entry.report.is_none() && entry.exitreason == exitreason)
+            .filter(|&(_, entry)| hits_all(edges, &entry.edges))
+            .max_by_key(|&(_, entry)| entry.edges.len());
+        if let Some((signature, entry)) = known_bug {
+            return (signature.clone(), entry.edges.clone());
+        }
+        return coverage_signature(exitreason, edges, known_bitmap);
+    }
+
+    //Saves a crashing input in the folder of its signature. Returns true if it is a new bug
+    pub fn add(
+        &mut self,
+        signature: String,
+        exitreason: ExitReason,
+        report: Option<CrashReport>,
+        edges: Vec<usize>,
+        code: &[u8],
+        tree: &Tree,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> bool {
+        let dir = format!("{}outputs/crashes/{}", self.work_dir, signature);
+        let is_new = !self.entries.contains_key(&signature);
+        let inputs = {
+            let entry = self.entries.entry(signature).or_insert_with(|| CrashEntry {
+                exitreason,
+                report,
+                edges,
+                inputs: 0,
+                first_found: othertime::strftime("[%Y-%m-%d] %H:%M:%S", &othertime::now())
+                    .expect("RAND_2138231233"),
+            });
+            entry.inputs += 1;
+            entry.inputs
+        };
+        if inputs <= MAX_INPUTS_PER_CRASH {
+            fs::create_dir_all(&dir).expect("RAND_2257182598");
+            let path = format!("{}/id:{:06}", dir, inputs - 1);
+            File::create(&path)
+                .and_then(|mut file| file.write_all(code))
+                .expect("RAND_1572069263");
+            write_tree(&path, tree);
+            File::create(format!("{}.stderr", path))
+                .and_then(|mut file| file.write_all(stderr))
+                .expect("RAND_3462129425");
+            if !stdout.is_empty() {
+                File::create(format!("{}.stdout", path))
+                    .and_then(|mut file| file.write_all(stdout))
+                    .expect("RAND_4078045457");
+            }
+        }
+        self.write_index();
+        return is_new;
+    }
+
+    //One line per bug: signature, number of inputs, when it was found, exit reason, bug type
+    //and top frames of the report
+    fn write_index(&self) {
+        let mut index = String::from("signature\tinputs\tfirst found\texit reason\tbug type\tframes\n");
+        for (signature, entry) in self.entries.iter() {
+            let (bug_type, frames) = match entry.report {
+                Some(ref report) => (report.bug_type.clone(), report.frames.join(" < ")),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            index += &format!(
+                "{}\t{}\t{}\t{:?}\t{}\t{}\n",
+                signature, entry.inputs, entry.first_found, entry.exitreason, bug_type, frames
+            );
+        }
+        let path = format!("{}outputs/crashes/index.txt", self.work_dir);
+        File::create(&path)
+            .and_then(|mut file| file.write_all(index.as_bytes()))
+            .expect("RAND_3325916381");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASAN_REPORT: &str = "=================================================================
+==1234==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000011 at pc 0x4f5e3a bp 0x7ffd sp 0x7ffc
+READ of size 1 at 0x602000000011 thread T0
+    #0 0x4f5e3a in __interceptor_strlen /build/llvm/compiler-rt/lib/asan/asan_interceptors.cc:10
+    #1 0x4f5e3b in mrb_str_new /src/mruby/src/string.c:120:7
+    #2 0x4f5e3c in mrb_load_string /src/mruby/src/parse.y:500:3
+    #3 0x4f5e3d in main /src/mruby/tools/mruby.c:42:10
+    #4 0x7f0000 in __libc_start_main (/lib/x86_64-linux-gnu/libc.so.6+0x21b96)
+
+0x602000000011 is located 0 bytes to the right of 1-byte region
+allocated by thread T0 here:
+    #0 0x4f0000 in malloc /build/llvm/compiler-rt/lib/asan/asan_malloc_linux.cc:5
+    #1 0x4f0001 in mrb_malloc /src/mruby/src/gc.c:12:3
+";
+
+    #[test]
+    fn check_parse_asan_report() {
+        let report = parse_report(ASAN_REPORT.as_bytes()).expect("RAND_1872293456");
+        assert_eq!(report.bug_type, "heap-buffer-overflow");
+        assert_eq!(
+            report.frames,
+            vec![
+                "mrb_str_new string.c:120",
+                "mrb_load_string parse.y:500",
+                "main mruby.c:42",
+            ]
+        );
+        //addresses and pids don't change the signature, other frames do
+        let other_run = ASAN_REPORT.replace("1234", "999").replace("0x4f5e3", "0x5f5e3");
+        let other_bug = ASAN_REPORT.replace("string.c:120", "string.c:121");
+        let signature = report_signature(&report);
+        assert!(signature.starts_with("heap-buffer-overflow_"));
+        assert_eq!(
+            signature,
+            report_signature(&parse_report(other_run.as_bytes()).expect("RAND_1337511833"))
+        );
+        assert_ne!(
+            signature,
+            report_signature(&parse_report(other_bug.as_bytes()).expect("RAND_1900469919"))
+        );
+    }
+
+    #[test]
+    fn check_parse_other_reports() {
+        let ubsan = "foo\n/src/a/b.c:12:5: runtime error: signed integer overflow: \
+                     2147483647 + 1 cannot be represented in type 'int'\n";
+        let report = parse_report(ubsan.as_bytes()).expect("RAND_239207157");
+        assert_eq!(report.bug_type, "signed integer overflow");
+        assert_eq!(report.frames, vec!["b.c:12"]);
+
+        let segv = "==1==ERROR: AddressSanitizer: SEGV on unknown address 0x000000000000\n\
+                    #0 0x4f5e3a (/bin/target+0x4f5e3a)\n";
+        let report = parse_report(segv.as_bytes()).expect("RAND_1426557434");
+        assert_eq!(report.bug_type, "SEGV");
+        assert_eq!(report.frames, vec!["target+0x4f5e3a"]);
+
+        let double_free = "==1==ERROR: AddressSanitizer: attempting double-free on 0x6020 in thread T0:\n";
+        let report = parse_report(double_free.as_bytes()).expect("RAND_3028215561");
+        assert_eq!(report.bug_type, "attempting double-free");
+
+        assert_eq!(parse_report(b"Segmentation fault\n"), None);
+    }
+
+    #[test]
+    fn check_coverage_signature() {
+        let unstable = HashSet::new();
+        let segv = ExitReason::Signaled(11);
+        //non-crashing inputs hit the edges 0, 1 and 4
+        let known = [1, 8, 0, 0, 2, 0];
+        let crash = stable_edges(&[1, 0, 0, 4, 0, 0], &unstable);
+        let (signature, edges) = coverage_signature(segv, &crash, &known);
+        assert!(signature.starts_with("signal11-cov_"));
+        assert_eq!(edges, vec![3]);
+        //another path into the crash at edge 3, hit counts don't matter either
+        let other_path = stable_edges(&[0, 2, 0, 1, 1, 0], &unstable);
+        assert_eq!(signature, coverage_signature(segv, &other_path, &known).0);
+        //another crash
+        let other_crash = stable_edges(&[1, 0, 1, 0, 0, 0], &unstable);
+        assert_ne!(signature, coverage_signature(segv, &other_crash, &known).0);
+        let unstable_edge = stable_edges(&[1, 0, 0, 4, 0, 1], &unstable);
+        assert_ne!(signature, coverage_signature(segv, &unstable_edge, &known).0);
+        let abort = ExitReason::Signaled(6);
+        assert_ne!(signature, coverage_signature(abort, &crash, &known).0);
+        let unstable = [5].iter().cloned().collect();
+        assert_eq!(crash, stable_edges(&[1, 0, 0, 4, 0, 1], &unstable));
+        //the known bitmap may still be empty
+        assert_ne!(signature, coverage_signature(segv, &crash, &[]).0);
+    }
+
+    #[test]
+    fn check_known_coverage_bugs() {
+        let unstable = HashSet::new();
+        let segv = ExitReason::Signaled(11);
+        let mut index = CrashIndex::new(String::new());
+        let crash = stable_edges(&[1, 0, 0, 4, 0, 0], &unstable);
+        let (signature, edges) = index.coverage_signature(segv, &crash, &[1, 8, 0, 0, 2, 0]);
+        assert_eq!(edges, vec![3]);
+        index.entries.insert(
+            signature.clone(),
+            CrashEntry {
+                exitreason: segv,
+                report: None,
+                edges,
+                inputs: 1,
+                first_found: String::new(),
+            },
+        );
+        //a non-crashing input reached edge 3 in the meantime, the crash is still the same bug
+        let known = [1, 8, 0, 1, 2, 0];
+        assert_ne!(signature, coverage_signature(segv, &crash, &known).0);
+        assert_eq!(index.coverage_signature(segv, &crash, &known), (signature.clone(), vec![3]));
+        //other paths into the crash hit edge 3 as well
+        let other_path = stable_edges(&[0, 2, 0, 1, 1, 1], &unstable);
+        assert_eq!(index.coverage_signature(segv, &other_path, &known).0, signature);
+        //crashes that miss it or end differently are other bugs
+        let other_crash = stable_edges(&[1, 0, 1, 0, 0, 1], &unstable);
+        assert_eq!(index.coverage_signature(segv, &other_crash, &known).1, vec![2, 5]);
+        let abort = ExitReason::Signaled(6);
+        assert_ne!(index.coverage_signature(abort, &crash, &known).0, signature);
+    }
+}
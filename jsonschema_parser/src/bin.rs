@@ -0,0 +1,35 @@
+extern crate jsonschema_parser;
+extern crate serde_json;
+
+use jsonschema_parser::SchemaOptions;
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+
+//Usage: jsonschema_parser [--near-valid] SCHEMA.json OUTPUT.json
+fn main() {
+    let mut options = SchemaOptions::new();
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        if arg == "--near-valid" {
+            options.near_valid = true;
+        } else {
+            args.push(arg);
+        }
+    }
+    if args.len() != 2 {
+        eprintln!("Usage: jsonschema_parser [--near-valid] SCHEMA.json OUTPUT.json");
+        process::exit(1);
+    }
+    let grammar = match jsonschema_parser::parse_file(Path::new(&args[0]), &options) {
+        Ok(grammar) => grammar,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let of = File::create(&args[1]).expect("cannot create output file");
+    serde_json::to_writer(&of, &grammar.rules).expect("Can not write to output file");
+}
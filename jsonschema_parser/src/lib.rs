@@ -0,0 +1,1032 @@
+extern crate serde_json;
+extern crate rule_format;
+
+pub mod regex;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+//`maxItems`, `maxLength` and bounded `{n,m}` patterns allow at most this many optional
+//repetitions. Generating fewer elements is still valid.
+const MAX_BOUNDED_REPEAT: usize = 32;
+
+//Strings with these formats are generated from examples
+const FORMAT_EXAMPLES: &[(&str, &[&str])] = &[
+    ("date-time", &["2018-11-13T20:20:39+00:00", "1970-01-01T00:00:00Z"]),
+    ("date", &["2018-11-13", "2000-02-29"]),
+    ("time", &["20:20:39+00:00", "00:00:00Z"]),
+    ("email", &["user@example.com", "first.last+tag@sub.example.org"]),
+    ("hostname", &["example.com", "localhost"]),
+    ("ipv4", &["127.0.0.1", "192.168.0.255"]),
+    ("ipv6", &["::1", "2001:db8::ff00:42:8329"]),
+    ("uri", &["https://example.com/path?query=1#fragment", "urn:isbn:0451450523"]),
+    ("uuid", &["123e4567-e89b-12d3-a456-426614174000"]),
+];
+
+//The characters of the strings that aren't restricted by a pattern, already JSON escaped
+const JSON_CHARS: &[&str] = &["a", "Z", "0", " ", "/", "\\\"", "\\\\", "\\n", "\\u00e9", "é"];
+
+//The grammar of arbitrary JSON values, used wherever a schema allows any value. The names are
+//replaced by the nonterminals the kinds get.
+const JSON_RULES: &[(&str, &str)] = &[
+    ("VALUE", "{OBJECT}"),
+    ("VALUE", "{ARRAY}"),
+    ("VALUE", "{STRING}"),
+    ("VALUE", "{NUMBER}"),
+    ("VALUE", "true"),
+    ("VALUE", "false"),
+    ("VALUE", "null"),
+    ("OBJECT", "\\{\\}"),
+    ("OBJECT", "\\{{MEMBERS}\\}"),
+    ("MEMBERS", "{STRING}:{VALUE}"),
+    ("MEMBERS", "{STRING}:{VALUE},{MEMBERS}"),
+    ("ARRAY", "[]"),
+    ("ARRAY", "[{ELEMENTS}]"),
+    ("ELEMENTS", "{VALUE}"),
+    ("ELEMENTS", "{VALUE},{ELEMENTS}"),
+    ("STRING", "\"\""),
+    ("STRING", "\"{CHARS}\""),
+    ("CHARS", "{CHAR}"),
+    ("CHARS", "{CHAR}{CHARS}"),
+    ("NUMBER", "{INTEGER}"),
+    ("NUMBER", "{INTEGER}.{DIGITS}"),
+    ("NUMBER", "{INTEGER}e{INTEGER}"),
+    ("INTEGER", "0"),
+    ("INTEGER", "{NATURAL}"),
+    ("INTEGER", "-{NATURAL}"),
+    ("NATURAL", "{NONZERO}"),
+    ("NATURAL", "{NONZERO}{DIGITS}"),
+    ("DIGITS", "{DIGIT}"),
+    ("DIGITS", "{DIGIT}{DIGITS}"),
+];
+const JSON_KINDS: &[&str] = &[
+    "VALUE", "OBJECT", "MEMBERS", "ARRAY", "ELEMENTS", "STRING", "CHARS", "CHAR", "NUMBER",
+    "INTEGER", "NATURAL", "DIGITS", "DIGIT", "NONZERO",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    pub file: Option<PathBuf>,
+    //The JSON pointer of the schema the error is about, like `#/properties/id`. None if the error
+    //isn't about a part of the schema, e.g. if the file can't be read.
+    pub pointer: Option<String>,
+    pub message: String,
+}
+
+impl SchemaError {
+    fn new(pointer: Option<&str>, message: String) -> Self {
+        return SchemaError {
+            file: None,
+            pointer: pointer.map(|pointer| pointer.to_string()),
+            message,
+        };
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        if let Some(ref pointer) = self.pointer {
+            write!(f, "{}: ", pointer)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for SchemaError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaOptions {
+    //Also generate documents that are just outside of the schema: required properties are
+    //left out, forbidden properties are added, numbers, lengths and item counts are off by one
+    //and strings and numbers don't have to be in their enum.
+    pub near_valid: bool,
+}
+
+impl SchemaOptions {
+    pub fn new() -> Self {
+        return SchemaOptions { near_valid: false };
+    }
+}
+
+impl Default for SchemaOptions {
+    fn default() -> Self {
+        return SchemaOptions::new();
+    }
+}
+
+//A JSON Schema converted to grammartec rules. The start rule is named after the `title` of the
+//schema, or DOCUMENT if it has none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grammar {
+    pub start: String,
+    pub rules: Vec<(String, String)>,
+}
+
+pub fn parse_file(path: &Path, options: &SchemaOptions) -> Result<Grammar, SchemaError> {
+    let in_file = |mut err: SchemaError| {
+        err.file = Some(path.to_path_buf());
+        return err;
+    };
+    let schema = File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::from_reader(file).map_err(|e| e.to_string()))
+        .map_err(|message| in_file(SchemaError::new(None, message)))?;
+    return convert(&schema, options).map_err(in_file);
+}
+
+pub fn parse_str(src: &str, options: &SchemaOptions) -> Result<Grammar, SchemaError> {
+    let schema = serde_json::from_str(src).map_err(|e| SchemaError::new(None, e.to_string()))?;
+    return convert(&schema, options);
+}
+
+pub fn convert(schema: &Value, options: &SchemaOptions) -> Result<Grammar, SchemaError> {
+    let mut lowering = Lowering {
+        root: schema,
+        options,
+        nonterminals: HashSet::new(),
+        rules: vec![],
+        refs: HashMap::new(),
+        json: HashMap::new(),
+        backslash: None,
+    };
+    let title = schema.get("title").and_then(Value::as_str).unwrap_or("DOCUMENT");
+    let start = lowering.add_nonterm(&nonterm_name(title));
+    lowering.refs.insert("#".to_string(), start.clone());
+    lowering.define(schema, &start, "#")?;
+    return Ok(Grammar {
+        start,
+        rules: lowering.rules,
+    });
+}
+
+//Turns a property or definition name like `user-id` or `$defs` into a nonterminal name like
+//USER-ID or NT__DEFS
+fn nonterm_name(name: &str) -> String {
+    let mut nonterm = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect::<String>();
+    if !nonterm.starts_with(|c: char| c.is_ascii_uppercase()) {
+        nonterm.insert_str(0, "NT_");
+    }
+    return nonterm;
+}
+
+fn json_string(text: &str) -> String {
+    return serde_json::to_string(text).expect("RAND_3015085443");
+}
+
+//The text of a character inside a JSON string
+fn json_char(c: char) -> String {
+    let quoted = json_string(&c.to_string());
+    return quoted[1..quoted.len() - 1].to_string();
+}
+
+//Escapes `~` and `/` in a part of a JSON pointer
+fn pointer_segment(key: &str) -> String {
+    return key.replace("~", "~0").replace("/", "~1");
+}
+
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        return format!("{}", value as i64);
+    }
+    return format!("{}", value);
+}
+
+//Merges the keywords of `source` into `target`, e.g. for `allOf`. Properties and required
+//properties are combined, other keywords of `source` replace those of `target`.
+fn merge_schema(target: &mut Map<String, Value>, source: &Map<String, Value>) {
+    for (key, value) in source.iter() {
+        match (key.as_str(), target.get_mut(key), value) {
+            (
+                "properties",
+                Some(&mut Value::Object(ref mut properties)),
+                &Value::Object(ref more),
+            ) => {
+                for (name, property) in more.iter() {
+                    properties.insert(name.clone(), property.clone());
+                }
+            }
+            ("required", Some(&mut Value::Array(ref mut required)), &Value::Array(ref more)) => {
+                for name in more.iter() {
+                    if !required.contains(name) {
+                        required.push(name.clone());
+                    }
+                }
+            }
+            _ => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+//Whether a schema has keywords that restrict its values, and not just annotations
+fn has_constraints(schema: &Map<String, Value>) -> bool {
+    let annotations = [
+        "$schema", "$id", "id", "$comment", "title", "description", "default", "examples",
+        "definitions", "$defs",
+    ];
+    return schema.keys().any(|key| !annotations.contains(&key.as_str()));
+}
+
+//Guesses the types of a schema without `type` from its keywords
+fn implied_types(schema: &Map<String, Value>) -> Vec<&'static str> {
+    let keywords: &[(&str, &[&str])] = &[
+        (
+            "object",
+            &["properties", "required", "additionalProperties", "minProperties", "maxProperties"],
+        ),
+        ("array", &["items", "prefixItems", "additionalItems", "minItems", "maxItems"]),
+        ("string", &["pattern", "format", "minLength", "maxLength"]),
+        (
+            "number",
+            &["minimum", "maximum", "exclusiveMinimum", "exclusiveMaximum", "multipleOf"],
+        ),
+    ];
+    return keywords
+        .iter()
+        .filter(|&&(_, keys)| keys.iter().any(|key| schema.contains_key(*key)))
+        .map(|&(ty, _)| ty)
+        .collect();
+}
+
+struct Lowering<'a> {
+    root: &'a Value,
+    options: &'a SchemaOptions,
+    nonterminals: HashSet<String>,
+    rules: Vec<(String, String)>,
+    //The nonterminals of the `$ref`s that were resolved so far
+    refs: HashMap<String, String>,
+    //The nonterminals of the JSON grammar, once it's used
+    json: HashMap<&'static str, String>,
+    backslash: Option<String>,
+}
+
+impl<'a> Lowering<'a> {
+    fn error<T>(&self, pointer: &str, message: String) -> Result<T, SchemaError> {
+        return Err(SchemaError::new(Some(pointer), message));
+    }
+
+    //Returns the nonterminal that generates the values matching `schema`
+    fn schema(&mut self, schema: &Value, name: &str, pointer: &str) -> Result<String, SchemaError> {
+        match schema {
+            &Value::Bool(true) => return Ok(self.json("VALUE")),
+            &Value::Object(ref object) if object.contains_key("$ref") => {
+                return self.reference(&object["$ref"], pointer)
+            }
+            _ => {}
+        }
+        let nonterm = self.add_nonterm(name);
+        self.define(schema, &nonterm, pointer)?;
+        return Ok(format!("{{{}}}", nonterm));
+    }
+
+    //Adds the rules of `nonterm`, the nonterminal of `schema`
+    fn define(&mut self, schema: &Value, nonterm: &str, pointer: &str) -> Result<(), SchemaError> {
+        let first = self.rules.len();
+        let definitions = match schema {
+            &Value::Bool(true) => vec![self.json("VALUE")],
+            &Value::Bool(false) => {
+                return self.error(pointer, "the schema false doesn't match any value".to_string())
+            }
+            &Value::Object(ref object) if object.contains_key("$ref") => {
+                vec![self.reference(&object["$ref"], pointer)?]
+            }
+            &Value::Object(ref object) => self.alternatives(object, nonterm, pointer)?,
+            _ => {
+                let message = "a schema has to be an object or a boolean".to_string();
+                return self.error(pointer, message);
+            }
+        };
+        let num_definitions = definitions.len();
+        for def in definitions {
+            self.rules.push((nonterm.to_string(), def));
+        }
+        //Helper rules were added while lowering, keep them behind the rules that use them
+        self.rules[first..].rotate_right(num_definitions);
+        return Ok(());
+    }
+
+    //Only references into the same document are supported, like `#/definitions/user`
+    fn reference(&mut self, reference: &Value, pointer: &str) -> Result<String, SchemaError> {
+        let reference = match reference.as_str() {
+            Some(reference) => reference,
+            None => return self.error(pointer, "$ref has to be a string".to_string()),
+        };
+        if let Some(nonterm) = self.refs.get(reference) {
+            return Ok(format!("{{{}}}", nonterm));
+        }
+        let target = match reference.starts_with('#') {
+            true => self.root.pointer(&reference[1..]),
+            false => {
+                let message = format!("cannot resolve the remote $ref {}", reference);
+                return self.error(pointer, message);
+            }
+        };
+        let target = match target {
+            Some(target) => target,
+            None => return self.error(pointer, format!("cannot resolve $ref {}", reference)),
+        };
+        let name = nonterm_name(reference.rsplit('/').next().expect("RAND_3126234554"));
+        let nonterm = self.add_nonterm(&name);
+        //Added before lowering the target, so recursive schemas refer to the nonterminal
+        self.refs.insert(reference.to_string(), nonterm.clone());
+        self.define(target, &nonterm, reference)?;
+        return Ok(format!("{{{}}}", nonterm));
+    }
+
+    fn alternatives(
+        &mut self,
+        object: &Map<String, Value>,
+        name: &str,
+        pointer: &str,
+    ) -> Result<Vec<String>, SchemaError> {
+        if let Some(all_of) = object.get("allOf") {
+            let mut merged = object.clone();
+            merged.remove("allOf");
+            let subs = self.schema_list(all_of, &format!("{}/allOf", pointer))?;
+            for (i, sub) in subs.iter().enumerate() {
+                match self.resolve(sub, &format!("{}/allOf/{}", pointer, i))? {
+                    Value::Object(ref sub) => merge_schema(&mut merged, sub),
+                    Value::Bool(true) => {}
+                    _ => return self.error(pointer, "allOf contains the schema false".to_string()),
+                }
+            }
+            return self.alternatives(&merged, name, pointer);
+        }
+        for &(keyword, kind) in [("oneOf", "ONEOF"), ("anyOf", "ANYOF")].iter() {
+            let branches = match object.get(keyword) {
+                Some(branches) => branches,
+                None => continue,
+            };
+            let mut base = object.clone();
+            base.remove(keyword);
+            let branch_name = format!("{}_{}", name, kind);
+            let mut definitions = vec![];
+            let branches = self.schema_list(branches, &format!("{}/{}", pointer, keyword))?;
+            for (i, branch) in branches.iter().enumerate() {
+                let branch_pointer = format!("{}/{}/{}", pointer, keyword, i);
+                //Keywords next to oneOf apply to every branch
+                let branch = match self.resolve(branch, &branch_pointer)? {
+                    Value::Object(ref sub) if has_constraints(&base) => {
+                        let mut merged = base.clone();
+                        merge_schema(&mut merged, sub);
+                        Value::Object(merged)
+                    }
+                    Value::Bool(false) => continue,
+                    _ => branch.clone(),
+                };
+                definitions.push(self.schema(&branch, &branch_name, &branch_pointer)?);
+            }
+            if definitions.is_empty() {
+                return self.error(pointer, format!("every branch of {} is false", keyword));
+            }
+            return Ok(definitions);
+        }
+        let values = match (object.get("enum"), object.get("const")) {
+            (Some(&Value::Array(ref values)), _) => Some(values.clone()),
+            (Some(_), _) => return self.error(pointer, "enum has to be an array".to_string()),
+            (None, Some(value)) => Some(vec![value.clone()]),
+            (None, None) => None,
+        };
+        if let Some(values) = values {
+            let mut definitions = values
+                .iter()
+                .map(|value| self.terminal(&value.to_string()))
+                .collect::<Vec<_>>();
+            if self.options.near_valid {
+                if values.iter().any(Value::is_string) {
+                    definitions.push(self.json("STRING"));
+                }
+                if values.iter().any(Value::is_number) {
+                    definitions.push(self.json("NUMBER"));
+                }
+            }
+            return Ok(definitions);
+        }
+        let types = match object.get("type") {
+            Some(&Value::String(ref ty)) => vec![ty.as_str()],
+            Some(&Value::Array(ref types)) => types.iter().filter_map(Value::as_str).collect(),
+            Some(_) => {
+                return self.error(pointer, "type has to be a string or an array".to_string())
+            }
+            None => implied_types(object),
+        };
+        if types.is_empty() {
+            return Ok(vec![self.json("VALUE")]);
+        }
+        let mut definitions = vec![];
+        for ty in types {
+            let typed = match ty {
+                "null" => vec!["null".to_string()],
+                "boolean" => vec!["true".to_string(), "false".to_string()],
+                "integer" => self.number(object, true, pointer)?,
+                "number" => self.number(object, false, pointer)?,
+                "string" => self.string(object, name, pointer)?,
+                "array" => self.array(object, name, pointer)?,
+                "object" => self.object(object, name, pointer)?,
+                _ => return self.error(pointer, format!("unknown type {}", ty)),
+            };
+            definitions.extend(typed);
+        }
+        return Ok(definitions);
+    }
+
+    fn schema_list(&self, list: &Value, pointer: &str) -> Result<Vec<Value>, SchemaError> {
+        return match list {
+            &Value::Array(ref list) => Ok(list.clone()),
+            _ => self.error(pointer, "has to be an array of schemas".to_string()),
+        };
+    }
+
+    //Follows `$ref`s, for the keywords that combine the contents of schemas
+    fn resolve(&self, schema: &Value, pointer: &str) -> Result<Value, SchemaError> {
+        let mut schema = schema.clone();
+        //Guards against references that only refer to each other
+        for _ in 0..64 {
+            let reference = match schema.get("$ref") {
+                Some(reference) => reference.as_str().unwrap_or("").to_string(),
+                None => return Ok(schema),
+            };
+            let target = match reference.starts_with('#') {
+                true => self.root.pointer(&reference[1..]),
+                false => None,
+            };
+            schema = match target {
+                Some(target) => target.clone(),
+                None => return self.error(pointer, format!("cannot resolve $ref {}", reference)),
+            };
+        }
+        return self.error(pointer, "too many nested $refs".to_string());
+    }
+
+    fn number(
+        &mut self,
+        object: &Map<String, Value>,
+        integer: bool,
+        pointer: &str,
+    ) -> Result<Vec<String>, SchemaError> {
+        let get = |key| object.get(key).and_then(Value::as_f64);
+        //(bound, exclusive). Draft 4 uses booleans for the exclusive bounds, later drafts numbers.
+        let bound = |inclusive, exclusive| match object.get(exclusive) {
+            Some(&Value::Bool(true)) => get(inclusive).map(|value| (value, true)),
+            Some(value) if value.is_number() => value.as_f64().map(|value| (value, true)),
+            _ => get(inclusive).map(|value| (value, false)),
+        };
+        let lower = bound("minimum", "exclusiveMinimum");
+        let upper = bound("maximum", "exclusiveMaximum");
+        let multiple = get("multipleOf").filter(|&multiple| multiple > 0.0);
+        let lower_ok = |v: f64| lower.is_none_or(|(b, excl)| if excl { v > b } else { v >= b });
+        let upper_ok = |v: f64| upper.is_none_or(|(b, excl)| if excl { v < b } else { v <= b });
+        let valid = |v: f64| {
+            return lower_ok(v)
+                && upper_ok(v)
+                && (!integer || v.fract() == 0.0)
+                && multiple.is_none_or(|m| (v / m).fract() == 0.0);
+        };
+        if lower.is_none() && upper.is_none() && multiple.is_none() {
+            return Ok(vec![self.json(if integer { "INTEGER" } else { "NUMBER" })]);
+        }
+        let mut candidates = match (lower, upper) {
+            (Some((lo, _)), Some((hi, _))) => {
+                vec![lo, lo + 1.0, (lo + hi) / 2.0, hi - 1.0, hi, 0.0]
+            }
+            (Some((lo, _)), None) => vec![lo, lo + 1.0, lo + 10.0, lo + 1000.0],
+            (None, Some((hi, _))) => vec![hi, hi - 1.0, hi - 10.0, hi - 1000.0],
+            (None, None) => vec![0.0, 1.0, -1.0, 10.0, 1000.0],
+        };
+        if !integer {
+            let fractions = candidates.iter().map(|v| v + 0.5).collect::<Vec<_>>();
+            candidates.extend(fractions);
+        }
+        if let Some(m) = multiple {
+            candidates = candidates.iter().map(|v| (v / m).ceil() * m).collect();
+        } else if integer {
+            candidates = candidates.iter().map(|v| v.ceil()).collect();
+        }
+        let mut samples = vec![];
+        for value in candidates.into_iter().filter(|&v| valid(v)) {
+            let sample = format_number(value);
+            if !samples.contains(&sample) {
+                samples.push(sample);
+            }
+        }
+        if samples.is_empty() {
+            return self.error(pointer, "no number satisfies the bounds".to_string());
+        }
+        if self.options.near_valid {
+            let step = if integer { 1.0 } else { 0.5 };
+            let mut invalid = vec![];
+            if let Some((lo, excl)) = lower {
+                invalid.push(if excl { lo } else { lo - step });
+            }
+            if let Some((hi, excl)) = upper {
+                invalid.push(if excl { hi } else { hi + step });
+            }
+            if let Some(m) = multiple {
+                invalid.extend(lower.map(|(lo, _)| (lo / m).ceil() * m + m / 2.0));
+                invalid.push(m / 2.0);
+            }
+            if integer {
+                invalid.push(samples[0].parse::<f64>().expect("RAND_1908339952") + 0.5);
+            }
+            for value in invalid.into_iter().filter(|&v| !valid(v)) {
+                samples.push(format_number(value));
+            }
+        }
+        return Ok(samples);
+    }
+
+    fn string(
+        &mut self,
+        object: &Map<String, Value>,
+        name: &str,
+        pointer: &str,
+    ) -> Result<Vec<String>, SchemaError> {
+        let mut definitions = vec![];
+        let format = object.get("format").and_then(Value::as_str);
+        let examples = FORMAT_EXAMPLES.iter().find(|&&(known, _)| Some(known) == format);
+        if let Some(pattern) = object.get("pattern") {
+            let pattern_pointer = format!("{}/pattern", pointer);
+            let regex = match pattern.as_str().map(regex::parse) {
+                Some(Ok(regex)) => regex,
+                Some(Err(err)) => {
+                    return self.error(&pattern_pointer, format!("invalid pattern: {}", err))
+                }
+                None => return self.error(&pattern_pointer, "has to be a string".to_string()),
+            };
+            let chars = self.regex(&regex, name);
+            definitions.push(format!("\"{}\"", chars));
+        } else if let Some(&(_, examples)) = examples {
+            for example in examples.iter() {
+                definitions.push(self.terminal(&json_string(example)));
+            }
+        } else {
+            let min = object.get("minLength").and_then(Value::as_u64).unwrap_or(0) as usize;
+            let max = object.get("maxLength").and_then(Value::as_u64).map(|max| max as usize);
+            if min == 0 && max.is_none() {
+                return Ok(vec![self.json("STRING")]);
+            }
+            let chars = self.json("CHAR");
+            let valid = self.repeat(&chars, "", min, max, name);
+            definitions.push(format!("\"{}\"", valid));
+            if self.options.near_valid {
+                if min > 0 {
+                    let short = self.repeat(&chars, "", min - 1, Some(min - 1), name);
+                    definitions.push(format!("\"{}\"", short));
+                }
+                if let Some(max) = max {
+                    let long = self.repeat(&chars, "", max + 1, Some(max + 1), name);
+                    definitions.push(format!("\"{}\"", long));
+                }
+            }
+            return Ok(definitions);
+        }
+        if self.options.near_valid {
+            definitions.push(self.json("STRING"));
+        }
+        return Ok(definitions);
+    }
+
+    fn array(
+        &mut self,
+        object: &Map<String, Value>,
+        name: &str,
+        pointer: &str,
+    ) -> Result<Vec<String>, SchemaError> {
+        //Draft 4 tuples are arrays in `items` followed by `additionalItems`, draft 2020-12
+        //tuples are `prefixItems` followed by `items`
+        let (prefix, rest, rest_keyword) = match (object.get("items"), object.get("prefixItems")) {
+            (Some(&Value::Array(ref items)), _) => {
+                (items.clone(), object.get("additionalItems"), "additionalItems")
+            }
+            (items, Some(&Value::Array(ref prefix))) => (prefix.clone(), items, "items"),
+            (items, _) => (vec![], items, "items"),
+        };
+        let item_name = format!("{}_ITEM", name);
+        let mut elements = vec![];
+        for (i, item) in prefix.iter().enumerate() {
+            let item_pointer = format!("{}/{}/{}", pointer, rest_keyword, i);
+            elements.push(self.schema(item, &item_name, &item_pointer)?);
+        }
+        let rest = match rest {
+            Some(&Value::Bool(false)) => None,
+            None if !prefix.is_empty() => None,
+            None => Some(self.json("VALUE")),
+            Some(rest) => {
+                let rest_pointer = format!("{}/{}", pointer, rest_keyword);
+                Some(self.schema(rest, &item_name, &rest_pointer)?)
+            }
+        };
+        let count = |key| object.get(key).and_then(Value::as_u64).map(|n| n as usize);
+        let min = count("minItems").unwrap_or(0).saturating_sub(prefix.len());
+        let max = count("maxItems").map(|max| max.saturating_sub(prefix.len()));
+        let rest = match rest {
+            Some(rest) => rest,
+            None => return Ok(vec![format!("[{}]", elements.join(","))]),
+        };
+        let mut bounds = vec![(min, max)];
+        if self.options.near_valid {
+            if min > 0 {
+                bounds.push((min - 1, Some(min - 1)));
+            }
+            if let Some(max) = max {
+                bounds.push((max + 1, Some(max + 1)));
+            }
+        }
+        let mut definitions = vec![];
+        for (min, max) in bounds {
+            let items = match elements.is_empty() {
+                true => self.repeat(&rest, ",", min, max, name),
+                //The first of the remaining items also follows a comma
+                false => self.separated(&rest, ",", min, max, name),
+            };
+            definitions.push(format!("[{}{}]", elements.join(","), items));
+        }
+        return Ok(definitions);
+    }
+
+    fn object(
+        &mut self,
+        object: &Map<String, Value>,
+        name: &str,
+        pointer: &str,
+    ) -> Result<Vec<String>, SchemaError> {
+        let empty = Map::new();
+        let properties = match object.get("properties") {
+            Some(&Value::Object(ref properties)) => properties,
+            Some(_) => return self.error(pointer, "properties has to be an object".to_string()),
+            None => &empty,
+        };
+        let required = object
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let additional = object.get("additionalProperties");
+        let near_valid = self.options.near_valid;
+
+        if properties.is_empty() && required.is_empty() {
+            let value = match additional {
+                Some(&Value::Bool(false)) => return Ok(vec!["\\{\\}".to_string()]),
+                None | Some(&Value::Bool(true)) => self.json("VALUE"),
+                Some(schema) => {
+                    let additional_pointer = format!("{}/additionalProperties", pointer);
+                    self.schema(schema, &format!("{}_VALUE", name), &additional_pointer)?
+                }
+            };
+            let member = format!("{}:{}", self.json("STRING"), value);
+            let count = |key| object.get(key).and_then(Value::as_u64).map(|n| n as usize);
+            let min = count("minProperties").unwrap_or(0);
+            let members = self.repeat(&member, ",", min, count("maxProperties"), name);
+            return Ok(vec![format!("\\{{{}\\}}", members)]);
+        }
+
+        //(key, value, required)
+        let mut members = vec![];
+        for (key, property) in properties.iter() {
+            let property_name = format!("{}_{}", name, nonterm_name(key));
+            let property_pointer = format!("{}/properties/{}", pointer, pointer_segment(key));
+            let value = self.schema(property, &property_name, &property_pointer)?;
+            let is_required = required.contains(&key.as_str()) && !near_valid;
+            members.push((self.terminal(&json_string(key)), value, is_required));
+        }
+        for key in required.iter().filter(|key| !properties.contains_key(**key)) {
+            let value = self.json("VALUE");
+            members.push((self.terminal(&json_string(key)), value, !near_valid));
+        }
+        if near_valid && additional == Some(&Value::Bool(false)) {
+            let value = self.json("VALUE");
+            members.push((self.terminal(&json_string("unexpected")), value, false));
+        }
+        let mut memo = HashMap::new();
+        let members = self.members(&members, 0, true, name, &mut memo);
+        return Ok(vec![format!("\\{{{}\\}}", members)]);
+    }
+
+    //Returns the format of the members from `i` on. Every optional member gets a nonterminal
+    //that either produces it or leaves it out. Commas only go between members, so the rest of
+    //the members depends on whether one was produced before.
+    fn members(
+        &mut self,
+        members: &[(String, String, bool)],
+        i: usize,
+        first: bool,
+        name: &str,
+        memo: &mut HashMap<(usize, bool), String>,
+    ) -> String {
+        if i == members.len() {
+            return String::new();
+        }
+        if let Some(format) = memo.get(&(i, first)) {
+            return format.clone();
+        }
+        let (ref key, ref value, required) = members[i];
+        let rest = self.members(members, i + 1, false, name, memo);
+        let with = format!("{}{}:{}{}", if first { "" } else { "," }, key, value, rest);
+        let format = match required {
+            true => with,
+            false => {
+                let without = self.members(members, i + 1, first, name, memo);
+                let nonterm = self.add_nonterm(&format!("{}_MEMBERS", name));
+                self.rules.push((nonterm.clone(), with));
+                self.rules.push((nonterm.clone(), without));
+                format!("{{{}}}", nonterm)
+            }
+        };
+        memo.insert((i, first), format.clone());
+        return format;
+    }
+
+    //Repeats `item` between `min` and `max` times with `separator` between the repetitions
+    fn repeat(
+        &mut self,
+        item: &str,
+        separator: &str,
+        min: usize,
+        max: Option<usize>,
+        name: &str,
+    ) -> String {
+        if min > 0 {
+            let head = vec![item; min].join(separator);
+            let extra = max.map(|max| max.saturating_sub(min));
+            let tail = self.separated(item, separator, 0, extra, name);
+            return format!("{}{}", head, tail);
+        }
+        if max == Some(0) {
+            return String::new();
+        }
+        let nonterm = self.add_nonterm(&format!("{}_REP", name));
+        let tail = self.separated(item, separator, 0, max.map(|max| max - 1), name);
+        self.rules.push((nonterm.clone(), format!("{}{}", item, tail)));
+        self.rules.push((nonterm.clone(), String::new()));
+        return format!("{{{}}}", nonterm);
+    }
+
+    //Like repeat, but every repetition is preceded by `separator`
+    fn separated(
+        &mut self,
+        item: &str,
+        separator: &str,
+        min: usize,
+        max: Option<usize>,
+        name: &str,
+    ) -> String {
+        let head = format!("{}{}", separator, item).repeat(min);
+        let extra = max.map(|max| max.saturating_sub(min).min(MAX_BOUNDED_REPEAT));
+        if extra == Some(0) {
+            return head;
+        }
+        let nonterm = self.add_nonterm(&format!("{}_MORE", name));
+        match extra {
+            None => {
+                let more = format!("{}{}{{{}}}", separator, item, nonterm);
+                self.rules.push((nonterm.clone(), more));
+            }
+            Some(extra) => {
+                let more = self.separated(item, separator, 0, Some(extra - 1), name);
+                self.rules.push((nonterm.clone(), format!("{}{}{}", separator, item, more)));
+            }
+        }
+        self.rules.push((nonterm.clone(), String::new()));
+        return format!("{}{{{}}}", head, nonterm);
+    }
+
+    //Returns the format of the JSON string contents matching `regex`
+    fn regex(&mut self, regex: &Regex, name: &str) -> String {
+        return match regex {
+            &Regex::Class(ref chars) if chars.len() == 1 => self.terminal(&json_char(chars[0])),
+            &Regex::Class(ref chars) => {
+                let nonterm = self.add_nonterm(&format!("{}_CHAR", name));
+                for c in chars.iter() {
+                    let def = self.terminal(&json_char(*c));
+                    self.rules.push((nonterm.clone(), def));
+                }
+                format!("{{{}}}", nonterm)
+            }
+            &Regex::Concat(ref items) => items.iter().map(|item| self.regex(item, name)).collect(),
+            &Regex::Alt(ref branches) => {
+                let nonterm = self.add_nonterm(&format!("{}_ALT", name));
+                for branch in branches.iter() {
+                    let def = self.regex(branch, name);
+                    self.rules.push((nonterm.clone(), def));
+                }
+                format!("{{{}}}", nonterm)
+            }
+            &Regex::Repeat(ref item, min, max) => {
+                let item = self.regex(item, name);
+                self.repeat(&item, "", min, max, name)
+            }
+        };
+    }
+
+    //Returns a reference to a nonterminal of the JSON grammar, which is added on first use
+    fn json(&mut self, kind: &str) -> String {
+        if self.json.is_empty() {
+            for kind in JSON_KINDS.iter() {
+                let nonterm = self.add_nonterm(&format!("JSON_{}", kind));
+                self.json.insert(kind, nonterm);
+            }
+            let reference = |json: &HashMap<&str, String>, format: &str| {
+                let mut format = format.to_string();
+                for (kind, nonterm) in json.iter() {
+                    format = format.replace(&format!("{{{}}}", kind), &format!("{{{}}}", nonterm));
+                }
+                return format;
+            };
+            for &(kind, format) in JSON_RULES.iter() {
+                let def = reference(&self.json, format);
+                self.rules.push((self.json[kind].clone(), def));
+            }
+            for c in JSON_CHARS.iter() {
+                let def = self.terminal(c);
+                self.rules.push((self.json["CHAR"].clone(), def));
+            }
+            for digit in 0..10 {
+                self.rules.push((self.json["DIGIT"].clone(), digit.to_string()));
+                if digit > 0 {
+                    self.rules.push((self.json["NONZERO"].clone(), digit.to_string()));
+                }
+            }
+        }
+        return format!("{{{}}}", self.json[kind]);
+    }
+
+    fn terminal(&mut self, literal: &str) -> String {
+        return rule_format::literal_to_format(literal, || {
+            if self.backslash.is_none() {
+                let backslash = self.add_nonterm("BACKSLASH");
+                self.rules.push((backslash.clone(), "\\".to_string()));
+                self.backslash = Some(backslash);
+            }
+            self.backslash.clone().expect("RAND_992586033")
+        });
+    }
+
+    //Nonterminals with the same name get numbered: USER_NAME, USER_NAME2, ...
+    fn add_nonterm(&mut self, base: &str) -> String {
+        let mut new_nonterm = base.to_string();
+        let mut i = 1;
+        while self.nonterminals.contains(&new_nonterm) {
+            i += 1;
+            new_nonterm = format!("{}{}", base, i);
+        }
+        self.nonterminals.insert(new_nonterm.clone());
+        return new_nonterm;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_for<'a>(grammar: &'a Grammar, nt: &str) -> Vec<&'a str> {
+        return grammar
+            .rules
+            .iter()
+            .filter(|&&(ref name, _)| name == nt)
+            .map(|&(_, ref format)| format.as_str())
+            .collect();
+    }
+
+    fn convert_str(src: &str) -> Grammar {
+        return parse_str(src, &SchemaOptions::new()).expect("RAND_3343062798");
+    }
+
+    #[test]
+    fn check_objects() {
+        let grammar = convert_str(
+            r#"{"title": "user", "type": "object", "required": ["id"],
+                "additionalProperties": false,
+                "properties": {
+                    "id": {"type": "integer", "minimum": 1, "maximum": 3},
+                    "name": {"enum": ["a{", "b\\"]}
+                }}"#,
+        );
+        assert_eq!(grammar.start, "USER");
+        assert_eq!(grammar.rules[0].0, "USER");
+        assert_eq!(rules_for(&grammar, "USER"), vec!["\\{\"id\":{USER_ID}{USER_MEMBERS}\\}"]);
+        assert_eq!(rules_for(&grammar, "USER_MEMBERS"), vec![",\"name\":{USER_NAME}", ""]);
+        assert_eq!(rules_for(&grammar, "USER_ID"), vec!["1", "2", "3"]);
+        assert_eq!(rules_for(&grammar, "USER_NAME"), vec!["\"a\\{\"", "\"b\\\\\""]);
+    }
+
+    #[test]
+    fn check_arrays_and_strings() {
+        let grammar = convert_str(
+            r#"{"type": "array", "minItems": 1, "maxItems": 3,
+                "items": {"type": "string", "minLength": 1, "maxLength": 2}}"#,
+        );
+        assert_eq!(rules_for(&grammar, "DOCUMENT"), vec!["[{DOCUMENT_ITEM}{DOCUMENT_MORE}]"]);
+        assert_eq!(
+            rules_for(&grammar, "DOCUMENT_MORE"),
+            vec![",{DOCUMENT_ITEM}{DOCUMENT_MORE2}", ""]
+        );
+        assert_eq!(rules_for(&grammar, "DOCUMENT_MORE2"), vec![",{DOCUMENT_ITEM}", ""]);
+        assert_eq!(
+            rules_for(&grammar, "DOCUMENT_ITEM"),
+            vec!["\"{JSON_CHAR}{DOCUMENT_ITEM_MORE}\""]
+        );
+        assert_eq!(rules_for(&grammar, "DOCUMENT_ITEM_MORE"), vec!["{JSON_CHAR}", ""]);
+        assert!(rules_for(&grammar, "JSON_CHAR").contains(&"{BACKSLASH}{BACKSLASH}"));
+
+        let grammar = convert_str(r#"{"type": "string", "pattern": "^a[bc]?$"}"#);
+        assert_eq!(rules_for(&grammar, "DOCUMENT"), vec!["\"a{DOCUMENT_REP}\""]);
+        assert_eq!(rules_for(&grammar, "DOCUMENT_REP"), vec!["{DOCUMENT_CHAR}", ""]);
+        assert_eq!(rules_for(&grammar, "DOCUMENT_CHAR"), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn check_refs_and_combinators() {
+        let grammar = convert_str(
+            r##"{"$ref": "#/definitions/node", "definitions": {"node": {
+                    "type": "object",
+                    "properties": {
+                        "next": {"$ref": "#/definitions/node"},
+                        "kind": {"oneOf": [{"type": "null"}, {"type": "boolean"}]}
+                    }}}}"##,
+        );
+        assert_eq!(rules_for(&grammar, "DOCUMENT"), vec!["{NODE}"]);
+        assert_eq!(rules_for(&grammar, "NODE"), vec!["\\{{NODE_MEMBERS3}\\}"]);
+        assert_eq!(
+            rules_for(&grammar, "NODE_MEMBERS3"),
+            vec!["\"kind\":{NODE_KIND}{NODE_MEMBERS}", "{NODE_MEMBERS2}"]
+        );
+        assert_eq!(rules_for(&grammar, "NODE_MEMBERS"), vec![",\"next\":{NODE}", ""]);
+        assert_eq!(rules_for(&grammar, "NODE_MEMBERS2"), vec!["\"next\":{NODE}", ""]);
+        assert_eq!(
+            rules_for(&grammar, "NODE_KIND"),
+            vec!["{NODE_KIND_ONEOF}", "{NODE_KIND_ONEOF2}"]
+        );
+        assert_eq!(rules_for(&grammar, "NODE_KIND_ONEOF2"), vec!["true", "false"]);
+
+        let grammar = convert_str(
+            r##"{"allOf": [
+                    {"properties": {"a": {"const": 1}}, "required": ["a"]},
+                    {"$ref": "#/definitions/b"}
+                ],
+                "definitions": {"b": {"properties": {"b": {"type": "null"}}, "required": ["b"]}}
+            }"##,
+        );
+        assert_eq!(
+            rules_for(&grammar, "DOCUMENT"),
+            vec!["\\{\"a\":{DOCUMENT_A},\"b\":{DOCUMENT_B}\\}"]
+        );
+    }
+
+    #[test]
+    fn check_near_valid() {
+        let mut options = SchemaOptions::new();
+        options.near_valid = true;
+        let grammar = parse_str(
+            r#"{"type": "object", "required": ["n"], "additionalProperties": false,
+                "properties": {"n": {"type": "integer", "minimum": 0, "maximum": 10}}}"#,
+            &options,
+        ).expect("RAND_983412195");
+        assert_eq!(
+            rules_for(&grammar, "DOCUMENT_N"),
+            vec!["0", "1", "5", "9", "10", "-1", "11", "0.5"]
+        );
+        assert_eq!(
+            rules_for(&grammar, "DOCUMENT_MEMBERS3"),
+            vec!["\"n\":{DOCUMENT_N}{DOCUMENT_MEMBERS}", "{DOCUMENT_MEMBERS2}"]
+        );
+        assert_eq!(
+            rules_for(&grammar, "DOCUMENT_MEMBERS"),
+            vec![",\"unexpected\":{JSON_VALUE}", ""]
+        );
+    }
+
+    #[test]
+    fn check_errors() {
+        let options = SchemaOptions::new();
+        let err = parse_str(
+            r##"{"properties": {"a/b": {"$ref": "#/definitions/missing"}}}"##,
+            &options,
+        ).expect_err("RAND_2399885254");
+        assert_eq!(err.to_string(), "#/properties/a~1b: cannot resolve $ref #/definitions/missing");
+        let err = parse_str(r#"{"pattern": "(a"}"#, &options).expect_err("RAND_2811019701");
+        assert_eq!(err.to_string(), "#/pattern: invalid pattern: missing ) at offset 2");
+        let err = parse_str(
+            r#"{"type": "integer", "minimum": 1, "maximum": 1.5, "multipleOf": 2}"#,
+            &options,
+        ).expect_err("RAND_3797051630");
+        assert_eq!(err.message, "no number satisfies the bounds");
+        let err = parse_str("false", &options).expect_err("RAND_2230532783");
+        assert_eq!(err.pointer, Some("#".to_string()));
+    }
+}
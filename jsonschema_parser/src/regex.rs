@@ -0,0 +1,346 @@
+use std::char;
+
+//Character ranges with more code points, like `[\u0100-\u7fff]`, are represented by this many
+//evenly spread code points
+const MAX_CLASS_CHARS: usize = 128;
+
+//The subset of ECMA 262 regular expressions that can be turned into rules. `pattern` is only
+//used to generate strings, so anchors are ignored and lazy quantifiers behave like greedy ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Regex {
+    //Matches one of the characters
+    Class(Vec<char>),
+    Concat(Vec<Regex>),
+    Alt(Vec<Regex>),
+    Repeat(Box<Regex>, usize, Option<usize>),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    idx: usize,
+}
+
+pub fn parse(pattern: &str) -> Result<Regex, String> {
+    let mut parser = Parser {
+        chars: pattern.chars().collect(),
+        idx: 0,
+    };
+    let regex = parser.parse_alt()?;
+    if parser.idx < parser.chars.len() {
+        return parser.error("unbalanced )");
+    }
+    return Ok(regex);
+}
+
+//`.` and the negated classes only produce printable ASCII characters
+fn printable() -> Vec<char> {
+    return (0x20u8..0x7f).map(|c| c as char).collect();
+}
+
+fn class_escape(c: char) -> Option<Vec<char>> {
+    let digits = (b'0'..b'9' + 1).map(|c| c as char).collect::<Vec<_>>();
+    let mut word = (b'a'..b'z' + 1)
+        .chain(b'A'..b'Z' + 1)
+        .map(|c| c as char)
+        .collect::<Vec<_>>();
+    word.extend(digits.iter().cloned());
+    word.push('_');
+    let space = vec![' ', '\t', '\n', '\r'];
+    return match c {
+        'd' => Some(digits),
+        'w' => Some(word),
+        's' => Some(space),
+        'D' => Some(complement(&digits)),
+        'W' => Some(complement(&word)),
+        'S' => Some(complement(&space)),
+        _ => None,
+    };
+}
+
+fn complement(chars: &[char]) -> Vec<char> {
+    return printable().into_iter().filter(|c| !chars.contains(c)).collect();
+}
+
+fn range_chars(first: char, last: char) -> Vec<char> {
+    let (first, last) = (first as u32, last as u32);
+    let len = (last - first + 1) as usize;
+    if len <= MAX_CLASS_CHARS {
+        return (first..=last).filter_map(char::from_u32).collect();
+    }
+    return (0..MAX_CLASS_CHARS)
+        .map(|i| first + (i * (len - 1) / (MAX_CLASS_CHARS - 1)) as u32)
+        .filter_map(char::from_u32)
+        .collect();
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.idx).cloned();
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.idx += 1;
+        return c;
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        if self.chars[self.idx.min(self.chars.len())..].starts_with(&text) {
+            self.idx += text.len();
+            return true;
+        }
+        return false;
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        return Err(format!("{} at offset {}", message, self.idx));
+    }
+
+    fn parse_alt(&mut self) -> Result<Regex, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat("|") {
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            return Ok(branches.pop().expect("RAND_2211015209"));
+        }
+        return Ok(Regex::Alt(branches));
+    }
+
+    fn parse_concat(&mut self) -> Result<Regex, String> {
+        let mut items = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = match self.parse_atom()? {
+                Some(atom) => atom,
+                None => continue,
+            };
+            items.push(self.parse_quantifiers(atom)?);
+        }
+        if items.len() == 1 {
+            return Ok(items.pop().expect("RAND_343698455"));
+        }
+        return Ok(Regex::Concat(items));
+    }
+
+    //Returns None for anchors, which don't match any characters
+    fn parse_atom(&mut self) -> Result<Option<Regex>, String> {
+        let atom = match self.bump().expect("RAND_621544970") {
+            '^' | '$' => return Ok(None),
+            '(' => {
+                if self.eat("?=") || self.eat("?!") || self.eat("?<=") || self.eat("?<!") {
+                    return self.error("lookarounds are not supported");
+                }
+                if !self.eat("?:") && self.eat("?<") {
+                    while self.bump().is_some_and(|c| c != '>') {}
+                }
+                let group = self.parse_alt()?;
+                if !self.eat(")") {
+                    return self.error("missing )");
+                }
+                group
+            }
+            '[' => self.parse_class()?,
+            '.' => Regex::Class(printable()),
+            '\\' => match self.peek() {
+                Some(c) if class_escape(c).is_some() => {
+                    self.bump();
+                    Regex::Class(class_escape(c).expect("RAND_538165666"))
+                }
+                Some('b') | Some('B') => return self.error("word boundaries are not supported"),
+                Some('1'..='9') => return self.error("backreferences are not supported"),
+                _ => Regex::Class(vec![self.parse_escape()?]),
+            },
+            '*' | '+' | '?' => return self.error("nothing to repeat"),
+            c => Regex::Class(vec![c]),
+        };
+        return Ok(Some(atom));
+    }
+
+    //Parses the character after a backslash
+    fn parse_escape(&mut self) -> Result<char, String> {
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return self.error("pattern ends with a backslash"),
+        };
+        let escaped = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '0' => '\0',
+            'x' | 'u' => {
+                let len = if c == 'x' { 2 } else { 4 };
+                let digits = self.chars[self.idx..].iter().take(len).collect::<String>();
+                let code = u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32);
+                match code {
+                    Some(code) if digits.len() == len => {
+                        self.idx += len;
+                        code
+                    }
+                    _ => return self.error("invalid escape sequence"),
+                }
+            }
+            c => c,
+        };
+        return Ok(escaped);
+    }
+
+    fn parse_class(&mut self) -> Result<Regex, String> {
+        let negated = self.eat("^");
+        let mut chars = vec![];
+        loop {
+            let first = match self.bump() {
+                None => return self.error("missing ]"),
+                Some(']') => break,
+                Some('\\') => match self.peek() {
+                    Some(c) if class_escape(c).is_some() => {
+                        self.bump();
+                        chars.extend(class_escape(c).expect("RAND_1714560830"));
+                        continue;
+                    }
+                    //Inside of a class \b is a backspace
+                    Some('b') => {
+                        self.bump();
+                        '\x08'
+                    }
+                    _ => self.parse_escape()?,
+                },
+                Some(c) => c,
+            };
+            if self.peek() == Some('-') && self.chars.get(self.idx + 1) != Some(&']') {
+                self.bump();
+                let last = match self.bump() {
+                    Some('\\') => self.parse_escape()?,
+                    Some(c) => c,
+                    None => return self.error("missing ]"),
+                };
+                if last < first {
+                    return self.error("character range out of order");
+                }
+                chars.extend(range_chars(first, last));
+            } else {
+                chars.push(first);
+            }
+        }
+        let mut unique = vec![];
+        for c in chars {
+            if !unique.contains(&c) {
+                unique.push(c);
+            }
+        }
+        if negated {
+            unique = complement(&unique);
+        }
+        if unique.is_empty() {
+            return self.error("character class doesn't match anything");
+        }
+        return Ok(Regex::Class(unique));
+    }
+
+    fn parse_quantifiers(&mut self, mut atom: Regex) -> Result<Regex, String> {
+        loop {
+            let (min, max) = if self.eat("*") {
+                (0, None)
+            } else if self.eat("+") {
+                (1, None)
+            } else if self.eat("?") {
+                (0, Some(1))
+            } else if let Some(bounds) = self.parse_bounds() {
+                bounds
+            } else {
+                return Ok(atom);
+            };
+            if max.is_some_and(|max| max < min) {
+                return self.error("numbers out of order in {} quantifier");
+            }
+            //Lazy quantifiers match the same strings
+            self.eat("?");
+            atom = Regex::Repeat(Box::new(atom), min, max);
+        }
+    }
+
+    //Parses `{n}`, `{n,}` and `{n,m}`. Anything else starting with `{` is a literal brace.
+    fn parse_bounds(&mut self) -> Option<(usize, Option<usize>)> {
+        if self.peek() != Some('{') {
+            return None;
+        }
+        let rest = self.chars[self.idx + 1..].iter().collect::<String>();
+        let end = rest.find('}')?;
+        let bounds = &rest[..end];
+        let parsed = match bounds.find(',') {
+            None => bounds.parse().ok().map(|n| (n, Some(n))),
+            Some(i) if i + 1 == bounds.len() => bounds[..i].parse().ok().map(|n| (n, None)),
+            Some(i) => match (bounds[..i].parse(), bounds[i + 1..].parse()) {
+                (Ok(min), Ok(max)) => Some((min, Some(max))),
+                _ => None,
+            },
+        };
+        if parsed.is_some() {
+            self.idx += bounds.chars().count() + 2;
+        }
+        return parsed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Regex {
+        return Regex::Class(text.chars().collect());
+    }
+
+    #[test]
+    fn check_patterns() {
+        assert_eq!(
+            parse("^(?:ab|c)+[x-z_]{2,3}$").expect("RAND_1026728156"),
+            Regex::Concat(vec![
+                Regex::Repeat(
+                    Box::new(Regex::Alt(vec![
+                        Regex::Concat(vec![chars("a"), chars("b")]),
+                        chars("c"),
+                    ])),
+                    1,
+                    None,
+                ),
+                Regex::Repeat(Box::new(chars("xyz_")), 2, Some(3)),
+            ])
+        );
+        assert_eq!(
+            parse("\\d?\\u00e9{,x}").expect("RAND_3974820405"),
+            Regex::Concat(vec![
+                Regex::Repeat(Box::new(chars("0123456789")), 0, Some(1)),
+                chars("é"),
+                chars("{"),
+                chars(","),
+                chars("x"),
+                chars("}"),
+            ])
+        );
+        match parse("[^\\x20-z]").expect("RAND_1615617024") {
+            Regex::Class(class) => assert_eq!(class, "{|}~".chars().collect::<Vec<_>>()),
+            regex => panic!("unexpected {:?}", regex),
+        }
+        match parse("[\\u0100-\\u7fff]").expect("RAND_3830419760") {
+            Regex::Class(class) => assert_eq!(class.len(), MAX_CLASS_CHARS),
+            regex => panic!("unexpected {:?}", regex),
+        }
+    }
+
+    #[test]
+    fn check_pattern_errors() {
+        assert_eq!(
+            parse("a(?=b)").expect_err("RAND_2977095782"),
+            "lookarounds are not supported at offset 4"
+        );
+        assert_eq!(parse("(a").expect_err("RAND_1093708432"), "missing ) at offset 2");
+        assert_eq!(parse("a)").expect_err("RAND_1030970149"), "unbalanced ) at offset 1");
+        assert!(parse("(a)\\1").is_err());
+        assert!(parse("*").is_err());
+    }
+}
@@ -0,0 +1,44 @@
+extern crate protobuf_parser;
+extern crate serde_json;
+
+use protobuf_parser::ProtoOptions;
+use std::env;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "Usage: protobuf_parser [--message NAME] [-I DIR]... FILE.proto OUTPUT.json";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+fn main() {
+    let mut options = ProtoOptions::new();
+    let mut args = vec![];
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        if arg == "--message" {
+            options.message = Some(argv.next().unwrap_or_else(|| usage()));
+        } else if arg == "-I" {
+            let dir = argv.next().unwrap_or_else(|| usage());
+            options.import_paths.push(PathBuf::from(dir));
+        } else {
+            args.push(arg);
+        }
+    }
+    if args.len() != 2 {
+        usage();
+    }
+    let grammar = match protobuf_parser::parse_file(Path::new(&args[0]), &options) {
+        Ok(grammar) => grammar,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let of = File::create(&args[1]).expect("cannot create output file");
+    serde_json::to_writer(&of, &grammar.rules).expect("Can not write to output file");
+}
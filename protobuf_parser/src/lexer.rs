@@ -0,0 +1,265 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    //Identifiers and keywords. Dotted names like `foo.Bar` are several tokens.
+    Ident(String),
+    Int(u64),
+    //Floats only appear in option values, so their value doesn't matter
+    Float,
+    //A string literal, already decoded
+    Str(String),
+    //`{`, `=`, `;`, `.`, `-` and the other punctuation
+    Symbol(char),
+    Eof,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    idx: usize,
+    pos: Pos,
+}
+
+//Splits a .proto file into tokens
+pub fn tokenize(src: &str) -> Result<Vec<(Token, Pos)>, ParseError> {
+    let mut lexer = Lexer {
+        chars: src.chars().collect(),
+        idx: 0,
+        pos: Pos { line: 1, column: 1 },
+    };
+    let mut tokens = vec![];
+    loop {
+        lexer.skip_whitespace_and_comments()?;
+        let pos = lexer.pos;
+        let token = match lexer.peek(0) {
+            None => Token::Eof,
+            Some(c) => lexer.read_token(c)?,
+        };
+        tokens.push((token.clone(), pos));
+        if token == Token::Eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+impl Lexer {
+    fn peek(&self, offset: usize) -> Option<char> {
+        return self.chars.get(self.idx + offset).cloned();
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.idx += 1;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        return Some(c);
+    }
+
+    fn error<T>(&self, pos: Pos, message: &str) -> Result<T, ParseError> {
+        return Err(ParseError {
+            pos,
+            message: message.to_string(),
+        });
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => while self.peek(0).is_some_and(|c| c != '\n') {
+                    self.bump();
+                },
+                (Some('/'), Some('*')) => {
+                    let start = self.pos;
+                    self.bump();
+                    self.bump();
+                    while (self.peek(0), self.peek(1)) != (Some('*'), Some('/')) {
+                        if self.bump().is_none() {
+                            return self.error(start, "unterminated comment");
+                        }
+                    }
+                    self.bump();
+                    self.bump();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_token(&mut self, c: char) -> Result<Token, ParseError> {
+        let start = self.pos;
+        if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(c) = self.peek(0) {
+                if !c.is_ascii_alphanumeric() && c != '_' {
+                    break;
+                }
+                ident.push(c);
+                self.bump();
+            }
+            return Ok(Token::Ident(ident));
+        }
+        if c.is_ascii_digit() || (c == '.' && self.peek(1).is_some_and(|c| c.is_ascii_digit())) {
+            return self.read_number(start);
+        }
+        self.bump();
+        return match c {
+            '"' | '\'' => Ok(Token::Str(self.read_string(c, start)?)),
+            '{' | '}' | '[' | ']' | '(' | ')' | '<' | '>' | ';' | '=' | ',' | '.' | '-' | '+'
+            | ':' | '/' => Ok(Token::Symbol(c)),
+            _ => self.error(start, &format!("unexpected character {:?}", c)),
+        };
+    }
+
+    fn read_number(&mut self, start: Pos) -> Result<Token, ParseError> {
+        let mut text = String::new();
+        while let Some(c) = self.peek(0) {
+            let exponent_sign = (c == '-' || c == '+') && text.ends_with(['e', 'E']);
+            if !c.is_ascii_alphanumeric() && c != '.' && !exponent_sign {
+                break;
+            }
+            text.push(c);
+            self.bump();
+        }
+        let lower = text.to_ascii_lowercase();
+        let octal = lower
+            .strip_prefix('0')
+            .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_digit(8)));
+        let value = if let Some(hex) = lower.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16).ok()
+        } else if let Some(octal) = octal {
+            u64::from_str_radix(octal, 8).ok()
+        } else {
+            lower.parse().ok()
+        };
+        if let Some(value) = value {
+            return Ok(Token::Int(value));
+        }
+        if lower.parse::<f64>().is_ok() {
+            return Ok(Token::Float);
+        }
+        return self.error(start, &format!("invalid number {}", text));
+    }
+
+    fn read_string(&mut self, quote: char, start: Pos) -> Result<String, ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(text),
+                Some('\\') => {
+                    let decoded = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('a') => '\x07',
+                        Some('b') => '\x08',
+                        Some('f') => '\x0c',
+                        Some('v') => '\x0b',
+                        Some(c) if c == 'x' || c == 'X' || c.is_digit(8) => {
+                            let (radix, max_len, mut value) = match c.to_digit(8) {
+                                Some(digit) => (8, 2, digit),
+                                None => (16, 2, 0),
+                            };
+                            let mut len = 0;
+                            while let Some(digit) = self.peek(0).and_then(|c| c.to_digit(radix)) {
+                                if len == max_len {
+                                    break;
+                                }
+                                value = value * radix + digit;
+                                len += 1;
+                                self.bump();
+                            }
+                            match ::std::char::from_u32(value) {
+                                Some(c) => c,
+                                None => return self.error(start, "invalid escape sequence"),
+                            }
+                        }
+                        Some(c) => c,
+                        None => return self.error(start, "unterminated string"),
+                    };
+                    text.push(decoded);
+                }
+                Some('\n') | None => return self.error(start, "unterminated string"),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        return tokenize(src)
+            .expect("RAND_2656541125")
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+    }
+
+    #[test]
+    fn check_tokens() {
+        assert_eq!(
+            tokens(
+                "syntax = \"proto3\"; // comment\n\
+                 /* block */ option (my.opt) = -1.5e-3;\n\
+                 int32 x_1 = 0x1F [default = 017];"
+            ),
+            vec![
+                Token::Ident("syntax".to_string()),
+                Token::Symbol('='),
+                Token::Str("proto3".to_string()),
+                Token::Symbol(';'),
+                Token::Ident("option".to_string()),
+                Token::Symbol('('),
+                Token::Ident("my".to_string()),
+                Token::Symbol('.'),
+                Token::Ident("opt".to_string()),
+                Token::Symbol(')'),
+                Token::Symbol('='),
+                Token::Symbol('-'),
+                Token::Float,
+                Token::Symbol(';'),
+                Token::Ident("int32".to_string()),
+                Token::Ident("x_1".to_string()),
+                Token::Symbol('='),
+                Token::Int(31),
+                Token::Symbol('['),
+                Token::Ident("default".to_string()),
+                Token::Symbol('='),
+                Token::Int(15),
+                Token::Symbol(']'),
+                Token::Symbol(';'),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(tokens("'\\x41\\101\\n'"), vec![Token::Str("AA\n".to_string()), Token::Eof]);
+        let err = tokenize("message A {\n  int32 a = 1; #").expect_err("RAND_1217047129");
+        assert_eq!(err.to_string(), "2:16: unexpected character '#'");
+    }
+}
@@ -0,0 +1,769 @@
+pub mod lexer;
+pub mod parser;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use lexer::{ParseError, Pos};
+use parser::{EnumDef, Field, FieldType, Label, Message, ProtoFile, Scalar};
+
+//Boundary values of the scalar types. They are written as decimal numbers, which the encoded
+//nonterminals ({INT32:num=varint}, {FLOAT:num=float}, ...) turn into the wire format.
+const SCALAR_VALUES: &[(&str, &[&str])] = &[
+    ("INT32", &["0", "1", "-1", "127", "128", "300", "2147483647", "-2147483648"]),
+    (
+        "INT64",
+        &["0", "1", "-1", "128", "4294967296", "9223372036854775807", "-9223372036854775808"],
+    ),
+    ("UINT32", &["0", "1", "127", "128", "65535", "4294967295"]),
+    ("UINT64", &["0", "1", "128", "4294967296", "18446744073709551615"]),
+    ("BOOL", &["0", "1"]),
+    ("FLOAT", &["0", "1", "-1.5", "3.4028235e38", "1e-45", "inf", "-inf", "NaN"]),
+    ("DOUBLE", &["0", "1", "-1.5", "1.7976931348623157e308", "5e-324", "inf", "NaN"]),
+    ("OCTET", &["0", "1", "10", "32", "65", "127", "128", "255"]),
+];
+
+//The characters of string fields, including multi byte UTF-8
+const STRING_CHARS: &[&str] = &["a", "Z", "0", " ", "/", "\n", "é", "☃"];
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtoError {
+    pub file: Option<PathBuf>,
+    //None if the error isn't about a place in a file, e.g. if the file can't be read
+    pub pos: Option<Pos>,
+    pub message: String,
+}
+
+impl ProtoError {
+    fn new(pos: Option<Pos>, message: String) -> Self {
+        return ProtoError {
+            file: None,
+            pos,
+            message,
+        };
+    }
+
+    fn in_file(mut self, file: &Option<PathBuf>) -> Self {
+        if self.file.is_none() {
+            self.file = file.clone();
+        }
+        return self;
+    }
+}
+
+impl From<ParseError> for ProtoError {
+    fn from(err: ParseError) -> Self {
+        return ProtoError::new(Some(err.pos), err.message);
+    }
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(pos) = self.pos {
+            write!(f, "{}:{}:", pos.line, pos.column)?;
+        }
+        if self.file.is_some() || self.pos.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ProtoError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtoOptions {
+    //The message the grammar produces, either its full name (`pkg.Outer.Inner`) or a name that
+    //only one message has. Defaults to the first message of the file.
+    pub message: Option<String>,
+    //Searched for imports after the directory of the importing file, like `protoc -I`
+    pub import_paths: Vec<PathBuf>,
+}
+
+impl ProtoOptions {
+    pub fn new() -> Self {
+        return ProtoOptions {
+            message: None,
+            import_paths: vec![],
+        };
+    }
+}
+
+impl Default for ProtoOptions {
+    fn default() -> Self {
+        return ProtoOptions::new();
+    }
+}
+
+//The wire encoding of a protobuf message as grammartec rules. Lengths and numbers are computed
+//at unparse time by the len= and num= encodings of grammartec.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grammar {
+    pub start: String,
+    pub rules: Vec<(String, String)>,
+}
+
+pub fn parse_file(path: &Path, options: &ProtoOptions) -> Result<Grammar, ProtoError> {
+    let file = Some(path.to_path_buf());
+    let src = read_file(path).map_err(|e| e.in_file(&file))?;
+    return convert(&src, file, options);
+}
+
+//Imports are only searched in options.import_paths
+pub fn parse_str(src: &str, options: &ProtoOptions) -> Result<Grammar, ProtoError> {
+    return convert(src, None, options);
+}
+
+fn read_file(path: &Path) -> Result<String, ProtoError> {
+    let mut src = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut src))
+        .map_err(|e| ProtoError::new(None, e.to_string()))?;
+    return Ok(src);
+}
+
+fn convert(
+    src: &str,
+    path: Option<PathBuf>,
+    options: &ProtoOptions,
+) -> Result<Grammar, ProtoError> {
+    let parse = |src: &str, path: &Option<PathBuf>| {
+        return parser::parse(src).map_err(|e| ProtoError::from(e).in_file(path));
+    };
+    let main = parse(src, &path)?;
+    let mut types = HashMap::new();
+    let mut loaded = HashSet::new();
+    if let Some(ref path) = path {
+        loaded.insert(path.canonicalize().unwrap_or(path.clone()));
+    }
+    let mut todo = VecDeque::new();
+    todo.push_back((main.clone(), path.clone()));
+    while let Some((file, path)) = todo.pop_front() {
+        collect_types(&file, &path, &mut types)?;
+        for &(ref import, pos) in file.imports.iter() {
+            let dirs = path
+                .as_ref()
+                .and_then(|p| p.parent())
+                .map(|dir| dir.to_path_buf())
+                .into_iter()
+                .chain(options.import_paths.iter().cloned());
+            let found = dirs.map(|dir| dir.join(import)).find(|p| p.is_file());
+            let import_path = match found {
+                Some(import_path) => import_path,
+                None => {
+                    let message = format!("cannot find import {}", import);
+                    return Err(ProtoError::new(Some(pos), message).in_file(&path));
+                }
+            };
+            if !loaded.insert(import_path.canonicalize().unwrap_or(import_path.clone())) {
+                continue;
+            }
+            let import_file = Some(import_path.clone());
+            let src = read_file(&import_path).map_err(|e| e.in_file(&import_file))?;
+            todo.push_back((parse(&src, &import_file)?, import_file));
+        }
+    }
+
+    let start = match options.message {
+        Some(ref name) => find_message(&types, name)?,
+        None => match main.messages.first() {
+            Some(message) => qualify(&main.package, &message.name),
+            None => return Err(ProtoError::new(None, "no message is defined".to_string())),
+        },
+    };
+    let mut lowering = Lowering {
+        types: &types,
+        nonterminals: HashSet::new(),
+        type_names: HashMap::new(),
+        todo: VecDeque::new(),
+        rules: vec![],
+        value_rules: vec![],
+        values: HashMap::new(),
+        tags: HashMap::new(),
+    };
+    let start = lowering.type_nonterm(&start);
+    while let Some(message) = lowering.todo.pop_front() {
+        lowering.lower_message(&message)?;
+    }
+    let mut rules = lowering.rules;
+    rules.extend(lowering.value_rules);
+    return Ok(Grammar { start, rules });
+}
+
+struct TypeDef {
+    //The name without the package, e.g. Outer.Inner
+    name: String,
+    file: Option<PathBuf>,
+    kind: TypeKind,
+}
+
+enum TypeKind {
+    Message {
+        message: Message,
+        packed_by_default: bool,
+    },
+    Enum(EnumDef),
+}
+
+fn qualify(scope: &Option<String>, name: &str) -> String {
+    return match scope {
+        &Some(ref scope) => format!("{}.{}", scope, name),
+        &None => name.to_string(),
+    };
+}
+
+//Adds all messages and enums of a file by their full name
+fn collect_types(
+    file: &ProtoFile,
+    path: &Option<PathBuf>,
+    types: &mut HashMap<String, TypeDef>,
+) -> Result<(), ProtoError> {
+    fn add(
+        types: &mut HashMap<String, TypeDef>,
+        package: &Option<String>,
+        name: String,
+        kind: TypeKind,
+        path: &Option<PathBuf>,
+    ) -> Result<(), ProtoError> {
+        let full_name = qualify(package, &name);
+        if types.contains_key(&full_name) {
+            let message = format!("{} is defined more than once", full_name);
+            return Err(ProtoError::new(None, message).in_file(path));
+        }
+        let def = TypeDef {
+            name,
+            file: path.clone(),
+            kind,
+        };
+        types.insert(full_name, def);
+        return Ok(());
+    }
+    fn add_message(
+        types: &mut HashMap<String, TypeDef>,
+        scope: &Option<String>,
+        message: &Message,
+        file: &ProtoFile,
+        path: &Option<PathBuf>,
+    ) -> Result<(), ProtoError> {
+        let name = qualify(scope, &message.name);
+        for nested in message.messages.iter() {
+            add_message(types, &Some(name.clone()), nested, file, path)?;
+        }
+        for def in message.enums.iter() {
+            let enum_name = format!("{}.{}", name, def.name);
+            add(types, &file.package, enum_name, TypeKind::Enum(def.clone()), path)?;
+        }
+        let kind = TypeKind::Message {
+            message: message.clone(),
+            packed_by_default: file.packed_by_default,
+        };
+        return add(types, &file.package, name, kind, path);
+    }
+    for message in file.messages.iter() {
+        add_message(types, &None, message, file, path)?;
+    }
+    for def in file.enums.iter() {
+        let kind = TypeKind::Enum(def.clone());
+        add(types, &file.package, def.name.clone(), kind, path)?;
+    }
+    return Ok(());
+}
+
+//Resolves a type name like protoc: `.a.B` is fully qualified, other names are looked up in the
+//scope of the message using them and then in each enclosing scope
+fn resolve(types: &HashMap<String, TypeDef>, name: &str, scope: &str) -> Option<String> {
+    if let Some(qualified) = name.strip_prefix('.') {
+        return Some(qualified.to_string()).filter(|name| types.contains_key(name));
+    }
+    let mut scope = scope.to_string();
+    loop {
+        let full_name = match scope.is_empty() {
+            true => name.to_string(),
+            false => format!("{}.{}", scope, name),
+        };
+        if types.contains_key(&full_name) {
+            return Some(full_name);
+        }
+        if scope.is_empty() {
+            return None;
+        }
+        scope.truncate(scope.rfind('.').unwrap_or(0));
+    }
+}
+
+fn find_message(types: &HashMap<String, TypeDef>, name: &str) -> Result<String, ProtoError> {
+    let name = name.trim_start_matches('.');
+    let suffix = format!(".{}", name);
+    let mut candidates = types
+        .iter()
+        .filter(|&(_, def)| match def.kind {
+            TypeKind::Message { .. } => true,
+            TypeKind::Enum(_) => false,
+        })
+        .map(|(full_name, _)| full_name)
+        .filter(|full_name| *full_name == name || full_name.ends_with(&suffix))
+        .collect::<Vec<_>>();
+    candidates.sort();
+    return match candidates.len() {
+        0 => Err(ProtoError::new(None, format!("message {} is not defined", name))),
+        1 => Ok(candidates[0].clone()),
+        _ if candidates.iter().any(|full_name| *full_name == name) => Ok(name.to_string()),
+        _ => {
+            let all = candidates.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+            let message = format!("message name {} is ambiguous: {}", name, all.join(", "));
+            Err(ProtoError::new(None, message))
+        }
+    };
+}
+
+//Turns a name like `Outer.inner_field` into a nonterminal name like OUTER_INNER_FIELD
+fn nonterm_name(name: &str) -> String {
+    let mut nonterm = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect::<String>();
+    if !nonterm.starts_with(|c: char| c.is_ascii_uppercase()) {
+        nonterm.insert_str(0, "NT_");
+    }
+    return nonterm;
+}
+
+struct Lowering<'a> {
+    types: &'a HashMap<String, TypeDef>,
+    nonterminals: HashSet<String>,
+    //Full name of a message or enum -> nonterminal
+    type_names: HashMap<String, String>,
+    //Messages that got a nonterminal but no rules yet
+    todo: VecDeque<String>,
+    rules: Vec<(String, String)>,
+    //The rules of enums, scalar values and tags, which are placed behind all messages
+    value_rules: Vec<(String, String)>,
+    values: HashMap<&'static str, String>,
+    //Tags that don't fit into one byte -> nonterminal
+    tags: HashMap<u64, String>,
+}
+
+impl<'a> Lowering<'a> {
+    //Returns the nonterminal of a message or enum. Messages are lowered later, such that the
+    //rules of the start message come first.
+    fn type_nonterm(&mut self, full_name: &str) -> String {
+        if let Some(nonterm) = self.type_names.get(full_name) {
+            return nonterm.clone();
+        }
+        let types = self.types;
+        let def = &types[full_name];
+        let nonterm = self.add_nonterm(&nonterm_name(&def.name));
+        self.type_names.insert(full_name.to_string(), nonterm.clone());
+        match def.kind {
+            TypeKind::Message { .. } => self.todo.push_back(full_name.to_string()),
+            TypeKind::Enum(ref def) => {
+                for &(_, value) in def.values.iter() {
+                    self.value_rules.push((nonterm.clone(), value.to_string()));
+                }
+                if def.values.is_empty() {
+                    self.value_rules.push((nonterm.clone(), "0".to_string()));
+                }
+            }
+        }
+        return nonterm;
+    }
+
+    //A message is the concatenation of its fields in the order they are declared. Fields that
+    //aren't required may be missing, repeated fields can occur any number of times.
+    fn lower_message(&mut self, full_name: &str) -> Result<(), ProtoError> {
+        let types = self.types;
+        let def = &types[full_name];
+        let file = &def.file;
+        let (message, packed_by_default) = match def.kind {
+            TypeKind::Message {
+                ref message,
+                packed_by_default,
+            } => (message, packed_by_default),
+            TypeKind::Enum(_) => unreachable!(),
+        };
+        let nonterm = self.type_names[full_name].clone();
+        //Map entries are added to self.rules right away, they have to come after the message
+        let entries_start = self.rules.len();
+        let mut parts = vec![];
+        let mut field_rules = vec![];
+        let mut oneofs = vec![];
+        for field in message.fields.iter() {
+            if let Some(ref oneof) = field.oneof {
+                if oneofs.contains(oneof) {
+                    continue;
+                }
+                oneofs.push(oneof.clone());
+                let oneof_nt = self.add_nonterm(&format!("{}_{}", nonterm, nonterm_name(oneof)));
+                field_rules.push((oneof_nt.clone(), String::new()));
+                for member in message.fields.iter() {
+                    if member.oneof.as_ref() == Some(oneof) {
+                        let def = self
+                            .field_once(member, full_name, &oneof_nt)
+                            .map_err(|e| e.in_file(file))?;
+                        field_rules.push((oneof_nt.clone(), def));
+                    }
+                }
+                parts.push(format!("{{{}}}", oneof_nt));
+                continue;
+            }
+            let field_nt = self.add_nonterm(&format!("{}_{}", nonterm, nonterm_name(&field.name)));
+            let once = self
+                .field_once(field, full_name, &field_nt)
+                .map_err(|e| e.in_file(file))?;
+            match field.label {
+                Label::Required => field_rules.push((field_nt.clone(), once)),
+                Label::Optional => {
+                    field_rules.push((field_nt.clone(), String::new()));
+                    field_rules.push((field_nt.clone(), once));
+                }
+                Label::Repeated => {
+                    field_rules.push((field_nt.clone(), String::new()));
+                    field_rules.push((field_nt.clone(), format!("{}{{{}}}", once, field_nt)));
+                    //Parsers have to accept both encodings of packable fields
+                    let packed = field.packed.unwrap_or(packed_by_default);
+                    if packed && self.is_packable(field, full_name) {
+                        let packed_nt = self.add_nonterm(&format!("{}_PACKED", field_nt));
+                        let (value, _) = self
+                            .value(&field.ty, full_name, field.pos, &field_nt)
+                            .map_err(|e| e.in_file(file))?;
+                        field_rules.push((packed_nt.clone(), value.clone()));
+                        let more = format!("{}{{{}}}", value, packed_nt);
+                        field_rules.push((packed_nt.clone(), more));
+                        let tag = self.tag(field.number, WIRE_LEN);
+                        let def = format!("{}{{{}:len=varint}}", tag, packed_nt);
+                        field_rules.push((field_nt.clone(), def));
+                    }
+                }
+            }
+            parts.push(format!("{{{}}}", field_nt));
+        }
+        let entries = self.rules.split_off(entries_start);
+        self.rules.push((nonterm, parts.join("")));
+        self.rules.extend(field_rules);
+        self.rules.extend(entries);
+        return Ok(());
+    }
+
+    //Only numeric scalars and enums can be packed
+    fn is_packable(&self, field: &Field, scope: &str) -> bool {
+        return match field.ty {
+            FieldType::Scalar(Scalar::String) | FieldType::Scalar(Scalar::Bytes) => false,
+            FieldType::Scalar(_) => true,
+            FieldType::Named(ref name) => match resolve(self.types, name, scope) {
+                Some(full_name) => match self.types[&full_name].kind {
+                    TypeKind::Enum(_) => true,
+                    TypeKind::Message { .. } => false,
+                },
+                None => false,
+            },
+            FieldType::Map(..) => false,
+        };
+    }
+
+    //The tag of the field followed by one value
+    fn field_once(&mut self, field: &Field, scope: &str, name: &str) -> Result<String, ProtoError> {
+        let (value, wire_type) = self.value(&field.ty, scope, field.pos, name)?;
+        return Ok(format!("{}{}", self.tag(field.number, wire_type), value));
+    }
+
+    //Returns the format of a value and its wire type. name is used for the entries of maps.
+    fn value(
+        &mut self,
+        ty: &FieldType,
+        scope: &str,
+        pos: Pos,
+        name: &str,
+    ) -> Result<(String, u64), ProtoError> {
+        return match ty {
+            &FieldType::Scalar(scalar) => Ok(self.scalar(scalar)),
+            &FieldType::Named(ref type_name) => {
+                let full_name = match resolve(self.types, type_name, scope) {
+                    Some(full_name) => full_name,
+                    None => {
+                        let message = format!("unknown type {}", type_name);
+                        return Err(ProtoError::new(Some(pos), message));
+                    }
+                };
+                let nonterm = self.type_nonterm(&full_name);
+                match self.types[&full_name].kind {
+                    TypeKind::Message { .. } => {
+                        Ok((format!("{{{}:len=varint}}", nonterm), WIRE_LEN))
+                    }
+                    TypeKind::Enum(_) => Ok((format!("{{{}:num=varint}}", nonterm), WIRE_VARINT)),
+                }
+            }
+            &FieldType::Map(key, ref value) => {
+                let entry_nt = self.add_nonterm(&format!("{}_ENTRY", name));
+                let (key, key_wire_type) = self.scalar(key);
+                let (value, value_wire_type) = self.value(value, scope, pos, &entry_nt)?;
+                let def = format!(
+                    "{}{}{}{}",
+                    self.tag(1, key_wire_type),
+                    key,
+                    self.tag(2, value_wire_type),
+                    value
+                );
+                self.rules.push((entry_nt.clone(), def));
+                Ok((format!("{{{}:len=varint}}", entry_nt), WIRE_LEN))
+            }
+        };
+    }
+
+    fn scalar(&mut self, scalar: Scalar) -> (String, u64) {
+        let (kind, encoding, wire_type) = match scalar {
+            Scalar::Double => ("DOUBLE", "num=double", WIRE_FIXED64),
+            Scalar::Float => ("FLOAT", "num=float", WIRE_FIXED32),
+            Scalar::Int32 => ("INT32", "num=varint", WIRE_VARINT),
+            Scalar::Int64 => ("INT64", "num=varint", WIRE_VARINT),
+            Scalar::Uint32 => ("UINT32", "num=varint", WIRE_VARINT),
+            Scalar::Uint64 => ("UINT64", "num=varint", WIRE_VARINT),
+            Scalar::Sint32 => ("INT32", "num=zigzag", WIRE_VARINT),
+            Scalar::Sint64 => ("INT64", "num=zigzag", WIRE_VARINT),
+            Scalar::Fixed32 => ("UINT32", "num=u32le", WIRE_FIXED32),
+            Scalar::Fixed64 => ("UINT64", "num=u64le", WIRE_FIXED64),
+            Scalar::Sfixed32 => ("INT32", "num=u32le", WIRE_FIXED32),
+            Scalar::Sfixed64 => ("INT64", "num=u64le", WIRE_FIXED64),
+            Scalar::Bool => ("BOOL", "num=varint", WIRE_VARINT),
+            Scalar::String => ("STRING", "len=varint", WIRE_LEN),
+            Scalar::Bytes => ("BYTES", "len=varint", WIRE_LEN),
+        };
+        let nonterm = self.value_nonterm(kind);
+        return (format!("{{{}:{}}}", nonterm, encoding), wire_type);
+    }
+
+    //Returns the nonterminal of a kind of scalar values, which is added on first use
+    fn value_nonterm(&mut self, kind: &'static str) -> String {
+        if let Some(nonterm) = self.values.get(kind) {
+            return nonterm.clone();
+        }
+        let nonterm = self.add_nonterm(kind);
+        self.values.insert(kind, nonterm.clone());
+        match kind {
+            "STRING" => {
+                let chars = self.add_nonterm("CHAR");
+                self.value_rules.push((nonterm.clone(), String::new()));
+                self.value_rules.push((nonterm.clone(), format!("{{{}}}{{{}}}", chars, nonterm)));
+                for c in STRING_CHARS.iter() {
+                    self.value_rules.push((chars.clone(), c.to_string()));
+                }
+            }
+            "BYTES" => {
+                let octet = self.value_nonterm("OCTET");
+                let def = format!("{{{}:num=u8}}{{{}}}", octet, nonterm);
+                self.value_rules.push((nonterm.clone(), String::new()));
+                self.value_rules.push((nonterm.clone(), def));
+            }
+            _ => {
+                let values = SCALAR_VALUES
+                    .iter()
+                    .find(|&&(k, _)| k == kind)
+                    .map(|&(_, values)| values)
+                    .expect("RAND_2972712662");
+                for value in values.iter() {
+                    self.value_rules.push((nonterm.clone(), value.to_string()));
+                }
+            }
+        }
+        return nonterm;
+    }
+
+    //The varint (number << 3 | wire_type). Tags of one byte are literals, wire types 3 and 4
+    //(groups) aren't used, so the only byte that needs escaping is `}`.
+    fn tag(&mut self, number: u64, wire_type: u64) -> String {
+        let tag = number << 3 | wire_type;
+        if tag < 0x80 {
+            return (tag as u8 as char).to_string().replace("}", "\\}");
+        }
+        if let Some(nonterm) = self.tags.get(&tag) {
+            return format!("{{{}:num=varint}}", nonterm);
+        }
+        let nonterm = self.add_nonterm(&format!("TAG_{}", tag));
+        self.value_rules.push((nonterm.clone(), tag.to_string()));
+        self.tags.insert(tag, nonterm.clone());
+        return format!("{{{}:num=varint}}", nonterm);
+    }
+
+    //Nonterminals with the same name get numbered: PERSON_ID, PERSON_ID2, ...
+    fn add_nonterm(&mut self, base: &str) -> String {
+        let mut new_nonterm = base.to_string();
+        let mut i = 1;
+        while self.nonterminals.contains(&new_nonterm) {
+            i += 1;
+            new_nonterm = format!("{}{}", base, i);
+        }
+        self.nonterminals.insert(new_nonterm.clone());
+        return new_nonterm;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn rules_for<'a>(grammar: &'a Grammar, nt: &str) -> Vec<&'a str> {
+        return grammar
+            .rules
+            .iter()
+            .filter(|&&(ref name, _)| name == nt)
+            .map(|&(_, ref format)| format.as_str())
+            .collect();
+    }
+
+    const PERSON: &str = "\
+syntax = \"proto2\";
+package demo;
+message Person {
+  required string name = 1;
+  optional int32 id = 2;
+  repeated PhoneNumber phones = 4;
+  message PhoneNumber {
+    required string number = 1;
+    optional PhoneType type = 2 [default = HOME];
+  }
+  enum PhoneType { MOBILE = 0; HOME = 1; }
+  repeated sint64 deltas = 5 [packed = true];
+  oneof contact { string email = 16; Person friend = 17; }
+  map<string, fixed32> scores = 20;
+  repeated bool flags = 15;
+}
+message Unused { optional bytes data = 1; }
+";
+
+    #[test]
+    fn check_lowering() {
+        let grammar = parse_str(PERSON, &ProtoOptions::new()).expect("RAND_2039940789");
+        assert_eq!(grammar.start, "PERSON");
+        assert_eq!(grammar.rules[0].0, "PERSON");
+        assert_eq!(
+            rules_for(&grammar, "PERSON"),
+            vec![
+                "{PERSON_NAME}{PERSON_ID}{PERSON_PHONES}{PERSON_DELTAS}{PERSON_CONTACT}\
+                 {PERSON_SCORES}{PERSON_FLAGS}",
+            ]
+        );
+        assert_eq!(rules_for(&grammar, "PERSON_NAME"), vec!["\n{STRING:len=varint}"]);
+        assert_eq!(rules_for(&grammar, "PERSON_ID"), vec!["", "\x10{INT32:num=varint}"]);
+        assert_eq!(
+            rules_for(&grammar, "PERSON_PHONES"),
+            vec!["", "\"{PERSON_PHONENUMBER:len=varint}{PERSON_PHONES}"]
+        );
+        assert_eq!(
+            rules_for(&grammar, "PERSON_DELTAS"),
+            vec![
+                "",
+                "({INT64:num=zigzag}{PERSON_DELTAS}",
+                "*{PERSON_DELTAS_PACKED:len=varint}",
+            ]
+        );
+        assert_eq!(
+            rules_for(&grammar, "PERSON_DELTAS_PACKED"),
+            vec!["{INT64:num=zigzag}", "{INT64:num=zigzag}{PERSON_DELTAS_PACKED}"]
+        );
+        assert_eq!(
+            rules_for(&grammar, "PERSON_CONTACT"),
+            vec![
+                "",
+                "{TAG_130:num=varint}{STRING:len=varint}",
+                "{TAG_138:num=varint}{PERSON:len=varint}",
+            ]
+        );
+        assert_eq!(
+            rules_for(&grammar, "PERSON_SCORES_ENTRY"),
+            vec!["\n{STRING:len=varint}\x15{UINT32:num=u32le}"]
+        );
+        //Field 15 with wire type 0 is 0x78, the packed form would be the escaped 0x7a
+        assert_eq!(
+            rules_for(&grammar, "PERSON_FLAGS"),
+            vec!["", "x{BOOL:num=varint}{PERSON_FLAGS}"]
+        );
+        assert_eq!(
+            rules_for(&grammar, "PERSON_PHONENUMBER_TYPE"),
+            vec!["", "\x10{PERSON_PHONETYPE:num=varint}"]
+        );
+        assert_eq!(rules_for(&grammar, "PERSON_PHONETYPE"), vec!["0", "1"]);
+        assert_eq!(rules_for(&grammar, "TAG_130"), vec!["130"]);
+        assert_eq!(rules_for(&grammar, "UNUSED"), Vec::<&str>::new());
+        //The values come after the messages
+        let first_value = grammar.rules.iter().position(|r| r.0 == "STRING");
+        let last_message = grammar.rules.iter().rposition(|r| r.0 == "PERSON_PHONENUMBER_TYPE");
+        assert!(first_value > last_message);
+    }
+
+    #[test]
+    fn check_proto3_and_imports() {
+        let dir = env::temp_dir().join(format!("protobuf_parser_{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).expect("RAND_434200972");
+        fs::write(
+            dir.join("lib/common.proto"),
+            "syntax = \"proto3\"; package lib; message Id { fixed64 value = 1; }\n\
+             enum Kind { UNKNOWN = 0; }",
+        ).expect("RAND_4276655582");
+        let mut options = ProtoOptions::new();
+        options.import_paths.push(dir.clone());
+        options.message = Some("Request".to_string());
+        let grammar = parse_str(
+            "syntax = \"proto3\";\n\
+             package api;\n\
+             import \"lib/common.proto\";\n\
+             message Other { lib.Kind kind = 1; }\n\
+             message Request {\n\
+             \x20 repeated lib.Kind kinds = 1;\n\
+             \x20 .lib.Id id = 2;\n\
+             \x20 repeated string names = 3;\n\
+             \x20 message Id { double x = 1; }\n\
+             \x20 Id local = 4;\n\
+             }",
+            &options,
+        ).expect("RAND_558650331");
+        assert_eq!(grammar.start, "REQUEST");
+        assert_eq!(
+            rules_for(&grammar, "REQUEST_KINDS"),
+            vec!["", "\x08{KIND:num=varint}{REQUEST_KINDS}", "\n{REQUEST_KINDS_PACKED:len=varint}"]
+        );
+        assert_eq!(rules_for(&grammar, "REQUEST_ID"), vec!["", "\x12{ID:len=varint}"]);
+        assert_eq!(rules_for(&grammar, "ID_VALUE"), vec!["", "\t{UINT64:num=u64le}"]);
+        assert_eq!(rules_for(&grammar, "REQUEST_NAMES").len(), 2);
+        //Request.Id gets numbered because the field Request.id already took REQUEST_ID
+        assert_eq!(rules_for(&grammar, "REQUEST_ID2"), vec!["{REQUEST_ID2_X}"]);
+        assert_eq!(rules_for(&grammar, "REQUEST_ID2_X"), vec!["", "\t{DOUBLE:num=double}"]);
+
+        options.message = Some("Id".to_string());
+        let src = "package api; import \"lib/common.proto\"; message Id {}";
+        let err = parse_str(src, &options).expect_err("RAND_1226409230");
+        assert_eq!(err.message, "message name Id is ambiguous: api.Id, lib.Id");
+        options.message = Some("lib.Id".to_string());
+        assert_eq!(parse_str(src, &options).expect("RAND_4246225516").start, "ID");
+        options.import_paths.clear();
+        let err = parse_str("import \"lib/common.proto\";", &options).expect_err("RAND_3220417656");
+        assert_eq!(err.to_string(), "1:8: cannot find import lib/common.proto");
+        fs::remove_dir_all(&dir).expect("RAND_597900786");
+    }
+
+    #[test]
+    fn check_errors() {
+        let options = ProtoOptions::new();
+        let err = parse_str("message A {\n  B b = 1;\n}", &options).expect_err("RAND_3072352319");
+        assert_eq!(err.to_string(), "2:3: unknown type B");
+        let err = parse_str("enum E { A = 0; }", &options).expect_err("RAND_4053405788");
+        assert_eq!(err.message, "no message is defined");
+        let err = parse_str("message A {} message A {}", &options).expect_err("RAND_2585480509");
+        assert_eq!(err.message, "A is defined more than once");
+    }
+}
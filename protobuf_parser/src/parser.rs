@@ -0,0 +1,537 @@
+use lexer::{tokenize, ParseError, Pos, Token};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtoFile {
+    //proto3 and editions pack repeated scalar fields by default, proto2 doesn't
+    pub packed_by_default: bool,
+    pub package: Option<String>,
+    pub imports: Vec<(String, Pos)>,
+    pub messages: Vec<Message>,
+    pub enums: Vec<EnumDef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub messages: Vec<Message>,
+    pub enums: Vec<EnumDef>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    Required,
+    //`optional` and proto3 fields without a label
+    Optional,
+    Repeated,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub label: Label,
+    pub ty: FieldType,
+    pub number: u64,
+    //Set by the `packed` option
+    pub packed: Option<bool>,
+    //The name of the oneof the field belongs to
+    pub oneof: Option<String>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldType {
+    Scalar(Scalar),
+    //A message or enum, as written in the file (e.g. `.pkg.Outer.Inner`)
+    Named(String),
+    //`map<K, V>` fields are repeated, length delimited entries with the key as field 1 and the
+    //value as field 2
+    Map(Scalar, Box<FieldType>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scalar {
+    Double,
+    Float,
+    Int32,
+    Int64,
+    Uint32,
+    Uint64,
+    Sint32,
+    Sint64,
+    Fixed32,
+    Fixed64,
+    Sfixed32,
+    Sfixed64,
+    Bool,
+    String,
+    Bytes,
+}
+
+static SCALAR_NAMES: [(&str, Scalar); 15] = [
+    ("double", Scalar::Double),
+    ("float", Scalar::Float),
+    ("int32", Scalar::Int32),
+    ("int64", Scalar::Int64),
+    ("uint32", Scalar::Uint32),
+    ("uint64", Scalar::Uint64),
+    ("sint32", Scalar::Sint32),
+    ("sint64", Scalar::Sint64),
+    ("fixed32", Scalar::Fixed32),
+    ("fixed64", Scalar::Fixed64),
+    ("sfixed32", Scalar::Sfixed32),
+    ("sfixed64", Scalar::Sfixed64),
+    ("bool", Scalar::Bool),
+    ("string", Scalar::String),
+    ("bytes", Scalar::Bytes),
+];
+
+impl Scalar {
+    pub fn from_name(name: &str) -> Option<Self> {
+        return SCALAR_NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, s)| s);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDef {
+    pub name: String,
+    pub values: Vec<(String, i64)>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    idx: usize,
+}
+
+//Parses the messages and enums of a .proto file. Services, extensions and options don't change
+//the wire format and are skipped.
+pub fn parse(src: &str) -> Result<ProtoFile, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        idx: 0,
+    };
+    let mut file = ProtoFile {
+        packed_by_default: false,
+        package: None,
+        imports: vec![],
+        messages: vec![],
+        enums: vec![],
+    };
+    loop {
+        let pos = parser.pos();
+        match parser.bump() {
+            Token::Eof => return Ok(file),
+            Token::Symbol(';') => {}
+            Token::Ident(ref keyword) => match keyword.as_str() {
+                "syntax" | "edition" => {
+                    parser.expect('=')?;
+                    let syntax = parser.string()?;
+                    parser.expect(';')?;
+                    file.packed_by_default = keyword == "edition" || syntax == "proto3";
+                }
+                "package" => {
+                    file.package = Some(parser.full_ident()?);
+                    parser.expect(';')?;
+                }
+                "import" => {
+                    if parser.is_ident("public") || parser.is_ident("weak") {
+                        parser.bump();
+                    }
+                    let path_pos = parser.pos();
+                    file.imports.push((parser.string()?, path_pos));
+                    parser.expect(';')?;
+                }
+                "message" => file.messages.push(parser.parse_message(pos)?),
+                "enum" => file.enums.push(parser.parse_enum()?),
+                "option" | "service" | "extend" => parser.skip_statement()?,
+                _ => return parser.error_at(pos, &format!("unexpected {}", keyword)),
+            },
+            _ => return parser.error_at(pos, "expected a declaration"),
+        }
+    }
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> &Token {
+        //tokenize always ends the list with Eof
+        let last = self.tokens.len() - 1;
+        return &self.tokens[(self.idx + offset).min(last)].0;
+    }
+
+    fn pos(&self) -> Pos {
+        return self.tokens[self.idx].1;
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.peek(0).clone();
+        if self.idx < self.tokens.len() - 1 {
+            self.idx += 1;
+        }
+        return token;
+    }
+
+    fn error_at<T>(&self, pos: Pos, message: &str) -> Result<T, ParseError> {
+        return Err(ParseError {
+            pos,
+            message: message.to_string(),
+        });
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        return self.error_at(self.pos(), message);
+    }
+
+    fn is_ident(&self, name: &str) -> bool {
+        return *self.peek(0) == Token::Ident(name.to_string());
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if *self.peek(0) == Token::Symbol(symbol) {
+            self.bump();
+            return true;
+        }
+        return false;
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), ParseError> {
+        if !self.eat(symbol) {
+            return self.error(&format!("expected {}", symbol));
+        }
+        return Ok(());
+    }
+
+    fn ident(&mut self) -> Result<String, ParseError> {
+        return match self.peek(0).clone() {
+            Token::Ident(name) => {
+                self.bump();
+                Ok(name)
+            }
+            _ => self.error("expected a name"),
+        };
+    }
+
+    //`foo.bar.Baz`, with a leading dot for fully qualified type names
+    fn full_ident(&mut self) -> Result<String, ParseError> {
+        let mut name = String::new();
+        if self.eat('.') {
+            name.push('.');
+        }
+        name.push_str(&self.ident()?);
+        while self.eat('.') {
+            name.push('.');
+            name.push_str(&self.ident()?);
+        }
+        return Ok(name);
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let mut text = match self.peek(0).clone() {
+            Token::Str(text) => text,
+            _ => return self.error("expected a string"),
+        };
+        self.bump();
+        //Adjacent strings are concatenated like in C
+        while let Token::Str(more) = self.peek(0).clone() {
+            text.push_str(&more);
+            self.bump();
+        }
+        return Ok(text);
+    }
+
+    fn int(&mut self) -> Result<i64, ParseError> {
+        let negative = self.eat('-');
+        return match self.bump() {
+            Token::Int(value) if negative => Ok((value as i64).wrapping_neg()),
+            Token::Int(value) => Ok(value as i64),
+            _ => self.error_at(self.tokens[self.idx - 1].1, "expected a number"),
+        };
+    }
+
+    //Skips everything up to the next `;` or the end of the next `{ ... }` block
+    fn skip_statement(&mut self) -> Result<(), ParseError> {
+        let start = self.pos();
+        let mut depth = 0;
+        loop {
+            match self.bump() {
+                Token::Symbol(';') if depth == 0 => return Ok(()),
+                Token::Symbol('{') => depth += 1,
+                Token::Symbol('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                Token::Eof => return self.error_at(start, "unterminated statement"),
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_message(&mut self, pos: Pos) -> Result<Message, ParseError> {
+        let mut message = Message {
+            name: self.ident()?,
+            fields: vec![],
+            messages: vec![],
+            enums: vec![],
+            pos,
+        };
+        self.expect('{')?;
+        while !self.eat('}') {
+            let pos = self.pos();
+            let keyword = match self.peek(0).clone() {
+                Token::Ident(keyword) => keyword,
+                Token::Symbol(';') => {
+                    self.bump();
+                    continue;
+                }
+                //A fully qualified type
+                Token::Symbol('.') => {
+                    message.fields.push(self.parse_field(Label::Optional)?);
+                    continue;
+                }
+                _ => return self.error("expected a field"),
+            };
+            let is_declaration = matches!(self.peek(1), &Token::Ident(_));
+            match keyword.as_str() {
+                "message" if is_declaration => {
+                    self.bump();
+                    message.messages.push(self.parse_message(pos)?);
+                }
+                "enum" if is_declaration => {
+                    self.bump();
+                    message.enums.push(self.parse_enum()?);
+                }
+                "oneof" if is_declaration => {
+                    self.bump();
+                    let name = self.ident()?;
+                    self.expect('{')?;
+                    while !self.eat('}') {
+                        if self.is_ident("option") {
+                            self.skip_statement()?;
+                            continue;
+                        }
+                        let mut field = self.parse_field(Label::Optional)?;
+                        field.oneof = Some(name.clone());
+                        message.fields.push(field);
+                    }
+                }
+                "option" | "reserved" | "extensions" | "extend" => self.skip_statement()?,
+                "group" => return self.error("groups are not supported"),
+                "required" | "optional" | "repeated" => {
+                    self.bump();
+                    let label = match keyword.as_str() {
+                        "required" => Label::Required,
+                        "optional" => Label::Optional,
+                        _ => Label::Repeated,
+                    };
+                    if self.is_ident("group") {
+                        return self.error("groups are not supported");
+                    }
+                    message.fields.push(self.parse_field(label)?);
+                }
+                _ => message.fields.push(self.parse_field(Label::Optional)?),
+            }
+        }
+        return Ok(message);
+    }
+
+    //`type name = number [options];` after the label
+    fn parse_field(&mut self, mut label: Label) -> Result<Field, ParseError> {
+        let pos = self.pos();
+        let ty = if self.is_ident("map") && *self.peek(1) == Token::Symbol('<') {
+            self.bump();
+            self.bump();
+            let key_pos = self.pos();
+            let key = match Scalar::from_name(&self.ident()?) {
+                Some(Scalar::Double) | Some(Scalar::Float) | Some(Scalar::Bytes) | None => {
+                    return self.error_at(key_pos, "invalid map key type")
+                }
+                Some(key) => key,
+            };
+            self.expect(',')?;
+            let value = self.field_type()?;
+            self.expect('>')?;
+            label = Label::Repeated;
+            FieldType::Map(key, Box::new(value))
+        } else {
+            self.field_type()?
+        };
+        let name = self.ident()?;
+        self.expect('=')?;
+        let number_pos = self.pos();
+        let number = self.int()?;
+        if !(1..1 << 29).contains(&number) {
+            return self.error_at(number_pos, "field numbers have to be between 1 and 2^29-1");
+        }
+        let mut packed = None;
+        if self.eat('[') {
+            loop {
+                let option = self.option_name()?;
+                self.expect('=')?;
+                if option == "packed" {
+                    packed = Some(self.is_ident("true"));
+                }
+                self.skip_option_value()?;
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            self.expect(']')?;
+        }
+        self.expect(';')?;
+        return Ok(Field {
+            name,
+            label,
+            ty,
+            number: number as u64,
+            packed,
+            oneof: None,
+            pos,
+        });
+    }
+
+    fn field_type(&mut self) -> Result<FieldType, ParseError> {
+        let name = self.full_ident()?;
+        return Ok(match Scalar::from_name(&name) {
+            Some(scalar) => FieldType::Scalar(scalar),
+            None => FieldType::Named(name),
+        });
+    }
+
+    //`name` or `(custom.option).field`
+    fn option_name(&mut self) -> Result<String, ParseError> {
+        let mut name = String::new();
+        loop {
+            if self.eat('(') {
+                name.push_str(&format!("({})", self.full_ident()?));
+                self.expect(')')?;
+            } else {
+                name.push_str(&self.ident()?);
+            }
+            if !self.eat('.') {
+                return Ok(name);
+            }
+            name.push('.');
+        }
+    }
+
+    //Option values are constants or `{ ... }` aggregates
+    fn skip_option_value(&mut self) -> Result<(), ParseError> {
+        if *self.peek(0) == Token::Symbol('{') {
+            let start = self.pos();
+            let mut depth = 0;
+            loop {
+                match self.bump() {
+                    Token::Symbol('{') => depth += 1,
+                    Token::Symbol('}') => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                    Token::Eof => return self.error_at(start, "unterminated option value"),
+                    _ => {}
+                }
+            }
+        }
+        self.eat('-');
+        self.eat('+');
+        return match self.bump() {
+            Token::Ident(_) | Token::Int(_) | Token::Float | Token::Str(_) => Ok(()),
+            _ => self.error_at(self.tokens[self.idx - 1].1, "expected an option value"),
+        };
+    }
+
+    fn parse_enum(&mut self) -> Result<EnumDef, ParseError> {
+        let mut def = EnumDef {
+            name: self.ident()?,
+            values: vec![],
+        };
+        self.expect('{')?;
+        while !self.eat('}') {
+            if self.eat(';') {
+                continue;
+            }
+            if self.is_ident("option") || self.is_ident("reserved") {
+                self.skip_statement()?;
+                continue;
+            }
+            let name = self.ident()?;
+            self.expect('=')?;
+            let value = self.int()?;
+            if self.eat('[') {
+                while !self.eat(']') {
+                    if self.bump() == Token::Eof {
+                        return self.error("expected ]");
+                    }
+                }
+            }
+            self.expect(';')?;
+            def.values.push((name, value));
+        }
+        return Ok(def);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_proto_syntax() {
+        let file = parse(
+            "syntax = \"proto3\";\n\
+             package a.b;\n\
+             import public \"other.proto\";\n\
+             option java_package = \"x\";\n\
+             service S { rpc Get (Req) returns (Res) { option (x) = { a: 1 }; } }\n\
+             message Outer {\n\
+             \x20 reserved 2, 15 to 20;\n\
+             \x20 message Inner {\n\
+             \x20   enum E { option allow_alias = true; A = 0; B = -1 [deprecated = true]; }\n\
+             \x20 }\n\
+             \x20 repeated int32 ids = 1 [packed = false, (my.opt).x = \"y\"];\n\
+             \x20 .a.b.Outer.Inner inner = 3;\n\
+             \x20 oneof choice { string text = 4; bytes data = 5; }\n\
+             \x20 map<string, Inner> by_name = 6;\n\
+             }\n",
+        ).expect("RAND_2775662313");
+        assert!(file.packed_by_default);
+        assert_eq!(file.package, Some("a.b".to_string()));
+        assert_eq!(file.imports[0].0, "other.proto");
+        let outer = &file.messages[0];
+        assert_eq!(outer.messages[0].enums[0].values[1], ("B".to_string(), -1));
+        let fields = outer
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.label, f.number, f.packed, f.oneof.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                ("ids", Label::Repeated, 1, Some(false), false),
+                ("inner", Label::Optional, 3, None, false),
+                ("text", Label::Optional, 4, None, true),
+                ("data", Label::Optional, 5, None, true),
+                ("by_name", Label::Repeated, 6, None, false),
+            ]
+        );
+        assert_eq!(outer.fields[1].ty, FieldType::Named(".a.b.Outer.Inner".to_string()));
+        assert_eq!(
+            outer.fields[4].ty,
+            FieldType::Map(Scalar::String, Box::new(FieldType::Named("Inner".to_string())))
+        );
+    }
+
+    #[test]
+    fn check_parse_errors() {
+        let err = parse("message A {\n  int32 a = 0;\n}").expect_err("RAND_233074571");
+        assert_eq!(err.to_string(), "2:13: field numbers have to be between 1 and 2^29-1");
+        let err = parse("message A { optional group G = 1 {} }").expect_err("RAND_3659596133");
+        assert_eq!(err.message, "groups are not supported");
+        let err = parse("message A { map<float, int32> m = 1; }").expect_err("RAND_1740460390");
+        assert_eq!(err.pos, Pos { line: 1, column: 17 });
+        assert!(parse("message A { int32 a = 1 }").is_err());
+    }
+}
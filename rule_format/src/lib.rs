@@ -0,0 +1,46 @@
+//Writes literals into grammartec rule formats like `if \{ {EXPR} \}`. Nonterminals are written in
+//braces and literal braces are escaped with a backslash. There is no escape for a backslash
+//itself, so a backslash right before a nonterminal would escape its opening brace. Every
+//converter producing formats uses these functions, such that all of them handle this the same.
+
+//Escapes the braces of a literal. Trailing backslashes are cut off and counted, they have to be
+//derived by a nonterminal producing a single backslash.
+pub fn escape_literal(literal: &str) -> (String, usize) {
+    let trimmed = literal.trim_end_matches('\\');
+    let escaped = trimmed.replace("{", "\\{").replace("}", "\\}");
+    return (escaped, literal.len() - trimmed.len());
+}
+
+//Returns the format of a literal. backslash is only called if the literal ends with a backslash
+//and returns the name of the nonterminal producing a single backslash.
+pub fn literal_to_format<F: FnOnce() -> String>(literal: &str, backslash: F) -> String {
+    let (mut format, count) = escape_literal(literal);
+    if count > 0 {
+        format.push_str(&format!("{{{}}}", backslash()).repeat(count));
+    }
+    return format;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_escape_literal() {
+        assert_eq!(escape_literal("a{b}"), ("a\\{b\\}".to_string(), 0));
+        assert_eq!(escape_literal("a\\b"), ("a\\b".to_string(), 0));
+        assert_eq!(escape_literal("a\\\\"), ("a".to_string(), 2));
+        assert_eq!(escape_literal("\\"), ("".to_string(), 1));
+        assert_eq!(escape_literal("{\\"), ("\\{".to_string(), 1));
+    }
+
+    #[test]
+    fn check_literal_to_format() {
+        let never = || -> String { panic!("RAND_1278867558") };
+        assert_eq!(literal_to_format("x{", never), "x\\{");
+        assert_eq!(
+            literal_to_format("x\\\\", || "BACKSLASH".to_string()),
+            "x{BACKSLASH}{BACKSLASH}"
+        );
+    }
+}